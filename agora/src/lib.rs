@@ -9,14 +9,16 @@ pub mod server;
 pub mod message;
 
 use common::config::Settings;
+use common::error::{AppError, ErrorCode};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
+use tokio::sync::broadcast;
 // Removed unused import: tokio::net::TcpStream
 use tokio_tungstenite::tungstenite::protocol::Message;
 // Removed unused import: WebSocketStream
-use tracing::{error, info};
+use tracing::info;
 
 // Type aliases for cleaner code
 // Removed unused type alias: WsStream
@@ -29,7 +31,7 @@ pub enum AgoraError {
     StringError(String),
     
     #[error("WebSocket error: {0}")]
-    ConnectionError(#[from] tokio_tungstenite::tungstenite::Error),
+    ConnectionError(#[source] Box<tokio_tungstenite::tungstenite::Error>),
     
     #[error("Configuration error: {0}")]
     ConfigError(#[from] common::error::CommonError),
@@ -53,6 +55,35 @@ pub enum AgoraError {
     TopicNotFound(String),
 }
 
+impl AgoraError {
+    /// Stable code of the error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AgoraError::TopicNotFound(_) => ErrorCode::TopicNotFound,
+            AgoraError::ConfigError(e) => e.code(),
+            AgoraError::StringError(_)
+            | AgoraError::ConnectionError(_)
+            | AgoraError::RoutingError(_)
+            | AgoraError::IoError(_)
+            | AgoraError::ClientError(_)
+            | AgoraError::SubscriptionError(_)
+            | AgoraError::MessageError(_) => ErrorCode::MessagingError,
+        }
+    }
+}
+
+impl From<AgoraError> for AppError {
+    fn from(err: AgoraError) -> Self {
+        AppError::new(err.code(), err.to_string())
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for AgoraError {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        AgoraError::ConnectionError(Box::new(err))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgoraRequest {
     pub id: String,
@@ -70,6 +101,7 @@ pub struct AgoraResponse {
 pub struct AgoraServer {
     subscription_manager: SubscriptionManager,
     settings: Settings,
+    topics: topic::TopicManager,
 }
 
 impl AgoraServer {
@@ -77,8 +109,24 @@ impl AgoraServer {
         AgoraServer {
             subscription_manager: Arc::new(Mutex::new(HashMap::new())),
             settings,
+            topics: topic::TopicManager::new(),
         }
     }
+
+    /// Topics of this server
+    pub fn topics(&self) -> &topic::TopicManager {
+        &self.topics
+    }
+
+    /// Publish a message to the subscribers of a tenant's topic, returning how many received it
+    pub fn publish_to_tenant(&self, tenant: &str, topic: &str, message: Message) -> Result<usize, AgoraError> {
+        self.topics.get_or_create_tenant_topic(tenant, topic)?.publish(message)
+    }
+
+    /// Receive the messages published on a tenant's topic from now on
+    pub fn subscribe_tenant(&self, tenant: &str, topic: &str) -> Result<broadcast::Receiver<Message>, AgoraError> {
+        Ok(self.topics.get_or_create_tenant_topic(tenant, topic)?.subscribe())
+    }
     
     // Methods for handling connections and messages
     pub async fn handle_connection(&self, client_id: String) -> Result<(), AgoraError> {
@@ -119,14 +167,23 @@ impl AgoraServer {
         
         info!("Starting Agora WebSocket server on {}", addr);
         
+        let tls = match &self.settings.agora.tls {
+            Some(tls) => {
+                info!(cert_path = %tls.cert_path, mutual = tls.client_ca_path.is_some(), "Serving WSS");
+                Some(Arc::new(common::tls::TlsReloader::new(tls.clone(), &[])?))
+            }
+            None => None,
+        };
         let listener = tokio::net::TcpListener::bind(&addr).await?;
         
-        while let Ok((_stream, _)) = listener.accept().await {
-            // Commented out until tokio-tungstenite is properly configured
-            // let ws_stream = accept_async(stream).await?;
-            info!("New WebSocket connection established");
-            
-            // TODO: Handle connection in separate task
+        while let Ok((stream, peer_addr)) = listener.accept().await {
+            info!("New WebSocket connection from {}", peer_addr);
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server::WebSocketServer::handle_connection(stream, tls).await {
+                    tracing::error!("Error handling WebSocket connection from {}: {}", peer_addr, e);
+                }
+            });
         }
         
         Ok(())
@@ -203,4 +260,69 @@ mod tests {
         // Clean up
         ws_stream.close(None).await.expect("Failed to close WebSocket connection");
     }
+
+    #[tokio::test]
+    async fn test_websocket_echo_over_tls() {
+        use common::config::{ClientIdentitySettings, TlsSettings};
+        use common::tls::TlsReloader;
+        use std::sync::Arc;
+
+        let fixture = |name: &str| format!("{}/../common/testdata/tls/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let pem = |name: &str| std::fs::read(fixture(name)).unwrap();
+        let tls = TlsReloader::new(
+            TlsSettings {
+                cert_path: fixture("server.pem"),
+                key_path: fixture("server.key"),
+                client_ca_path: Some(fixture("ca.pem")),
+                require_client_cert: true,
+                client_identities: vec![ClientIdentitySettings {
+                    subject: "agent-1".to_string(),
+                    username: "agent-bot".to_string(),
+                }],
+                map_unlisted_subjects: false,
+            },
+            &[],
+        )
+        .unwrap();
+
+        let (_tx, rx) = mpsc::channel::<String>(100);
+        let port = 10000 + rand::random::<u16>() % 1000;
+        let server = WebSocketServer::new(port, rx).with_tls(Arc::new(tls));
+        tokio::spawn(async move {
+            if let Err(e) = server.run().await {
+                eprintln!("Server error: {:?}", e);
+            }
+        });
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+        // Connect as agent-1 with a certificate signed by the test authority
+        let mut roots = rustls::RootCertStore::empty();
+        for certificate in rustls_pemfile::certs(&mut pem("ca.pem").as_slice()) {
+            roots.add(certificate.unwrap()).unwrap();
+        }
+        let client_certs = rustls_pemfile::certs(&mut pem("client.pem").as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let client_key = rustls_pemfile::private_key(&mut pem("client.key").as_slice()).unwrap().unwrap();
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(client_certs, client_key)
+            .unwrap();
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+            .connect(rustls::pki_types::ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .expect("TLS handshake failed");
+        let url = format!("wss://localhost:{}/ws", port);
+        let (mut ws_stream, _) = tokio_tungstenite::client_async(url, stream)
+            .await
+            .expect("Failed to open WebSocket over TLS");
+
+        ws_stream.send(tokio_tungstenite::tungstenite::Message::Text("over tls".into())).await.unwrap();
+        let reply = ws_stream.next().await.unwrap().unwrap();
+        assert_eq!(reply.to_text().unwrap(), "over tls");
+        ws_stream.close(None).await.unwrap();
+    }
 }
@@ -1,10 +1,13 @@
+use common::tls::{ClientCertificate, TlsReloader};
 use futures::{SinkExt, StreamExt};
 use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
 use tokio_tungstenite::{accept_async, WebSocketStream};
 use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 /// WebSocket server for real-time communication
 pub struct WebSocketServer {
@@ -15,6 +18,8 @@ pub struct WebSocketServer {
     /// Collection of active connections
     #[allow(dead_code)]
     connections: Vec<mpsc::Sender<String>>,
+    /// TLS termination, for WSS
+    tls: Option<Arc<TlsReloader>>,
 }
 
 impl WebSocketServer {
@@ -24,8 +29,15 @@ impl WebSocketServer {
             port,
             receiver,
             connections: Vec::new(),
+            tls: None,
         }
     }
+
+    /// Accept WSS instead of plain WebSocket connections
+    pub fn with_tls(mut self, tls: Arc<TlsReloader>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
     
     /// Run the WebSocket server
     pub async fn run(mut self) -> Result<(), Box<dyn std::error::Error>> {
@@ -39,8 +51,9 @@ impl WebSocketServer {
                 // Handle new connections
                 Ok((stream, peer_addr)) = listener.accept() => {
                     info!("New WebSocket connection from {}", peer_addr);
+                    let tls = self.tls.clone();
                     tokio::spawn(async move {
-                        if let Err(e) = Self::handle_connection(stream).await {
+                        if let Err(e) = Self::handle_connection(stream, tls).await {
                             error!("Error handling WebSocket connection: {}", e);
                         }
                     });
@@ -59,15 +72,43 @@ impl WebSocketServer {
         Ok(())
     }
     
-    /// Handle a WebSocket connection
-    async fn handle_connection(stream: TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+    /// Handle a WebSocket connection, completing the TLS handshake first when configured
+    pub(crate) async fn handle_connection(
+        stream: TcpStream,
+        tls: Option<Arc<TlsReloader>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match tls {
+            Some(tls) => {
+                let stream = tls.acceptor().accept(stream).await?;
+                let client = tls.client_certificate(stream.get_ref().1);
+                Self::handle_stream(stream, client).await
+            }
+            None => Self::handle_stream(stream, None).await,
+        }
+    }
+
+    /// Upgrade a connection to a WebSocket and process it
+    async fn handle_stream<S>(stream: S, client: Option<ClientCertificate>) -> Result<(), Box<dyn std::error::Error>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match client.as_ref().map(|client| (&client.subject, &client.identity)) {
+            Some((subject, Some(identity))) => {
+                info!(%subject, %identity, "Agent authenticated with a client certificate")
+            }
+            Some((subject, None)) => warn!(%subject, "Client certificate subject is not mapped to an identity"),
+            None => {}
+        }
         let ws_stream = accept_async(stream).await?;
         
         Self::process_websocket(ws_stream).await
     }
     
     /// Process WebSocket messages
-    async fn process_websocket(mut ws_stream: WebSocketStream<TcpStream>) -> Result<(), Box<dyn std::error::Error>> {
+    async fn process_websocket<S>(mut ws_stream: WebSocketStream<S>) -> Result<(), Box<dyn std::error::Error>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
         // Process incoming messages from the WebSocket client
         while let Some(msg) = ws_stream.next().await {
             match msg {
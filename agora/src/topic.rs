@@ -7,6 +7,14 @@ use tracing::{debug, warn};
 
 const CHANNEL_CAPACITY: usize = 1000;
 
+/// Separates the tenant from the topic name in scoped topic names
+const TENANT_SEPARATOR: char = '/';
+
+/// Name a tenant's topic is stored under, e.g. `acme/system`
+pub fn tenant_topic_name(tenant: &str, name: &str) -> String {
+    format!("{}{}{}", tenant, TENANT_SEPARATOR, name)
+}
+
 #[derive(Debug, Clone)]
 pub struct Topic {
     name: String,
@@ -42,6 +50,12 @@ pub struct TopicManager {
     topics: RwLock<HashMap<String, Topic>>,
 }
 
+impl Default for TopicManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TopicManager {
     pub fn new() -> Self {
         Self {
@@ -107,4 +121,49 @@ impl TopicManager {
             
         Ok(topics.keys().cloned().collect())
     }
+
+    /// Get or create a topic visible only to one tenant
+    pub fn get_or_create_tenant_topic(&self, tenant: &str, name: &str) -> Result<Topic, AgoraError> {
+        self.get_or_create_topic(&tenant_topic_name(tenant, name))
+    }
+
+    /// Get a topic of one tenant; other tenants' topics are not found
+    pub fn get_tenant_topic(&self, tenant: &str, name: &str) -> Result<Topic, AgoraError> {
+        self.get_topic(&tenant_topic_name(tenant, name))
+    }
+
+    /// Names of a tenant's topics, without the tenant prefix
+    pub fn list_tenant_topics(&self, tenant: &str) -> Result<Vec<String>, AgoraError> {
+        let prefix = tenant_topic_name(tenant, "");
+        Ok(self
+            .list_topics()?
+            .into_iter()
+            .filter_map(|name| name.strip_prefix(&prefix).map(str::to_string))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_topics_are_isolated() {
+        let manager = TopicManager::new();
+        let acme = manager.get_or_create_tenant_topic("acme", "system").unwrap();
+        manager.get_or_create_tenant_topic("globex", "system").unwrap();
+        manager.get_or_create_tenant_topic("globex", "alerts").unwrap();
+
+        assert_eq!(acme.get_name(), "acme/system");
+        assert_eq!(manager.list_tenant_topics("acme").unwrap(), ["system"]);
+        assert!(manager.get_tenant_topic("acme", "alerts").is_err());
+
+        let mut receiver = acme.subscribe();
+        manager
+            .get_tenant_topic("globex", "system")
+            .unwrap()
+            .publish(Message::Text("for globex".into()))
+            .unwrap_or_default();
+        assert!(receiver.try_recv().is_err());
+    }
 }
@@ -2,7 +2,7 @@ use axum::{
     extract::{Path, State},
     Json,
 };
-use common::errors::AppError;
+use common::AppError;
 
 // Define a placeholder type for now
 pub struct AppState;
@@ -0,0 +1,91 @@
+//! API keys for machine clients
+//!
+//! Agents and CI jobs authenticate with long-lived keys of the form
+//! `nxk_<id>_<secret>`, sent as `X-API-Key` or as a bearer token. The ID is
+//! stored in the clear to look the key up; only a SHA-256 hash of the secret
+//! is kept. A key acts for the user who owns it, limited to its scopes:
+//! permissions such as `agent:read` or `agent:*` that the owner's roles
+//! must also grant.
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Prefix identifying API keys among bearer credentials
+pub const API_KEY_PREFIX: &str = "nxk_";
+
+/// Header carrying an API key
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Seconds between updates of a key's last use, to avoid a write per request
+pub const LAST_USED_RESOLUTION_SECONDS: i64 = 60;
+
+/// Random bytes in a key ID
+const ID_BYTES: usize = 8;
+
+/// Random bytes in a key secret
+const SECRET_BYTES: usize = 32;
+
+/// Whether a credential is an API key rather than a JWT
+pub fn is_api_key(credential: &str) -> bool {
+    credential.starts_with(API_KEY_PREFIX)
+}
+
+/// Generate a key, returning its ID, the hash of its secret and the full key
+pub(crate) fn generate() -> (String, String, String) {
+    let mut id = [0u8; ID_BYTES];
+    let mut secret = [0u8; SECRET_BYTES];
+    rand::rngs::OsRng.fill_bytes(&mut id);
+    rand::rngs::OsRng.fill_bytes(&mut secret);
+
+    let (id, secret) = (hex::encode(id), hex::encode(secret));
+    let key = format!("{}{}_{}", API_KEY_PREFIX, id, secret);
+    (id, hash_secret(&secret), key)
+}
+
+/// Split a key into its ID and secret
+pub(crate) fn parse(key: &str) -> Option<(&str, &str)> {
+    let (id, secret) = key.strip_prefix(API_KEY_PREFIX)?.split_once('_')?;
+    let is_hex = |part: &str, bytes: usize| part.len() == bytes * 2 && part.bytes().all(|b| b.is_ascii_hexdigit());
+    (is_hex(id, ID_BYTES) && is_hex(secret, SECRET_BYTES)).then_some((id, secret))
+}
+
+/// Secrets are high-entropy, so an unsalted hash is enough
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// Compare a presented secret with a stored hash in constant time
+pub(crate) fn verify_secret(secret: &str, secret_hash: &str) -> bool {
+    hash_secret(secret).as_bytes().ct_eq(secret_hash.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_and_verify() {
+        let (id, secret_hash, key) = generate();
+        assert!(is_api_key(&key));
+        assert!(!key.contains(&secret_hash));
+
+        let (parsed_id, secret) = parse(&key).unwrap();
+        assert_eq!(parsed_id, id);
+        assert!(verify_secret(secret, &secret_hash));
+        assert!(!verify_secret(&secret.replace(|c| c != '0', "0"), &secret_hash));
+
+        let (_, other_hash, _) = generate();
+        assert!(!verify_secret(secret, &other_hash));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_keys() {
+        assert!(parse("nxk_").is_none());
+        assert!(parse("nxk_0123456789abcdef").is_none());
+        assert!(parse(&format!("nxk_0123456789abcdef_{}", "z".repeat(64))).is_none());
+        assert!(parse(&format!("nxk_0123_{}", "0".repeat(64))).is_none());
+        assert!(parse(&format!("key_0123456789abcdef_{}", "0".repeat(64))).is_none());
+        assert!(parse(&format!("nxk_0123456789abcdef_{}", "0".repeat(64))).is_some());
+    }
+}
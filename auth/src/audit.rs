@@ -0,0 +1,434 @@
+//! Audit log
+//!
+//! Records who did what to which object: logins and other security events,
+//! and every change made through the API or the CLI, with the state of the
+//! object before and after. Events are appended to the database and,
+//! optionally, to a JSON Lines file that can be shipped off the host;
+//! nothing updates or deletes them. Secrets are redacted before an event is
+//! written anywhere.
+
+use crate::error::AuthError;
+use crate::jwt::Claims;
+use crate::tenants;
+use crate::AuthResult;
+use async_trait::async_trait;
+use common::config::AuditSettings;
+use common::secret::{is_secret_field, REDACTED};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use uuid::Uuid;
+
+/// Events a query returns when it sets no limit
+pub const DEFAULT_QUERY_LIMIT: u32 = 100;
+
+/// Most events a single query returns
+pub const MAX_QUERY_LIMIT: u32 = 1000;
+
+/// Whether an action succeeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditOutcome::Success => "success",
+            AuditOutcome::Failure => "failure",
+        }
+    }
+
+    /// Outcome from its stored name
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "failure" => AuditOutcome::Failure,
+            _ => AuditOutcome::Success,
+        }
+    }
+}
+
+/// A recorded action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Time-ordered event ID
+    pub id: String,
+    /// Unix timestamp of the action
+    pub occurred_at: i64,
+    /// Tenant the action happened in
+    #[serde(default = "tenants::default_tenant")]
+    pub tenant: String,
+    /// ID of the user who acted, when known
+    pub actor_id: Option<String>,
+    /// Who acted: a username, `cli:<os user>` for the CLI, or the username
+    /// given in a failed login
+    pub actor: Option<String>,
+    /// What happened, e.g. `user.create` or `auth.login`
+    pub action: String,
+    /// ID or name of the object acted on
+    pub target: Option<String>,
+    /// Whether the action succeeded
+    pub outcome: AuditOutcome,
+    /// Why the action failed
+    pub reason: Option<String>,
+    /// State of the target before the action
+    pub before: Option<Value>,
+    /// State of the target after the action
+    pub after: Option<Value>,
+    /// Address of the client
+    pub source_ip: Option<String>,
+}
+
+/// A top-level field that differs between an event's before and after states
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditChange {
+    /// Field name, empty when the states are not objects
+    pub field: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+impl AuditEvent {
+    /// A successful action happening now
+    pub fn new(action: &str) -> Self {
+        Self {
+            id: Uuid::now_v7().to_string(),
+            occurred_at: chrono::Utc::now().timestamp(),
+            tenant: tenants::default_tenant(),
+            actor_id: None,
+            actor: None,
+            action: action.to_string(),
+            target: None,
+            outcome: AuditOutcome::Success,
+            reason: None,
+            before: None,
+            after: None,
+            source_ip: None,
+        }
+    }
+
+    /// Attribute the action to the user of a token or API key, in their tenant
+    pub fn with_actor(self, claims: &Claims) -> Self {
+        self.with_user(&claims.sub, &claims.username).with_tenant(&claims.tenant)
+    }
+
+    /// Set the tenant the action happened in
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = tenant.to_string();
+        self
+    }
+
+    /// Attribute the action to a user
+    pub fn with_user(mut self, id: &str, username: &str) -> Self {
+        self.actor_id = Some(id.to_string());
+        self.actor = Some(username.to_string());
+        self
+    }
+
+    /// Attribute the action to a name without a user account behind it
+    pub fn with_actor_name(mut self, name: impl Into<String>) -> Self {
+        self.actor = Some(name.into());
+        self
+    }
+
+    /// Set the object acted on
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Record the state before the action, secrets redacted
+    pub fn with_before<T: Serialize>(mut self, state: &T) -> Self {
+        self.before = serde_json::to_value(state).ok().map(redact_secrets);
+        self
+    }
+
+    /// Record the state after the action, secrets redacted
+    pub fn with_after<T: Serialize>(mut self, state: &T) -> Self {
+        self.after = serde_json::to_value(state).ok().map(redact_secrets);
+        self
+    }
+
+    /// Set the client address
+    pub fn with_source_ip(mut self, source_ip: Option<String>) -> Self {
+        self.source_ip = source_ip;
+        self
+    }
+
+    /// Mark the action as failed
+    pub fn failed(mut self, reason: impl ToString) -> Self {
+        self.outcome = AuditOutcome::Failure;
+        self.reason = Some(reason.to_string());
+        self
+    }
+
+    /// Fields changed by the action
+    pub fn changes(&self) -> Vec<AuditChange> {
+        let before = self.before.clone().unwrap_or(Value::Null);
+        let after = self.after.clone().unwrap_or(Value::Null);
+        let (Some(old), Some(new)) = (object_or_empty(&before), object_or_empty(&after)) else {
+            return if before == after {
+                Vec::new()
+            } else {
+                vec![AuditChange { field: String::new(), before, after }]
+            };
+        };
+
+        let fields: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        fields
+            .into_iter()
+            .filter_map(|field| {
+                let before = old.get(field).cloned().unwrap_or(Value::Null);
+                let after = new.get(field).cloned().unwrap_or(Value::Null);
+                (before != after).then(|| AuditChange {
+                    field: field.clone(),
+                    before,
+                    after,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Fields of an object state, treating a missing state as an empty object
+fn object_or_empty(state: &Value) -> Option<serde_json::Map<String, Value>> {
+    match state {
+        Value::Object(fields) => Some(fields.clone()),
+        Value::Null => Some(serde_json::Map::new()),
+        _ => None,
+    }
+}
+
+/// Replace the values of secret fields, at any depth
+pub fn redact_secrets(value: Value) -> Value {
+    redact_at("", value)
+}
+
+/// Redact `value`, found at the dotted `path`
+fn redact_at(path: &str, value: Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(field, value)| {
+                    let path = if path.is_empty() { field.clone() } else { format!("{}.{}", path, field) };
+                    if is_secret_field(&path) {
+                        (field, Value::String(REDACTED.to_string()))
+                    } else {
+                        (field, redact_at(&path, value))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(|item| redact_at(path, item)).collect()),
+        value => value,
+    }
+}
+
+/// Filters for reading the audit log; unset filters match every event
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditQuery {
+    /// Tenant the action happened in
+    pub tenant: Option<String>,
+    /// User ID or name of the actor
+    pub actor: Option<String>,
+    /// Action, or a dotted prefix: `user` matches `user.create`
+    pub action: Option<String>,
+    /// Object acted on
+    pub target: Option<String>,
+    /// Whether the action succeeded
+    pub outcome: Option<AuditOutcome>,
+    /// Events at or after this Unix timestamp
+    pub since: Option<i64>,
+    /// Events before this Unix timestamp
+    pub until: Option<i64>,
+    /// Most events returned, newest first
+    pub limit: Option<u32>,
+}
+
+impl AuditQuery {
+    /// Limit applied to the query
+    pub fn effective_limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_QUERY_LIMIT).clamp(1, MAX_QUERY_LIMIT)
+    }
+}
+
+/// Append-only storage for audit events
+#[async_trait]
+pub trait AuditStore: Send + Sync {
+    /// Append an event
+    async fn append_audit_event(&self, event: &AuditEvent) -> AuthResult<()>;
+
+    /// Events matching a query, newest first
+    async fn audit_events(&self, query: &AuditQuery) -> AuthResult<Vec<AuditEvent>>;
+}
+
+/// JSON Lines file audit events are appended to
+pub struct AuditFile {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditFile {
+    /// Open a file for appending, creating it if needed
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append an event as one line
+    fn append(&self, event: &AuditEvent) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        // One write per line, so concurrent writers never interleave within a line
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        file.write_all(&line)?;
+        file.flush()
+    }
+}
+
+/// Audit log writing to the database and an optional file
+pub struct AuditLog {
+    store: Arc<dyn AuditStore>,
+    file: Option<AuditFile>,
+}
+
+impl AuditLog {
+    /// Create an audit log over a store
+    pub fn new(store: Arc<dyn AuditStore>) -> Self {
+        Self { store, file: None }
+    }
+
+    /// Also append events to a JSON Lines file
+    pub fn with_file(mut self, file: AuditFile) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Create an audit log over a store, with the file configured in settings
+    pub fn from_settings(store: Arc<dyn AuditStore>, settings: &AuditSettings) -> AuthResult<Self> {
+        let log = Self::new(store);
+        match &settings.file_path {
+            Some(path) => {
+                let file = AuditFile::open(path)
+                    .map_err(|e| AuthError::Configuration(format!("Cannot open audit log {}: {}", path, e)))?;
+                Ok(log.with_file(file))
+            }
+            None => Ok(log),
+        }
+    }
+
+    /// Record an event
+    ///
+    /// The action has already happened, so a sink that cannot be written is
+    /// logged as an error instead of failing the caller.
+    pub async fn record(&self, event: AuditEvent) {
+        tracing::info!(
+            target: "audit",
+            action = %event.action,
+            actor = event.actor.as_deref().unwrap_or("-"),
+            target_id = event.target.as_deref().unwrap_or("-"),
+            outcome = event.outcome.as_str(),
+            "Audit event"
+        );
+        if let Err(e) = self.store.append_audit_event(&event).await {
+            tracing::error!(error = %e, action = %event.action, "Failed to store audit event");
+        }
+        if let Some(file) = &self.file {
+            if let Err(e) = file.append(&event) {
+                tracing::error!(error = %e, path = %file.path().display(), "Failed to write audit event");
+            }
+        }
+    }
+
+    /// Events matching a query, newest first
+    pub async fn query(&self, query: &AuditQuery) -> AuthResult<Vec<AuditEvent>> {
+        self.store.audit_events(query).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_secrets_are_redacted_at_any_depth() {
+        let event = AuditEvent::new("user.update").with_after(&json!({
+            "username": "alice",
+            "password": "hunter2",
+            "key": "nxk_1_secret",
+            "oauth": { "client_secret": "s3cret", "client_id": "gateway" },
+            "keys": [{ "private_key_path": "/etc/key.pem" }],
+            "refresh_token": "abc",
+            "max_tokens": 2048,
+            "database": { "url": "postgres://gateway:hunter2@db/nexa" },
+        }));
+        let after = event.after.unwrap();
+        assert_eq!(after["username"], "alice");
+        assert_eq!(after["password"], REDACTED);
+        assert_eq!(after["key"], REDACTED);
+        assert_eq!(after["oauth"]["client_secret"], REDACTED);
+        assert_eq!(after["oauth"]["client_id"], "gateway");
+        assert_eq!(after["keys"][0]["private_key_path"], REDACTED);
+        assert_eq!(after["refresh_token"], REDACTED);
+        assert_eq!(after["max_tokens"], 2048);
+        assert_eq!(after["database"]["url"], REDACTED);
+    }
+
+    #[test]
+    fn test_changes_compare_top_level_fields() {
+        let event = AuditEvent::new("role.put")
+            .with_before(&json!({ "name": "ops", "permissions": ["agent:read"] }))
+            .with_after(&json!({ "name": "ops", "permissions": ["agent:*"], "inherits": ["user"] }));
+        let changes = event.changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].field, "inherits");
+        assert_eq!(changes[0].before, Value::Null);
+        assert_eq!(changes[1].field, "permissions");
+        assert_eq!(changes[1].after, json!(["agent:*"]));
+
+        let created = AuditEvent::new("user.create").with_after(&json!({ "username": "bob" }));
+        assert_eq!(created.changes().len(), 1);
+        assert!(AuditEvent::new("auth.logout").changes().is_empty());
+    }
+
+    #[test]
+    fn test_file_appends_one_line_per_event() {
+        let path = std::env::temp_dir().join(format!("nexa-audit-{}.jsonl", Uuid::new_v4()));
+        let file = AuditFile::open(&path).unwrap();
+        file.append(&AuditEvent::new("auth.login").with_actor_name("alice")).unwrap();
+        file.append(&AuditEvent::new("auth.login").failed("Invalid credentials")).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let events: Vec<AuditEvent> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].actor.as_deref(), Some("alice"));
+        assert_eq!(events[1].outcome, AuditOutcome::Failure);
+        assert!(events[0].id < events[1].id);
+    }
+}
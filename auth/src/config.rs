@@ -1,52 +1,187 @@
-use config::{Config, ConfigError, Environment, File};
-use serde::Deserialize;
-use std::env;
-use std::path::PathBuf;
+//! JWT configuration
+//!
+//! Builds token signing and validation settings from the application's
+//! `auth` configuration section.
 
-#[derive(Debug, Deserialize, Clone)]
+use crate::error::AuthError;
+use crate::keys::{is_hmac, parse_algorithm, JwtKey, KeyRing, KeySet};
+use common::config::AuthConfig;
+use common::secret::Secret;
+use common::validation::insecure_secret;
+use std::sync::Arc;
+
+/// Settings used to sign and validate JWTs
+#[derive(Debug, Clone)]
 pub struct JwtConfig {
-    pub secret: String,
+    /// Keys tokens are signed and verified with
+    pub keys: Arc<KeyRing>,
+    /// Shared secret when tokens are signed with HMAC
+    pub secret: Option<Secret>,
+    /// Value of the `iss` claim
     pub issuer: String,
+    /// Value of the `aud` claim
     pub audience: String,
-    pub algorithm: String,
+    /// Access token lifetime in seconds
     pub access_token_expiry: u64,
+    /// Refresh token lifetime in seconds
     pub refresh_token_expiry: u64,
+    /// Allowed clock skew in seconds when checking `exp` and `nbf`
+    pub leeway: u64,
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct SecurityConfig {
-    pub cors_origins: Vec<String>,
-    pub rate_limit: u32,
-    pub rate_limit_window: u64,
+impl JwtConfig {
+    /// Build the JWT configuration from the `auth` settings
+    pub fn from_settings(settings: &AuthConfig) -> Result<Self, AuthError> {
+        Ok(Self {
+            keys: Arc::new(KeyRing::new(key_set(settings)?)),
+            secret: settings.jwt_keys.is_empty().then(|| settings.jwt_secret.clone()),
+            issuer: settings.jwt_issuer.clone(),
+            audience: settings.jwt_audience.clone(),
+            access_token_expiry: settings.jwt_expiration * 3600,
+            refresh_token_expiry: settings.refresh_expiration * 3600,
+            leeway: settings.jwt_leeway_seconds,
+        })
+    }
+
+    /// Refuse well-known or short secrets when running in production
+    pub fn ensure_secure(&self, environment: &str) -> Result<(), AuthError> {
+        match self.secret.as_ref().and_then(|secret| insecure_secret(secret.expose(), environment)) {
+            Some(message) => Err(AuthError::Configuration(format!("auth.jwt_secret {}", message))),
+            None => Ok(()),
+        }
+    }
+
+    /// Longest time a token stays valid, in seconds
+    pub fn max_token_lifetime(&self) -> i64 {
+        (self.access_token_expiry + self.leeway) as i64
+    }
+
+    /// Fixed configuration for unit tests
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        let secret = "test-secret-key";
+        Self {
+            keys: Arc::new(KeyRing::new(
+                KeySet::shared_secret(jsonwebtoken::Algorithm::HS256, secret.as_bytes())
+                    .expect("HS256 is an HMAC algorithm"),
+            )),
+            secret: Some(secret.into()),
+            issuer: "nexa-gateway-test".to_string(),
+            audience: "nexa-gateway-test".to_string(),
+            access_token_expiry: 3600,
+            refresh_token_expiry: 86400,
+            leeway: 0,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct AuthConfig {
-    pub jwt: JwtConfig,
-    pub security: SecurityConfig,
-    pub service_endpoint: String,
+/// Load the configured PEM keys, or fall back to the shared secret
+fn key_set(settings: &AuthConfig) -> Result<KeySet, AuthError> {
+    if settings.jwt_keys.is_empty() {
+        if settings.jwt_secret.is_empty() {
+            return Err(AuthError::Configuration("auth.jwt_secret must not be empty".to_string()));
+        }
+        let algorithm = parse_algorithm(&settings.jwt_algorithm)?;
+        if !is_hmac(algorithm) {
+            return Err(AuthError::Configuration(format!(
+                "auth.jwt_algorithm {} requires auth.jwt_keys",
+                settings.jwt_algorithm
+            )));
+        }
+        return KeySet::shared_secret(algorithm, settings.jwt_secret.expose().as_bytes());
+    }
+
+    let keys = settings
+        .jwt_keys
+        .iter()
+        .map(JwtKey::from_settings)
+        .collect::<Result<Vec<_>, _>>()?;
+    let signing_kid = match &settings.jwt_signing_kid {
+        Some(kid) => kid.clone(),
+        None => keys
+            .iter()
+            .find(|key| key.can_sign())
+            .map(|key| key.kid.clone())
+            .ok_or_else(|| AuthError::Configuration("auth.jwt_keys has no key with a private key".to_string()))?,
+    };
+    KeySet::new(&signing_kid, keys)
 }
 
-impl AuthConfig {
-    pub fn new() -> Result<Self, ConfigError> {
-        let run_mode = env::var("RUN_MODE").unwrap_or_else(|_| "development".into());
-        let config_path = env::var("CONFIG_PATH").unwrap_or_else(|_| "config".into());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::config::JwtKeySettings;
+    use jsonwebtoken::Algorithm;
+
+    fn settings(secret: &str) -> AuthConfig {
+        AuthConfig {
+            jwt_secret: secret.into(),
+            jwt_expiration: 2,
+            jwt_issuer: "issuer".to_string(),
+            jwt_audience: "audience".to_string(),
+            jwt_algorithm: "HS384".to_string(),
+            jwt_leeway_seconds: 5,
+            refresh_expiration: 720,
+            lockout: Default::default(),
+            jwt_keys: Vec::new(),
+            jwt_signing_kid: None,
+            roles: Vec::new(),
+            oauth: None,
+            two_factor: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_from_settings() {
+        let config = JwtConfig::from_settings(&settings("secret")).unwrap();
+        assert_eq!(config.keys.current().signing_key().algorithm, Algorithm::HS384);
+        assert_eq!(config.access_token_expiry, 7200);
 
-        let mut config = Config::builder()
-            .add_source(File::with_name(&format!("{}/default", config_path)).required(false))
-            .add_source(File::with_name(&format!("{}/{}", config_path, run_mode)).required(false))
-            .add_source(Environment::with_prefix("AUTH").separator("__"))
-            .build()?;
+        let mut unsupported = settings("secret");
+        unsupported.jwt_algorithm = "none".to_string();
+        assert!(JwtConfig::from_settings(&unsupported).is_err());
 
-        config.try_deserialize()
+        // Asymmetric algorithms need key files
+        unsupported.jwt_algorithm = "RS256".to_string();
+        assert!(JwtConfig::from_settings(&unsupported).is_err());
     }
 
-    pub fn get_jwt_algorithm(&self) -> Result<jsonwebtoken::Algorithm, String> {
-        match self.jwt.algorithm.as_str() {
-            "HS256" => Ok(jsonwebtoken::Algorithm::HS256),
-            "HS384" => Ok(jsonwebtoken::Algorithm::HS384),
-            "HS512" => Ok(jsonwebtoken::Algorithm::HS512),
-            _ => Err(format!("Unsupported JWT algorithm: {}", self.jwt.algorithm)),
-        }
+    #[test]
+    fn test_from_key_files() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/testdata");
+        let mut with_keys = settings("supersecretkey");
+        with_keys.jwt_keys = vec![
+            JwtKeySettings {
+                kid: "old".to_string(),
+                algorithm: "RS256".to_string(),
+                private_key_path: None,
+                public_key_path: format!("{}/rsa_public.pem", dir),
+            },
+            JwtKeySettings {
+                kid: "current".to_string(),
+                algorithm: "ES256".to_string(),
+                private_key_path: Some(format!("{}/ec_private.pem", dir)),
+                public_key_path: format!("{}/ec_public.pem", dir),
+            },
+        ];
+
+        let config = JwtConfig::from_settings(&with_keys).unwrap();
+        assert_eq!(config.keys.current().signing_key().kid, "current");
+        assert_eq!(config.keys.current().jwks().keys.len(), 2);
+        // The unused shared secret is not held to production rules
+        assert!(config.ensure_secure("production").is_ok());
+
+        with_keys.jwt_signing_kid = Some("old".to_string());
+        assert!(JwtConfig::from_settings(&with_keys).is_err());
+    }
+
+    #[test]
+    fn test_production_rejects_default_secret() {
+        let config = JwtConfig::from_settings(&settings("supersecretkey")).unwrap();
+        assert!(config.ensure_secure("development").is_ok());
+        assert!(config.ensure_secure("production").is_err());
+
+        let config = JwtConfig::from_settings(&settings("a-unique-and-sufficiently-long-secret")).unwrap();
+        assert!(config.ensure_secure("production").is_ok());
     }
 }
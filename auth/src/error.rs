@@ -2,6 +2,8 @@
 //!
 //! This module defines authentication-related errors.
 
+use axum::response::{IntoResponse, Response};
+use common::error::{AppError, ErrorCode, ProblemDetails};
 use thiserror::Error;
 
 /// Authentication errors
@@ -15,6 +17,10 @@ pub enum AuthError {
     #[error("Invalid token")]
     InvalidToken,
     
+    /// Token or its session was revoked before expiring
+    #[error("Token has been revoked")]
+    TokenRevoked,
+    
     /// Error creating JWT token
     #[error("Failed to create token")]
     TokenCreationError,
@@ -31,10 +37,86 @@ pub enum AuthError {
     #[error("Invalid role")]
     InvalidRole,
     
+    /// Role definition is malformed or inconsistent
+    #[error("Invalid role definition: {0}")]
+    InvalidRoleDefinition(String),
+    
+    /// Tenant name is not acceptable
+    #[error("Invalid tenant name: {0}")]
+    InvalidTenant(String),
+    
+    /// Role does not exist
+    #[error("Role not found")]
+    RoleNotFound,
+    
+    /// Role is built in or configured and cannot be changed at runtime
+    #[error("Role is defined in configuration: {0}")]
+    RoleReadOnly(String),
+    
+    /// Role is still inherited by other roles
+    #[error("Role is inherited by: {0}")]
+    RoleInUse(String),
+    
+    /// API key settings are invalid, e.g. a scope that is not a permission
+    #[error("Invalid API key: {0}")]
+    InvalidApiKey(String),
+    
+    /// API key does not exist or was revoked
+    #[error("API key not found")]
+    ApiKeyNotFound,
+    
+    /// TOTP or recovery code is wrong or was already used
+    #[error("Invalid two-factor code")]
+    InvalidTwoFactorCode,
+    
+    /// User has no second factor, or has not started enrolling
+    #[error("Two-factor authentication is not enrolled")]
+    TwoFactorNotEnrolled,
+    
+    /// User already has a confirmed second factor
+    #[error("Two-factor authentication is already enabled")]
+    TwoFactorAlreadyEnabled,
+    
+    /// No OpenID Connect provider is configured
+    #[error("OpenID Connect login is not configured")]
+    OidcNotConfigured,
+    
+    /// OpenID Connect login was refused, e.g. an unknown state or an invalid ID token
+    #[error("OpenID Connect login failed: {0}")]
+    OidcLogin(String),
+    
+    /// OpenID Connect provider could not be reached or answered unexpectedly
+    #[error("OpenID Connect provider error: {0}")]
+    OidcProvider(String),
+    
     /// User lacks permission
     #[error("Permission denied")]
     PermissionDenied,
     
+    /// Account is locked after repeated failed logins
+    #[error("Account is temporarily locked")]
+    AccountLocked,
+    
+    /// Username is already taken
+    #[error("User already exists: {0}")]
+    UserExists(String),
+    
+    /// User does not exist
+    #[error("User not found")]
+    UserNotFound,
+    
+    /// Session does not exist or has ended
+    #[error("Session not found")]
+    SessionNotFound,
+    
+    /// Password does not meet the password policy
+    #[error("Weak password: {0}")]
+    WeakPassword(String),
+    
+    /// Invalid authentication configuration
+    #[error("Invalid authentication configuration: {0}")]
+    Configuration(String),
+    
     /// Database error
     #[error("Database error: {0}")]
     DatabaseError(String),
@@ -44,6 +126,53 @@ pub enum AuthError {
     Unknown(String),
 }
 
+impl AuthError {
+    /// Stable code of the error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AuthError::TokenExpired => ErrorCode::TokenExpired,
+            AuthError::InvalidToken => ErrorCode::InvalidToken,
+            AuthError::TokenRevoked => ErrorCode::TokenRevoked,
+            AuthError::MissingAuth => ErrorCode::AuthenticationRequired,
+            AuthError::InvalidCredentials | AuthError::OidcLogin(_) => ErrorCode::InvalidCredentials,
+            AuthError::InvalidTwoFactorCode => ErrorCode::InvalidTwoFactorCode,
+            AuthError::PermissionDenied => ErrorCode::PermissionDenied,
+            AuthError::AccountLocked => ErrorCode::AccountLocked,
+            AuthError::UserExists(_)
+            | AuthError::RoleReadOnly(_)
+            | AuthError::RoleInUse(_)
+            | AuthError::TwoFactorAlreadyEnabled => ErrorCode::Conflict,
+            AuthError::UserNotFound
+            | AuthError::SessionNotFound
+            | AuthError::RoleNotFound
+            | AuthError::ApiKeyNotFound
+            | AuthError::TwoFactorNotEnrolled
+            | AuthError::OidcNotConfigured => ErrorCode::NotFound,
+            AuthError::InvalidRole
+            | AuthError::InvalidRoleDefinition(_)
+            | AuthError::InvalidApiKey(_)
+            | AuthError::WeakPassword(_)
+            | AuthError::InvalidTenant(_) => ErrorCode::ValidationFailed,
+            AuthError::OidcProvider(_) => ErrorCode::UpstreamError,
+            AuthError::Configuration(_) => ErrorCode::ConfigurationError,
+            AuthError::DatabaseError(_) => ErrorCode::DatabaseError,
+            AuthError::TokenCreationError | AuthError::Unknown(_) => ErrorCode::InternalError,
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        ProblemDetails::from_error(self.code(), &self).into_response()
+    }
+}
+
+impl From<AuthError> for AppError {
+    fn from(err: AuthError) -> Self {
+        AppError::new(err.code(), err.to_string())
+    }
+}
+
 impl From<sqlx::Error> for AuthError {
     fn from(err: sqlx::Error) -> Self {
         AuthError::DatabaseError(err.to_string())
@@ -2,111 +2,310 @@
 //!
 //! This module handles JWT token generation, validation, and decoding.
 
+use crate::config::JwtConfig;
 use crate::error::AuthError;
+use crate::tenants;
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, Header, Validation};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 /// Claims structure for JWT tokens
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     /// Subject (user ID)
     pub sub: String,
-    /// Role for permission checking
-    pub role: String,
+    /// Roles for permission checking
+    pub roles: Vec<String>,
     /// Issuer
     pub iss: String,
+    /// Audience
+    pub aud: String,
     /// Username for display
     pub username: String,
+    /// Tenant the user belongs to; tokens issued before tenants belong to the default tenant
+    #[serde(default = "tenants::default_tenant")]
+    pub tenant: String,
+    /// Unique token ID, used to revoke a single token
+    pub jti: String,
+    /// Session (login) the token was issued in, used to revoke a whole session
+    pub sid: String,
+    /// Permissions the credential is limited to; `None` for user sessions,
+    /// set for API keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
     /// Issued at timestamp
     pub iat: i64,
+    /// Not valid before timestamp
+    pub nbf: i64,
+    /// Expiration timestamp
+    pub exp: i64,
+}
+
+/// Appended to the audience of login challenges so they are never accepted as access tokens
+const CHALLENGE_AUDIENCE_SUFFIX: &str = "#second-factor";
+
+/// Claims of a login challenge: proof of a correct password while a
+/// second factor is still outstanding
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    /// Subject (user ID)
+    pub sub: String,
+    /// Issuer
+    pub iss: String,
+    /// Audience, the access token audience with a suffix
+    pub aud: String,
+    /// Unique challenge ID, revoked once the challenge is completed
+    pub jti: String,
+    /// Issued at timestamp
+    pub iat: i64,
+    /// Not valid before timestamp
+    pub nbf: i64,
     /// Expiration timestamp
     pub exp: i64,
 }
 
 /// Validate a JWT token
-pub async fn validate_token(token: &str, secret: &str) -> Result<bool, AuthError> {
+pub fn validate_token(token: &str, config: &JwtConfig) -> Result<bool, AuthError> {
     // Decode and verify the token
-    let _ = decode_token(token, secret).await?;
-    
+    let _ = decode_token(token, config)?;
+
     // If no error was thrown during decoding, the token is valid
     Ok(true)
 }
 
-/// Generate a JWT token
-pub async fn generate_token(user_id: &str, role: &str, username: &str, secret: &str, expiry_hours: u64) -> Result<String, AuthError> {
+/// Generate a JWT token for a session
+pub fn generate_token(
+    config: &JwtConfig,
+    user_id: &str,
+    roles: &[String],
+    username: &str,
+    tenant: &str,
+    session_id: &str,
+) -> Result<String, AuthError> {
     let now = Utc::now();
-    let expiry = now + Duration::hours(expiry_hours as i64);
-    
+    let expiry = now + Duration::seconds(config.access_token_expiry as i64);
+
     let claims = Claims {
         sub: user_id.to_string(),
-        role: role.to_string(),
-        iss: "nexa-gateway".to_string(),
+        roles: roles.to_vec(),
+        iss: config.issuer.clone(),
+        aud: config.audience.clone(),
         username: username.to_string(),
+        tenant: tenant.to_string(),
+        jti: Uuid::new_v4().to_string(),
+        sid: session_id.to_string(),
+        scopes: None,
         iat: now.timestamp(),
+        nbf: now.timestamp(),
         exp: expiry.timestamp(),
     };
-    
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )
-    .map_err(|_| AuthError::TokenCreationError)?;
-    
-    Ok(token)
-}
-
-/// Decode a JWT token
-pub async fn decode_token(token: &str, secret: &str) -> Result<Claims, AuthError> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )
-    .map_err(|e| match e.kind() {
-        jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::TokenExpired,
-        _ => AuthError::InvalidToken,
+
+    encode_claims(config, &claims)
+}
+
+/// Decode a JWT token, checking signature, issuer, audience, expiry and not-before
+pub fn decode_token(token: &str, config: &JwtConfig) -> Result<Claims, AuthError> {
+    decode_claims(token, config)
+}
+
+/// Issue a login challenge valid for `lifetime` seconds
+pub fn generate_challenge(config: &JwtConfig, user_id: &str, lifetime: i64) -> Result<String, AuthError> {
+    let now = Utc::now().timestamp();
+    let claims = ChallengeClaims {
+        sub: user_id.to_string(),
+        iss: config.issuer.clone(),
+        aud: challenge_audience(config),
+        jti: Uuid::new_v4().to_string(),
+        iat: now,
+        nbf: now,
+        exp: now + lifetime,
+    };
+    encode_claims(config, &claims)
+}
+
+/// Decode a login challenge, checking it like an access token but for its own audience
+pub fn decode_challenge(token: &str, config: &JwtConfig) -> Result<ChallengeClaims, AuthError> {
+    decode_with_audience(token, config, &challenge_audience(config))
+}
+
+fn challenge_audience(config: &JwtConfig) -> String {
+    format!("{}{}", config.audience, CHALLENGE_AUDIENCE_SUFFIX)
+}
+
+/// Key ID from a token header, without verifying the token
+pub fn key_id(token: &str) -> Option<String> {
+    decode_header(token).ok().and_then(|header| header.kid)
+}
+
+/// Sign claims with the current signing key, naming it in the `kid` header
+fn encode_claims<T: Serialize>(config: &JwtConfig, claims: &T) -> Result<String, AuthError> {
+    let keys = config.keys.current();
+    let key = keys.signing_key();
+    let encoding_key = key.encoding_key().ok_or(AuthError::TokenCreationError)?;
+
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+
+    encode(&header, claims, encoding_key).map_err(|_| AuthError::TokenCreationError)
+}
+
+/// Verify a token against the key named in its header
+fn decode_claims(token: &str, config: &JwtConfig) -> Result<Claims, AuthError> {
+    decode_with_audience(token, config, &config.audience)
+}
+
+fn decode_with_audience<T: DeserializeOwned>(token: &str, config: &JwtConfig, audience: &str) -> Result<T, AuthError> {
+    let header = decode_header(token).map_err(|_| AuthError::InvalidToken)?;
+    let keys = config.keys.current();
+    let key = keys
+        .verification_key(header.kid.as_deref())
+        .ok_or(AuthError::InvalidToken)?;
+
+    // The key decides the algorithm, never the token header
+    let token_data = decode::<T>(token, key.decoding_key(), &validation(config, key.algorithm, audience)).map_err(|e| {
+        match e.kind() {
+            jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::TokenExpired,
+            _ => AuthError::InvalidToken,
+        }
     })?;
-    
+
     Ok(token_data.claims)
 }
 
+/// Validation rules derived from the configuration
+fn validation(config: &JwtConfig, algorithm: Algorithm, audience: &str) -> Validation {
+    let mut validation = Validation::new(algorithm);
+    validation.set_issuer(&[&config.issuer]);
+    validation.set_audience(&[audience]);
+    validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+    validation.validate_exp = true;
+    validation.validate_nbf = true;
+    validation.leeway = config.leeway;
+    validation
+}
+
 /// Simplified JWT token creation for tests
+#[cfg(test)]
 pub fn create_jwt(user_id: &str, username: &str) -> Result<String, AuthError> {
+    let config = JwtConfig::for_tests();
     let now = Utc::now();
-    let expiry = now + Duration::hours(24);
-    
+    let expiry = now + Duration::seconds(config.access_token_expiry as i64);
+
     let claims = Claims {
         sub: user_id.to_string(),
-        role: "user".to_string(),
-        iss: "nexa-gateway-test".to_string(),
+        roles: vec!["user".to_string()],
+        iss: config.issuer.clone(),
+        aud: config.audience.clone(),
         username: username.to_string(),
+        tenant: tenants::default_tenant(),
+        jti: Uuid::new_v4().to_string(),
+        sid: Uuid::new_v4().to_string(),
+        scopes: None,
         iat: now.timestamp(),
+        nbf: now.timestamp(),
         exp: expiry.timestamp(),
     };
-    
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret("test-secret-key".as_bytes()),
-    )
-    .map_err(|_| AuthError::TokenCreationError)?;
-    
-    Ok(token)
+
+    encode_claims(&config, &claims)
 }
 
 /// Simplified JWT token validation for tests
+#[cfg(test)]
 pub fn validate_jwt(token: &str) -> Result<Claims, AuthError> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret("test-secret-key".as_bytes()),
-        &Validation::default(),
-    )
-    .map_err(|e| match e.kind() {
-        jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::TokenExpired,
-        _ => AuthError::InvalidToken,
-    })?;
-    
-    Ok(token_data.claims)
+    decode_claims(token, &JwtConfig::for_tests())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::testdata::*;
+    use crate::keys::{JwtKey, KeyRing, KeySet};
+    use std::sync::Arc;
+
+    fn token_with(config: &JwtConfig, mutate: impl FnOnce(&mut Claims)) -> String {
+        let now = Utc::now().timestamp();
+        let mut claims = Claims {
+            sub: "user-1".to_string(),
+            roles: vec!["user".to_string()],
+            iss: config.issuer.clone(),
+            aud: config.audience.clone(),
+            username: "user".to_string(),
+            tenant: tenants::default_tenant(),
+            jti: "token-1".to_string(),
+            sid: "session-1".to_string(),
+            scopes: None,
+            iat: now,
+            nbf: now,
+            exp: now + 60,
+        };
+        mutate(&mut claims);
+        encode_claims(config, &claims).unwrap()
+    }
+
+    fn with_keys(signing_kid: &str, keys: Vec<JwtKey>) -> JwtConfig {
+        JwtConfig {
+            keys: Arc::new(KeyRing::new(KeySet::new(signing_kid, keys).unwrap())),
+            secret: None,
+            ..JwtConfig::for_tests()
+        }
+    }
+
+    #[test]
+    fn test_decode_checks_registered_claims() {
+        let config = JwtConfig::for_tests();
+        let now = Utc::now().timestamp();
+
+        assert!(decode_token(&token_with(&config, |_| {}), &config).is_ok());
+        assert!(matches!(
+            decode_token(&token_with(&config, |c| c.exp = now - 10), &config),
+            Err(AuthError::TokenExpired)
+        ));
+        assert!(decode_token(&token_with(&config, |c| c.nbf = now + 600), &config).is_err());
+        assert!(decode_token(&token_with(&config, |c| c.iss = "other".to_string()), &config).is_err());
+        assert!(decode_token(&token_with(&config, |c| c.aud = "other".to_string()), &config).is_err());
+
+        let other_secret = with_keys(
+            "default",
+            vec![JwtKey::from_secret("default", Algorithm::HS256, b"another-secret").unwrap()],
+        );
+        assert!(decode_token(&token_with(&config, |_| {}), &other_secret).is_err());
+    }
+
+    #[test]
+    fn test_asymmetric_signing_with_kid() {
+        let rsa = JwtKey::from_pem("rsa", Algorithm::RS256, Some(RSA_PRIVATE.as_bytes()), RSA_PUBLIC.as_bytes()).unwrap();
+        let ed = JwtKey::from_pem("ed", Algorithm::EdDSA, Some(ED25519_PRIVATE.as_bytes()), ED25519_PUBLIC.as_bytes())
+            .unwrap();
+        let rsa_config = with_keys("rsa", vec![rsa.clone(), ed.clone()]);
+        let ed_config = with_keys("ed", vec![rsa, ed]);
+
+        let token = generate_token(&rsa_config, "user-1", &["user".to_string()], "user", "default", "session-1").unwrap();
+        let header = decode_header(&token).unwrap();
+        assert_eq!(header.kid.as_deref(), Some("rsa"));
+        assert_eq!(header.alg, Algorithm::RS256);
+
+        // Tokens signed by any configured key verify, whichever key signs new ones
+        assert_eq!(decode_token(&token, &ed_config).unwrap().sub, "user-1");
+
+        // A public key cannot be used as an HMAC secret to forge tokens
+        let forged = encode(
+            &Header {
+                kid: Some("rsa".to_string()),
+                ..Header::new(Algorithm::HS256)
+            },
+            &decode_token(&token, &ed_config).unwrap(),
+            &jsonwebtoken::EncodingKey::from_secret(RSA_PUBLIC.as_bytes()),
+        )
+        .unwrap();
+        assert!(decode_token(&forged, &ed_config).is_err());
+
+        // Unknown key IDs are rejected
+        let unknown = with_keys(
+            "other",
+            vec![JwtKey::from_pem("other", Algorithm::ES256, Some(EC_PRIVATE.as_bytes()), EC_PUBLIC.as_bytes()).unwrap()],
+        );
+        assert!(decode_token(&token, &unknown).is_err());
+    }
 }
@@ -0,0 +1,582 @@
+//! JWT signing and verification keys
+//!
+//! Tokens are signed with one current key and verified against any key in
+//! the set, selected by the `kid` header. Asymmetric keys are loaded from
+//! PEM files and their public halves are published as a JWKS so other
+//! services can verify tokens without holding a secret.
+//!
+//! Shared secrets can be rotated at runtime: the [`KeyRing`] combines the
+//! configured keys with keys created by rotation and stored alongside the
+//! user accounts, keeping retired keys for verification until every token
+//! they signed has expired.
+
+use crate::error::AuthError;
+use crate::store::SigningKeyRecord;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use common::config::JwtKeySettings;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters, EllipticCurveKeyType, Jwk,
+    KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use simple_asn1::ASN1Block;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+pub use jsonwebtoken::jwk::JwkSet;
+
+/// Key ID used for the shared secret from `auth.jwt_secret`
+pub const SHARED_SECRET_KID: &str = "default";
+
+/// A key used to sign and/or verify tokens
+#[derive(Clone)]
+pub struct JwtKey {
+    /// Key ID written to and matched against the token header
+    pub kid: String,
+    /// Algorithm this key is used with
+    pub algorithm: Algorithm,
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+    /// Public key in JWK form; `None` for shared secrets, which are never published
+    public_jwk: Option<Jwk>,
+}
+
+impl std::fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .field("can_sign", &self.encoding.is_some())
+            .finish()
+    }
+}
+
+impl JwtKey {
+    /// HMAC key from a shared secret
+    pub fn from_secret(kid: &str, algorithm: Algorithm, secret: &[u8]) -> Result<Self, AuthError> {
+        if !is_hmac(algorithm) {
+            return Err(AuthError::Configuration(format!(
+                "{:?} is not an HMAC algorithm",
+                algorithm
+            )));
+        }
+
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm,
+            encoding: Some(EncodingKey::from_secret(secret)),
+            decoding: DecodingKey::from_secret(secret),
+            public_jwk: None,
+        })
+    }
+
+    /// Asymmetric key from PEM data; without a private key it can only verify
+    pub fn from_pem(
+        kid: &str,
+        algorithm: Algorithm,
+        private_pem: Option<&[u8]>,
+        public_pem: &[u8],
+    ) -> Result<Self, AuthError> {
+        let invalid = |e: jsonwebtoken::errors::Error| AuthError::Configuration(format!("Invalid key {}: {}", kid, e));
+
+        let (encoding, decoding) = match algorithm {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => (
+                private_pem.map(EncodingKey::from_rsa_pem).transpose().map_err(invalid)?,
+                DecodingKey::from_rsa_pem(public_pem).map_err(invalid)?,
+            ),
+            Algorithm::ES256 | Algorithm::ES384 => (
+                private_pem.map(EncodingKey::from_ec_pem).transpose().map_err(invalid)?,
+                DecodingKey::from_ec_pem(public_pem).map_err(invalid)?,
+            ),
+            Algorithm::EdDSA => (
+                private_pem.map(EncodingKey::from_ed_pem).transpose().map_err(invalid)?,
+                DecodingKey::from_ed_pem(public_pem).map_err(invalid)?,
+            ),
+            _ => {
+                return Err(AuthError::Configuration(format!(
+                    "{:?} is not an asymmetric algorithm",
+                    algorithm
+                )))
+            }
+        };
+
+        let key = Self {
+            kid: kid.to_string(),
+            algorithm,
+            encoding,
+            decoding,
+            public_jwk: Some(public_jwk(kid, algorithm, public_pem)?),
+        };
+        key.check_pair()?;
+        Ok(key)
+    }
+
+    /// Load a key described in configuration
+    pub fn from_settings(settings: &JwtKeySettings) -> Result<Self, AuthError> {
+        let algorithm = parse_algorithm(&settings.algorithm)?;
+        let read = |path: &str| {
+            std::fs::read(path).map_err(|e| AuthError::Configuration(format!("Failed to read key file {}: {}", path, e)))
+        };
+
+        let private_pem = settings.private_key_path.as_deref().map(read).transpose()?;
+        let public_pem = read(&settings.public_key_path)?;
+        Self::from_pem(&settings.kid, algorithm, private_pem.as_deref(), &public_pem)
+    }
+
+    /// Whether this key can sign tokens
+    pub fn can_sign(&self) -> bool {
+        self.encoding.is_some()
+    }
+
+    /// Key used to sign tokens
+    pub fn encoding_key(&self) -> Option<&EncodingKey> {
+        self.encoding.as_ref()
+    }
+
+    /// Key used to verify tokens
+    pub fn decoding_key(&self) -> &DecodingKey {
+        &self.decoding
+    }
+
+    /// Public JWK, `None` for shared secrets
+    pub fn public_jwk(&self) -> Option<&Jwk> {
+        self.public_jwk.as_ref()
+    }
+
+    /// Sign and verify a probe message so a mismatched key pair fails at startup
+    fn check_pair(&self) -> Result<(), AuthError> {
+        let Some(encoding) = &self.encoding else {
+            return Ok(());
+        };
+
+        let probe = format!("key-check.{}", self.kid);
+        let signature = jsonwebtoken::crypto::sign(probe.as_bytes(), encoding, self.algorithm)
+            .map_err(|e| AuthError::Configuration(format!("Key {} cannot sign: {}", self.kid, e)))?;
+        let valid = jsonwebtoken::crypto::verify(&signature, probe.as_bytes(), &self.decoding, self.algorithm)
+            .unwrap_or(false);
+
+        if !valid {
+            return Err(AuthError::Configuration(format!(
+                "Private and public key of {} do not match",
+                self.kid
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The keys tokens are signed and verified with
+#[derive(Debug, Clone)]
+pub struct KeySet {
+    signing_kid: String,
+    keys: Vec<JwtKey>,
+}
+
+impl KeySet {
+    /// Create a key set signing with `signing_kid`
+    pub fn new(signing_kid: &str, keys: Vec<JwtKey>) -> Result<Self, AuthError> {
+        let signer = keys
+            .iter()
+            .find(|key| key.kid == signing_kid)
+            .ok_or_else(|| AuthError::Configuration(format!("Signing key {} is not configured", signing_kid)))?;
+        if !signer.can_sign() {
+            return Err(AuthError::Configuration(format!(
+                "Signing key {} has no private key",
+                signing_kid
+            )));
+        }
+
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].iter().any(|other| other.kid == key.kid) {
+                return Err(AuthError::Configuration(format!("Duplicate key ID {}", key.kid)));
+            }
+        }
+
+        Ok(Self {
+            signing_kid: signing_kid.to_string(),
+            keys,
+        })
+    }
+
+    /// Key set with a single shared secret
+    pub fn shared_secret(algorithm: Algorithm, secret: &[u8]) -> Result<Self, AuthError> {
+        Self::new(
+            SHARED_SECRET_KID,
+            vec![JwtKey::from_secret(SHARED_SECRET_KID, algorithm, secret)?],
+        )
+    }
+
+    /// Key used to sign new tokens
+    pub fn signing_key(&self) -> &JwtKey {
+        self.keys
+            .iter()
+            .find(|key| key.kid == self.signing_kid)
+            .expect("signing key is validated on construction")
+    }
+
+    /// Verification key for a token header's `kid`
+    ///
+    /// Tokens without a `kid` predate key IDs and are checked against the
+    /// signing key.
+    pub fn verification_key(&self, kid: Option<&str>) -> Option<&JwtKey> {
+        match kid {
+            Some(kid) => self.keys.iter().find(|key| key.kid == kid),
+            None => Some(self.signing_key()),
+        }
+    }
+
+    /// All keys in the set
+    pub fn keys(&self) -> &[JwtKey] {
+        &self.keys
+    }
+
+    /// Whether tokens are signed with a shared secret
+    pub fn uses_shared_secret(&self) -> bool {
+        is_hmac(self.signing_key().algorithm)
+    }
+
+    /// Public keys as a JSON Web Key Set
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().filter_map(|key| key.public_jwk.clone()).collect(),
+        }
+    }
+}
+
+/// How long a loaded key ring is used before it is reloaded from the store
+pub const KEY_RING_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Minimum time between reloads caused by tokens signed with an unknown key
+pub const UNKNOWN_KEY_RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Configured keys combined with the keys created by rotation
+#[derive(Debug)]
+pub struct KeyRing {
+    base: Arc<KeySet>,
+    state: RwLock<RingState>,
+    reload: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug)]
+struct RingState {
+    keys: Arc<KeySet>,
+    /// Unix timestamp after which a retired key stops verifying tokens
+    retires_at: HashMap<String, i64>,
+    checked_at: Option<Instant>,
+}
+
+impl KeyRing {
+    /// Key ring holding only the configured keys until rotated keys are loaded
+    pub fn new(base: KeySet) -> Self {
+        let base = Arc::new(base);
+        Self {
+            state: RwLock::new(RingState {
+                keys: base.clone(),
+                retires_at: HashMap::new(),
+                checked_at: None,
+            }),
+            base,
+            reload: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Keys from configuration
+    pub fn base(&self) -> &KeySet {
+        &self.base
+    }
+
+    /// Keys currently used to sign and verify tokens
+    pub fn current(&self) -> Arc<KeySet> {
+        self.state.read().unwrap_or_else(PoisonError::into_inner).keys.clone()
+    }
+
+    /// When a retired key stops verifying tokens; `None` for keys still in use
+    pub fn retires_at(&self, kid: &str) -> Option<i64> {
+        self.state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retires_at
+            .get(kid)
+            .copied()
+    }
+
+    /// Whether the store has not been checked within `max_age`
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        self.state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .checked_at
+            .is_none_or(|at| at.elapsed() >= max_age)
+    }
+
+    /// Serialises reloads so concurrent requests do not all query the store
+    pub(crate) async fn lock_reload(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.reload.lock().await
+    }
+
+    /// Record a failed reload so it is not retried on every request
+    pub(crate) fn mark_checked(&self) {
+        self.state.write().unwrap_or_else(PoisonError::into_inner).checked_at = Some(Instant::now());
+    }
+
+    /// Rebuild the active keys from stored rotation records
+    ///
+    /// # Arguments
+    ///
+    /// * `records` - Signing key records ordered by creation time
+    /// * `max_token_lifetime` - Seconds a token stays valid, including leeway
+    /// * `now` - Current Unix timestamp
+    pub fn apply(&self, records: &[SigningKeyRecord], max_token_lifetime: i64, now: i64) -> Result<(), AuthError> {
+        let is_base = |kid: &str| self.base.verification_key(Some(kid)).is_some();
+        let cutoff = now - max_token_lifetime;
+        let signer = records
+            .iter()
+            .rev()
+            .find(|record| record.retired_at.is_none() && record.secret.is_some() && !is_base(&record.kid));
+
+        let mut keys = Vec::new();
+        let mut retires_at = HashMap::new();
+
+        for key in self.base.keys() {
+            // Retirement markers only count once a rotated key has taken over
+            let retired = records
+                .iter()
+                .find(|record| record.kid == key.kid)
+                .and_then(|record| record.retired_at)
+                .filter(|_| signer.is_some());
+            if let Some(retired) = retired {
+                if retired <= cutoff {
+                    continue;
+                }
+                retires_at.insert(key.kid.clone(), retired + max_token_lifetime);
+            }
+            keys.push(key.clone());
+        }
+
+        for record in records.iter().filter(|record| !is_base(&record.kid)) {
+            let Some(secret) = &record.secret else {
+                continue;
+            };
+            if let Some(retired) = record.retired_at {
+                if retired <= cutoff {
+                    continue;
+                }
+                retires_at.insert(record.kid.clone(), retired + max_token_lifetime);
+            }
+            keys.push(JwtKey::from_secret(
+                &record.kid,
+                parse_algorithm(&record.algorithm)?,
+                secret.expose().as_bytes(),
+            )?);
+        }
+
+        let signing_kid = signer.map_or(self.base.signing_key().kid.as_str(), |record| record.kid.as_str());
+        let keys = KeySet::new(signing_kid, keys)?;
+
+        *self.state.write().unwrap_or_else(PoisonError::into_inner) = RingState {
+            keys: Arc::new(keys),
+            retires_at,
+            checked_at: Some(Instant::now()),
+        };
+        Ok(())
+    }
+}
+
+/// Parse a configured algorithm name
+pub fn parse_algorithm(name: &str) -> Result<Algorithm, AuthError> {
+    Algorithm::from_str(name).map_err(|_| AuthError::Configuration(format!("Unsupported JWT algorithm: {}", name)))
+}
+
+/// Whether an algorithm uses a shared secret
+pub fn is_hmac(algorithm: Algorithm) -> bool {
+    matches!(algorithm, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512)
+}
+
+/// Build the public JWK for a SubjectPublicKeyInfo PEM
+fn public_jwk(kid: &str, algorithm: Algorithm, public_pem: &[u8]) -> Result<Jwk, AuthError> {
+    let invalid = |reason: &str| AuthError::Configuration(format!("Invalid public key {}: {}", kid, reason));
+
+    let pem = pem::parse(public_pem).map_err(|e| invalid(&e.to_string()))?;
+    if pem.tag() != "PUBLIC KEY" {
+        return Err(invalid("expected a PUBLIC KEY PEM block"));
+    }
+    let key_bits = match simple_asn1::from_der(pem.contents()).map_err(|e| invalid(&e.to_string()))?.as_slice() {
+        [ASN1Block::Sequence(_, spki)] => match spki.as_slice() {
+            [_, ASN1Block::BitString(_, _, bits)] => bits.clone(),
+            _ => return Err(invalid("malformed SubjectPublicKeyInfo")),
+        },
+        _ => return Err(invalid("malformed SubjectPublicKeyInfo")),
+    };
+
+    let b64 = |bytes: &[u8]| URL_SAFE_NO_PAD.encode(bytes);
+    let parameters = match algorithm {
+        Algorithm::RS256
+        | Algorithm::RS384
+        | Algorithm::RS512
+        | Algorithm::PS256
+        | Algorithm::PS384
+        | Algorithm::PS512 => {
+            match simple_asn1::from_der(&key_bits).map_err(|e| invalid(&e.to_string()))?.as_slice() {
+                [ASN1Block::Sequence(_, parts)] => match parts.as_slice() {
+                    [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n: b64(&n.to_bytes_be().1),
+                        e: b64(&e.to_bytes_be().1),
+                    }),
+                    _ => return Err(invalid("malformed RSA public key")),
+                },
+                _ => return Err(invalid("malformed RSA public key")),
+            }
+        }
+        Algorithm::ES256 | Algorithm::ES384 => {
+            let (curve, size) = match algorithm {
+                Algorithm::ES256 => (EllipticCurve::P256, 32),
+                _ => (EllipticCurve::P384, 48),
+            };
+            // Uncompressed point: 0x04 || x || y
+            if key_bits.len() != 1 + 2 * size || key_bits[0] != 0x04 {
+                return Err(invalid("expected an uncompressed point on the algorithm's curve"));
+            }
+            AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve,
+                x: b64(&key_bits[1..1 + size]),
+                y: b64(&key_bits[1 + size..]),
+            })
+        }
+        Algorithm::EdDSA => {
+            if key_bits.len() != 32 {
+                return Err(invalid("expected an Ed25519 key"));
+            }
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: b64(&key_bits),
+            })
+        }
+        _ => return Err(invalid("not an asymmetric algorithm")),
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: KeyAlgorithm::from_str(&format!("{:?}", algorithm)).ok(),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    })
+}
+
+/// Fixture keys for tests
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testdata {
+    pub const RSA_PRIVATE: &str = include_str!("testdata/rsa_private.pem");
+    pub const RSA_PUBLIC: &str = include_str!("testdata/rsa_public.pem");
+    pub const EC_PRIVATE: &str = include_str!("testdata/ec_private.pem");
+    pub const EC_PUBLIC: &str = include_str!("testdata/ec_public.pem");
+    pub const ED25519_PRIVATE: &str = include_str!("testdata/ed25519_private.pem");
+    pub const ED25519_PUBLIC: &str = include_str!("testdata/ed25519_public.pem");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testdata::*;
+    use super::*;
+
+    #[test]
+    fn test_asymmetric_keys_and_jwks() {
+        let cases = [
+            ("rsa", Algorithm::RS256, RSA_PRIVATE, RSA_PUBLIC),
+            ("ec", Algorithm::ES256, EC_PRIVATE, EC_PUBLIC),
+            ("ed", Algorithm::EdDSA, ED25519_PRIVATE, ED25519_PUBLIC),
+        ];
+
+        for (kid, algorithm, private, public) in cases {
+            let key = JwtKey::from_pem(kid, algorithm, Some(private.as_bytes()), public.as_bytes()).unwrap();
+            assert!(key.can_sign());
+
+            // The published JWK must verify signatures made with the private key
+            let jwk = key.public_jwk().unwrap().clone();
+            assert_eq!(jwk.common.key_id.as_deref(), Some(kid));
+            let message = b"payload";
+            let signature = jsonwebtoken::crypto::sign(message, key.encoding_key().unwrap(), algorithm).unwrap();
+            let from_jwk = DecodingKey::from_jwk(&jwk).unwrap();
+            assert!(jsonwebtoken::crypto::verify(&signature, message, &from_jwk, algorithm).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_mismatched_pair_is_rejected() {
+        let other = JwtKey::from_pem("rsa", Algorithm::ES256, Some(EC_PRIVATE.as_bytes()), RSA_PUBLIC.as_bytes());
+        assert!(other.is_err());
+    }
+
+    #[test]
+    fn test_key_set_selection() {
+        let current = JwtKey::from_pem("new", Algorithm::EdDSA, Some(ED25519_PRIVATE.as_bytes()), ED25519_PUBLIC.as_bytes())
+            .unwrap();
+        let previous = JwtKey::from_pem("old", Algorithm::RS256, None, RSA_PUBLIC.as_bytes()).unwrap();
+        let keys = KeySet::new("new", vec![current, previous.clone()]).unwrap();
+
+        assert_eq!(keys.signing_key().kid, "new");
+        assert_eq!(keys.verification_key(Some("old")).unwrap().kid, "old");
+        assert!(keys.verification_key(Some("missing")).is_none());
+        assert_eq!(keys.jwks().keys.len(), 2);
+        assert!(!keys.uses_shared_secret());
+
+        // A verification-only key cannot be the signing key
+        assert!(KeySet::new("old", vec![previous]).is_err());
+
+        let shared = KeySet::shared_secret(Algorithm::HS256, b"secret").unwrap();
+        assert!(shared.uses_shared_secret());
+        assert!(shared.jwks().keys.is_empty());
+    }
+
+    #[test]
+    fn test_key_ring_retires_keys_after_token_lifetime() {
+        let ring = KeyRing::new(KeySet::shared_secret(Algorithm::HS256, b"configured").unwrap());
+        assert!(ring.is_stale(KEY_RING_RELOAD_INTERVAL));
+
+        let record = |kid: &str, created_at: i64, retired_at: Option<i64>| SigningKeyRecord {
+            kid: kid.to_string(),
+            algorithm: "HS256".to_string(),
+            secret: (kid != SHARED_SECRET_KID).then(|| format!("{}-secret", kid).into()),
+            created_at,
+            retired_at,
+        };
+        let records = vec![
+            record(SHARED_SECRET_KID, 1000, Some(1000)),
+            record("first", 1000, Some(2000)),
+            record("second", 2000, None),
+        ];
+
+        // Within the token lifetime of both retirements every key verifies
+        ring.apply(&records, 3600, 2500).unwrap();
+        let keys = ring.current();
+        assert_eq!(keys.signing_key().kid, "second");
+        assert_eq!(keys.keys().len(), 3);
+        assert_eq!(ring.retires_at(SHARED_SECRET_KID), Some(4600));
+        assert_eq!(ring.retires_at("second"), None);
+        assert!(!ring.is_stale(KEY_RING_RELOAD_INTERVAL));
+
+        // The configured secret goes first, then the first rotated key
+        ring.apply(&records, 3600, 4600).unwrap();
+        let kids: Vec<_> = ring.current().keys().iter().map(|key| key.kid.clone()).collect();
+        assert_eq!(kids, vec!["first", "second"]);
+        ring.apply(&records, 3600, 5600).unwrap();
+        assert_eq!(ring.current().keys().len(), 1);
+
+        // Without a rotated key the configured secret keeps signing
+        ring.apply(&records[..1], 3600, 9000).unwrap();
+        assert_eq!(ring.current().signing_key().kid, SHARED_SECRET_KID);
+    }
+}
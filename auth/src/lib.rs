@@ -1,15 +1,37 @@
 //! Authentication crate for Nexa Gateway
 //!
-//! This crate handles authentication (JWT-based) and permissions.
+//! This crate handles authentication (JWT-based, API keys, OpenID Connect
+//! logins and TOTP second factors), permissions, the audit log and the
+//! LLM token usage counters budgets are enforced with.
 
+pub mod api_keys;
+pub mod audit;
+pub mod config;
 pub mod jwt;
+pub mod keys;
 pub mod permissions;
 pub mod middleware;
+pub mod oidc;
 pub mod error;
+pub mod password;
+pub mod revocation;
 pub mod service;
+pub mod store;
+pub mod tenants;
+pub mod totp;
+pub mod usage;
 
+pub use audit::{AuditEvent, AuditLog, AuditQuery};
 pub use error::AuthError;
+pub use keys::{JwtKey, KeyRing, KeySet};
+pub use oidc::OidcClient;
+pub use permissions::{Role, RoleDefinition, RoleRegistry};
 pub use service::AuthService;
+pub use usage::{TokenUsage, UsagePeriod, UsageStore, UsageSubject};
+pub use store::{
+    connect_store, AccountStore, ApiKey, AuthStore, IdentityStore, KeyStore, OidcLoginRecord, RefreshTokenRecord,
+    RoleStore, SecondFactorStore, Session, SigningKeyRecord, TokenStore, TotpCredential, User,
+};
 
 /// Result type for authentication operations
 pub type AuthResult<T> = Result<T, AuthError>;
@@ -34,6 +56,6 @@ mod tests {
         // Check claims
         assert_eq!(claims.sub, user_id);
         assert_eq!(claims.username, username);
-        assert_eq!(claims.role, "user");
+        assert_eq!(claims.roles, vec!["user"]);
     }
 }
@@ -1,22 +1,33 @@
 //! Authentication middleware for Axum
 //!
-//! This module provides middleware for authenticating requests.
+//! This module provides middleware for authenticating requests. Requests
+//! carry either a JWT or an API key as `Authorization: Bearer ...`, or an
+//! API key as `X-API-Key`.
 
 use axum::{
     body::Body,
     extract::State,
-    http::{Request, StatusCode},
+    http::{HeaderMap, Request, StatusCode},
     middleware::Next,
     response::Response,
 };
 use http::header::AUTHORIZATION;
+use crate::api_keys::API_KEY_HEADER;
+use crate::jwt::Claims;
 use crate::{AuthService, AuthError};
 
-/// Extract JWT token from request header
-fn extract_token(request: &Request<Body>) -> Result<String, AuthError> {
+/// Extract the JWT or API key from request headers
+pub fn extract_credential(headers: &HeaderMap) -> Result<String, AuthError> {
+    if let Some(key) = headers.get(API_KEY_HEADER) {
+        let key = key.to_str().map_err(|_| AuthError::InvalidToken)?.trim();
+        if key.is_empty() {
+            return Err(AuthError::InvalidToken);
+        }
+        return Ok(key.to_string());
+    }
+
     // Get the Authorization header
-    let auth_header = request
-        .headers()
+    let auth_header = headers
         .get(AUTHORIZATION)
         .ok_or(AuthError::MissingAuth)?
         .to_str()
@@ -38,64 +49,146 @@ fn extract_token(request: &Request<Body>) -> Result<String, AuthError> {
 }
 
 /// Authentication middleware
-pub async fn auth_middleware<S>(
+pub async fn auth_middleware(
     State(auth_service): State<AuthService>,
     request: Request<Body>,
     next: Next,
-) -> Result<Response, StatusCode> 
-where
-    S: Send + Sync,
-{
+) -> Result<Response, AuthError> {
     // Skip authentication for certain paths
     let path = request.uri().path();
-    if path == "/health" || path == "/api/login" {
+    if matches!(path, "/health" | "/api/login" | "/api/token/refresh" | "/api/logout" | "/api/token/revoke" | "/.well-known/jwks.json") {
         return Ok(next.run(request).await);
     }
     
     // Extract the token
-    let token = match extract_token(&request) {
-        Ok(token) => token,
-        Err(_) => return Err(StatusCode::UNAUTHORIZED),
-    };
+    let token = extract_credential(request.headers())?;
     
-    // Validate the token
-    match auth_service.validate_token(&token).await {
-        Ok(true) => {
+    // Validate the token, including the revocation list
+    match auth_service.claims(&token).await {
+        Ok(_) => {
             // Token is valid, proceed with the request
             Ok(next.run(request).await)
         }
-        _ => {
-            // Invalid token
-            Err(StatusCode::UNAUTHORIZED)
+        Err(AuthError::TokenRevoked) => {
+            tracing::warn!("Rejected revoked token");
+            Err(AuthError::TokenRevoked)
         }
+        Err(e) => Err(rejected(e)),
     }
 }
 
-/// Permission check middleware
-pub async fn require_permission<S>(
+/// Error answering a credential that did not verify
+///
+/// Lookup failures such as an unknown API key are reported as an invalid
+/// token rather than as the missing resource.
+fn rejected(error: AuthError) -> AuthError {
+    match error.code().status() {
+        status if status == StatusCode::UNAUTHORIZED || status.is_server_error() => error,
+        _ => AuthError::InvalidToken,
+    }
+}
+
+/// Claims of the request, verified by an earlier layer or from its credential
+async fn verified_claims(
+    auth_service: &AuthService,
+    verified: Option<&Claims>,
+    headers: &HeaderMap,
+) -> Result<Claims, AuthError> {
+    match verified {
+        Some(claims) => Ok(claims.clone()),
+        None => {
+            // Extract the token
+            let token = extract_credential(headers)?;
+
+            auth_service.claims(&token).await.map_err(rejected)
+        }
+    }
+}
+
+/// Authentication check middleware
+///
+/// Responds 401 without a valid token and otherwise adds the verified
+/// [`Claims`] to the request extensions, for routes whose handlers decide
+/// access per resource, such as an account its owner may manage.
+pub async fn authenticate(
     State(auth_service): State<AuthService>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Result<Response, AuthError> {
+    let claims = verified_claims(&auth_service, request.extensions().get(), request.headers()).await?;
+    request.extensions_mut().insert(claims);
+    Ok(next.run(request).await)
+}
+
+/// State for [`require_permission`]: the permission a route needs
+#[derive(Clone)]
+pub struct RequiredPermission {
+    auth_service: AuthService,
     permission: &'static str,
-    request: Request<Body>,
-    next: Next
-) -> Result<Response, StatusCode> 
-where
-    S: Send + Sync,
-{
-    // Extract the token
-    let token = match extract_token(&request) {
-        Ok(token) => token,
-        Err(_) => return Err(StatusCode::UNAUTHORIZED),
-    };
-    
-    // Check permission
-    match auth_service.check_permission(&token, permission).await {
-        Ok(true) => {
-            // User has permission, proceed with the request
-            Ok(next.run(request).await)
+    platform: bool,
+}
+
+impl RequiredPermission {
+    /// Require `permission`, e.g. `agent:write`, of the caller's roles
+    pub fn new(auth_service: &AuthService, permission: &'static str) -> Self {
+        Self {
+            auth_service: auth_service.clone(),
+            permission,
+            platform: false,
         }
-        _ => {
-            // Permission denied
-            Err(StatusCode::FORBIDDEN)
+    }
+
+    /// Require a platform admin, for routes changing what every tenant shares
+    ///
+    /// See [`AuthService::is_platform_admin`].
+    pub fn platform_admin(auth_service: &AuthService) -> Self {
+        Self {
+            auth_service: auth_service.clone(),
+            permission: crate::permissions::ADMIN_PERMISSION,
+            platform: true,
         }
     }
+
+    async fn is_granted(&self, claims: &Claims) -> bool {
+        if self.platform {
+            self.auth_service.is_platform_admin(claims).await
+        } else {
+            self.auth_service.has_permission(claims, self.permission).await
+        }
+    }
+}
+
+/// Permission check middleware
+///
+/// Attach per route with
+/// `axum::middleware::from_fn_with_state(RequiredPermission::new(&auth, "agent:write"), require_permission)`.
+/// Responds 401 without a valid token and 403 when none of the token's
+/// roles grants the permission, or for platform routes when the caller is
+/// not a platform admin. On success the verified [`Claims`] are added to the
+/// request extensions for the handler; claims already there, verified by an
+/// earlier layer, are used instead of verifying the credential again.
+pub async fn require_permission(
+    State(required): State<RequiredPermission>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Result<Response, AuthError> {
+    let claims = verified_claims(&required.auth_service, request.extensions().get(), request.headers()).await?;
+    
+    // Check permission
+    if required.is_granted(&claims).await {
+        // User has permission, proceed with the request
+        request.extensions_mut().insert(claims);
+        Ok(next.run(request).await)
+    } else {
+        // Permission denied
+        tracing::warn!(
+            user_id = %claims.sub,
+            roles = %claims.roles.join(","),
+            tenant = %claims.tenant,
+            permission = required.permission,
+            platform = required.platform,
+            "Permission denied"
+        );
+        Err(AuthError::PermissionDenied)
+    }
 }
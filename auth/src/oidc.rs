@@ -0,0 +1,715 @@
+//! OpenID Connect login
+//!
+//! Users can log in at an external identity provider with the authorization
+//! code flow and PKCE. The provider's endpoints and signing keys come from
+//! its discovery document, and the ID token returned for the code is checked
+//! against those keys, the configured client ID and the nonce sent with the
+//! authorization request. The user's groups at the provider are then mapped
+//! to gateway roles and the gateway issues its own tokens, so the rest of
+//! the API never handles provider tokens.
+
+use crate::error::AuthError;
+use crate::keys::is_hmac;
+use crate::store::OidcLoginRecord;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use common::config::OAuthSettings;
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Header, Validation};
+use rand::RngCore;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Seconds a user has to complete a login at the provider
+pub const OIDC_LOGIN_TTL_SECONDS: i64 = 600;
+
+/// How often the discovery document and signing keys are refetched
+pub const DISCOVERY_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Minimum time between refetches of the signing keys for an unknown `kid`
+const UNKNOWN_KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Timeout for requests to the provider
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Random bytes in `state`, `nonce` and the PKCE code verifier
+const RANDOM_BYTES: usize = 32;
+
+/// Endpoints read from a provider's discovery document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderMetadata {
+    /// Issuer ID tokens must name in `iss`
+    pub issuer: String,
+    /// Where users are sent to log in
+    pub authorization_endpoint: String,
+    /// Where authorization codes are exchanged for tokens
+    pub token_endpoint: String,
+    /// Signing keys of ID tokens
+    pub jwks_uri: String,
+}
+
+/// A user's identity at the provider, taken from a verified ID token
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    /// Issuer of the ID token
+    pub issuer: String,
+    /// Stable user ID at the provider (`sub`)
+    pub subject: String,
+    /// Username for a new gateway account
+    pub username: String,
+    /// Gateway roles mapped from the user's groups, or the default roles
+    pub roles: Vec<String>,
+}
+
+/// Discovery document and signing keys, as last fetched
+struct Provider {
+    metadata: ProviderMetadata,
+    jwks: JwkSet,
+    fetched_at: Instant,
+    keys_fetched_at: Instant,
+}
+
+/// Response of the token endpoint
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+/// Error response of the token endpoint
+#[derive(Debug, Deserialize)]
+struct TokenError {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Client for one OpenID Connect provider
+pub struct OidcClient {
+    settings: OAuthSettings,
+    http: reqwest::Client,
+    /// Allowed clock skew in seconds when checking ID token expiry
+    leeway: u64,
+    provider: tokio::sync::Mutex<Option<Arc<Provider>>>,
+}
+
+impl OidcClient {
+    /// Create a client from the `auth.oauth` settings
+    ///
+    /// The provider is contacted lazily, on the first login.
+    pub fn new(settings: &OAuthSettings, leeway: u64) -> Result<Self, AuthError> {
+        let invalid = |message: &str| AuthError::Configuration(format!("auth.oauth: {}", message));
+
+        Url::parse(&settings.issuer_url).map_err(|_| invalid("issuer_url must be an absolute URL"))?;
+        Url::parse(&settings.redirect_uri).map_err(|_| invalid("redirect_uri must be an absolute URL"))?;
+        if settings.client_id.trim().is_empty() {
+            return Err(invalid("client_id must not be empty"));
+        }
+        if !settings.scopes.iter().any(|scope| scope == "openid") {
+            return Err(invalid("scopes must include openid"));
+        }
+
+        let http = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .map_err(|e| AuthError::Configuration(format!("auth.oauth: {}", e)))?;
+
+        Ok(Self {
+            settings: settings.clone(),
+            http,
+            leeway,
+            provider: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    /// Provider name from configuration
+    pub fn provider_name(&self) -> &str {
+        &self.settings.provider
+    }
+
+    /// Start a login, returning the URL to send the user to and the
+    /// pending login to keep until the callback
+    pub async fn authorization_url(&self, now: i64) -> Result<(String, OidcLoginRecord), AuthError> {
+        let provider = self.provider(None).await?;
+        let login = OidcLoginRecord {
+            state: random_token(),
+            code_verifier: random_token(),
+            nonce: random_token(),
+            expires_at: now + OIDC_LOGIN_TTL_SECONDS,
+        };
+
+        let url = Url::parse_with_params(
+            &provider.metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", self.settings.client_id.as_str()),
+                ("redirect_uri", self.settings.redirect_uri.as_str()),
+                ("scope", self.settings.scopes.join(" ").as_str()),
+                ("state", login.state.as_str()),
+                ("nonce", login.nonce.as_str()),
+                ("code_challenge", code_challenge(&login.code_verifier).as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| AuthError::OidcProvider(format!("invalid authorization endpoint: {}", e)))?;
+
+        Ok((url.into(), login))
+    }
+
+    /// Exchange an authorization code for an ID token and verify it
+    pub async fn exchange(&self, code: &str, login: &OidcLoginRecord) -> Result<OidcIdentity, AuthError> {
+        let provider = self.provider(None).await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.settings.redirect_uri.as_str()),
+            ("code_verifier", login.code_verifier.as_str()),
+        ];
+        let mut request = self.http.post(&provider.metadata.token_endpoint);
+        if self.settings.client_secret.is_empty() {
+            form.push(("client_id", self.settings.client_id.as_str()));
+        } else {
+            request = request.basic_auth(&self.settings.client_id, Some(self.settings.client_secret.expose()));
+        }
+
+        let response = request
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| AuthError::OidcProvider(format!("token request failed: {}", e)))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(match response.json::<TokenError>().await {
+                Ok(error) => AuthError::OidcLogin(format!(
+                    "provider rejected the authorization code: {}",
+                    error.error_description.unwrap_or(error.error)
+                )),
+                Err(_) => AuthError::OidcProvider(format!("token endpoint returned {}", status)),
+            });
+        }
+        let tokens: TokenResponse = response
+            .json()
+            .await
+            .map_err(|e| AuthError::OidcProvider(format!("invalid token response: {}", e)))?;
+        let id_token = tokens
+            .id_token
+            .ok_or_else(|| AuthError::OidcProvider("token response has no ID token".to_string()))?;
+
+        let claims = self.verify_id_token(provider, &id_token, &login.nonce).await?;
+        self.identity(claims)
+    }
+
+    /// Verify an ID token's signature, issuer, audience, expiry and nonce
+    async fn verify_id_token(
+        &self,
+        mut provider: Arc<Provider>,
+        token: &str,
+        nonce: &str,
+    ) -> Result<Map<String, Value>, AuthError> {
+        let rejected = |message: &str| AuthError::OidcLogin(message.to_string());
+        let header = decode_header(token).map_err(|_| rejected("malformed ID token"))?;
+        if is_hmac(header.alg) {
+            return Err(rejected("ID token is not signed with the provider's keys"));
+        }
+
+        let (key, algorithm) = match verification_key(&provider.jwks, &header) {
+            Some(found) => found,
+            // The provider may have rotated its keys since they were fetched
+            None => {
+                provider = self.provider(header.kid.as_deref()).await?;
+                verification_key(&provider.jwks, &header)
+                    .ok_or_else(|| rejected("ID token is signed with an unknown key"))?
+            }
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.set_issuer(&[&provider.metadata.issuer]);
+        validation.set_audience(&[&self.settings.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.leeway = self.leeway;
+
+        let claims = decode::<Map<String, Value>>(token, &key, &validation)
+            .map_err(|e| match e.kind() {
+                jsonwebtoken::errors::ErrorKind::ExpiredSignature => rejected("ID token has expired"),
+                _ => rejected("invalid ID token"),
+            })?
+            .claims;
+
+        if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+            return Err(rejected("ID token nonce does not match the login"));
+        }
+        Ok(claims)
+    }
+
+    /// Read the user's identity and roles from verified ID token claims
+    fn identity(&self, claims: Map<String, Value>) -> Result<OidcIdentity, AuthError> {
+        let claim = |name: &str| claims.get(name).and_then(Value::as_str).filter(|value| !value.is_empty());
+        let subject = claim("sub").ok_or_else(|| AuthError::OidcLogin("ID token has no subject".to_string()))?;
+
+        Ok(OidcIdentity {
+            issuer: claim("iss").unwrap_or_default().to_string(),
+            subject: subject.to_string(),
+            username: claim(&self.settings.username_claim).unwrap_or(subject).to_string(),
+            roles: self.map_roles(&claims),
+        })
+    }
+
+    /// Gateway roles for the values of the roles claim, in claim order
+    fn map_roles(&self, claims: &Map<String, Value>) -> Vec<String> {
+        let groups: Vec<&str> = match claims.get(&self.settings.roles_claim) {
+            Some(Value::String(group)) => vec![group.as_str()],
+            Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+
+        let mut roles: Vec<String> = Vec::new();
+        for role in groups
+            .iter()
+            .filter_map(|group| self.settings.role_mapping.get(*group))
+            .flatten()
+        {
+            if !roles.contains(role) {
+                roles.push(role.clone());
+            }
+        }
+        if roles.is_empty() {
+            roles = self.settings.default_roles.clone();
+        }
+        roles
+    }
+
+    /// Discovery document and signing keys, refetched when older than the
+    /// refresh interval or, rate-limited, when `unknown_kid` is not among them
+    async fn provider(&self, unknown_kid: Option<&str>) -> Result<Arc<Provider>, AuthError> {
+        let mut cached = self.provider.lock().await;
+        if let Some(provider) = cached.as_ref() {
+            if provider.fetched_at.elapsed() < DISCOVERY_REFRESH_INTERVAL {
+                let refresh_keys = unknown_kid.is_some_and(|kid| {
+                    provider.jwks.find(kid).is_none()
+                        && provider.keys_fetched_at.elapsed() >= UNKNOWN_KEY_REFRESH_INTERVAL
+                });
+                if !refresh_keys {
+                    return Ok(provider.clone());
+                }
+
+                let jwks = self.fetch_jwks(&provider.metadata.jwks_uri).await?;
+                let provider = Arc::new(Provider {
+                    metadata: provider.metadata.clone(),
+                    jwks,
+                    fetched_at: provider.fetched_at,
+                    keys_fetched_at: Instant::now(),
+                });
+                *cached = Some(provider.clone());
+                return Ok(provider);
+            }
+        }
+
+        let metadata = self.discover().await?;
+        let jwks = self.fetch_jwks(&metadata.jwks_uri).await?;
+        let provider = Arc::new(Provider {
+            metadata,
+            jwks,
+            fetched_at: Instant::now(),
+            keys_fetched_at: Instant::now(),
+        });
+        *cached = Some(provider.clone());
+        Ok(provider)
+    }
+
+    /// Fetch the discovery document, checking it is the configured issuer's
+    async fn discover(&self) -> Result<ProviderMetadata, AuthError> {
+        let issuer = self.settings.issuer_url.trim_end_matches('/');
+        let url = format!("{}/.well-known/openid-configuration", issuer);
+        let metadata: ProviderMetadata = self.get_json(&url).await?;
+
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(AuthError::OidcProvider(format!(
+                "discovery document names issuer {}, expected {}",
+                metadata.issuer, self.settings.issuer_url
+            )));
+        }
+        tracing::debug!(provider = %self.settings.provider, issuer = %metadata.issuer, "Loaded OpenID Connect discovery document");
+        Ok(metadata)
+    }
+
+    async fn fetch_jwks(&self, url: &str) -> Result<JwkSet, AuthError> {
+        self.get_json(url).await
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, AuthError> {
+        let fetch_failed = |e: reqwest::Error| AuthError::OidcProvider(format!("failed to fetch {}: {}", url, e));
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(fetch_failed)?
+            .json()
+            .await
+            .map_err(fetch_failed)
+    }
+}
+
+/// Key and algorithm to verify a token with, chosen by its `kid`
+///
+/// A key set with a single key may be used for tokens without a `kid`.
+/// The algorithm comes from the key when it names one; otherwise the
+/// header's algorithm is used only if it fits the key type.
+fn verification_key(jwks: &JwkSet, header: &Header) -> Option<(DecodingKey, Algorithm)> {
+    let jwk: &Jwk = match &header.kid {
+        Some(kid) => jwks.find(kid)?,
+        None if jwks.keys.len() == 1 => &jwks.keys[0],
+        None => return None,
+    };
+
+    let algorithm = match jwk.common.key_algorithm {
+        Some(key_algorithm) => Algorithm::from_str(&format!("{:?}", key_algorithm)).ok()?,
+        None => header.alg,
+    };
+    let fits = match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => matches!(
+            algorithm,
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512
+        ),
+        AlgorithmParameters::EllipticCurve(_) => matches!(algorithm, Algorithm::ES256 | Algorithm::ES384),
+        AlgorithmParameters::OctetKeyPair(_) => algorithm == Algorithm::EdDSA,
+        AlgorithmParameters::OctetKey(_) => false,
+    };
+    if !fits || algorithm != header.alg {
+        return None;
+    }
+    Some((DecodingKey::from_jwk(jwk).ok()?, algorithm))
+}
+
+/// Random URL-safe value for `state`, `nonce` and code verifiers
+fn random_token() -> String {
+    let mut bytes = [0u8; RANDOM_BYTES];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// PKCE `S256` challenge for a code verifier
+fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// Minimal OpenID Connect provider for tests
+///
+/// Serves discovery, JWKS, authorization and token endpoints on a local
+/// port. The authorization endpoint logs in a fixed user without a prompt
+/// and redirects straight back with a code; the ID token carries the claims
+/// set with [`MockProvider::set_claims`].
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock {
+    use super::code_challenge;
+    use crate::keys::testdata::{RSA_PRIVATE, RSA_PUBLIC};
+    use crate::keys::JwtKey;
+    use axum::extract::{Query, State};
+    use axum::http::{header, HeaderMap, StatusCode};
+    use axum::response::{IntoResponse, Redirect, Response};
+    use axum::routing::{get, post};
+    use axum::{Form, Json, Router};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use common::config::OAuthSettings;
+    use jsonwebtoken::{Algorithm, Header};
+    use reqwest::Url;
+    use serde_json::{json, Map, Value};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    /// Key ID of the mock provider's signing key
+    pub const MOCK_KID: &str = "mock-oidc";
+
+    /// A running mock provider
+    pub struct MockProvider {
+        /// Issuer URL, also the base URL of every endpoint
+        pub issuer: String,
+        state: Arc<MockState>,
+        server: tokio::task::JoinHandle<()>,
+    }
+
+    struct MockState {
+        issuer: String,
+        client_id: String,
+        client_secret: String,
+        key: JwtKey,
+        claims: Mutex<Map<String, Value>>,
+        codes: Mutex<HashMap<String, IssuedCode>>,
+    }
+
+    struct IssuedCode {
+        redirect_uri: String,
+        code_challenge: String,
+        nonce: String,
+    }
+
+    impl MockProvider {
+        /// Start a provider for one client on a random local port
+        pub async fn start(client_id: &str, client_secret: &str) -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind mock provider");
+            let issuer = format!("http://{}", listener.local_addr().expect("mock provider address"));
+            let state = Arc::new(MockState {
+                issuer: issuer.clone(),
+                client_id: client_id.to_string(),
+                client_secret: client_secret.to_string(),
+                key: JwtKey::from_pem(MOCK_KID, Algorithm::RS256, Some(RSA_PRIVATE.as_bytes()), RSA_PUBLIC.as_bytes())
+                    .expect("mock provider key"),
+                claims: Mutex::new(Map::new()),
+                codes: Mutex::new(HashMap::new()),
+            });
+
+            let app = Router::new()
+                .route("/.well-known/openid-configuration", get(discovery))
+                .route("/jwks", get(jwks))
+                .route("/authorize", get(authorize))
+                .route("/token", post(token))
+                .with_state(state.clone());
+            let server = tokio::spawn(async move {
+                axum::serve(listener, app).await.expect("mock provider");
+            });
+
+            Self { issuer, state, server }
+        }
+
+        /// Claims added to the next ID tokens, e.g. `sub` and `groups`
+        pub fn set_claims(&self, claims: Value) {
+            let claims = claims.as_object().cloned().unwrap_or_default();
+            *self.state.claims.lock().unwrap_or_else(PoisonError::into_inner) = claims;
+        }
+
+        /// Client settings for this provider and a gateway callback URL
+        pub fn settings(&self, redirect_uri: &str) -> OAuthSettings {
+            OAuthSettings {
+                provider: "mock".to_string(),
+                issuer_url: self.issuer.clone(),
+                client_id: self.state.client_id.clone(),
+                client_secret: self.state.client_secret.as_str().into(),
+                redirect_uri: redirect_uri.to_string(),
+                scopes: vec!["openid".to_string(), "profile".to_string()],
+                username_claim: "preferred_username".to_string(),
+                roles_claim: "groups".to_string(),
+                role_mapping: HashMap::new(),
+                default_roles: vec!["readonly".to_string()],
+            }
+        }
+    }
+
+    impl Drop for MockProvider {
+        fn drop(&mut self) {
+            self.server.abort();
+        }
+    }
+
+    async fn discovery(State(state): State<Arc<MockState>>) -> Json<Value> {
+        Json(json!({
+            "issuer": state.issuer,
+            "authorization_endpoint": format!("{}/authorize", state.issuer),
+            "token_endpoint": format!("{}/token", state.issuer),
+            "jwks_uri": format!("{}/jwks", state.issuer),
+            "response_types_supported": ["code"],
+            "code_challenge_methods_supported": ["S256"],
+        }))
+    }
+
+    async fn jwks(State(state): State<Arc<MockState>>) -> Json<Value> {
+        Json(json!({ "keys": [state.key.public_jwk()] }))
+    }
+
+    async fn authorize(State(state): State<Arc<MockState>>, Query(params): Query<HashMap<String, String>>) -> Response {
+        let param = |name: &str| params.get(name).cloned().unwrap_or_default();
+        if param("client_id") != state.client_id
+            || param("response_type") != "code"
+            || param("code_challenge_method") != "S256"
+        {
+            return (StatusCode::BAD_REQUEST, "invalid authorization request").into_response();
+        }
+
+        let code = super::random_token();
+        state.codes.lock().unwrap_or_else(PoisonError::into_inner).insert(
+            code.clone(),
+            IssuedCode {
+                redirect_uri: param("redirect_uri"),
+                code_challenge: param("code_challenge"),
+                nonce: param("nonce"),
+            },
+        );
+
+        let Ok(location) = Url::parse_with_params(
+            &param("redirect_uri"),
+            &[("code", code.as_str()), ("state", param("state").as_str())],
+        ) else {
+            return (StatusCode::BAD_REQUEST, "invalid redirect_uri").into_response();
+        };
+        Redirect::to(location.as_str()).into_response()
+    }
+
+    async fn token(
+        State(state): State<Arc<MockState>>,
+        headers: HeaderMap,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Response {
+        let error = |error: &str| (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+        let param = |name: &str| form.get(name).cloned().unwrap_or_default();
+
+        let client_id = match headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()) {
+            Some(value) => {
+                let credentials = value
+                    .strip_prefix("Basic ")
+                    .and_then(|encoded| STANDARD.decode(encoded).ok())
+                    .and_then(|decoded| String::from_utf8(decoded).ok())
+                    .unwrap_or_default();
+                if credentials != format!("{}:{}", state.client_id, state.client_secret) {
+                    return error("invalid_client");
+                }
+                state.client_id.clone()
+            }
+            None if state.client_secret.is_empty() => param("client_id"),
+            None => return error("invalid_client"),
+        };
+        if client_id != state.client_id {
+            return error("invalid_client");
+        }
+
+        let issued = state.codes.lock().unwrap_or_else(PoisonError::into_inner).remove(&param("code"));
+        let Some(issued) = issued else {
+            return error("invalid_grant");
+        };
+        if param("grant_type") != "authorization_code"
+            || param("redirect_uri") != issued.redirect_uri
+            || code_challenge(&param("code_verifier")) != issued.code_challenge
+        {
+            return error("invalid_grant");
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let mut claims = json!({
+            "iss": state.issuer,
+            "aud": state.client_id,
+            "sub": "mock-user",
+            "nonce": issued.nonce,
+            "iat": now,
+            "exp": now + 300,
+        });
+        let extra = state.claims.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        claims.as_object_mut().expect("claims object").extend(extra);
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(MOCK_KID.to_string());
+        let id_token = jsonwebtoken::encode(&header, &claims, state.key.encoding_key().expect("mock signing key"))
+            .expect("sign ID token");
+        Json(json!({ "access_token": "mock-access-token", "token_type": "Bearer", "id_token": id_token }))
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockProvider;
+    use super::*;
+    use serde_json::json;
+
+    const REDIRECT_URI: &str = "http://gateway.test/api/oauth/callback";
+
+    /// Follow the mock provider's redirect back to the gateway, returning the code
+    async fn authorize(url: &str, login: &OidcLoginRecord) -> String {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let response = client.get(url).send().await.unwrap();
+        let location = Url::parse(response.headers()["location"].to_str().unwrap()).unwrap();
+        let params: std::collections::HashMap<_, _> = location.query_pairs().into_owned().collect();
+        assert_eq!(params["state"], login.state);
+        params["code"].clone()
+    }
+
+    #[test]
+    fn test_code_challenge_matches_rfc_7636() {
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_settings_are_validated() {
+        let mut settings = OAuthSettings {
+            provider: "test".to_string(),
+            issuer_url: "https://idp.example.com".to_string(),
+            client_id: "gateway".to_string(),
+            client_secret: Default::default(),
+            redirect_uri: REDIRECT_URI.to_string(),
+            scopes: vec!["openid".to_string()],
+            username_claim: "preferred_username".to_string(),
+            roles_claim: "groups".to_string(),
+            role_mapping: Default::default(),
+            default_roles: vec!["readonly".to_string()],
+        };
+        assert!(OidcClient::new(&settings, 0).is_ok());
+
+        settings.scopes = vec!["profile".to_string()];
+        assert!(OidcClient::new(&settings, 0).is_err());
+        settings.scopes = vec!["openid".to_string()];
+        settings.issuer_url = "idp.example.com".to_string();
+        assert!(OidcClient::new(&settings, 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_login_against_mock_provider() {
+        let provider = MockProvider::start("gateway", "client-secret").await;
+        let mut settings = provider.settings(REDIRECT_URI);
+        settings.role_mapping.insert("ops".to_string(), vec!["admin".to_string()]);
+        settings.role_mapping.insert("dev".to_string(), vec!["user".to_string(), "admin".to_string()]);
+        let client = OidcClient::new(&settings, 0).unwrap();
+
+        provider.set_claims(json!({ "sub": "u-1", "preferred_username": "alice", "groups": ["dev", "sales", "ops"] }));
+        let (url, login) = client.authorization_url(1_000).await.unwrap();
+        assert!(url.contains("code_challenge_method=S256"));
+        assert_eq!(login.expires_at, 1_000 + OIDC_LOGIN_TTL_SECONDS);
+
+        let code = authorize(&url, &login).await;
+        let identity = client.exchange(&code, &login).await.unwrap();
+        assert_eq!(identity.issuer, provider.issuer);
+        assert_eq!(identity.subject, "u-1");
+        assert_eq!(identity.username, "alice");
+        assert_eq!(identity.roles, vec!["user", "admin"]);
+
+        // Codes are single-use
+        assert!(matches!(client.exchange(&code, &login).await, Err(AuthError::OidcLogin(_))));
+
+        // Unmapped groups fall back to the default roles
+        provider.set_claims(json!({ "sub": "u-2", "groups": "sales" }));
+        let (url, login) = client.authorization_url(1_000).await.unwrap();
+        let code = authorize(&url, &login).await;
+        let identity = client.exchange(&code, &login).await.unwrap();
+        assert_eq!(identity.username, "u-2");
+        assert_eq!(identity.roles, vec!["readonly"]);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_rejects_wrong_verifier_and_nonce() {
+        let provider = MockProvider::start("gateway", "").await;
+        let client = OidcClient::new(&provider.settings(REDIRECT_URI), 0).unwrap();
+
+        let (url, login) = client.authorization_url(1_000).await.unwrap();
+        let code = authorize(&url, &login).await;
+        let forged = OidcLoginRecord {
+            code_verifier: random_token(),
+            ..login.clone()
+        };
+        assert!(matches!(client.exchange(&code, &forged).await, Err(AuthError::OidcLogin(_))));
+
+        let (url, login) = client.authorization_url(1_000).await.unwrap();
+        let code = authorize(&url, &login).await;
+        let replayed = OidcLoginRecord {
+            nonce: random_token(),
+            ..login.clone()
+        };
+        assert!(matches!(client.exchange(&code, &replayed).await, Err(AuthError::OidcLogin(_))));
+    }
+}
@@ -0,0 +1,69 @@
+//! Password hashing
+//!
+//! Passwords are hashed with Argon2id and stored as PHC strings.
+
+use crate::error::AuthError;
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use once_cell::sync::Lazy;
+
+/// Minimum accepted password length
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Hash verified against when a user does not exist, so lookups take the same time
+static DUMMY_HASH: Lazy<String> =
+    Lazy::new(|| hash_password("nexa-dummy-password").expect("Failed to hash dummy password"));
+
+/// Check a new password against the password policy
+pub fn validate_password(password: &str) -> Result<(), AuthError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AuthError::WeakPassword(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+/// Hash a password with Argon2id and a random salt
+pub fn hash_password(password: &str) -> Result<String, AuthError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AuthError::Unknown(format!("Failed to hash password: {}", e)))
+}
+
+/// Verify a password against a stored PHC hash
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, AuthError> {
+    let parsed = PasswordHash::new(hash)
+        .map_err(|e| AuthError::Unknown(format!("Invalid password hash: {}", e)))?;
+
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .is_ok())
+}
+
+/// Burn the same work as a real verification when the user is unknown
+pub fn verify_dummy_password(password: &str) {
+    let _ = verify_password(password, &DUMMY_HASH);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse", &hash).unwrap());
+        assert!(!verify_password("wrong horse", &hash).unwrap());
+    }
+
+    #[test]
+    fn test_password_policy() {
+        assert!(validate_password("short").is_err());
+        assert!(validate_password("long enough").is_ok());
+    }
+}
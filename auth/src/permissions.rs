@@ -1,56 +1,443 @@
 //! Permission handling for user roles
 //!
-//! This module manages role-based access control (RBAC).
+//! This module manages role-based access control (RBAC). Roles grant
+//! permissions of the form `resource:action`; `resource:*` grants every
+//! action on a resource and `*` grants everything. A role may inherit the
+//! permissions of other roles, and a user holds the union of the
+//! permissions of all their roles.
+//!
+//! The built-in `admin`, `user` and `readonly` roles can be redefined and
+//! new roles added under `auth.roles` in the configuration. Further roles
+//! are created at runtime through the admin API and stored in the database,
+//! where every gateway instance picks them up on its next reload.
 
 use crate::error::AuthError;
-use std::collections::HashMap;
-use once_cell::sync::Lazy;
-
-/// Map of roles to their permissions
-static ROLE_PERMISSIONS: Lazy<HashMap<&'static str, Vec<&'static str>>> = Lazy::new(|| {
-    let mut map = HashMap::new();
-    
-    // Admin role has all permissions
-    map.insert("admin", vec![
-        "user:read", "user:write", "user:delete",
-        "agent:read", "agent:write", "agent:delete",
-        "system:read", "system:write", "system:admin"
-    ]);
-    
-    // User role has limited permissions
-    map.insert("user", vec![
-        "user:read", 
-        "agent:read", "agent:write",
-        "system:read"
-    ]);
-    
-    // ReadOnly role has only read permissions
-    map.insert("readonly", vec![
-        "user:read",
-        "agent:read",
-        "system:read"
-    ]);
-    
-    map
-});
-
-/// Check if a role has a specific permission
-pub fn check_permission(role: &str, permission: &str) -> Result<bool, AuthError> {
-    let permissions = ROLE_PERMISSIONS.get(role)
-        .ok_or(AuthError::InvalidRole)?;
-        
-    Ok(permissions.contains(&permission))
-}
-
-/// Get all permissions for a role
-pub fn get_role_permissions(role: &str) -> Result<Vec<&'static str>, AuthError> {
-    let permissions = ROLE_PERMISSIONS.get(role)
-        .ok_or(AuthError::InvalidRole)?;
-        
-    Ok(permissions.clone())
-}
-
-/// Check if a user has admin privileges
-pub fn is_admin(role: &str) -> bool {
-    role == "admin"
+use common::config::RoleSettings;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+/// How often roles stored in the database are reloaded
+pub const ROLE_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Permission grant covering every permission
+pub const ALL_PERMISSIONS: &str = "*";
+
+/// Permission required to administer users, roles and the gateway itself
+pub const ADMIN_PERMISSION: &str = "system:admin";
+
+/// The permissions a role grants directly and the roles it inherits from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleDefinition {
+    /// Role name assigned to users
+    pub name: String,
+    /// Permissions granted directly, e.g. `agent:read` or `agent:*`
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    /// Roles whose permissions are granted as well
+    #[serde(default)]
+    pub inherits: Vec<String>,
+}
+
+impl From<&RoleSettings> for RoleDefinition {
+    fn from(settings: &RoleSettings) -> Self {
+        Self {
+            name: settings.name.clone(),
+            permissions: settings.permissions.clone(),
+            inherits: settings.inherits.clone(),
+        }
+    }
+}
+
+/// Where a role is defined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoleSource {
+    /// Shipped with the gateway
+    Builtin,
+    /// Defined in `auth.roles`
+    Config,
+    /// Created through the admin API
+    Stored,
+}
+
+/// A role together with every permission it grants
+#[derive(Debug, Clone, Serialize)]
+pub struct Role {
+    #[serde(flatten)]
+    pub definition: RoleDefinition,
+    /// Where the role is defined; only stored roles can be changed at runtime
+    pub source: RoleSource,
+    /// Permissions granted directly or by inheritance
+    pub effective_permissions: Vec<String>,
+}
+
+/// Roles shipped with the gateway
+pub fn builtin_roles() -> Vec<RoleDefinition> {
+    let role = |name: &str, permissions: &[&str], inherits: &[&str]| RoleDefinition {
+        name: name.to_string(),
+        permissions: permissions.iter().map(|p| p.to_string()).collect(),
+        inherits: inherits.iter().map(|r| r.to_string()).collect(),
+    };
+    vec![
+        // Admin role has all permissions
+        role("admin", &[ALL_PERMISSIONS], &[]),
+        // User role can also manage agents
+        role("user", &["agent:write"], &["readonly"]),
+        // ReadOnly role has only read permissions
+        role("readonly", &["user:read", "agent:read", "system:read"], &[]),
+    ]
+}
+
+/// Whether a granted permission covers the requested one
+pub fn permission_matches(granted: &str, permission: &str) -> bool {
+    if granted == ALL_PERMISSIONS || granted == permission {
+        return true;
+    }
+    match (granted.strip_suffix(":*"), permission.split_once(':')) {
+        (Some(resource), Some((requested, _))) => resource == requested,
+        _ => false,
+    }
+}
+
+/// A resolved set of roles
+#[derive(Debug, Clone, Default)]
+pub struct RoleSet {
+    roles: BTreeMap<String, Role>,
+}
+
+impl RoleSet {
+    /// Resolve role definitions; later definitions replace earlier ones of the same name
+    ///
+    /// Inheriting from an unknown role grants nothing and inheritance cycles
+    /// are cut, so removing a role elsewhere never widens permissions.
+    pub fn new(definitions: impl IntoIterator<Item = (RoleDefinition, RoleSource)>) -> Self {
+        let definitions: BTreeMap<String, (RoleDefinition, RoleSource)> = definitions
+            .into_iter()
+            .map(|(definition, source)| (definition.name.clone(), (definition, source)))
+            .collect();
+        let inherits = |name: &str| definitions.get(name).map(|(definition, _)| definition.inherits.as_slice());
+
+        let roles = definitions
+            .iter()
+            .map(|(name, (definition, source))| {
+                let effective_permissions: BTreeSet<String> = lineage(name, inherits)
+                    .iter()
+                    .flat_map(|role| definitions[role].0.permissions.iter().cloned())
+                    .collect();
+                let role = Role {
+                    definition: definition.clone(),
+                    source: *source,
+                    effective_permissions: effective_permissions.into_iter().collect(),
+                };
+                (name.clone(), role)
+            })
+            .collect();
+        Self { roles }
+    }
+
+    /// Look a role up by name
+    pub fn get(&self, name: &str) -> Option<&Role> {
+        self.roles.get(name)
+    }
+
+    /// Whether a role exists
+    pub fn contains(&self, name: &str) -> bool {
+        self.roles.contains_key(name)
+    }
+
+    /// All roles ordered by name
+    pub fn roles(&self) -> impl Iterator<Item = &Role> {
+        self.roles.values()
+    }
+
+    /// Whether any of the roles grants the permission; unknown roles grant nothing
+    pub fn has_permission(&self, roles: &[String], permission: &str) -> bool {
+        roles.iter().filter_map(|name| self.roles.get(name)).any(|role| {
+            role.effective_permissions
+                .iter()
+                .any(|granted| permission_matches(granted, permission))
+        })
+    }
+
+    /// Roles that inherit directly from `name`
+    pub fn dependents(&self, name: &str) -> Vec<&str> {
+        self.roles
+            .values()
+            .filter(|role| role.definition.inherits.iter().any(|parent| parent == name))
+            .map(|role| role.definition.name.as_str())
+            .collect()
+    }
+
+    /// Check a definition before adding it to, or replacing it in, this set
+    ///
+    /// Names and permissions must be well formed, inherited roles must exist
+    /// and the role must not end up inheriting from itself.
+    pub fn check(&self, definition: &RoleDefinition) -> Result<(), AuthError> {
+        let invalid = |message: String| Err(AuthError::InvalidRoleDefinition(message));
+
+        if !is_valid_name(&definition.name) {
+            return invalid(format!(
+                "role name '{}' must be lowercase letters, digits, '-', '_' or '.'",
+                definition.name
+            ));
+        }
+        if let Some(permission) = definition.permissions.iter().find(|p| !is_valid_permission(p)) {
+            return invalid(format!(
+                "permission '{}' must be '*', 'resource:*' or 'resource:action'",
+                permission
+            ));
+        }
+        if let Some(parent) = definition.inherits.iter().find(|parent| !self.contains(parent)) {
+            return invalid(format!("role '{}' inherits unknown role '{}'", definition.name, parent));
+        }
+
+        let inherits = |name: &str| {
+            if name == definition.name {
+                Some(definition.inherits.as_slice())
+            } else {
+                self.roles.get(name).map(|role| role.definition.inherits.as_slice())
+            }
+        };
+        let cyclic = definition
+            .inherits
+            .iter()
+            .any(|parent| lineage(parent, inherits).contains(&definition.name));
+        if cyclic {
+            return invalid(format!("role '{}' would inherit from itself", definition.name));
+        }
+        Ok(())
+    }
+}
+
+/// A role and every role it inherits from, directly or indirectly
+fn lineage<'a>(name: &str, inherits: impl Fn(&str) -> Option<&'a [String]>) -> BTreeSet<String> {
+    let mut seen = BTreeSet::new();
+    let mut pending = vec![name.to_string()];
+    while let Some(role) = pending.pop() {
+        let Some(parents) = inherits(&role) else {
+            continue;
+        };
+        if seen.insert(role) {
+            pending.extend(parents.iter().filter(|parent| !seen.contains(*parent)).cloned());
+        }
+    }
+    seen
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Whether a permission is `*`, `resource:*` or `resource:action`
+pub fn is_valid_permission(permission: &str) -> bool {
+    if permission == ALL_PERMISSIONS {
+        return true;
+    }
+    match permission.split_once(':') {
+        Some((resource, action)) => is_valid_name(resource) && (action == "*" || is_valid_name(action)),
+        None => false,
+    }
+}
+
+/// Built-in and configured roles, plus roles stored in the database
+#[derive(Debug)]
+pub struct RoleRegistry {
+    base: Vec<(RoleDefinition, RoleSource)>,
+    state: RwLock<RegistryState>,
+    reload: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug)]
+struct RegistryState {
+    roles: Arc<RoleSet>,
+    checked_at: Option<Instant>,
+}
+
+impl Default for RoleRegistry {
+    fn default() -> Self {
+        Self::with_base(builtin_roles().into_iter().map(|role| (role, RoleSource::Builtin)).collect())
+    }
+}
+
+impl RoleRegistry {
+    /// Built-in roles, replaced or extended by the configured ones
+    pub fn from_settings(settings: &[RoleSettings]) -> Result<Self, AuthError> {
+        let configured: Vec<RoleDefinition> = settings.iter().map(RoleDefinition::from).collect();
+        let mut base: Vec<(RoleDefinition, RoleSource)> = builtin_roles()
+            .into_iter()
+            .filter(|builtin| configured.iter().all(|role| role.name != builtin.name))
+            .map(|role| (role, RoleSource::Builtin))
+            .collect();
+        base.extend(configured.iter().cloned().map(|role| (role, RoleSource::Config)));
+
+        let roles = RoleSet::new(base.clone());
+        for role in &configured {
+            roles
+                .check(role)
+                .map_err(|e| AuthError::Configuration(format!("auth.roles: {}", e)))?;
+        }
+        Ok(Self::with_base(base))
+    }
+
+    fn with_base(base: Vec<(RoleDefinition, RoleSource)>) -> Self {
+        Self {
+            state: RwLock::new(RegistryState {
+                roles: Arc::new(RoleSet::new(base.clone())),
+                checked_at: None,
+            }),
+            base,
+            reload: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Roles currently in effect
+    pub fn current(&self) -> Arc<RoleSet> {
+        self.state.read().unwrap_or_else(PoisonError::into_inner).roles.clone()
+    }
+
+    /// Whether a role is built in or configured, and so cannot be changed at runtime
+    pub fn is_fixed(&self, name: &str) -> bool {
+        self.base.iter().any(|(role, _)| role.name == name)
+    }
+
+    /// Whether the store has not been checked within `max_age`
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        self.state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .checked_at
+            .is_none_or(|at| at.elapsed() >= max_age)
+    }
+
+    /// Serialises reloads so concurrent requests do not all query the store
+    pub(crate) async fn lock_reload(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.reload.lock().await
+    }
+
+    /// Record a failed reload so it is not retried on every request
+    pub(crate) fn mark_checked(&self) {
+        self.state.write().unwrap_or_else(PoisonError::into_inner).checked_at = Some(Instant::now());
+    }
+
+    /// Rebuild the roles in effect from the roles stored in the database
+    ///
+    /// A stored role named like a built-in or configured role, e.g. one
+    /// added to the configuration after it was stored, is ignored.
+    pub fn apply(&self, stored: Vec<RoleDefinition>) {
+        let mut definitions = self.base.clone();
+        for role in stored {
+            if self.is_fixed(&role.name) {
+                tracing::warn!(role = %role.name, "Ignoring stored role shadowed by a configured role");
+                continue;
+            }
+            definitions.push((role, RoleSource::Stored));
+        }
+
+        *self.state.write().unwrap_or_else(PoisonError::into_inner) = RegistryState {
+            roles: Arc::new(RoleSet::new(definitions)),
+            checked_at: Some(Instant::now()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn role(name: &str, permissions: &[&str], inherits: &[&str]) -> RoleDefinition {
+        RoleDefinition {
+            name: name.to_string(),
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            inherits: inherits.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+
+    fn names(roles: &[&str]) -> Vec<String> {
+        roles.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn test_builtin_roles() {
+        let roles = RoleRegistry::default().current();
+        assert!(roles.has_permission(&names(&["admin"]), "anything:at-all"));
+        assert!(roles.has_permission(&names(&["user"]), "agent:write"));
+        assert!(roles.has_permission(&names(&["user"]), "system:read"));
+        assert!(!roles.has_permission(&names(&["user"]), "system:admin"));
+        assert!(!roles.has_permission(&names(&["readonly"]), "agent:write"));
+        assert!(!roles.has_permission(&names(&["superuser"]), "agent:read"));
+        assert!(!roles.has_permission(&[], "agent:read"));
+    }
+
+    #[test]
+    fn test_wildcards_inheritance_and_multiple_roles() {
+        assert!(permission_matches("agent:*", "agent:delete"));
+        assert!(!permission_matches("agent:*", "agents:read"));
+        assert!(!permission_matches("agent:read", "agent:write"));
+
+        let roles = RoleSet::new([
+            (role("agents", &["agent:*"], &[]), RoleSource::Config),
+            (role("auditor", &["system:read"], &["viewer"]), RoleSource::Config),
+            (role("viewer", &["user:read"], &["missing"]), RoleSource::Config),
+        ]);
+        assert_eq!(
+            roles.get("auditor").unwrap().effective_permissions,
+            names(&["system:read", "user:read"])
+        );
+        assert!(roles.has_permission(&names(&["agents", "auditor"]), "agent:delete"));
+        assert!(roles.has_permission(&names(&["agents", "auditor"]), "user:read"));
+        assert!(!roles.has_permission(&names(&["auditor"]), "agent:read"));
+        assert_eq!(roles.dependents("viewer"), vec!["auditor"]);
+    }
+
+    #[test]
+    fn test_check_rejects_invalid_definitions() {
+        let roles = RoleSet::new([
+            (role("a", &["agent:read"], &[]), RoleSource::Stored),
+            (role("b", &[], &["a"]), RoleSource::Stored),
+        ]);
+        assert!(roles.check(&role("c", &["agent:*", "*"], &["b"])).is_ok());
+        assert!(roles.check(&role("Bad Name", &[], &[])).is_err());
+        assert!(roles.check(&role("c", &["agent"], &[])).is_err());
+        assert!(roles.check(&role("c", &["agent:*:x"], &[])).is_err());
+        assert!(roles.check(&role("c", &[], &["missing"])).is_err());
+        assert!(roles.check(&role("a", &[], &["b"])).is_err());
+        assert!(roles.check(&role("a", &[], &["a"])).is_err());
+    }
+
+    #[test]
+    fn test_registry_layers_configured_and_stored_roles() {
+        let settings = vec![
+            RoleSettings {
+                name: "user".to_string(),
+                permissions: vec!["agent:read".to_string()],
+                inherits: Vec::new(),
+            },
+            RoleSettings {
+                name: "operator".to_string(),
+                permissions: vec!["system:*".to_string()],
+                inherits: vec!["user".to_string()],
+            },
+        ];
+        let registry = RoleRegistry::from_settings(&settings).unwrap();
+        assert!(registry.is_fixed("admin"));
+        assert!(!registry.current().has_permission(&names(&["user"]), "agent:write"));
+        assert!(registry.current().has_permission(&names(&["operator"]), "system:write"));
+        assert_eq!(registry.current().get("operator").unwrap().source, RoleSource::Config);
+
+        registry.apply(vec![role("support", &["user:read"], &["user"]), role("admin", &[], &[])]);
+        let roles = registry.current();
+        assert_eq!(roles.get("support").unwrap().source, RoleSource::Stored);
+        assert!(roles.has_permission(&names(&["support"]), "agent:read"));
+        // Stored roles cannot shadow built-in or configured ones
+        assert!(roles.has_permission(&names(&["admin"]), "system:admin"));
+
+        let mut cyclic = settings;
+        cyclic[0].inherits = vec!["operator".to_string()];
+        assert!(RoleRegistry::from_settings(&cyclic).is_err());
+    }
 }
@@ -0,0 +1,198 @@
+//! Token revocation
+//!
+//! Access tokens are self-contained, so killing one before `exp` means
+//! remembering its `jti` (or the `sid` of the session it belongs to) until
+//! the token would have expired anyway. Revocations are written to a
+//! persistent store shared by every gateway instance and the CLI, and kept
+//! in an in-memory cache that is checked on each request and synced with
+//! the store every few seconds.
+
+use crate::AuthResult;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+/// How often the in-memory cache picks up revocations made elsewhere
+pub const REVOCATION_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Overlap between syncs to tolerate clock skew between instances
+const SYNC_OVERLAP_SECONDS: i64 = 60;
+
+/// Storage for revoked token and session IDs
+#[async_trait]
+pub trait RevocationStore: Send + Sync {
+    /// Revoke an ID until `expires_at`, after which no token carrying it is valid
+    async fn revoke(&self, id: &str, revoked_at: i64, expires_at: i64) -> AuthResult<()>;
+
+    /// IDs revoked at or after `since` that are still in force at `now`,
+    /// with the time their revocation expires
+    async fn revoked_since(&self, since: i64, now: i64) -> AuthResult<Vec<(String, i64)>>;
+
+    /// Forget revocations that expired before `now`, returning how many were removed
+    async fn purge_expired_revocations(&self, now: i64) -> AuthResult<u64>;
+}
+
+/// Revoked IDs held in memory until they expire
+#[derive(Debug, Default)]
+pub struct MemoryRevocationStore {
+    entries: RwLock<HashMap<String, Revocation>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Revocation {
+    revoked_at: i64,
+    expires_at: i64,
+}
+
+impl MemoryRevocationStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether an ID is revoked at `now`
+    pub fn is_revoked(&self, id: &str, now: i64) -> bool {
+        self.entries
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(id)
+            .is_some_and(|revocation| revocation.expires_at > now)
+    }
+
+    fn insert(&self, id: &str, revoked_at: i64, expires_at: i64) {
+        self.entries
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id.to_string(), Revocation { revoked_at, expires_at });
+    }
+
+    fn purge(&self, now: i64) -> u64 {
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        let before = entries.len();
+        entries.retain(|_, revocation| revocation.expires_at > now);
+        (before - entries.len()) as u64
+    }
+}
+
+#[async_trait]
+impl RevocationStore for MemoryRevocationStore {
+    async fn revoke(&self, id: &str, revoked_at: i64, expires_at: i64) -> AuthResult<()> {
+        self.insert(id, revoked_at, expires_at);
+        Ok(())
+    }
+
+    async fn revoked_since(&self, since: i64, now: i64) -> AuthResult<Vec<(String, i64)>> {
+        Ok(self
+            .entries
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|(_, revocation)| revocation.revoked_at >= since && revocation.expires_at > now)
+            .map(|(id, revocation)| (id.clone(), revocation.expires_at))
+            .collect())
+    }
+
+    async fn purge_expired_revocations(&self, now: i64) -> AuthResult<u64> {
+        Ok(self.purge(now))
+    }
+}
+
+/// In-memory cache of revocations backed by a persistent store
+pub struct RevocationList {
+    cache: MemoryRevocationStore,
+    backend: Arc<dyn RevocationStore>,
+    sync: tokio::sync::Mutex<SyncState>,
+}
+
+#[derive(Default)]
+struct SyncState {
+    synced_at: Option<Instant>,
+    /// Revocation time up to which the backend has been read
+    watermark: i64,
+}
+
+impl RevocationList {
+    /// Create a revocation list over a persistent store
+    pub fn new(backend: Arc<dyn RevocationStore>) -> Self {
+        Self {
+            cache: MemoryRevocationStore::new(),
+            backend,
+            sync: tokio::sync::Mutex::new(SyncState::default()),
+        }
+    }
+
+    /// Revoke an ID until `expires_at`
+    pub async fn revoke(&self, id: &str, now: i64, expires_at: i64) -> AuthResult<()> {
+        self.backend.revoke(id, now, expires_at).await?;
+        self.cache.insert(id, now, expires_at);
+        Ok(())
+    }
+
+    /// Whether any of the IDs is revoked at `now`
+    ///
+    /// When the persistent store cannot be reached the cached revocations
+    /// are used, so an outage does not lock every user out.
+    pub async fn any_revoked(&self, ids: &[&str], now: i64) -> bool {
+        if let Err(e) = self.sync(now).await {
+            tracing::warn!(error = %e, "Failed to sync token revocations; using cached revocations");
+        }
+        ids.iter().any(|id| self.cache.is_revoked(id, now))
+    }
+
+    /// Load revocations made since the last sync, at most once per interval
+    async fn sync(&self, now: i64) -> AuthResult<()> {
+        let mut state = self.sync.lock().await;
+        if state
+            .synced_at
+            .is_some_and(|at| at.elapsed() < REVOCATION_SYNC_INTERVAL)
+        {
+            return Ok(());
+        }
+        // Retry after the interval rather than on every request when the store fails
+        state.synced_at = Some(Instant::now());
+
+        let since = state.watermark - SYNC_OVERLAP_SECONDS;
+        for (id, expires_at) in self.backend.revoked_since(since, now).await? {
+            self.cache.insert(&id, now, expires_at);
+        }
+        state.watermark = now;
+
+        self.cache.purge(now);
+        self.backend.purge_expired_revocations(now).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store_expires_revocations() {
+        let store = MemoryRevocationStore::new();
+        store.revoke("token-1", 100, 200).await.unwrap();
+
+        assert!(store.is_revoked("token-1", 199));
+        assert!(!store.is_revoked("token-1", 200));
+        assert!(!store.is_revoked("token-2", 150));
+
+        assert_eq!(store.revoked_since(100, 150).await.unwrap().len(), 1);
+        assert!(store.revoked_since(101, 150).await.unwrap().is_empty());
+        assert_eq!(store.purge_expired_revocations(200).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_list_loads_revocations_from_backend() {
+        let backend = Arc::new(MemoryRevocationStore::new());
+        backend.revoke("elsewhere", 100, 1000).await.unwrap();
+
+        let list = RevocationList::new(backend.clone());
+        assert!(list.any_revoked(&["other", "elsewhere"], 150).await);
+
+        list.revoke("local", 150, 1000).await.unwrap();
+        assert!(list.any_revoked(&["local"], 150).await);
+        assert!(backend.is_revoked("local", 150));
+        assert!(!list.any_revoked(&["local"], 1000).await);
+    }
+}
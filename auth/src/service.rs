@@ -1,8 +1,31 @@
+use crate::api_keys::{self, LAST_USED_RESOLUTION_SECONDS};
+use crate::config::JwtConfig;
 use crate::error::AuthError;
-use crate::jwt;
+use crate::jwt::{self, Claims};
+use crate::keys::{JwkSet, KEY_RING_RELOAD_INTERVAL, UNKNOWN_KEY_RELOAD_INTERVAL};
+use crate::oidc::OidcClient;
+use crate::password;
+use crate::permissions::{self, Role, RoleDefinition, RoleRegistry, ADMIN_PERMISSION, ROLE_RELOAD_INTERVAL};
+use crate::revocation::{RevocationList, RevocationStore};
+use crate::tenants::{self, DEFAULT_TENANT};
+use crate::totp;
+use crate::store::{ApiKey, AuthStore, RefreshTokenRecord, Session, SigningKeyRecord, TotpCredential, User};
+use chrono::Utc;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use common::config::{LockoutSettings, Settings, TwoFactorSettings};
+use common::secret::{MasterKey, Secret};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
+/// Seconds a user has to enter their second factor after the password
+pub const LOGIN_CHALLENGE_SECONDS: i64 = 300;
+
+/// Seconds the claims of a client certificate are valid; they are issued per request
+const CERTIFICATE_CLAIMS_SECONDS: i64 = 60;
+
 /// User credentials for authentication
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Credentials {
@@ -15,82 +38,1770 @@ pub struct Credentials {
 pub struct UserInfo {
     pub id: String,
     pub username: String,
-    pub role: String,
+    pub roles: Vec<String>,
+    pub tenant: String,
+}
+
+/// Access and refresh tokens issued together
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenPair {
+    /// Short-lived JWT for API requests
+    pub access_token: String,
+    /// Long-lived opaque token exchanged for a new pair
+    pub refresh_token: String,
+    /// Access token lifetime in seconds
+    pub expires_in: u64,
+}
+
+/// Result of checking a password
+#[derive(Debug)]
+pub enum LoginOutcome {
+    /// Tokens were issued
+    Authenticated(UserInfo, TokenPair),
+    /// The password was right, but a second factor is needed before tokens are issued
+    SecondFactorRequired(TwoFactorChallenge),
+}
+
+/// A login waiting for its second factor
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorChallenge {
+    /// Token identifying the login, sent back with the code
+    pub challenge_token: String,
+    /// Whether the user must enroll before entering a code, because policy
+    /// requires a second factor they do not have yet
+    pub enrollment_required: bool,
+    /// Challenge lifetime in seconds
+    pub expires_in: u64,
+}
+
+/// A new TOTP secret, shown to the user once
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpEnrollment {
+    /// Base32 secret for manual entry
+    pub secret: String,
+    /// `otpauth://` URI to render as a QR code
+    pub provisioning_uri: String,
+    /// Single-use codes for when the device is lost
+    pub recovery_codes: Vec<String>,
+}
+
+/// Whether a user has a second factor
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpStatus {
+    /// Whether logins require a code
+    pub enabled: bool,
+    /// Unix timestamp at which enrollment was confirmed
+    pub enabled_at: Option<i64>,
+    /// Unused recovery codes
+    pub recovery_codes_remaining: u64,
+    /// Whether policy requires a second factor for this user
+    pub required: bool,
+}
+
+/// A JWT signing key and how long it stays usable
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SigningKeyInfo {
+    /// Key ID written to token headers
+    pub kid: String,
+    /// Signing algorithm
+    pub algorithm: String,
+    /// Whether new tokens are signed with this key
+    pub signing: bool,
+    /// Unix timestamp after which a retired key stops verifying tokens
+    pub retires_at: Option<i64>,
+}
+
+impl From<&User> for UserInfo {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id.clone(),
+            username: user.username.clone(),
+            roles: user.roles.clone(),
+            tenant: user.tenant.clone(),
+        }
+    }
 }
 
 /// AuthService for handling authentication and authorization
+#[derive(Clone)]
 pub struct AuthService {
-    /// JWT secret key
-    #[allow(dead_code)]
-    secret: String,
-    /// Token expiry in hours
-    #[allow(dead_code)]
-    token_expiry: u64,
+    /// User account storage
+    store: Arc<dyn AuthStore>,
+    /// Token signing and validation settings
+    jwt: JwtConfig,
+    /// Login lockout policy
+    lockout: LockoutSettings,
+    /// Revoked token and session IDs
+    revocations: Arc<RevocationList>,
+    /// Roles and the permissions they grant
+    roles: Arc<RoleRegistry>,
+    /// OpenID Connect provider, when configured
+    oidc: Option<Arc<OidcClient>>,
+    /// Two-factor authentication policy
+    two_factor: TwoFactorSettings,
 }
 
 impl AuthService {
-    /// Create a new AuthService
-    pub async fn new() -> Result<Self, AuthError> {
-        // In a real implementation, this would load from config
-        // For testing, we use a static secret
-        Ok(Self {
-            secret: "test-secret-key".to_string(),
-            token_expiry: 24,
-        })
+    /// Create a new AuthService over a store
+    pub fn new(store: Arc<dyn AuthStore>, jwt: JwtConfig, lockout: LockoutSettings) -> Self {
+        let revocations = Arc::new(RevocationList::new(store.clone() as Arc<dyn RevocationStore>));
+        Self {
+            store,
+            jwt,
+            lockout,
+            revocations,
+            roles: Arc::new(RoleRegistry::default()),
+            oidc: None,
+            two_factor: TwoFactorSettings::default(),
+        }
+    }
+
+    /// Use configured roles instead of only the built-in ones
+    pub fn with_roles(mut self, roles: RoleRegistry) -> Self {
+        self.roles = Arc::new(roles);
+        self
+    }
+
+    /// Apply a two-factor authentication policy
+    pub fn with_two_factor(mut self, two_factor: TwoFactorSettings) -> Self {
+        self.two_factor = two_factor;
+        self
     }
-    
+
+    /// Let users log in at an OpenID Connect provider
+    pub fn with_oidc(mut self, oidc: OidcClient) -> Self {
+        self.oidc = Some(Arc::new(oidc));
+        self
+    }
+
+    /// Create an AuthService from application settings
+    ///
+    /// Fails when the JWT settings are invalid, or when running in
+    /// production with a default or weak secret.
+    pub fn from_settings(store: Arc<dyn AuthStore>, settings: &Settings) -> Result<Self, AuthError> {
+        let jwt = JwtConfig::from_settings(&settings.auth)?;
+        jwt.ensure_secure(&settings.environment)?;
+        let roles = RoleRegistry::from_settings(&settings.auth.roles)?;
+        let service = Self::new(store, jwt, settings.auth.lockout.clone())
+            .with_roles(roles)
+            .with_two_factor(settings.auth.two_factor.clone());
+        match &settings.auth.oauth {
+            Some(oauth) => Ok(service.with_oidc(OidcClient::new(oauth, settings.auth.jwt_leeway_seconds)?)),
+            None => Ok(service),
+        }
+    }
+
     /// Authenticate a user with credentials
-    pub async fn authenticate(&self, credentials: Credentials) -> Result<(UserInfo, String), AuthError> {
-        // For testing, any credentials work with a random user ID
-        let user_id = Uuid::new_v4().to_string();
-        
-        // Create a token for the user using our simple helper
-        let token = jwt::create_jwt(&user_id, &credentials.username)?;
-        
-        // Return user info and token
-        Ok((
-            UserInfo {
-                id: user_id,
-                username: credentials.username,
-                role: "user".to_string(),
-            },
-            token
-        ))
-    }
-    
+    ///
+    /// Repeated failures lock the account according to the lockout policy;
+    /// a locked account is refused without checking the password. Users with
+    /// a second factor, or required to have one, get a challenge instead of
+    /// tokens.
+    pub async fn authenticate(&self, credentials: Credentials) -> Result<LoginOutcome, AuthError> {
+        let user = match self.store.find_by_username(&credentials.username).await? {
+            Some(user) => user,
+            None => {
+                let password = credentials.password;
+                tokio::task::spawn_blocking(move || password::verify_dummy_password(&password))
+                    .await
+                    .map_err(|e| AuthError::Unknown(e.to_string()))?;
+                return Err(AuthError::InvalidCredentials);
+            }
+        };
+
+        let now = Utc::now().timestamp();
+        if user.is_locked(now) {
+            tracing::warn!(username = %user.username, "Login refused for locked account");
+            return Err(AuthError::AccountLocked);
+        }
+
+        let hash = user.password_hash.clone();
+        let valid = tokio::task::spawn_blocking(move || password::verify_password(&credentials.password, &hash))
+            .await
+            .map_err(|e| AuthError::Unknown(e.to_string()))??;
+
+        if !valid {
+            let locked = self
+                .store
+                .record_login_failure(
+                    &user.id,
+                    self.lockout.max_failed_attempts as i32,
+                    now + self.lockout.lockout_seconds as i64,
+                )
+                .await?;
+            if locked.is_some() {
+                tracing::warn!(username = %user.username, "Account locked after repeated failed logins");
+            }
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        self.finish_login(&user, now).await
+    }
+
+    /// Issue tokens for a user whose first factor was accepted, or a
+    /// challenge when they have a second factor or are required to have one
+    async fn finish_login(&self, user: &User, now: i64) -> Result<LoginOutcome, AuthError> {
+        let enabled = self
+            .store
+            .find_totp(&user.id)
+            .await?
+            .is_some_and(|credential| credential.enabled_at.is_some());
+        if enabled || self.requires_two_factor(user).await {
+            return Ok(LoginOutcome::SecondFactorRequired(TwoFactorChallenge {
+                challenge_token: jwt::generate_challenge(&self.jwt, &user.id, LOGIN_CHALLENGE_SECONDS)?,
+                enrollment_required: !enabled,
+                expires_in: LOGIN_CHALLENGE_SECONDS as u64,
+            }));
+        }
+
+        self.store.record_login_success(&user.id, now).await?;
+        let tokens = self.issue_tokens(user, Uuid::new_v4().to_string()).await?;
+        Ok(LoginOutcome::Authenticated(UserInfo::from(user), tokens))
+    }
+
+    /// Complete a login with a TOTP or recovery code
+    ///
+    /// During enrollment required by policy only a TOTP code is accepted, and
+    /// it confirms the enrollment. Wrong codes count towards the lockout.
+    pub async fn complete_login(&self, challenge_token: &str, code: &str) -> Result<(UserInfo, TokenPair), AuthError> {
+        let (user, challenge) = self.challenge_user(challenge_token).await?;
+        let now = Utc::now().timestamp();
+        if user.is_locked(now) {
+            tracing::warn!(username = %user.username, "Login refused for locked account");
+            return Err(AuthError::AccountLocked);
+        }
+
+        let credential = self
+            .store
+            .find_totp(&user.id)
+            .await?
+            .ok_or(AuthError::TwoFactorNotEnrolled)?;
+        let accepted = if credential.enabled_at.is_some() {
+            self.check_second_factor(&credential, code, now).await?
+        } else {
+            self.confirm_enrollment(&credential, code, now).await?
+        };
+        if !accepted {
+            let locked = self
+                .store
+                .record_login_failure(
+                    &user.id,
+                    self.lockout.max_failed_attempts as i32,
+                    now + self.lockout.lockout_seconds as i64,
+                )
+                .await?;
+            if locked.is_some() {
+                tracing::warn!(username = %user.username, "Account locked after repeated failed logins");
+            }
+            return Err(AuthError::InvalidTwoFactorCode);
+        }
+
+        // A completed challenge cannot start another session
+        self.revocations
+            .revoke(&token_revocation_id(&challenge.jti), now, challenge.exp + self.jwt.leeway as i64)
+            .await?;
+        self.store.record_login_success(&user.id, now).await?;
+        let tokens = self.issue_tokens(&user, Uuid::new_v4().to_string()).await?;
+        Ok((UserInfo::from(&user), tokens))
+    }
+
+    /// Start the enrollment required by policy during a login
+    pub async fn start_login_enrollment(&self, challenge_token: &str) -> Result<TotpEnrollment, AuthError> {
+        let (user, _) = self.challenge_user(challenge_token).await?;
+        self.enroll_totp(&user.id).await
+    }
+
+    /// ID of the user a login challenge was issued to, without checking whether it was completed
+    pub fn challenge_subject(&self, challenge_token: &str) -> Option<String> {
+        jwt::decode_challenge(challenge_token, &self.jwt).ok().map(|challenge| challenge.sub)
+    }
+
+    /// User of a login challenge that has not been completed
+    async fn challenge_user(&self, challenge_token: &str) -> Result<(User, jwt::ChallengeClaims), AuthError> {
+        self.sync_signing_keys(KEY_RING_RELOAD_INTERVAL).await;
+        let challenge = jwt::decode_challenge(challenge_token, &self.jwt)?;
+        if self
+            .revocations
+            .any_revoked(&[&token_revocation_id(&challenge.jti)], Utc::now().timestamp())
+            .await
+        {
+            return Err(AuthError::TokenRevoked);
+        }
+        let user = self
+            .store
+            .find_by_id(&challenge.sub)
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+        Ok((user, challenge))
+    }
+
+    /// Check a TOTP code, or else a recovery code, of an enabled second factor
+    async fn check_second_factor(&self, credential: &TotpCredential, code: &str, now: i64) -> Result<bool, AuthError> {
+        let code = code.trim();
+        if totp::is_totp_code(code) {
+            return match totp::verify(credential.secret.expose(), code, now) {
+                Some(step) => self.store.use_totp_step(&credential.user_id, step).await,
+                None => Ok(false),
+            };
+        }
+
+        let used = self
+            .store
+            .use_recovery_code(&credential.user_id, &totp::hash_recovery_code(code), now)
+            .await?;
+        if used {
+            tracing::info!(user_id = %credential.user_id, "Recovery code used for login");
+        }
+        Ok(used)
+    }
+
+    /// Confirm a pending enrollment with its first code
+    async fn confirm_enrollment(&self, credential: &TotpCredential, code: &str, now: i64) -> Result<bool, AuthError> {
+        let Some(step) = totp::verify(credential.secret.expose(), code.trim(), now) else {
+            return Ok(false);
+        };
+        if !self.store.use_totp_step(&credential.user_id, step).await? {
+            return Ok(false);
+        }
+        self.store.enable_totp(&credential.user_id, now).await?;
+        tracing::info!(user_id = %credential.user_id, "Enabled two-factor authentication");
+        Ok(true)
+    }
+
+    /// Whether policy requires a second factor for a user
+    pub async fn requires_two_factor(&self, user: &User) -> bool {
+        if !self.two_factor.require_for_admins {
+            return false;
+        }
+        self.sync_roles(ROLE_RELOAD_INTERVAL).await;
+        self.roles.current().has_permission(&user.roles, ADMIN_PERMISSION)
+    }
+
+    /// Start TOTP enrollment, replacing an enrollment that was never confirmed
+    ///
+    /// The secret and recovery codes are only ever returned here; the
+    /// second factor is enabled once a first code confirms the secret.
+    pub async fn enroll_totp(&self, user_id: &str) -> Result<TotpEnrollment, AuthError> {
+        let user = self.get_user(user_id).await?;
+        if let Some(credential) = self.store.find_totp(user_id).await? {
+            if credential.enabled_at.is_some() {
+                return Err(AuthError::TwoFactorAlreadyEnabled);
+            }
+        }
+
+        let secret = totp::generate_secret();
+        self.store
+            .save_totp(&TotpCredential {
+                user_id: user.id.clone(),
+                secret: sealed(&secret, "TOTP secret")?,
+                enabled_at: None,
+                last_used_step: 0,
+                created_at: Utc::now().timestamp(),
+            })
+            .await?;
+        let recovery_codes = totp::generate_recovery_codes();
+        let hashes: Vec<String> = recovery_codes.iter().map(|code| totp::hash_recovery_code(code)).collect();
+        self.store.replace_recovery_codes(&user.id, &hashes).await?;
+
+        Ok(TotpEnrollment {
+            provisioning_uri: totp::provisioning_uri(&secret, &self.two_factor.issuer, &user.username),
+            secret,
+            recovery_codes,
+        })
+    }
+
+    /// Confirm TOTP enrollment with a first code from the authenticator
+    pub async fn confirm_totp(&self, user_id: &str, code: &str) -> Result<(), AuthError> {
+        let credential = self
+            .store
+            .find_totp(user_id)
+            .await?
+            .ok_or(AuthError::TwoFactorNotEnrolled)?;
+        if credential.enabled_at.is_some() {
+            return Err(AuthError::TwoFactorAlreadyEnabled);
+        }
+        if !self.confirm_enrollment(&credential, code, Utc::now().timestamp()).await? {
+            return Err(AuthError::InvalidTwoFactorCode);
+        }
+        Ok(())
+    }
+
+    /// Remove a user's second factor and recovery codes
+    pub async fn disable_totp(&self, user_id: &str) -> Result<(), AuthError> {
+        if !self.store.delete_totp(user_id).await? {
+            return Err(AuthError::TwoFactorNotEnrolled);
+        }
+        tracing::info!(user_id = %user_id, "Disabled two-factor authentication");
+        Ok(())
+    }
+
+    /// Whether a user has a second factor, and how many recovery codes remain
+    pub async fn totp_status(&self, user_id: &str) -> Result<TotpStatus, AuthError> {
+        let user = self.get_user(user_id).await?;
+        let enabled_at = self
+            .store
+            .find_totp(user_id)
+            .await?
+            .and_then(|credential| credential.enabled_at);
+        Ok(TotpStatus {
+            enabled: enabled_at.is_some(),
+            enabled_at,
+            recovery_codes_remaining: self.store.count_recovery_codes(user_id).await?,
+            required: self.requires_two_factor(&user).await,
+        })
+    }
+
+    /// Start a login at the OpenID Connect provider, returning the URL to
+    /// send the user to
+    pub async fn oidc_authorization_url(&self) -> Result<String, AuthError> {
+        let oidc = self.oidc.as_ref().ok_or(AuthError::OidcNotConfigured)?;
+        let now = Utc::now().timestamp();
+        let (url, login) = oidc.authorization_url(now).await?;
+
+        if let Err(e) = self.store.purge_expired_oidc_logins(now).await {
+            tracing::warn!(error = %e, "Failed to purge expired OpenID Connect logins");
+        }
+        self.store.insert_oidc_login(&login).await?;
+        Ok(url)
+    }
+
+    /// Complete a login from the OpenID Connect provider's callback
+    ///
+    /// The first login of a provider user creates an account linked to
+    /// their identity at the provider. Their roles are mapped from the ID
+    /// token on every login, so changes at the provider apply at the next
+    /// login. The gateway's second factor policy applies as for passwords.
+    pub async fn oidc_authenticate(&self, code: &str, state: &str) -> Result<LoginOutcome, AuthError> {
+        let oidc = self.oidc.as_ref().ok_or(AuthError::OidcNotConfigured)?;
+        let login = self
+            .store
+            .take_oidc_login(state)
+            .await?
+            .ok_or_else(|| AuthError::OidcLogin("unknown or already used login state".to_string()))?;
+        let now = Utc::now().timestamp();
+        if login.expires_at <= now {
+            return Err(AuthError::OidcLogin("login has expired".to_string()));
+        }
+
+        let identity = oidc.exchange(code, &login).await?;
+        let roles = self.mapped_roles(&identity.roles).await?;
+        let user = match self.store.find_identity(&identity.issuer, &identity.subject).await? {
+            Some(user_id) => {
+                let user = self.get_user(&user_id).await?;
+                if user.roles != roles {
+                    self.store.update_roles(&user.id, &roles).await?;
+                }
+                User { roles, ..user }
+            }
+            None => {
+                // Nobody knows the password; the account logs in through the provider
+                let user = self.create_user(&identity.username, &new_refresh_token(), &roles).await?;
+                self.store
+                    .link_identity(&identity.issuer, &identity.subject, &user.id, now)
+                    .await?;
+                tracing::info!(
+                    username = %user.username,
+                    provider = %oidc.provider_name(),
+                    "Linked OpenID Connect identity to new user"
+                );
+                user
+            }
+        };
+
+        if user.is_locked(now) {
+            tracing::warn!(username = %user.username, "Login refused for locked account");
+            return Err(AuthError::AccountLocked);
+        }
+        self.finish_login(&user, now).await
+    }
+
+    /// Drop mapped roles that do not exist, failing if none are left
+    async fn mapped_roles(&self, roles: &[String]) -> Result<Vec<String>, AuthError> {
+        self.reload_roles().await?;
+        let current = self.roles.current();
+
+        let (known, unknown): (Vec<String>, Vec<String>) = roles.iter().cloned().partition(|role| current.contains(role));
+        if !unknown.is_empty() {
+            tracing::warn!(roles = %unknown.join(","), "Ignoring unknown roles mapped from OpenID Connect claims");
+        }
+        if known.is_empty() {
+            return Err(AuthError::OidcLogin("no known role is mapped for this user".to_string()));
+        }
+        Ok(known)
+    }
+
     /// Validate a token
     pub async fn validate(&self, token: &str) -> Result<bool, AuthError> {
-        // Use our simple helper to validate
-        let _ = jwt::validate_jwt(token)?;
+        let _ = self.claims(token).await?;
         Ok(true)
     }
-    
+
     /// Validate a token (alias for compatibility)
     pub async fn validate_token(&self, token: &str) -> Result<bool, AuthError> {
         self.validate(token).await
     }
-    
+
+    /// Verify a JWT or an API key, returning its claims
+    ///
+    /// Fails with `TokenRevoked` when the token or its session was revoked.
+    pub async fn claims(&self, token: &str) -> Result<Claims, AuthError> {
+        if api_keys::is_api_key(token) {
+            self.api_key_claims(token).await
+        } else {
+            self.token_claims(token).await
+        }
+    }
+
+    /// Claims for a client that authenticated with a TLS client certificate
+    ///
+    /// The certificate was verified by the TLS layer and its subject mapped
+    /// to `username`; the user's current roles apply. `jti` and `sid` name
+    /// the certificate subject, and the claims last for one request.
+    pub async fn certificate_claims(&self, username: &str, subject: &str) -> Result<Claims, AuthError> {
+        let user = self
+            .store
+            .find_by_username(username)
+            .await?
+            .ok_or(AuthError::InvalidCredentials)?;
+        let now = Utc::now().timestamp();
+        if user.is_locked(now) {
+            return Err(AuthError::AccountLocked);
+        }
+        let id = format!("mtls:{}", subject);
+        Ok(Claims {
+            sub: user.id,
+            roles: user.roles,
+            iss: self.jwt.issuer.clone(),
+            aud: self.jwt.audience.clone(),
+            username: user.username,
+            tenant: user.tenant,
+            jti: id.clone(),
+            sid: id,
+            scopes: None,
+            iat: now,
+            nbf: now,
+            exp: now + CERTIFICATE_CLAIMS_SECONDS,
+        })
+    }
+
+    /// Decode and verify a JWT, returning its claims
+    async fn token_claims(&self, token: &str) -> Result<Claims, AuthError> {
+        self.sync_signing_keys(KEY_RING_RELOAD_INTERVAL).await;
+
+        let claims = match jwt::decode_token(token, &self.jwt) {
+            // Another instance may have rotated to a key this one has not loaded yet
+            Err(AuthError::InvalidToken) if self.is_unknown_key(token) => {
+                self.sync_signing_keys(UNKNOWN_KEY_RELOAD_INTERVAL).await;
+                jwt::decode_token(token, &self.jwt)?
+            }
+            result => result?,
+        };
+
+        let ids = [
+            token_revocation_id(&claims.jti),
+            session_revocation_id(&claims.sid),
+            user_revocation_id(&claims.sub),
+        ];
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        if self.revocations.any_revoked(&ids, Utc::now().timestamp()).await {
+            return Err(AuthError::TokenRevoked);
+        }
+        Ok(claims)
+    }
+
+    /// Verify an API key, returning claims for its owner limited to its scopes
+    ///
+    /// The owner's current roles apply, and `jti` and `sid` are the key ID.
+    async fn api_key_claims(&self, key: &str) -> Result<Claims, AuthError> {
+        let (id, secret) = api_keys::parse(key).ok_or(AuthError::InvalidToken)?;
+        let record = self.store.find_api_key(id).await?.ok_or(AuthError::InvalidToken)?;
+        if !api_keys::verify_secret(secret, &record.secret_hash) || record.revoked_at.is_some() {
+            return Err(AuthError::InvalidToken);
+        }
+
+        let now = Utc::now().timestamp();
+        if record.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(AuthError::TokenExpired);
+        }
+        let user = self
+            .store
+            .find_by_id(&record.user_id)
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+        if user.is_locked(now) {
+            tracing::warn!(username = %user.username, key_id = %record.id, "API key refused for locked account");
+            return Err(AuthError::AccountLocked);
+        }
+
+        if record
+            .last_used_at
+            .is_none_or(|at| now - at >= LAST_USED_RESOLUTION_SECONDS)
+        {
+            if let Err(e) = self.store.touch_api_key(&record.id, now).await {
+                tracing::warn!(error = %e, key_id = %record.id, "Failed to record API key use");
+            }
+        }
+
+        Ok(Claims {
+            sub: user.id,
+            roles: user.roles,
+            iss: self.jwt.issuer.clone(),
+            aud: self.jwt.audience.clone(),
+            username: user.username,
+            tenant: user.tenant,
+            jti: record.id.clone(),
+            sid: record.id,
+            scopes: Some(record.scopes),
+            iat: record.created_at,
+            nbf: record.created_at,
+            exp: record.expires_at.unwrap_or(i64::MAX),
+        })
+    }
+
+    /// Create an API key for a user, returning it with the full key
+    ///
+    /// The key is only ever returned here; the store keeps a hash of its
+    /// secret. `expires_at` is a Unix timestamp, `None` for a key that does
+    /// not expire.
+    pub async fn create_api_key(
+        &self,
+        user_id: &str,
+        name: &str,
+        scopes: &[impl AsRef<str>],
+        expires_at: Option<i64>,
+    ) -> Result<(ApiKey, String), AuthError> {
+        self.get_user(user_id).await?;
+        if name.trim().is_empty() {
+            return Err(AuthError::InvalidApiKey("name must not be empty".to_string()));
+        }
+        let scopes: Vec<String> = scopes.iter().map(|scope| scope.as_ref().to_string()).collect();
+        if scopes.is_empty() {
+            return Err(AuthError::InvalidApiKey("at least one scope is required".to_string()));
+        }
+        if let Some(scope) = scopes.iter().find(|scope| !permissions::is_valid_permission(scope)) {
+            return Err(AuthError::InvalidApiKey(format!("scope '{}' is not a permission", scope)));
+        }
+        let now = Utc::now().timestamp();
+        if expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(AuthError::InvalidApiKey("expiry must be in the future".to_string()));
+        }
+
+        let (id, secret_hash, key) = api_keys::generate();
+        let record = ApiKey {
+            id,
+            user_id: user_id.to_string(),
+            name: name.trim().to_string(),
+            secret_hash,
+            scopes,
+            created_at: now,
+            expires_at,
+            last_used_at: None,
+            revoked_at: None,
+        };
+        self.store.insert_api_key(&record).await?;
+
+        tracing::info!(user_id = %user_id, key_id = %record.id, name = %record.name, "Created API key");
+        Ok((record, key))
+    }
+
+    /// API keys of a user, revoked ones included, oldest first
+    pub async fn list_api_keys(&self, user_id: &str) -> Result<Vec<ApiKey>, AuthError> {
+        self.get_user(user_id).await?;
+        self.store.list_api_keys(user_id).await
+    }
+
+    /// Revoke one of a user's API keys
+    pub async fn revoke_api_key(&self, user_id: &str, key_id: &str) -> Result<(), AuthError> {
+        let owned = self
+            .store
+            .find_api_key(key_id)
+            .await?
+            .is_some_and(|key| key.user_id == user_id);
+        if !owned || !self.store.revoke_api_key(key_id, Utc::now().timestamp()).await? {
+            return Err(AuthError::ApiKeyNotFound);
+        }
+        tracing::info!(user_id = %user_id, key_id = %key_id, "Revoked API key");
+        Ok(())
+    }
+
+    /// Revoke a single access token before it expires
+    ///
+    /// Revoking a token that is already revoked succeeds. API keys are
+    /// revoked with [`revoke_api_key`](Self::revoke_api_key) instead.
+    pub async fn revoke_token(&self, token: &str) -> Result<(), AuthError> {
+        let claims = match self.token_claims(token).await {
+            Ok(claims) => claims,
+            Err(AuthError::TokenRevoked) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let expires_at = claims.exp + self.jwt.leeway as i64;
+        self.revocations
+            .revoke(&token_revocation_id(&claims.jti), Utc::now().timestamp(), expires_at)
+            .await
+    }
+
+    /// Active sessions of a user, oldest first
+    pub async fn list_sessions(&self, user_id: &str) -> Result<Vec<Session>, AuthError> {
+        self.get_user(user_id).await?;
+        self.store.list_sessions(user_id, Utc::now().timestamp()).await
+    }
+
+    /// End one session of a user, including its outstanding access tokens
+    pub async fn revoke_session(&self, user_id: &str, session_id: &str) -> Result<(), AuthError> {
+        let sessions = self.list_sessions(user_id).await?;
+        if !sessions.iter().any(|session| session.id == session_id) {
+            return Err(AuthError::SessionNotFound);
+        }
+
+        let now = Utc::now().timestamp();
+        self.store.revoke_refresh_token_family(session_id, now).await?;
+        self.revoke_session_tokens(session_id, now).await?;
+        tracing::info!(user_id = %user_id, session_id = %session_id, "Revoked session");
+        Ok(())
+    }
+
+    /// End every session of a user, returning how many were active
+    pub async fn revoke_all_sessions(&self, user_id: &str) -> Result<usize, AuthError> {
+        let sessions = self.list_sessions(user_id).await?;
+        let now = Utc::now().timestamp();
+
+        self.store.revoke_user_refresh_tokens(user_id, now).await?;
+        for session in &sessions {
+            self.revoke_session_tokens(&session.id, now).await?;
+        }
+        tracing::info!(user_id = %user_id, sessions = sessions.len(), "Revoked all sessions");
+        Ok(sessions.len())
+    }
+
+    /// Reject the access tokens of a session until the last one has expired
+    async fn revoke_session_tokens(&self, session_id: &str, now: i64) -> Result<(), AuthError> {
+        self.revocations
+            .revoke(
+                &session_revocation_id(session_id),
+                now,
+                now + self.jwt.max_token_lifetime(),
+            )
+            .await
+    }
+
+    /// Public verification keys in JWKS form
+    pub fn jwks(&self) -> JwkSet {
+        self.jwt.keys.current().jwks()
+    }
+
     /// Check if a user has permission
-    pub async fn check_permission(&self, token: &str, _permission: &str) -> Result<bool, AuthError> {
-        // For testing, we'll always return true if the token is valid
-        self.validate(token).await
+    pub async fn check_permission(&self, token: &str, permission: &str) -> Result<bool, AuthError> {
+        let claims = self.claims(token).await?;
+        Ok(self.has_permission(&claims, permission).await)
     }
-    
-    /// Refresh an existing token
-    pub async fn refresh_token(&self, token: &str) -> Result<String, AuthError> {
-        // Validate the existing token
-        let valid = self.validate(token).await?;
-        
-        if valid {
-            // In a real implementation, this would decode the token and create a new one
-            // We would extract the user ID, username, etc.
-            // For testing, we'll just simulate this
-            let user_id = Uuid::new_v4().to_string();
-            let username = "test_user";
-            
-            jwt::create_jwt(&user_id, username)
-        } else {
-            Err(AuthError::InvalidToken)
+
+    /// Whether the roles in verified claims grant a permission, within
+    /// the scopes of an API key
+    ///
+    /// Roles are resolved when checked, so changes to role definitions apply
+    /// to tokens already issued.
+    pub async fn has_permission(&self, claims: &Claims, permission: &str) -> bool {
+        let in_scope = claims.scopes.as_ref().is_none_or(|scopes| {
+            scopes
+                .iter()
+                .any(|scope| permissions::permission_matches(scope, permission))
+        });
+        if !in_scope {
+            return false;
+        }
+        self.sync_roles(ROLE_RELOAD_INTERVAL).await;
+        self.roles.current().has_permission(&claims.roles, permission)
+    }
+
+    /// Whether verified claims belong to a platform admin, who administers
+    /// every tenant and the gateway itself
+    ///
+    /// Platform admins are the admins of the default tenant; admins of other
+    /// tenants administer their own tenant only.
+    pub async fn is_platform_admin(&self, claims: &Claims) -> bool {
+        claims.tenant == DEFAULT_TENANT && self.has_permission(claims, ADMIN_PERMISSION).await
+    }
+
+    /// All roles ordered by name, with the permissions they grant
+    pub async fn list_roles(&self) -> Result<Vec<Role>, AuthError> {
+        self.reload_roles().await?;
+        Ok(self.roles.current().roles().cloned().collect())
+    }
+
+    /// Look up a role by name
+    pub async fn get_role(&self, name: &str) -> Result<Role, AuthError> {
+        self.reload_roles().await?;
+        self.roles.current().get(name).cloned().ok_or(AuthError::RoleNotFound)
+    }
+
+    /// Create a role or replace a role created earlier
+    ///
+    /// Built-in and configured roles cannot be changed at runtime.
+    pub async fn save_role(&self, role: RoleDefinition) -> Result<Role, AuthError> {
+        if self.roles.is_fixed(&role.name) {
+            return Err(AuthError::RoleReadOnly(role.name));
+        }
+
+        let _reload = self.roles.lock_reload().await;
+        self.load_roles().await?;
+        self.roles.current().check(&role)?;
+        self.store.upsert_role(&role).await?;
+        self.load_roles().await?;
+
+        tracing::info!(role = %role.name, "Saved role");
+        self.roles.current().get(&role.name).cloned().ok_or(AuthError::RoleNotFound)
+    }
+
+    /// Delete a role created at runtime
+    ///
+    /// Roles other roles inherit from cannot be deleted. Users keep the
+    /// name of a deleted role, but it no longer grants anything.
+    pub async fn delete_role(&self, name: &str) -> Result<(), AuthError> {
+        if self.roles.is_fixed(name) {
+            return Err(AuthError::RoleReadOnly(name.to_string()));
+        }
+
+        let _reload = self.roles.lock_reload().await;
+        self.load_roles().await?;
+        let dependents = self.roles.current().dependents(name).join(", ");
+        if !dependents.is_empty() {
+            return Err(AuthError::RoleInUse(dependents));
+        }
+        if !self.store.delete_role(name).await? {
+            return Err(AuthError::RoleNotFound);
+        }
+        self.load_roles().await?;
+
+        tracing::info!(role = %name, "Deleted role");
+        Ok(())
+    }
+
+    /// Load roles created at runtime from the store
+    pub async fn reload_roles(&self) -> Result<(), AuthError> {
+        let _reload = self.roles.lock_reload().await;
+        self.load_roles().await
+    }
+
+    /// Reload roles when they have not been checked within `max_age`
+    async fn sync_roles(&self, max_age: Duration) {
+        if !self.roles.is_stale(max_age) {
+            return;
+        }
+        let _reload = self.roles.lock_reload().await;
+        // Another request may have reloaded while this one waited
+        if !self.roles.is_stale(max_age) {
+            return;
+        }
+
+        if let Err(e) = self.load_roles().await {
+            tracing::warn!(error = %e, "Failed to reload roles; keeping the current roles");
+            self.roles.mark_checked();
+        }
+    }
+
+    /// Callers must hold the role registry's reload lock
+    async fn load_roles(&self) -> Result<(), AuthError> {
+        self.roles.apply(self.store.list_roles().await?);
+        Ok(())
+    }
+
+    /// Check that roles exist, dropping duplicates
+    async fn known_roles(&self, roles: &[impl AsRef<str>]) -> Result<Vec<String>, AuthError> {
+        self.reload_roles().await?;
+        let current = self.roles.current();
+
+        let mut known: Vec<String> = Vec::new();
+        for role in roles.iter().map(AsRef::as_ref) {
+            if !current.contains(role) {
+                return Err(AuthError::InvalidRole);
+            }
+            if !known.iter().any(|name| name == role) {
+                known.push(role.to_string());
+            }
+        }
+        if known.is_empty() {
+            return Err(AuthError::InvalidRole);
+        }
+        Ok(known)
+    }
+
+    /// Exchange a refresh token for a new token pair
+    ///
+    /// Every refresh token can be used once. Presenting a token that was
+    /// already exchanged means it leaked, so its whole family (every token
+    /// descending from the same login) is revoked. Locked accounts cannot
+    /// refresh; their token stays usable once the lock expires.
+    pub async fn refresh_token(&self, refresh_token: &str) -> Result<TokenPair, AuthError> {
+        let token_hash = hash_refresh_token(refresh_token);
+        let record = self
+            .store
+            .find_refresh_token(&token_hash)
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+
+        let now = Utc::now().timestamp();
+        if record.revoked_at.is_some() {
+            return Err(AuthError::InvalidToken);
+        }
+        if record.used_at.is_some() {
+            return Err(self.revoke_reused_family(&record, now).await);
+        }
+        if record.expires_at <= now {
+            return Err(AuthError::TokenExpired);
+        }
+
+        let user = self
+            .store
+            .find_by_id(&record.user_id)
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+        if user.is_locked(now) {
+            tracing::warn!(username = %user.username, "Token refresh refused for locked account");
+            return Err(AuthError::AccountLocked);
+        }
+
+        if !self.store.mark_refresh_token_used(&token_hash, now).await? {
+            // Lost a race with a concurrent exchange of the same token
+            return Err(self.revoke_reused_family(&record, now).await);
+        }
+        self.issue_tokens(&user, record.family_id).await
+    }
+
+    /// Revoke the family of a reused refresh token, returning the error to report
+    async fn revoke_reused_family(&self, record: &RefreshTokenRecord, now: i64) -> AuthError {
+        let revoked = match self.store.revoke_refresh_token_family(&record.family_id, now).await {
+            Ok(revoked) => revoked,
+            Err(e) => return e,
+        };
+        match self.revoke_session_tokens(&record.family_id, now).await {
+            Ok(()) => {
+                tracing::warn!(
+                    user_id = %record.user_id,
+                    family_id = %record.family_id,
+                    revoked,
+                    "Refresh token reuse detected; revoked token family"
+                );
+                AuthError::InvalidToken
+            }
+            Err(e) => e,
+        }
+    }
+
+    /// Revoke the session a refresh token belongs to, including its access tokens,
+    /// returning the ID of the session's user
+    ///
+    /// Unknown tokens are ignored so logout is idempotent.
+    pub async fn logout(&self, refresh_token: &str) -> Result<Option<String>, AuthError> {
+        let Some(record) = self.store.find_refresh_token(&hash_refresh_token(refresh_token)).await? else {
+            return Ok(None);
+        };
+        let now = Utc::now().timestamp();
+        self.store.revoke_refresh_token_family(&record.family_id, now).await?;
+        self.revoke_session_tokens(&record.family_id, now).await?;
+        Ok(Some(record.user_id))
+    }
+
+    /// Start signing tokens with a new random secret
+    ///
+    /// The previous key keeps verifying tokens until every token it signed
+    /// has expired, so rotation does not log anyone out. Other gateway
+    /// instances pick the new key up on their next reload.
+    ///
+    /// The new secret is stored encrypted with the master key when one is
+    /// configured.
+    pub async fn rotate_signing_key(&self) -> Result<String, AuthError> {
+        let base = self.jwt.keys.base();
+        if !base.uses_shared_secret() {
+            return Err(AuthError::Configuration(
+                "auth.jwt_keys is configured; rotate asymmetric keys by adding one and setting auth.jwt_signing_kid"
+                    .to_string(),
+            ));
+        }
+
+        let _reload = self.jwt.keys.lock_reload().await;
+        let now = Utc::now().timestamp();
+        let configured = base.signing_key();
+        let algorithm = format!("{:?}", configured.algorithm);
+
+        // Mark the configured secret as retired so it expires like rotated keys
+        let records = self.store.list_signing_keys().await?;
+        if !records.iter().any(|record| record.kid == configured.kid) {
+            self.store
+                .insert_signing_key(&SigningKeyRecord {
+                    kid: configured.kid.clone(),
+                    algorithm: algorithm.clone(),
+                    secret: None,
+                    created_at: now,
+                    retired_at: Some(now),
+                })
+                .await?;
+        }
+
+        let kid = format!(
+            "{}-{}",
+            Utc::now().format("%Y%m%d%H%M%S"),
+            &Uuid::new_v4().simple().to_string()[..8]
+        );
+        let mut secret = [0u8; 64];
+        rand::rngs::OsRng.fill_bytes(&mut secret);
+        let secret = sealed(&hex::encode(secret), "rotated signing key")?;
+
+        // Insert before retiring so there is always a key to sign with
+        self.store
+            .insert_signing_key(&SigningKeyRecord {
+                kid: kid.clone(),
+                algorithm,
+                secret: Some(secret),
+                created_at: now,
+                retired_at: None,
+            })
+            .await?;
+        self.store.retire_signing_keys(&kid, now).await?;
+        self.load_signing_keys().await?;
+
+        tracing::info!(kid = %kid, "Rotated JWT signing key");
+        Ok(kid)
+    }
+
+    /// Keys currently used to sign and verify tokens
+    pub async fn signing_keys(&self) -> Result<Vec<SigningKeyInfo>, AuthError> {
+        self.reload_signing_keys().await?;
+
+        let keys = self.jwt.keys.current();
+        let signing_kid = &keys.signing_key().kid;
+        Ok(keys
+            .keys()
+            .iter()
+            .map(|key| SigningKeyInfo {
+                kid: key.kid.clone(),
+                algorithm: format!("{:?}", key.algorithm),
+                signing: &key.kid == signing_kid,
+                retires_at: self.jwt.keys.retires_at(&key.kid),
+            })
+            .collect())
+    }
+
+    /// Load rotated keys from the store, erasing those past retirement
+    pub async fn reload_signing_keys(&self) -> Result<(), AuthError> {
+        let _reload = self.jwt.keys.lock_reload().await;
+        self.load_signing_keys().await
+    }
+
+    /// Reload the key ring when it has not been checked within `max_age`
+    async fn sync_signing_keys(&self, max_age: Duration) {
+        if !self.jwt.keys.is_stale(max_age) {
+            return;
+        }
+        let _reload = self.jwt.keys.lock_reload().await;
+        // Another request may have reloaded while this one waited
+        if !self.jwt.keys.is_stale(max_age) {
+            return;
+        }
+
+        if let Err(e) = self.load_signing_keys().await {
+            tracing::warn!(error = %e, "Failed to reload JWT signing keys; keeping the current keys");
+            self.jwt.keys.mark_checked();
+        }
+    }
+
+    /// Callers must hold the key ring's reload lock
+    async fn load_signing_keys(&self) -> Result<(), AuthError> {
+        let now = Utc::now().timestamp();
+        let lifetime = self.jwt.max_token_lifetime();
+
+        self.store.erase_retired_signing_keys(now - lifetime).await?;
+        let records = self.store.list_signing_keys().await?;
+        self.jwt.keys.apply(&records, lifetime, now)
+    }
+
+    /// Whether a token names a key that is not loaded
+    fn is_unknown_key(&self, token: &str) -> bool {
+        jwt::key_id(token).is_some_and(|kid| self.jwt.keys.current().verification_key(Some(&kid)).is_none())
+    }
+
+    /// Issue an access token and a refresh token in the given family
+    ///
+    /// The family ID doubles as the session ID carried in the access token.
+    async fn issue_tokens(&self, user: &User, family_id: String) -> Result<TokenPair, AuthError> {
+        self.sync_signing_keys(KEY_RING_RELOAD_INTERVAL).await;
+        let access_token = jwt::generate_token(&self.jwt, &user.id, &user.roles, &user.username, &user.tenant, &family_id)?;
+        let refresh_token = new_refresh_token();
+        let now = Utc::now().timestamp();
+
+        self.store
+            .insert_refresh_token(&RefreshTokenRecord {
+                token_hash: hash_refresh_token(&refresh_token),
+                user_id: user.id.clone(),
+                family_id,
+                expires_at: now + self.jwt.refresh_token_expiry as i64,
+                created_at: now,
+                used_at: None,
+                revoked_at: None,
+            })
+            .await?;
+
+        Ok(TokenPair {
+            access_token,
+            refresh_token,
+            expires_in: self.jwt.access_token_expiry,
+        })
+    }
+
+    /// Create a user account in the default tenant holding one or more roles
+    pub async fn create_user(
+        &self,
+        username: &str,
+        password: &str,
+        roles: &[impl AsRef<str>],
+    ) -> Result<User, AuthError> {
+        self.create_tenant_user(DEFAULT_TENANT, username, password, roles).await
+    }
+
+    /// Create a user account in a tenant holding one or more roles
+    pub async fn create_tenant_user(
+        &self,
+        tenant: &str,
+        username: &str,
+        password: &str,
+        roles: &[impl AsRef<str>],
+    ) -> Result<User, AuthError> {
+        if username.trim().is_empty() {
+            return Err(AuthError::InvalidCredentials);
+        }
+        tenants::validate_tenant(tenant)?;
+        let roles = self.known_roles(roles).await?;
+        password::validate_password(password)?;
+
+        let user = User {
+            id: Uuid::new_v4().to_string(),
+            username: username.trim().to_string(),
+            password_hash: hash_blocking(password).await?,
+            roles,
+            tenant: tenant.to_string(),
+            failed_attempts: 0,
+            locked_until: None,
+            last_login: None,
+            created_at: Utc::now().timestamp(),
+        };
+
+        self.store.create_user(&user).await?;
+        tracing::info!(username = %user.username, tenant = %user.tenant, roles = %user.roles.join(","), "Created user");
+        Ok(user)
+    }
+
+    /// Look up a user by ID
+    pub async fn get_user(&self, id: &str) -> Result<User, AuthError> {
+        self.store.find_by_id(id).await?.ok_or(AuthError::UserNotFound)
+    }
+
+    /// Look up a user by username
+    pub async fn get_user_by_username(&self, username: &str) -> Result<User, AuthError> {
+        self.store
+            .find_by_username(username)
+            .await?
+            .ok_or(AuthError::UserNotFound)
+    }
+
+    /// All users ordered by username
+    pub async fn list_users(&self) -> Result<Vec<User>, AuthError> {
+        self.store.list_users().await
+    }
+
+    /// Set a new password, which also clears any lockout and ends all
+    /// sessions
+    pub async fn change_password(&self, id: &str, password: &str) -> Result<(), AuthError> {
+        password::validate_password(password)?;
+        let hash = hash_blocking(password).await?;
+        self.store.update_password(id, &hash).await?;
+        self.revoke_all_sessions(id).await?;
+        Ok(())
+    }
+
+    /// Replace a user's roles, ending all sessions so no token keeps the old ones
+    pub async fn change_roles(&self, id: &str, roles: &[impl AsRef<str>]) -> Result<(), AuthError> {
+        let roles = self.known_roles(roles).await?;
+        self.store.update_roles(id, &roles).await?;
+        self.revoke_all_sessions(id).await?;
+        Ok(())
+    }
+
+    /// Delete a user account, ending its sessions and outstanding access tokens
+    pub async fn delete_user(&self, id: &str) -> Result<(), AuthError> {
+        self.revoke_all_sessions(id).await?;
+        if !self.store.delete_user(id).await? {
+            return Err(AuthError::UserNotFound);
+        }
+
+        // Access tokens outside any listed session must not outlive the account either
+        let now = Utc::now().timestamp();
+        self.revocations
+            .revoke(&user_revocation_id(id), now, now + self.jwt.max_token_lifetime())
+            .await
+    }
+}
+
+/// Revocation entry for a single access token
+fn token_revocation_id(jti: &str) -> String {
+    format!("jti:{}", jti)
+}
+
+/// Revocation entry for every access token of a session
+fn session_revocation_id(sid: &str) -> String {
+    format!("sid:{}", sid)
+}
+
+/// Revocation entry for every access token of a deleted user
+fn user_revocation_id(user_id: &str) -> String {
+    format!("user:{}", user_id)
+}
+
+/// Random opaque refresh token
+fn new_refresh_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Refresh tokens are stored as SHA-256 hashes; they are high-entropy so no salt is needed
+fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// A generated secret as it is stored: encrypted with the master key, or
+/// as is when none is configured
+fn sealed(secret: &str, what: &str) -> Result<Secret, AuthError> {
+    match MasterKey::configured().map_err(|e| AuthError::Configuration(e.to_string()))? {
+        Some(master_key) => Secret::encrypt(secret, &master_key).map_err(|e| AuthError::Configuration(e.to_string())),
+        None => {
+            tracing::warn!("No master key is configured; the {} is stored unencrypted", what);
+            Ok(Secret::from(secret))
         }
     }
 }
+
+/// Hash a password off the async runtime
+async fn hash_blocking(password: &str) -> Result<String, AuthError> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || password::hash_password(&password))
+        .await
+        .map_err(|e| AuthError::Unknown(e.to_string()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::SqliteStore;
+
+    fn service(max_failed_attempts: u32) -> AuthService {
+        let lockout = LockoutSettings {
+            max_failed_attempts,
+            lockout_seconds: 60,
+        };
+        AuthService::new(
+            Arc::new(SqliteStore::in_memory().unwrap()),
+            JwtConfig::for_tests(),
+            lockout,
+        )
+    }
+
+    fn credentials(username: &str, password: &str) -> Credentials {
+        Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    /// Tokens of a login that needed no second factor
+    fn authenticated(outcome: LoginOutcome) -> (UserInfo, TokenPair) {
+        match outcome {
+            LoginOutcome::Authenticated(info, tokens) => (info, tokens),
+            LoginOutcome::SecondFactorRequired(_) => panic!("unexpected second factor challenge"),
+        }
+    }
+
+    /// Password login for users without a second factor
+    async fn login(service: &AuthService, username: &str, password: &str) -> Result<(UserInfo, TokenPair), AuthError> {
+        Ok(authenticated(service.authenticate(credentials(username, password)).await?))
+    }
+
+    /// Code and state the provider redirects back to the callback with
+    async fn oidc_callback(service: &AuthService) -> (String, String) {
+        let browser = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let url = service.oidc_authorization_url().await.unwrap();
+        let response = browser.get(url).send().await.unwrap();
+        let location = reqwest::Url::parse(response.headers()["location"].to_str().unwrap()).unwrap();
+        let params: std::collections::HashMap<_, _> = location.query_pairs().into_owned().collect();
+        (params["code"].clone(), params["state"].clone())
+    }
+
+    /// Challenge token of a login that needs a second factor
+    async fn challenge(service: &AuthService, username: &str, password: &str) -> TwoFactorChallenge {
+        match service.authenticate(credentials(username, password)).await.unwrap() {
+            LoginOutcome::SecondFactorRequired(challenge) => challenge,
+            LoginOutcome::Authenticated(..) => panic!("expected a second factor challenge"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_enforces_credentials() {
+        let service = service(5);
+        let user = service.create_user("dave", "correct-password", &["readonly"]).await.unwrap();
+
+        let (info, tokens) = login(&service, "dave", "correct-password").await.unwrap();
+        assert_eq!(info.id, user.id);
+        let claims = service.claims(&tokens.access_token).await.unwrap();
+        assert_eq!(claims.sub, user.id);
+        assert_eq!(claims.roles, vec!["readonly"]);
+
+        assert!(matches!(
+            login(&service, "dave", "wrong-password").await,
+            Err(AuthError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            login(&service, "nobody", "correct-password").await,
+            Err(AuthError::InvalidCredentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_lockout_after_repeated_failures() {
+        let service = service(2);
+        let user = service.create_user("erin", "correct-password", &["user"]).await.unwrap();
+
+        let (_, tokens) = login(&service, "erin", "correct-password").await.unwrap();
+        let (_, key) = service.create_api_key(&user.id, "ci", &["agent:read"], None).await.unwrap();
+        for _ in 0..2 {
+            assert!(login(&service, "erin", "wrong-password").await.is_err());
+        }
+        assert!(matches!(
+            login(&service, "erin", "correct-password").await,
+            Err(AuthError::AccountLocked)
+        ));
+        // A session started before the lock cannot be extended while it lasts
+        assert!(matches!(
+            service.refresh_token(&tokens.refresh_token).await,
+            Err(AuthError::AccountLocked)
+        ));
+        // API keys act for their owner, so the lock applies to them too
+        assert!(matches!(service.claims(&key).await, Err(AuthError::AccountLocked)));
+
+        // Resetting the password clears the lock
+        service.change_password(&user.id, "another-password").await.unwrap();
+        assert!(login(&service, "erin", "another-password").await.is_ok());
+        assert!(service.claims(&key).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_create_user_validation() {
+        let service = service(5);
+        assert!(matches!(
+            service.create_user("frank", "short", &["user"]).await,
+            Err(AuthError::WeakPassword(_))
+        ));
+        assert!(matches!(
+            service.create_user("frank", "long-enough", &["superuser"]).await,
+            Err(AuthError::InvalidRole)
+        ));
+        service.create_user("frank", "long-enough", &["user"]).await.unwrap();
+        assert!(matches!(
+            service.create_user("frank", "long-enough", &["user"]).await,
+            Err(AuthError::UserExists(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rotation_and_reuse_detection() {
+        let service = service(5);
+        service.create_user("gail", "correct-password", &["user"]).await.unwrap();
+        let (_, first) = login(&service, "gail", "correct-password").await.unwrap();
+
+        let second = service.refresh_token(&first.refresh_token).await.unwrap();
+        assert_ne!(second.refresh_token, first.refresh_token);
+        assert!(service.claims(&second.access_token).await.is_ok());
+
+        // Replaying the rotated token revokes the whole family
+        assert!(matches!(
+            service.refresh_token(&first.refresh_token).await,
+            Err(AuthError::InvalidToken)
+        ));
+        assert!(matches!(
+            service.refresh_token(&second.refresh_token).await,
+            Err(AuthError::InvalidToken)
+        ));
+
+        // Other sessions are unaffected until logout
+        let (_, other) = login(&service, "gail", "correct-password").await.unwrap();
+        service.logout(&other.refresh_token).await.unwrap();
+        assert!(service.refresh_token(&other.refresh_token).await.is_err());
+        assert!(service.logout("unknown-token").await.is_ok());
+    }
+
+    /// Configure the master key; every test setting it sets the same one,
+    /// since the environment is shared by all of them
+    fn set_master_key() {
+        std::env::set_var(common::secret::MASTER_KEY_ENV, "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=");
+    }
+
+    #[tokio::test]
+    async fn test_signing_key_rotation_keeps_tokens_valid() {
+        set_master_key();
+        let store: Arc<dyn AuthStore> = Arc::new(SqliteStore::in_memory().unwrap());
+        let first = AuthService::new(store.clone(), JwtConfig::for_tests(), LockoutSettings::default());
+        let second = AuthService::new(store.clone(), JwtConfig::for_tests(), LockoutSettings::default());
+        first.create_user("erin", "correct-password", &["user"]).await.unwrap();
+
+        let (_, before) = login(&first, "erin", "correct-password").await.unwrap();
+        assert!(second.claims(&before.access_token).await.is_ok());
+
+        let kid = first.rotate_signing_key().await.unwrap();
+        let (_, after) = login(&first, "erin", "correct-password").await.unwrap();
+        assert_eq!(jwt::key_id(&after.access_token), Some(kid.clone()));
+
+        // The new secret is stored encrypted and redacted from debug output
+        let rotated = store.list_signing_keys().await.unwrap().into_iter().find(|record| record.kid == kid).unwrap();
+        let secret = rotated.secret.unwrap();
+        assert!(secret.source().starts_with("enc:"));
+        assert!(!format!("{:?}", secret).contains(secret.expose()));
+
+        // Tokens signed before the rotation stay valid on every instance
+        assert!(first.claims(&before.access_token).await.is_ok());
+        assert!(second.claims(&before.access_token).await.is_ok());
+
+        // The other instance loads the new key when it first sees it
+        tokio::time::sleep(UNKNOWN_KEY_RELOAD_INTERVAL).await;
+        assert!(second.claims(&after.access_token).await.is_ok());
+
+        let keys = second.signing_keys().await.unwrap();
+        assert_eq!(keys.len(), 2);
+        assert!(keys.iter().any(|key| key.kid == kid && key.signing && key.retires_at.is_none()));
+        assert!(keys.iter().any(|key| key.kid == "default" && !key.signing && key.retires_at.is_some()));
+
+        // Once a retired key outlives the longest token lifetime it is dropped
+        let retired_at = Utc::now().timestamp() - first.jwt.max_token_lifetime() - 1;
+        store
+            .insert_signing_key(&SigningKeyRecord {
+                kid: "expired".to_string(),
+                algorithm: "HS256".to_string(),
+                secret: Some("expired-secret".into()),
+                created_at: retired_at,
+                retired_at: Some(retired_at),
+            })
+            .await
+            .unwrap();
+        assert!(first.signing_keys().await.unwrap().iter().all(|key| key.kid != "expired"));
+        let erased = store.list_signing_keys().await.unwrap();
+        assert!(erased.iter().any(|record| record.kid == "expired" && record.secret.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_session_and_token_revocation() {
+        let store: Arc<dyn AuthStore> = Arc::new(SqliteStore::in_memory().unwrap());
+        let service = AuthService::new(store.clone(), JwtConfig::for_tests(), LockoutSettings::default());
+        let user = service.create_user("ivan", "correct-password", &["user"]).await.unwrap();
+
+        let (_, laptop) = login(&service, "ivan", "correct-password").await.unwrap();
+        let (_, phone) = login(&service, "ivan", "correct-password").await.unwrap();
+        let sessions = service.list_sessions(&user.id).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+
+        // Revoking one session kills its access and refresh tokens only
+        let laptop_sid = service.claims(&laptop.access_token).await.unwrap().sid;
+        service.revoke_session(&user.id, &laptop_sid).await.unwrap();
+        assert!(matches!(service.claims(&laptop.access_token).await, Err(AuthError::TokenRevoked)));
+        assert!(service.refresh_token(&laptop.refresh_token).await.is_err());
+        assert!(service.claims(&phone.access_token).await.is_ok());
+        assert!(matches!(
+            service.revoke_session(&user.id, &laptop_sid).await,
+            Err(AuthError::SessionNotFound)
+        ));
+
+        // A single token can be revoked without ending its session
+        let rotated = service.refresh_token(&phone.refresh_token).await.unwrap();
+        service.revoke_token(&phone.access_token).await.unwrap();
+        service.revoke_token(&phone.access_token).await.unwrap();
+        assert!(matches!(service.claims(&phone.access_token).await, Err(AuthError::TokenRevoked)));
+        assert!(service.claims(&rotated.access_token).await.is_ok());
+
+        // Revocations are persisted, so other instances see them
+        let other = AuthService::new(store, JwtConfig::for_tests(), LockoutSettings::default());
+        assert!(matches!(other.claims(&laptop.access_token).await, Err(AuthError::TokenRevoked)));
+
+        assert_eq!(service.revoke_all_sessions(&user.id).await.unwrap(), 1);
+        assert!(matches!(service.claims(&rotated.access_token).await, Err(AuthError::TokenRevoked)));
+        assert!(service.list_sessions(&user.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_role_change_ends_sessions() {
+        let service = service(3);
+        let user = service.create_user("judy", "correct-password", &["admin"]).await.unwrap();
+        let (_, before) = login(&service, "judy", "correct-password").await.unwrap();
+
+        // A demoted user's tokens do not keep the admin role
+        service.change_roles(&user.id, &["user"]).await.unwrap();
+        assert!(matches!(service.claims(&before.access_token).await, Err(AuthError::TokenRevoked)));
+        assert!(service.refresh_token(&before.refresh_token).await.is_err());
+        let (_, after) = login(&service, "judy", "correct-password").await.unwrap();
+        assert_eq!(service.claims(&after.access_token).await.unwrap().roles, vec!["user".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_runtime_roles_apply_across_instances() {
+        let store: Arc<dyn AuthStore> = Arc::new(SqliteStore::in_memory().unwrap());
+        let service = AuthService::new(store.clone(), JwtConfig::for_tests(), LockoutSettings::default());
+        let other = AuthService::new(store, JwtConfig::for_tests(), LockoutSettings::default());
+        let role = |name: &str, permissions: &[&str], inherits: &[&str]| RoleDefinition {
+            name: name.to_string(),
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            inherits: inherits.iter().map(|r| r.to_string()).collect(),
+        };
+
+        let support = service.save_role(role("support", &["user:*"], &["readonly"])).await.unwrap();
+        assert!(support.effective_permissions.contains(&"agent:read".to_string()));
+        assert!(matches!(
+            service.save_role(role("admin", &[], &[])).await,
+            Err(AuthError::RoleReadOnly(_))
+        ));
+        assert!(matches!(
+            service.save_role(role("broken", &[], &["missing"])).await,
+            Err(AuthError::InvalidRoleDefinition(_))
+        ));
+
+        // Another instance sees the role as soon as it is assigned
+        other
+            .create_user("judy", "correct-password", &["support", "user", "support"])
+            .await
+            .unwrap();
+        let (info, tokens) = login(&other, "judy", "correct-password").await.unwrap();
+        assert_eq!(info.roles, vec!["support", "user"]);
+        let claims = service.claims(&tokens.access_token).await.unwrap();
+        assert!(service.has_permission(&claims, "user:delete").await);
+        assert!(service.has_permission(&claims, "agent:write").await);
+        assert!(!service.has_permission(&claims, "system:admin").await);
+
+        // Redefining a role changes what existing tokens grant
+        service.save_role(role("support", &["user:read"], &[])).await.unwrap();
+        assert!(!service.has_permission(&claims, "user:delete").await);
+
+        service.save_role(role("lead", &[], &["support"])).await.unwrap();
+        assert!(matches!(service.delete_role("support").await, Err(AuthError::RoleInUse(_))));
+        service.delete_role("lead").await.unwrap();
+        service.delete_role("support").await.unwrap();
+        assert!(matches!(service.delete_role("support").await, Err(AuthError::RoleNotFound)));
+        assert!(matches!(service.delete_role("readonly").await, Err(AuthError::RoleReadOnly(_))));
+        // The user keeps the permissions of their remaining role
+        assert!(service.has_permission(&claims, "agent:write").await);
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_act_for_owner_within_scopes() {
+        let service = service(5);
+        let user = service.create_user("kim", "correct-password", &["user"]).await.unwrap();
+
+        assert!(matches!(
+            service.create_api_key(&user.id, "ci", &["agent"], None).await,
+            Err(AuthError::InvalidApiKey(_))
+        ));
+        assert!(matches!(
+            service.create_api_key(&user.id, "ci", &[] as &[&str], None).await,
+            Err(AuthError::InvalidApiKey(_))
+        ));
+
+        let (record, key) = service
+            .create_api_key(&user.id, "ci", &["agent:*", "system:*"], None)
+            .await
+            .unwrap();
+        assert!(key.starts_with("nxk_") && key.contains(&record.id));
+
+        let claims = service.claims(&key).await.unwrap();
+        assert_eq!((claims.sub.as_str(), claims.username.as_str()), (user.id.as_str(), "kim"));
+        assert!(service.has_permission(&claims, "agent:write").await);
+        // Scopes narrow the owner's permissions but never widen them
+        assert!(!service.has_permission(&claims, "user:read").await);
+        assert!(!service.has_permission(&claims, "system:admin").await);
+        assert!(service.list_api_keys(&user.id).await.unwrap()[0].last_used_at.is_some());
+
+        // A wrong secret, an expired key or a revoked key is rejected
+        let (id, _) = api_keys::parse(&key).unwrap();
+        let forged = format!("nxk_{}_{}", id, "0".repeat(64));
+        assert!(matches!(service.claims(&forged).await, Err(AuthError::InvalidToken)));
+
+        let (expiring, expiring_key) = service
+            .create_api_key(&user.id, "short", &["agent:read"], Some(Utc::now().timestamp() + 1))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(matches!(service.claims(&expiring_key).await, Err(AuthError::TokenExpired)));
+
+        service.revoke_api_key(&user.id, &record.id).await.unwrap();
+        assert!(matches!(service.claims(&key).await, Err(AuthError::InvalidToken)));
+        assert!(matches!(
+            service.revoke_api_key(&user.id, &record.id).await,
+            Err(AuthError::ApiKeyNotFound)
+        ));
+        let other = service.create_user("lee", "correct-password", &["user"]).await.unwrap();
+        assert!(matches!(
+            service.revoke_api_key(&other.id, &expiring.id).await,
+            Err(AuthError::ApiKeyNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login_creates_and_updates_linked_user() {
+        use crate::oidc::mock::MockProvider;
+
+        let provider = MockProvider::start("gateway", "client-secret").await;
+        let mut settings = provider.settings("http://gateway.test/api/oauth/callback");
+        settings.role_mapping.insert("ops".to_string(), vec!["admin".to_string(), "missing".to_string()]);
+        let service = service(5).with_oidc(OidcClient::new(&settings, 0).unwrap());
+        let login = || oidc_callback(&service);
+
+        provider.set_claims(serde_json::json!({ "sub": "u-1", "preferred_username": "olga", "groups": ["ops"] }));
+        let (code, state) = login().await;
+        let (info, tokens) = authenticated(service.oidc_authenticate(&code, &state).await.unwrap());
+        assert_eq!((info.username.as_str(), info.roles.clone()), ("olga", vec!["admin".to_string()]));
+        let claims = service.claims(&tokens.access_token).await.unwrap();
+        assert_eq!(claims.sub, info.id);
+
+        // A callback is accepted once
+        assert!(matches!(
+            service.oidc_authenticate(&code, &state).await,
+            Err(AuthError::OidcLogin(_))
+        ));
+
+        // The same identity logs into the same account, with roles from the latest token
+        provider.set_claims(serde_json::json!({ "sub": "u-1", "preferred_username": "renamed", "groups": [] }));
+        let (code, state) = login().await;
+        let (again, _) = authenticated(service.oidc_authenticate(&code, &state).await.unwrap());
+        assert_eq!(again.id, info.id);
+        assert_eq!(service.get_user(&info.id).await.unwrap().roles, vec!["readonly"]);
+
+        // A new identity cannot take over a local account's username
+        service.create_user("local", "correct-password", &["user"]).await.unwrap();
+        provider.set_claims(serde_json::json!({ "sub": "u-2", "preferred_username": "local" }));
+        let (code, state) = login().await;
+        assert!(matches!(
+            service.oidc_authenticate(&code, &state).await,
+            Err(AuthError::UserExists(_))
+        ));
+
+        let unconfigured = self::service(5);
+        assert!(matches!(
+            unconfigured.oidc_authorization_url().await,
+            Err(AuthError::OidcNotConfigured)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login_applies_second_factor_policy() {
+        use crate::oidc::mock::MockProvider;
+
+        let provider = MockProvider::start("gateway", "client-secret").await;
+        let mut settings = provider.settings("http://gateway.test/api/oauth/callback");
+        settings.role_mapping.insert("ops".to_string(), vec!["admin".to_string()]);
+        let service = service(5)
+            .with_oidc(OidcClient::new(&settings, 0).unwrap())
+            .with_two_factor(TwoFactorSettings {
+                require_for_admins: true,
+                ..Default::default()
+            });
+
+        // An admin without a second factor must enroll before getting tokens
+        provider.set_claims(serde_json::json!({ "sub": "u-1", "preferred_username": "pia", "groups": ["ops"] }));
+        let (code, state) = oidc_callback(&service).await;
+        let pending = match service.oidc_authenticate(&code, &state).await.unwrap() {
+            LoginOutcome::SecondFactorRequired(challenge) => challenge,
+            LoginOutcome::Authenticated(..) => panic!("expected a second factor challenge"),
+        };
+        assert!(pending.enrollment_required);
+        let enrollment = service.start_login_enrollment(&pending.challenge_token).await.unwrap();
+        let now = Utc::now().timestamp();
+        let code = totp::code_for(&enrollment.secret, now);
+        let (info, _) = service.complete_login(&pending.challenge_token, &code).await.unwrap();
+        assert_eq!(info.username, "pia");
+
+        // Once enrolled, the code is asked for even without admin roles
+        provider.set_claims(serde_json::json!({ "sub": "u-1", "preferred_username": "pia", "groups": [] }));
+        let (code, state) = oidc_callback(&service).await;
+        assert!(matches!(
+            service.oidc_authenticate(&code, &state).await.unwrap(),
+            LoginOutcome::SecondFactorRequired(TwoFactorChallenge { enrollment_required: false, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_totp_enrollment_and_login() {
+        let service = service(3).with_two_factor(TwoFactorSettings {
+            require_for_admins: true,
+            ..Default::default()
+        });
+        let user = service.create_user("mia", "correct-password", &["user"]).await.unwrap();
+        assert!(login(&service, "mia", "correct-password").await.is_ok());
+
+        // Enrollment only takes effect once confirmed
+        set_master_key();
+        let enrollment = service.enroll_totp(&user.id).await.unwrap();
+        assert!(enrollment.provisioning_uri.contains(&enrollment.secret));
+        // The seed is stored encrypted, not as its base32 self
+        let stored = service.store.find_totp(&user.id).await.unwrap().unwrap().secret;
+        assert!(stored.source().starts_with("enc:"));
+        assert!(!stored.source().contains(&enrollment.secret));
+        assert_eq!(stored.expose(), enrollment.secret);
+        assert_eq!(enrollment.recovery_codes.len(), totp::RECOVERY_CODE_COUNT);
+        assert!(login(&service, "mia", "correct-password").await.is_ok());
+        let now = Utc::now().timestamp();
+        assert!(matches!(
+            service.confirm_totp(&user.id, "000000x").await,
+            Err(AuthError::InvalidTwoFactorCode)
+        ));
+        service.confirm_totp(&user.id, &totp::code_for(&enrollment.secret, now)).await.unwrap();
+        assert!(matches!(service.enroll_totp(&user.id).await, Err(AuthError::TwoFactorAlreadyEnabled)));
+
+        // The password alone now yields a challenge, and a code cannot be replayed
+        let pending = challenge(&service, "mia", "correct-password").await;
+        assert!(!pending.enrollment_required);
+        assert!(matches!(
+            service.complete_login(&pending.challenge_token, &totp::code_for(&enrollment.secret, now)).await,
+            Err(AuthError::InvalidTwoFactorCode)
+        ));
+        let next = totp::code_for(&enrollment.secret, now + totp::TOTP_STEP_SECONDS);
+        let (info, tokens) = service.complete_login(&pending.challenge_token, &next).await.unwrap();
+        assert_eq!(info.id, user.id);
+        assert!(service.claims(&tokens.access_token).await.is_ok());
+        assert!(matches!(
+            service.complete_login(&pending.challenge_token, &enrollment.recovery_codes[0]).await,
+            Err(AuthError::TokenRevoked)
+        ));
+
+        // Recovery codes work once each, and challenges are not access tokens
+        let pending = challenge(&service, "mia", "correct-password").await;
+        assert!(service.claims(&pending.challenge_token).await.is_err());
+        let recovery = enrollment.recovery_codes[0].to_uppercase();
+        service.complete_login(&pending.challenge_token, &recovery).await.unwrap();
+        let pending = challenge(&service, "mia", "correct-password").await;
+        assert!(service.complete_login(&pending.challenge_token, &recovery).await.is_err());
+        let status = service.totp_status(&user.id).await.unwrap();
+        assert!(status.enabled && !status.required);
+        assert_eq!(status.recovery_codes_remaining, totp::RECOVERY_CODE_COUNT as u64 - 1);
+
+        service.disable_totp(&user.id).await.unwrap();
+        assert!(login(&service, "mia", "correct-password").await.is_ok());
+
+        // Admins must enroll during login before getting tokens
+        service.create_user("nico", "correct-password", &["admin"]).await.unwrap();
+        let pending = challenge(&service, "nico", "correct-password").await;
+        assert!(pending.enrollment_required);
+        assert!(matches!(
+            service.complete_login(&pending.challenge_token, "123456").await,
+            Err(AuthError::TwoFactorNotEnrolled)
+        ));
+        let enrollment = service.start_login_enrollment(&pending.challenge_token).await.unwrap();
+        let code = totp::code_for(&enrollment.secret, Utc::now().timestamp());
+        let (info, _) = service.complete_login(&pending.challenge_token, &code).await.unwrap();
+        assert!(service.totp_status(&info.id).await.unwrap().enabled);
+    }
+}
@@ -0,0 +1,271 @@
+//! User accounts and their login failure tracking
+
+use super::{join_names, map_insert_error, split_names, PgStore, SqliteStore};
+use crate::error::AuthError;
+use crate::AuthResult;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+const USER_COLUMNS: &str =
+    "id, username, password_hash, role, tenant, failed_attempts, locked_until, last_login, created_at";
+
+/// A stored user account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    /// User ID
+    pub id: String,
+    /// Unique login name
+    pub username: String,
+    /// Argon2id PHC hash of the password
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    /// Roles whose permissions the user holds
+    pub roles: Vec<String>,
+    /// Tenant the account belongs to
+    pub tenant: String,
+    /// Consecutive failed logins since the last success or lockout
+    pub failed_attempts: i32,
+    /// Unix timestamp until which logins are refused
+    pub locked_until: Option<i64>,
+    /// Unix timestamp of the last successful login
+    pub last_login: Option<i64>,
+    /// Unix timestamp of account creation
+    pub created_at: i64,
+}
+
+impl User {
+    /// Whether the account is locked at the given time
+    pub fn is_locked(&self, now: i64) -> bool {
+        self.locked_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Persistence operations for user accounts
+#[async_trait]
+pub trait AccountStore: Send + Sync {
+    /// Insert a new user, failing with `UserExists` on a duplicate username
+    async fn create_user(&self, user: &User) -> AuthResult<()>;
+
+    /// Look a user up by login name
+    async fn find_by_username(&self, username: &str) -> AuthResult<Option<User>>;
+
+    /// Look a user up by ID
+    async fn find_by_id(&self, id: &str) -> AuthResult<Option<User>>;
+
+    /// All users ordered by username
+    async fn list_users(&self) -> AuthResult<Vec<User>>;
+
+    /// Replace a user's password hash and clear any lockout
+    async fn update_password(&self, id: &str, password_hash: &str) -> AuthResult<()>;
+
+    /// Replace a user's roles
+    async fn update_roles(&self, id: &str, roles: &[String]) -> AuthResult<()>;
+
+    /// Delete a user, returning whether it existed
+    async fn delete_user(&self, id: &str) -> AuthResult<bool>;
+
+    /// Count a failed login, locking the account until `lock_until` once
+    /// `max_attempts` consecutive failures are reached. Returns the lock
+    /// expiry when this failure locked the account.
+    async fn record_login_failure(&self, id: &str, max_attempts: i32, lock_until: i64) -> AuthResult<Option<i64>>;
+
+    /// Record a successful login, resetting failure tracking
+    async fn record_login_success(&self, id: &str, at: i64) -> AuthResult<()>;
+}
+
+/// Implements `AccountStore` for a store; both backends share the same SQL
+macro_rules! sql_account_store {
+    ($name:ident, $row:ty) => {
+        impl $name {
+            fn user_from_row(row: &$row) -> AuthResult<User> {
+                Ok(User {
+                    id: row.try_get("id")?,
+                    username: row.try_get("username")?,
+                    password_hash: row.try_get("password_hash")?,
+                    roles: split_names(row.try_get("role")?),
+                    tenant: row.try_get("tenant")?,
+                    failed_attempts: row.try_get("failed_attempts")?,
+                    locked_until: row.try_get("locked_until")?,
+                    last_login: row.try_get("last_login")?,
+                    created_at: row.try_get("created_at")?,
+                })
+            }
+
+            async fn find_one(&self, column: &str, value: &str) -> AuthResult<Option<User>> {
+                let sql = format!("SELECT {} FROM users WHERE {} = $1", USER_COLUMNS, column);
+                let row = sqlx::query(&sql).bind(value).fetch_optional(self.pool().await?).await?;
+                row.as_ref().map(Self::user_from_row).transpose()
+            }
+        }
+
+        #[async_trait]
+        impl AccountStore for $name {
+            async fn create_user(&self, user: &User) -> AuthResult<()> {
+                sqlx::query(
+                    "INSERT INTO users (id, username, password_hash, role, tenant, failed_attempts, locked_until, last_login, created_at) \
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                )
+                .bind(&user.id)
+                .bind(&user.username)
+                .bind(&user.password_hash)
+                .bind(join_names(&user.roles))
+                .bind(&user.tenant)
+                .bind(user.failed_attempts)
+                .bind(user.locked_until)
+                .bind(user.last_login)
+                .bind(user.created_at)
+                .execute(self.pool().await?)
+                .await
+                .map_err(|e| map_insert_error(e, &user.username))?;
+                Ok(())
+            }
+
+            async fn find_by_username(&self, username: &str) -> AuthResult<Option<User>> {
+                self.find_one("username", username).await
+            }
+
+            async fn find_by_id(&self, id: &str) -> AuthResult<Option<User>> {
+                self.find_one("id", id).await
+            }
+
+            async fn list_users(&self) -> AuthResult<Vec<User>> {
+                let sql = format!("SELECT {} FROM users ORDER BY username", USER_COLUMNS);
+                let rows = sqlx::query(&sql).fetch_all(self.pool().await?).await?;
+                rows.iter().map(Self::user_from_row).collect()
+            }
+
+            async fn update_password(&self, id: &str, password_hash: &str) -> AuthResult<()> {
+                let result = sqlx::query(
+                    "UPDATE users SET password_hash = $2, failed_attempts = 0, locked_until = NULL WHERE id = $1",
+                )
+                .bind(id)
+                .bind(password_hash)
+                .execute(self.pool().await?)
+                .await?;
+
+                if result.rows_affected() == 0 {
+                    return Err(AuthError::UserNotFound);
+                }
+                Ok(())
+            }
+
+            async fn update_roles(&self, id: &str, roles: &[String]) -> AuthResult<()> {
+                let result = sqlx::query("UPDATE users SET role = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(join_names(roles))
+                    .execute(self.pool().await?)
+                    .await?;
+
+                if result.rows_affected() == 0 {
+                    return Err(AuthError::UserNotFound);
+                }
+                Ok(())
+            }
+
+            async fn delete_user(&self, id: &str) -> AuthResult<bool> {
+                let result = sqlx::query("DELETE FROM users WHERE id = $1")
+                    .bind(id)
+                    .execute(self.pool().await?)
+                    .await?;
+                Ok(result.rows_affected() > 0)
+            }
+
+            async fn record_login_failure(&self, id: &str, max_attempts: i32, lock_until: i64) -> AuthResult<Option<i64>> {
+                // Single statement so concurrent failures cannot lose counts
+                let row = sqlx::query(
+                    "UPDATE users SET \
+                         failed_attempts = CASE WHEN $2 > 0 AND failed_attempts + 1 >= $2 THEN 0 ELSE failed_attempts + 1 END, \
+                         locked_until = CASE WHEN $2 > 0 AND failed_attempts + 1 >= $2 THEN $3 ELSE locked_until END \
+                     WHERE id = $1 \
+                     RETURNING locked_until",
+                )
+                .bind(id)
+                .bind(max_attempts)
+                .bind(lock_until)
+                .fetch_optional(self.pool().await?)
+                .await?;
+
+                let locked_until: Option<i64> = match row {
+                    Some(row) => row.try_get("locked_until")?,
+                    None => return Err(AuthError::UserNotFound),
+                };
+                Ok(locked_until.filter(|until| *until == lock_until))
+            }
+
+            async fn record_login_success(&self, id: &str, at: i64) -> AuthResult<()> {
+                sqlx::query("UPDATE users SET failed_attempts = 0, locked_until = NULL, last_login = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(at)
+                    .execute(self.pool().await?)
+                    .await?;
+                Ok(())
+            }
+        }
+    };
+}
+
+sql_account_store!(PgStore, sqlx::postgres::PgRow);
+sql_account_store!(SqliteStore, sqlx::sqlite::SqliteRow);
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::store::tests::user;
+    use crate::store::AuthStore;
+
+    pub(in crate::store) async fn check_crud(store: &dyn AuthStore) {
+        let alice = user("alice");
+        store.create_user(&alice).await.unwrap();
+        store.create_user(&user("bob")).await.unwrap();
+
+        let duplicate = store.create_user(&user("alice")).await;
+        assert!(matches!(duplicate, Err(AuthError::UserExists(_))));
+
+        let found = store.find_by_username("alice").await.unwrap().unwrap();
+        assert_eq!(found.id, alice.id);
+
+        let roles = vec!["admin".to_string(), "auditor".to_string()];
+        store.update_roles(&alice.id, &roles).await.unwrap();
+        store.update_password(&alice.id, "new-hash").await.unwrap();
+        let found = store.find_by_id(&alice.id).await.unwrap().unwrap();
+        assert_eq!(found.roles, roles);
+        assert_eq!(found.password_hash, "new-hash");
+
+        let names: Vec<_> = store.list_users().await.unwrap().into_iter().map(|u| u.username).collect();
+        assert_eq!(names, vec!["alice", "bob"]);
+
+        assert!(store.delete_user(&alice.id).await.unwrap());
+        assert!(!store.delete_user(&alice.id).await.unwrap());
+        assert!(matches!(store.update_roles(&alice.id, &roles).await, Err(AuthError::UserNotFound)));
+    }
+
+    pub(in crate::store) async fn check_login_failure_locks_account(store: &dyn AuthStore) {
+        let carol = user("carol");
+        store.create_user(&carol).await.unwrap();
+
+        assert_eq!(store.record_login_failure(&carol.id, 3, 500).await.unwrap(), None);
+        assert_eq!(store.record_login_failure(&carol.id, 3, 500).await.unwrap(), None);
+        assert_eq!(store.record_login_failure(&carol.id, 3, 500).await.unwrap(), Some(500));
+
+        let locked = store.find_by_id(&carol.id).await.unwrap().unwrap();
+        assert!(locked.is_locked(499));
+        assert!(!locked.is_locked(500));
+        assert_eq!(locked.failed_attempts, 0);
+
+        store.record_login_success(&carol.id, 600).await.unwrap();
+        let unlocked = store.find_by_id(&carol.id).await.unwrap().unwrap();
+        assert_eq!(unlocked.locked_until, None);
+        assert_eq!(unlocked.last_login, Some(600));
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_user_crud() {
+        check_crud(&SqliteStore::in_memory().unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_login_failure_locks_account() {
+        check_login_failure_locks_account(&SqliteStore::in_memory().unwrap()).await;
+    }
+}
@@ -0,0 +1,137 @@
+//! The audit log
+
+use super::{PgStore, SqliteStore};
+use crate::audit::{AuditEvent, AuditOutcome, AuditQuery, AuditStore};
+use crate::error::AuthError;
+use crate::AuthResult;
+use async_trait::async_trait;
+use sqlx::Row;
+
+const AUDIT_EVENT_COLUMNS: &str =
+    "id, occurred_at, tenant, actor_id, actor, action, target, outcome, reason, before_state, after_state, source_ip";
+
+/// Implements `AuditStore` for a store; both backends share the same SQL
+macro_rules! sql_audit_store {
+    ($name:ident, $row:ty) => {
+        impl $name {
+            fn audit_event_from_row(row: &$row) -> AuthResult<AuditEvent> {
+                let state = |column: &str| -> AuthResult<Option<serde_json::Value>> {
+                    let json: Option<String> = row.try_get(column)?;
+                    json.map(|json| serde_json::from_str(&json))
+                        .transpose()
+                        .map_err(|e| AuthError::DatabaseError(format!("Invalid audit state: {}", e)))
+                };
+                Ok(AuditEvent {
+                    id: row.try_get("id")?,
+                    occurred_at: row.try_get("occurred_at")?,
+                    tenant: row.try_get("tenant")?,
+                    actor_id: row.try_get("actor_id")?,
+                    actor: row.try_get("actor")?,
+                    action: row.try_get("action")?,
+                    target: row.try_get("target")?,
+                    outcome: AuditOutcome::from_name(row.try_get("outcome")?),
+                    reason: row.try_get("reason")?,
+                    before: state("before_state")?,
+                    after: state("after_state")?,
+                    source_ip: row.try_get("source_ip")?,
+                })
+            }
+        }
+
+        #[async_trait]
+        impl AuditStore for $name {
+            async fn append_audit_event(&self, event: &AuditEvent) -> AuthResult<()> {
+                let sql = format!(
+                    "INSERT INTO audit_events ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+                    AUDIT_EVENT_COLUMNS
+                );
+                sqlx::query(&sql)
+                    .bind(&event.id)
+                    .bind(event.occurred_at)
+                    .bind(&event.tenant)
+                    .bind(&event.actor_id)
+                    .bind(&event.actor)
+                    .bind(&event.action)
+                    .bind(&event.target)
+                    .bind(event.outcome.as_str())
+                    .bind(&event.reason)
+                    .bind(event.before.as_ref().map(|state| state.to_string()))
+                    .bind(event.after.as_ref().map(|state| state.to_string()))
+                    .bind(&event.source_ip)
+                    .execute(self.pool().await?)
+                    .await?;
+                Ok(())
+            }
+
+            async fn audit_events(&self, query: &AuditQuery) -> AuthResult<Vec<AuditEvent>> {
+                let sql = format!(
+                    "SELECT {} FROM audit_events \
+                     WHERE ($1 IS NULL OR actor_id = $1 OR actor = $1) \
+                     AND ($2 IS NULL OR action = $2 OR action LIKE $2 || '.%') \
+                     AND ($3 IS NULL OR target = $3) \
+                     AND ($4 IS NULL OR outcome = $4) \
+                     AND ($5 IS NULL OR occurred_at >= $5) \
+                     AND ($6 IS NULL OR occurred_at < $6) \
+                     AND ($8 IS NULL OR tenant = $8) \
+                     ORDER BY occurred_at DESC, id DESC LIMIT $7",
+                    AUDIT_EVENT_COLUMNS
+                );
+                let rows = sqlx::query(&sql)
+                    .bind(&query.actor)
+                    .bind(&query.action)
+                    .bind(&query.target)
+                    .bind(query.outcome.map(|outcome| outcome.as_str()))
+                    .bind(query.since)
+                    .bind(query.until)
+                    .bind(i64::from(query.effective_limit()))
+                    .bind(&query.tenant)
+                    .fetch_all(self.pool().await?)
+                    .await?;
+                rows.iter().map(Self::audit_event_from_row).collect()
+            }
+        }
+    };
+}
+
+sql_audit_store!(PgStore, sqlx::postgres::PgRow);
+sql_audit_store!(SqliteStore, sqlx::sqlite::SqliteRow);
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::store::AuthStore;
+
+    pub(in crate::store) async fn check_audit_events(store: &dyn AuthStore) {
+        let mut login = AuditEvent::new("auth.login").with_actor_name("alice").failed("Invalid credentials");
+        login.occurred_at = 100;
+        let mut create = AuditEvent::new("user.create")
+            .with_actor_name("admin")
+            .with_target("user-1")
+            .with_after(&serde_json::json!({ "username": "bob" }));
+        create.occurred_at = 200;
+        let mut rename = AuditEvent::new("user.update").with_actor_name("admin").with_target("user-1");
+        rename.occurred_at = 200;
+        for event in [&login, &create, &rename] {
+            store.append_audit_event(event).await.unwrap();
+        }
+
+        let all = store.audit_events(&AuditQuery::default()).await.unwrap();
+        assert_eq!(all, vec![rename.clone(), create.clone(), login.clone()]);
+
+        let query = |query: AuditQuery| async move { store.audit_events(&query).await.unwrap() };
+        let by_prefix = query(AuditQuery { action: Some("user".to_string()), ..Default::default() }).await;
+        assert_eq!(by_prefix.len(), 2);
+        let failures = query(AuditQuery { outcome: Some(AuditOutcome::Failure), ..Default::default() }).await;
+        assert_eq!(failures, vec![login]);
+        let window = query(AuditQuery { since: Some(150), until: Some(201), limit: Some(1), ..Default::default() }).await;
+        assert_eq!(window, vec![rename]);
+        let by_actor = query(AuditQuery { actor: Some("admin".to_string()), target: Some("user-1".to_string()), ..Default::default() }).await;
+        assert_eq!(by_actor.len(), 2);
+        assert!(query(AuditQuery { action: Some("user.cre".to_string()), ..Default::default() }).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_audit_events() {
+        check_audit_events(&SqliteStore::in_memory().unwrap()).await;
+    }
+}
@@ -0,0 +1,148 @@
+//! Accounts at OpenID Connect providers and logins in progress there
+
+use super::{PgStore, SqliteStore};
+use crate::AuthResult;
+use async_trait::async_trait;
+use sqlx::Row;
+
+const OIDC_LOGIN_COLUMNS: &str = "state, code_verifier, nonce, expires_at";
+
+/// A login started at an OpenID Connect provider, awaiting its callback
+#[derive(Debug, Clone)]
+pub struct OidcLoginRecord {
+    /// Random value round-tripped through the provider to match the callback
+    pub state: String,
+    /// PKCE secret whose hash was sent with the authorization request
+    pub code_verifier: String,
+    /// Random value the provider must echo in the ID token
+    pub nonce: String,
+    /// Unix timestamp after which the callback is refused
+    pub expires_at: i64,
+}
+
+/// Persistence operations for OpenID Connect identities and logins
+#[async_trait]
+pub trait IdentityStore: Send + Sync {
+    /// User linked to an account at an OpenID Connect provider
+    async fn find_identity(&self, issuer: &str, subject: &str) -> AuthResult<Option<String>>;
+
+    /// Link an account at an OpenID Connect provider to a user
+    async fn link_identity(&self, issuer: &str, subject: &str, user_id: &str, at: i64) -> AuthResult<()>;
+
+    /// Store a login started at an OpenID Connect provider
+    async fn insert_oidc_login(&self, login: &OidcLoginRecord) -> AuthResult<()>;
+
+    /// Remove and return a pending login, so each callback is accepted once
+    async fn take_oidc_login(&self, state: &str) -> AuthResult<Option<OidcLoginRecord>>;
+
+    /// Forget logins whose callback never came, returning how many were removed
+    async fn purge_expired_oidc_logins(&self, now: i64) -> AuthResult<u64>;
+}
+
+/// Implements `IdentityStore` for a store; both backends share the same SQL
+macro_rules! sql_identity_store {
+    ($name:ident, $row:ty) => {
+        impl $name {
+            fn oidc_login_from_row(row: &$row) -> AuthResult<OidcLoginRecord> {
+                Ok(OidcLoginRecord {
+                    state: row.try_get("state")?,
+                    code_verifier: row.try_get("code_verifier")?,
+                    nonce: row.try_get("nonce")?,
+                    expires_at: row.try_get("expires_at")?,
+                })
+            }
+        }
+
+        #[async_trait]
+        impl IdentityStore for $name {
+            async fn find_identity(&self, issuer: &str, subject: &str) -> AuthResult<Option<String>> {
+                let row = sqlx::query("SELECT user_id FROM user_identities WHERE issuer = $1 AND subject = $2")
+                    .bind(issuer)
+                    .bind(subject)
+                    .fetch_optional(self.pool().await?)
+                    .await?;
+                Ok(row.map(|row| row.try_get("user_id")).transpose()?)
+            }
+
+            async fn link_identity(&self, issuer: &str, subject: &str, user_id: &str, at: i64) -> AuthResult<()> {
+                sqlx::query("INSERT INTO user_identities (issuer, subject, user_id, created_at) VALUES ($1, $2, $3, $4)")
+                    .bind(issuer)
+                    .bind(subject)
+                    .bind(user_id)
+                    .bind(at)
+                    .execute(self.pool().await?)
+                    .await?;
+                Ok(())
+            }
+
+            async fn insert_oidc_login(&self, login: &OidcLoginRecord) -> AuthResult<()> {
+                sqlx::query("INSERT INTO oidc_logins (state, code_verifier, nonce, expires_at) VALUES ($1, $2, $3, $4)")
+                    .bind(&login.state)
+                    .bind(&login.code_verifier)
+                    .bind(&login.nonce)
+                    .bind(login.expires_at)
+                    .execute(self.pool().await?)
+                    .await?;
+                Ok(())
+            }
+
+            async fn take_oidc_login(&self, state: &str) -> AuthResult<Option<OidcLoginRecord>> {
+                let sql = format!("DELETE FROM oidc_logins WHERE state = $1 RETURNING {}", OIDC_LOGIN_COLUMNS);
+                let row = sqlx::query(&sql).bind(state).fetch_optional(self.pool().await?).await?;
+                row.as_ref().map(Self::oidc_login_from_row).transpose()
+            }
+
+            async fn purge_expired_oidc_logins(&self, now: i64) -> AuthResult<u64> {
+                let result = sqlx::query("DELETE FROM oidc_logins WHERE expires_at <= $1")
+                    .bind(now)
+                    .execute(self.pool().await?)
+                    .await?;
+                Ok(result.rows_affected())
+            }
+        }
+    };
+}
+
+sql_identity_store!(PgStore, sqlx::postgres::PgRow);
+sql_identity_store!(SqliteStore, sqlx::sqlite::SqliteRow);
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::store::tests::user;
+    use crate::store::AuthStore;
+
+    pub(in crate::store) async fn check_oidc_identities_and_logins(store: &dyn AuthStore) {
+        let alice = user("alice");
+        store.create_user(&alice).await.unwrap();
+
+        let issuer = "https://idp.example.com";
+        assert!(store.find_identity(issuer, "sub-1").await.unwrap().is_none());
+        store.link_identity(issuer, "sub-1", &alice.id, 100).await.unwrap();
+        assert_eq!(store.find_identity(issuer, "sub-1").await.unwrap(), Some(alice.id.clone()));
+        assert!(store.find_identity("https://other.example.com", "sub-1").await.unwrap().is_none());
+
+        let login = |state: &str, expires_at| OidcLoginRecord {
+            state: state.to_string(),
+            code_verifier: "verifier".to_string(),
+            nonce: "nonce".to_string(),
+            expires_at,
+        };
+        store.insert_oidc_login(&login("current", 200)).await.unwrap();
+        store.insert_oidc_login(&login("stale", 100)).await.unwrap();
+        assert_eq!(store.purge_expired_oidc_logins(150).await.unwrap(), 1);
+        assert!(store.take_oidc_login("stale").await.unwrap().is_none());
+
+        let taken = store.take_oidc_login("current").await.unwrap().unwrap();
+        assert_eq!(taken.code_verifier, "verifier");
+        assert!(store.take_oidc_login("current").await.unwrap().is_none());
+
+        store.delete_user(&alice.id).await.unwrap();
+        assert!(store.find_identity(issuer, "sub-1").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_oidc_identities_and_logins() {
+        check_oidc_identities_and_logins(&SqliteStore::in_memory().unwrap()).await;
+    }
+}
@@ -0,0 +1,296 @@
+//! API keys and rotated JWT signing keys
+
+use super::{join_names, split_names, PgStore, SqliteStore};
+use crate::error::AuthError;
+use crate::AuthResult;
+use async_trait::async_trait;
+use common::secret::Secret;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+const API_KEY_COLUMNS: &str =
+    "id, user_id, name, secret_hash, scopes, created_at, expires_at, last_used_at, revoked_at";
+
+const SIGNING_KEY_COLUMNS: &str = "kid, algorithm, secret, created_at, retired_at";
+
+/// A long-lived credential acting for its owner, limited to its scopes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    /// Public part of the key, between `nxk_` and the secret
+    pub id: String,
+    /// Owner of the key
+    pub user_id: String,
+    /// Label identifying the client using the key
+    pub name: String,
+    /// SHA-256 hash of the secret part of the key
+    #[serde(skip_serializing)]
+    pub secret_hash: String,
+    /// Permissions the key is limited to, e.g. `agent:read` or `agent:*`
+    pub scopes: Vec<String>,
+    /// Unix timestamp of creation
+    pub created_at: i64,
+    /// Unix timestamp after which the key is rejected; `None` never expires
+    pub expires_at: Option<i64>,
+    /// Unix timestamp of the last request made with the key, to the minute
+    pub last_used_at: Option<i64>,
+    /// Unix timestamp at which the key was revoked
+    pub revoked_at: Option<i64>,
+}
+
+/// A JWT signing key created by rotation
+///
+/// A record without a secret whose `kid` names a configured key marks that
+/// key as retired; secrets of other keys are erased once they are no longer
+/// needed to verify tokens. Secrets are stored encrypted with the master
+/// key when one is configured.
+#[derive(Debug, Clone)]
+pub struct SigningKeyRecord {
+    /// Key ID written to token headers
+    pub kid: String,
+    /// HMAC algorithm the key is used with
+    pub algorithm: String,
+    /// Shared secret, `None` once erased or for retirement markers
+    pub secret: Option<Secret>,
+    /// Unix timestamp of creation
+    pub created_at: i64,
+    /// Unix timestamp at which the key stopped signing new tokens
+    pub retired_at: Option<i64>,
+}
+
+/// Persistence operations for API keys and signing keys
+#[async_trait]
+pub trait KeyStore: Send + Sync {
+    /// Store a new API key
+    async fn insert_api_key(&self, key: &ApiKey) -> AuthResult<()>;
+
+    /// Look an API key up by ID
+    async fn find_api_key(&self, id: &str) -> AuthResult<Option<ApiKey>>;
+
+    /// API keys of a user, revoked ones included, oldest first
+    async fn list_api_keys(&self, user_id: &str) -> AuthResult<Vec<ApiKey>>;
+
+    /// Record a request made with an API key
+    async fn touch_api_key(&self, id: &str, at: i64) -> AuthResult<()>;
+
+    /// Revoke an API key, returning false if it was unknown or already revoked
+    async fn revoke_api_key(&self, id: &str, at: i64) -> AuthResult<bool>;
+
+    /// Store a signing key created by rotation
+    async fn insert_signing_key(&self, key: &SigningKeyRecord) -> AuthResult<()>;
+
+    /// All signing key records ordered by creation time
+    async fn list_signing_keys(&self) -> AuthResult<Vec<SigningKeyRecord>>;
+
+    /// Retire every active signing key except `current`, returning how many were retired
+    async fn retire_signing_keys(&self, current: &str, at: i64) -> AuthResult<u64>;
+
+    /// Erase the secrets of keys retired at or before `retired_before`,
+    /// returning how many were erased
+    async fn erase_retired_signing_keys(&self, retired_before: i64) -> AuthResult<u64>;
+}
+
+/// Implements `KeyStore` for a store; both backends share the same SQL
+macro_rules! sql_key_store {
+    ($name:ident, $row:ty) => {
+        impl $name {
+            fn api_key_from_row(row: &$row) -> AuthResult<ApiKey> {
+                Ok(ApiKey {
+                    id: row.try_get("id")?,
+                    user_id: row.try_get("user_id")?,
+                    name: row.try_get("name")?,
+                    secret_hash: row.try_get("secret_hash")?,
+                    scopes: split_names(row.try_get("scopes")?),
+                    created_at: row.try_get("created_at")?,
+                    expires_at: row.try_get("expires_at")?,
+                    last_used_at: row.try_get("last_used_at")?,
+                    revoked_at: row.try_get("revoked_at")?,
+                })
+            }
+
+            fn signing_key_from_row(row: &$row) -> AuthResult<SigningKeyRecord> {
+                Ok(SigningKeyRecord {
+                    kid: row.try_get("kid")?,
+                    algorithm: row.try_get("algorithm")?,
+                    secret: row
+                        .try_get::<Option<String>, _>("secret")?
+                        .map(|stored| Secret::from_stored(&stored))
+                        .transpose()
+                        .map_err(|e| AuthError::Configuration(format!("Cannot read signing key: {}", e)))?,
+                    created_at: row.try_get("created_at")?,
+                    retired_at: row.try_get("retired_at")?,
+                })
+            }
+        }
+
+        #[async_trait]
+        impl KeyStore for $name {
+            async fn insert_api_key(&self, key: &ApiKey) -> AuthResult<()> {
+                sqlx::query(
+                    "INSERT INTO api_keys (id, user_id, name, secret_hash, scopes, created_at, expires_at, last_used_at, revoked_at) \
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                )
+                .bind(&key.id)
+                .bind(&key.user_id)
+                .bind(&key.name)
+                .bind(&key.secret_hash)
+                .bind(join_names(&key.scopes))
+                .bind(key.created_at)
+                .bind(key.expires_at)
+                .bind(key.last_used_at)
+                .bind(key.revoked_at)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_api_key(&self, id: &str) -> AuthResult<Option<ApiKey>> {
+                let sql = format!("SELECT {} FROM api_keys WHERE id = $1", API_KEY_COLUMNS);
+                let row = sqlx::query(&sql).bind(id).fetch_optional(self.pool().await?).await?;
+                row.as_ref().map(Self::api_key_from_row).transpose()
+            }
+
+            async fn list_api_keys(&self, user_id: &str) -> AuthResult<Vec<ApiKey>> {
+                let sql = format!(
+                    "SELECT {} FROM api_keys WHERE user_id = $1 ORDER BY created_at, id",
+                    API_KEY_COLUMNS
+                );
+                let rows = sqlx::query(&sql).bind(user_id).fetch_all(self.pool().await?).await?;
+                rows.iter().map(Self::api_key_from_row).collect()
+            }
+
+            async fn touch_api_key(&self, id: &str, at: i64) -> AuthResult<()> {
+                sqlx::query("UPDATE api_keys SET last_used_at = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(at)
+                    .execute(self.pool().await?)
+                    .await?;
+                Ok(())
+            }
+
+            async fn revoke_api_key(&self, id: &str, at: i64) -> AuthResult<bool> {
+                let result = sqlx::query("UPDATE api_keys SET revoked_at = $2 WHERE id = $1 AND revoked_at IS NULL")
+                    .bind(id)
+                    .bind(at)
+                    .execute(self.pool().await?)
+                    .await?;
+                Ok(result.rows_affected() > 0)
+            }
+
+            async fn insert_signing_key(&self, key: &SigningKeyRecord) -> AuthResult<()> {
+                sqlx::query(
+                    "INSERT INTO signing_keys (kid, algorithm, secret, created_at, retired_at) VALUES ($1, $2, $3, $4, $5)",
+                )
+                .bind(&key.kid)
+                .bind(&key.algorithm)
+                .bind(key.secret.as_ref().map(Secret::source))
+                .bind(key.created_at)
+                .bind(key.retired_at)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(())
+            }
+
+            async fn list_signing_keys(&self) -> AuthResult<Vec<SigningKeyRecord>> {
+                let sql = format!("SELECT {} FROM signing_keys ORDER BY created_at, kid", SIGNING_KEY_COLUMNS);
+                let rows = sqlx::query(&sql).fetch_all(self.pool().await?).await?;
+                rows.iter().map(Self::signing_key_from_row).collect()
+            }
+
+            async fn retire_signing_keys(&self, current: &str, at: i64) -> AuthResult<u64> {
+                let result = sqlx::query(
+                    "UPDATE signing_keys SET retired_at = $2 WHERE kid <> $1 AND retired_at IS NULL",
+                )
+                .bind(current)
+                .bind(at)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(result.rows_affected())
+            }
+
+            async fn erase_retired_signing_keys(&self, retired_before: i64) -> AuthResult<u64> {
+                let result = sqlx::query(
+                    "UPDATE signing_keys SET secret = NULL \
+                     WHERE secret IS NOT NULL AND retired_at IS NOT NULL AND retired_at <= $1",
+                )
+                .bind(retired_before)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(result.rows_affected())
+            }
+        }
+    };
+}
+
+sql_key_store!(PgStore, sqlx::postgres::PgRow);
+sql_key_store!(SqliteStore, sqlx::sqlite::SqliteRow);
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::store::tests::user;
+    use crate::store::AuthStore;
+
+    pub(in crate::store) async fn check_api_keys(store: &dyn AuthStore) {
+        let hank = user("hank");
+        store.create_user(&hank).await.unwrap();
+
+        let key = ApiKey {
+            id: "0123456789abcdef".to_string(),
+            user_id: hank.id.clone(),
+            name: "ci".to_string(),
+            secret_hash: "hash".to_string(),
+            scopes: vec!["agent:read".to_string(), "system:*".to_string()],
+            created_at: 100,
+            expires_at: Some(1_000),
+            last_used_at: None,
+            revoked_at: None,
+        };
+        store.insert_api_key(&key).await.unwrap();
+        store.touch_api_key(&key.id, 150).await.unwrap();
+
+        let found = store.find_api_key(&key.id).await.unwrap().unwrap();
+        assert_eq!(found.scopes, key.scopes);
+        assert_eq!((found.expires_at, found.last_used_at), (Some(1_000), Some(150)));
+
+        assert!(store.revoke_api_key(&key.id, 200).await.unwrap());
+        assert!(!store.revoke_api_key(&key.id, 300).await.unwrap());
+        assert_eq!(store.list_api_keys(&hank.id).await.unwrap()[0].revoked_at, Some(200));
+
+        // Deleting the user removes its keys
+        store.delete_user(&hank.id).await.unwrap();
+        assert!(store.find_api_key(&key.id).await.unwrap().is_none());
+    }
+
+    pub(in crate::store) async fn check_signing_key_retirement(store: &dyn AuthStore) {
+        let key = |kid: &str, created_at: i64| SigningKeyRecord {
+            kid: kid.to_string(),
+            algorithm: "HS256".to_string(),
+            secret: Some(format!("{}-secret", kid).into()),
+            created_at,
+            retired_at: None,
+        };
+        store.insert_signing_key(&key("first", 100)).await.unwrap();
+        store.insert_signing_key(&key("second", 200)).await.unwrap();
+
+        assert_eq!(store.retire_signing_keys("second", 300).await.unwrap(), 1);
+        assert_eq!(store.retire_signing_keys("second", 400).await.unwrap(), 0);
+        assert_eq!(store.erase_retired_signing_keys(299).await.unwrap(), 0);
+        assert_eq!(store.erase_retired_signing_keys(300).await.unwrap(), 1);
+
+        let keys = store.list_signing_keys().await.unwrap();
+        assert_eq!(keys[0].kid, "first");
+        let secret = |record: &SigningKeyRecord| record.secret.as_ref().map(|secret| secret.expose().to_string());
+        assert_eq!((secret(&keys[0]), keys[0].retired_at), (None, Some(300)));
+        assert_eq!((secret(&keys[1]).as_deref(), keys[1].retired_at), (Some("second-secret"), None));
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_api_keys() {
+        check_api_keys(&SqliteStore::in_memory().unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_signing_key_retirement() {
+        check_signing_key_retirement(&SqliteStore::in_memory().unwrap()).await;
+    }
+}
@@ -0,0 +1,379 @@
+//! Storage of the authentication service
+//!
+//! Accounts, their refresh tokens, API keys, OpenID Connect identities and
+//! second factors, rotated signing keys, token revocations, roles created at
+//! runtime, logins in progress at an OpenID Connect provider, the audit log
+//! and LLM token usage counters live in Postgres, or SQLite for tests and
+//! single-node setups. Tables are created on first use.
+//!
+//! Each kind of record has its own store trait in its own module; both
+//! backends implement all of them, and [`AuthStore`] names the whole set.
+
+mod accounts;
+mod audit;
+mod identities;
+mod keys;
+mod roles;
+mod second_factor;
+mod tokens;
+mod usage;
+
+pub use accounts::{AccountStore, User};
+pub use identities::{IdentityStore, OidcLoginRecord};
+pub use keys::{ApiKey, KeyStore, SigningKeyRecord};
+pub use roles::RoleStore;
+pub use second_factor::{SecondFactorStore, TotpCredential};
+pub use tokens::{RefreshTokenRecord, Session, TokenStore};
+
+use crate::audit::AuditStore;
+use crate::error::AuthError;
+use crate::revocation::RevocationStore;
+use crate::usage::UsageStore;
+use crate::AuthResult;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+/// Schema shared by both backends, one statement per entry
+const SCHEMA: &[&str] = &[
+    r#"
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    -- Comma-separated role names
+    role TEXT NOT NULL,
+    tenant TEXT NOT NULL DEFAULT 'default',
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    locked_until BIGINT,
+    last_login BIGINT,
+    created_at BIGINT NOT NULL
+)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    family_id TEXT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    used_at BIGINT,
+    revoked_at BIGINT
+)
+"#,
+    "CREATE INDEX IF NOT EXISTS refresh_tokens_family_idx ON refresh_tokens (family_id)",
+    "CREATE INDEX IF NOT EXISTS refresh_tokens_user_idx ON refresh_tokens (user_id)",
+    r#"
+CREATE TABLE IF NOT EXISTS signing_keys (
+    kid TEXT PRIMARY KEY,
+    algorithm TEXT NOT NULL,
+    secret TEXT,
+    created_at BIGINT NOT NULL,
+    retired_at BIGINT
+)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    id TEXT PRIMARY KEY,
+    revoked_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL
+)
+"#,
+    "CREATE INDEX IF NOT EXISTS revoked_tokens_revoked_idx ON revoked_tokens (revoked_at)",
+    r#"
+CREATE TABLE IF NOT EXISTS api_keys (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    secret_hash TEXT NOT NULL,
+    -- Comma-separated permissions the key is limited to
+    scopes TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT,
+    last_used_at BIGINT,
+    revoked_at BIGINT
+)
+"#,
+    "CREATE INDEX IF NOT EXISTS api_keys_user_idx ON api_keys (user_id)",
+    r#"
+CREATE TABLE IF NOT EXISTS roles (
+    name TEXT PRIMARY KEY,
+    -- Comma-separated permissions and inherited role names
+    permissions TEXT NOT NULL,
+    inherits TEXT NOT NULL
+)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS user_identities (
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (issuer, subject)
+)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS totp_credentials (
+    user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- Base32 secret, an enc: reference when a master key is configured
+    secret TEXT NOT NULL,
+    enabled_at BIGINT,
+    -- Time step of the last accepted code, so no code is accepted twice
+    last_used_step BIGINT NOT NULL,
+    created_at BIGINT NOT NULL
+)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    code_hash TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    used_at BIGINT
+)
+"#,
+    "CREATE INDEX IF NOT EXISTS recovery_codes_user_idx ON recovery_codes (user_id)",
+    r#"
+CREATE TABLE IF NOT EXISTS oidc_logins (
+    state TEXT PRIMARY KEY,
+    code_verifier TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    expires_at BIGINT NOT NULL
+)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS audit_events (
+    id TEXT PRIMARY KEY,
+    occurred_at BIGINT NOT NULL,
+    tenant TEXT NOT NULL DEFAULT 'default',
+    actor_id TEXT,
+    actor TEXT,
+    action TEXT NOT NULL,
+    target TEXT,
+    outcome TEXT NOT NULL,
+    reason TEXT,
+    -- JSON states of the target, secrets redacted
+    before_state TEXT,
+    after_state TEXT,
+    source_ip TEXT
+)
+"#,
+    "CREATE INDEX IF NOT EXISTS audit_events_occurred_idx ON audit_events (occurred_at)",
+    r#"
+CREATE TABLE IF NOT EXISTS token_usage (
+    tenant TEXT NOT NULL,
+    -- `user:<username>`, `api_key:<id>` or `team:<name>`
+    subject TEXT NOT NULL,
+    -- `YYYY-MM-DD` for a day, `YYYY-MM` for a month
+    period TEXT NOT NULL,
+    tokens BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL,
+    PRIMARY KEY (tenant, subject, period)
+)
+"#,
+];
+
+/// Every store the authentication service uses
+pub trait AuthStore:
+    AccountStore
+    + TokenStore
+    + KeyStore
+    + RoleStore
+    + SecondFactorStore
+    + IdentityStore
+    + RevocationStore
+    + AuditStore
+    + UsageStore
+{
+}
+
+impl<T> AuthStore for T where
+    T: AccountStore
+        + TokenStore
+        + KeyStore
+        + RoleStore
+        + SecondFactorStore
+        + IdentityStore
+        + RevocationStore
+        + AuditStore
+        + UsageStore
+{
+}
+
+/// Map a unique constraint violation to `UserExists`
+fn map_insert_error(err: sqlx::Error, username: &str) -> AuthError {
+    match &err {
+        sqlx::Error::Database(db) if db.is_unique_violation() => AuthError::UserExists(username.to_string()),
+        _ => err.into(),
+    }
+}
+
+/// Names are stored comma-separated; role names and permissions never contain commas
+fn join_names(names: &[String]) -> String {
+    names.join(",")
+}
+
+fn split_names(joined: &str) -> Vec<String> {
+    joined
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Declares a store over a sqlx pool; the store modules implement their
+/// traits for both backends with the same SQL
+macro_rules! sql_store {
+    ($(#[$meta:meta])* $name:ident, $pool:ty) => {
+        $(#[$meta])*
+        pub struct $name {
+            pool: $pool,
+            schema_ready: OnceCell<()>,
+        }
+
+        impl $name {
+            /// Wrap an existing connection pool
+            pub fn new(pool: $pool) -> Self {
+                Self {
+                    pool,
+                    schema_ready: OnceCell::new(),
+                }
+            }
+
+            /// Connection pool with the schema created
+            async fn pool(&self) -> AuthResult<&$pool> {
+                self.schema_ready
+                    .get_or_try_init(|| async {
+                        for statement in SCHEMA {
+                            sqlx::query(statement).execute(&self.pool).await?;
+                        }
+                        Ok::<_, sqlx::Error>(())
+                    })
+                    .await?;
+                Ok(&self.pool)
+            }
+        }
+    };
+}
+
+sql_store!(
+    /// Store backed by Postgres
+    PgStore,
+    PgPool
+);
+
+sql_store!(
+    /// Store backed by SQLite
+    SqliteStore,
+    SqlitePool
+);
+
+impl SqliteStore {
+    /// Private in-memory database, mainly for tests
+    pub fn in_memory() -> AuthResult<Self> {
+        connect_sqlite("sqlite::memory:", 1)
+    }
+}
+
+fn connect_sqlite(url: &str, max_connections: u32) -> AuthResult<SqliteStore> {
+    let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+    let in_memory = url.contains(":memory:") || url.contains("mode=memory");
+
+    // Every connection to an in-memory database sees its own empty copy, so
+    // keep exactly one connection open for the lifetime of the pool
+    let pool = if in_memory {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_lazy_with(options)
+    } else {
+        SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_lazy_with(options)
+    };
+
+    Ok(SqliteStore::new(pool))
+}
+
+/// Open the store for a database URL
+///
+/// `postgres://` URLs use Postgres and `sqlite:` URLs use SQLite. The
+/// connection is established lazily on first use.
+pub fn connect_store(url: &str, max_connections: u32) -> AuthResult<Arc<dyn AuthStore>> {
+    if url.starts_with("sqlite:") {
+        Ok(Arc::new(connect_sqlite(url, max_connections)?))
+    } else if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect_lazy(url)?;
+        Ok(Arc::new(PgStore::new(pool)))
+    } else {
+        Err(AuthError::DatabaseError(format!("Unsupported database URL: {}", url)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgConnectOptions;
+
+    /// Postgres server the Postgres tests run against
+    const POSTGRES_URL_VAR: &str = "NEXA_TEST_POSTGRES_URL";
+
+    /// A user of the default tenant, not yet stored
+    pub(super) fn user(username: &str) -> User {
+        User {
+            id: uuid::Uuid::new_v4().to_string(),
+            username: username.to_string(),
+            password_hash: "hash".to_string(),
+            roles: vec!["user".to_string()],
+            tenant: crate::tenants::DEFAULT_TENANT.to_string(),
+            failed_attempts: 0,
+            locked_until: None,
+            last_login: None,
+            created_at: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn test_unsupported_database_url() {
+        assert!(connect_store("mysql://localhost/nexa", 5).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_postgres_store() {
+        // Skip test if no Postgres server is configured
+        let Ok(url) = std::env::var(POSTGRES_URL_VAR) else {
+            println!("Skipping test_postgres_store as {} is not set", POSTGRES_URL_VAR);
+            return;
+        };
+        let server = PgPool::connect(&url).await.unwrap();
+
+        // Each check runs in a schema of its own, dropped afterwards
+        macro_rules! check {
+            ($($check:path),* $(,)?) => {$({
+                let schema = format!("nexa_test_{}", uuid::Uuid::new_v4().simple());
+                sqlx::query(&format!("CREATE SCHEMA {}", schema)).execute(&server).await.unwrap();
+                let options = PgConnectOptions::from_str(&url).unwrap().options([("search_path", schema.as_str())]);
+                let store = PgStore::new(PgPoolOptions::new().max_connections(2).connect_lazy_with(options));
+                $check(&store).await;
+                store.pool.close().await;
+                sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema)).execute(&server).await.unwrap();
+            })*};
+        }
+        check!(
+            accounts::tests::check_crud,
+            accounts::tests::check_login_failure_locks_account,
+            tokens::tests::check_refresh_token_rotation_and_cascade,
+            tokens::tests::check_sessions_and_revocations,
+            keys::tests::check_api_keys,
+            keys::tests::check_signing_key_retirement,
+            roles::tests::check_roles,
+            second_factor::tests::check_totp_and_recovery_codes,
+            identities::tests::check_oidc_identities_and_logins,
+            audit::tests::check_audit_events,
+            usage::tests::check_token_usage,
+        );
+    }
+}
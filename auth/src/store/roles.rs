@@ -0,0 +1,99 @@
+//! Roles created at runtime
+
+use super::{join_names, split_names, PgStore, SqliteStore};
+use crate::permissions::RoleDefinition;
+use crate::AuthResult;
+use async_trait::async_trait;
+use sqlx::Row;
+
+const ROLE_COLUMNS: &str = "name, permissions, inherits";
+
+/// Persistence operations for roles created at runtime
+#[async_trait]
+pub trait RoleStore: Send + Sync {
+    /// Roles created at runtime, ordered by name
+    async fn list_roles(&self) -> AuthResult<Vec<RoleDefinition>>;
+
+    /// Create a role or replace the one with the same name
+    async fn upsert_role(&self, role: &RoleDefinition) -> AuthResult<()>;
+
+    /// Delete a role, returning whether it existed
+    async fn delete_role(&self, name: &str) -> AuthResult<bool>;
+}
+
+/// Implements `RoleStore` for a store; both backends share the same SQL
+macro_rules! sql_role_store {
+    ($name:ident, $row:ty) => {
+        impl $name {
+            fn role_from_row(row: &$row) -> AuthResult<RoleDefinition> {
+                Ok(RoleDefinition {
+                    name: row.try_get("name")?,
+                    permissions: split_names(row.try_get("permissions")?),
+                    inherits: split_names(row.try_get("inherits")?),
+                })
+            }
+        }
+
+        #[async_trait]
+        impl RoleStore for $name {
+            async fn list_roles(&self) -> AuthResult<Vec<RoleDefinition>> {
+                let sql = format!("SELECT {} FROM roles ORDER BY name", ROLE_COLUMNS);
+                let rows = sqlx::query(&sql).fetch_all(self.pool().await?).await?;
+                rows.iter().map(Self::role_from_row).collect()
+            }
+
+            async fn upsert_role(&self, role: &RoleDefinition) -> AuthResult<()> {
+                sqlx::query(
+                    "INSERT INTO roles (name, permissions, inherits) VALUES ($1, $2, $3) \
+                     ON CONFLICT (name) DO UPDATE SET permissions = excluded.permissions, inherits = excluded.inherits",
+                )
+                .bind(&role.name)
+                .bind(join_names(&role.permissions))
+                .bind(join_names(&role.inherits))
+                .execute(self.pool().await?)
+                .await?;
+                Ok(())
+            }
+
+            async fn delete_role(&self, name: &str) -> AuthResult<bool> {
+                let result = sqlx::query("DELETE FROM roles WHERE name = $1")
+                    .bind(name)
+                    .execute(self.pool().await?)
+                    .await?;
+                Ok(result.rows_affected() > 0)
+            }
+        }
+    };
+}
+
+sql_role_store!(PgStore, sqlx::postgres::PgRow);
+sql_role_store!(SqliteStore, sqlx::sqlite::SqliteRow);
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::store::AuthStore;
+
+    pub(in crate::store) async fn check_roles(store: &dyn AuthStore) {
+        let mut role = RoleDefinition {
+            name: "support".to_string(),
+            permissions: vec!["user:read".to_string(), "agent:*".to_string()],
+            inherits: vec!["readonly".to_string()],
+        };
+        store.upsert_role(&role).await.unwrap();
+        assert_eq!(store.list_roles().await.unwrap(), vec![role.clone()]);
+
+        role.permissions.clear();
+        store.upsert_role(&role).await.unwrap();
+        assert_eq!(store.list_roles().await.unwrap(), vec![role]);
+
+        assert!(store.delete_role("support").await.unwrap());
+        assert!(!store.delete_role("support").await.unwrap());
+        assert!(store.list_roles().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_roles() {
+        check_roles(&SqliteStore::in_memory().unwrap()).await;
+    }
+}
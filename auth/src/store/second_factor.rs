@@ -0,0 +1,220 @@
+//! TOTP secrets and recovery codes
+
+use super::{PgStore, SqliteStore};
+use crate::{AuthError, AuthResult};
+use async_trait::async_trait;
+use common::secret::Secret;
+use sqlx::Row;
+
+const TOTP_COLUMNS: &str = "user_id, secret, enabled_at, last_used_step, created_at";
+
+/// A user's TOTP secret; enrollment is pending until a first code confirms it
+#[derive(Debug, Clone)]
+pub struct TotpCredential {
+    /// Owner of the secret
+    pub user_id: String,
+    /// Base32 shared secret, encrypted at rest when a master key is configured
+    pub secret: Secret,
+    /// Unix timestamp at which enrollment was confirmed; `None` while pending
+    pub enabled_at: Option<i64>,
+    /// Time step of the last accepted code
+    pub last_used_step: i64,
+    /// Unix timestamp at which enrollment started
+    pub created_at: i64,
+}
+
+/// Persistence operations for second factors
+#[async_trait]
+pub trait SecondFactorStore: Send + Sync {
+    /// Store a new TOTP secret, replacing any earlier one of the user
+    async fn save_totp(&self, credential: &TotpCredential) -> AuthResult<()>;
+
+    /// TOTP secret of a user
+    async fn find_totp(&self, user_id: &str) -> AuthResult<Option<TotpCredential>>;
+
+    /// Confirm a pending enrollment
+    async fn enable_totp(&self, user_id: &str, at: i64) -> AuthResult<()>;
+
+    /// Record the time step of an accepted code. Returns false when that step
+    /// or a later one was already used, so a code cannot be replayed.
+    async fn use_totp_step(&self, user_id: &str, step: i64) -> AuthResult<bool>;
+
+    /// Remove a user's TOTP secret and recovery codes, returning whether a secret existed
+    async fn delete_totp(&self, user_id: &str) -> AuthResult<bool>;
+
+    /// Replace a user's recovery codes
+    async fn replace_recovery_codes(&self, user_id: &str, code_hashes: &[String]) -> AuthResult<()>;
+
+    /// Mark a recovery code as used, returning false if it is unknown or already used
+    async fn use_recovery_code(&self, user_id: &str, code_hash: &str, at: i64) -> AuthResult<bool>;
+
+    /// Number of unused recovery codes of a user
+    async fn count_recovery_codes(&self, user_id: &str) -> AuthResult<u64>;
+}
+
+/// Implements `SecondFactorStore` for a store; both backends share the same SQL
+macro_rules! sql_second_factor_store {
+    ($name:ident, $row:ty) => {
+        impl $name {
+            fn totp_from_row(row: &$row) -> AuthResult<TotpCredential> {
+                Ok(TotpCredential {
+                    user_id: row.try_get("user_id")?,
+                    secret: Secret::from_stored(&row.try_get::<String, _>("secret")?)
+                        .map_err(|e| AuthError::Configuration(format!("Cannot read TOTP secret: {}", e)))?,
+                    enabled_at: row.try_get("enabled_at")?,
+                    last_used_step: row.try_get("last_used_step")?,
+                    created_at: row.try_get("created_at")?,
+                })
+            }
+        }
+
+        #[async_trait]
+        impl SecondFactorStore for $name {
+            async fn save_totp(&self, credential: &TotpCredential) -> AuthResult<()> {
+                sqlx::query(
+                    "INSERT INTO totp_credentials (user_id, secret, enabled_at, last_used_step, created_at) \
+                     VALUES ($1, $2, $3, $4, $5) \
+                     ON CONFLICT (user_id) DO UPDATE SET secret = excluded.secret, enabled_at = excluded.enabled_at, \
+                         last_used_step = excluded.last_used_step, created_at = excluded.created_at",
+                )
+                .bind(&credential.user_id)
+                .bind(credential.secret.source())
+                .bind(credential.enabled_at)
+                .bind(credential.last_used_step)
+                .bind(credential.created_at)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_totp(&self, user_id: &str) -> AuthResult<Option<TotpCredential>> {
+                let sql = format!("SELECT {} FROM totp_credentials WHERE user_id = $1", TOTP_COLUMNS);
+                let row = sqlx::query(&sql).bind(user_id).fetch_optional(self.pool().await?).await?;
+                row.as_ref().map(Self::totp_from_row).transpose()
+            }
+
+            async fn enable_totp(&self, user_id: &str, at: i64) -> AuthResult<()> {
+                sqlx::query("UPDATE totp_credentials SET enabled_at = $2 WHERE user_id = $1")
+                    .bind(user_id)
+                    .bind(at)
+                    .execute(self.pool().await?)
+                    .await?;
+                Ok(())
+            }
+
+            async fn use_totp_step(&self, user_id: &str, step: i64) -> AuthResult<bool> {
+                let result = sqlx::query(
+                    "UPDATE totp_credentials SET last_used_step = $2 WHERE user_id = $1 AND last_used_step < $2",
+                )
+                .bind(user_id)
+                .bind(step)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(result.rows_affected() > 0)
+            }
+
+            async fn delete_totp(&self, user_id: &str) -> AuthResult<bool> {
+                let pool = self.pool().await?;
+                sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+                    .bind(user_id)
+                    .execute(pool)
+                    .await?;
+                let result = sqlx::query("DELETE FROM totp_credentials WHERE user_id = $1")
+                    .bind(user_id)
+                    .execute(pool)
+                    .await?;
+                Ok(result.rows_affected() > 0)
+            }
+
+            async fn replace_recovery_codes(&self, user_id: &str, code_hashes: &[String]) -> AuthResult<()> {
+                let mut tx = self.pool().await?.begin().await?;
+                sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+                    .bind(user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                for code_hash in code_hashes {
+                    sqlx::query("INSERT INTO recovery_codes (code_hash, user_id) VALUES ($1, $2)")
+                        .bind(code_hash)
+                        .bind(user_id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                tx.commit().await?;
+                Ok(())
+            }
+
+            async fn use_recovery_code(&self, user_id: &str, code_hash: &str, at: i64) -> AuthResult<bool> {
+                let result = sqlx::query(
+                    "UPDATE recovery_codes SET used_at = $3 WHERE code_hash = $2 AND user_id = $1 AND used_at IS NULL",
+                )
+                .bind(user_id)
+                .bind(code_hash)
+                .bind(at)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(result.rows_affected() > 0)
+            }
+
+            async fn count_recovery_codes(&self, user_id: &str) -> AuthResult<u64> {
+                let row = sqlx::query("SELECT COUNT(*) AS remaining FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL")
+                    .bind(user_id)
+                    .fetch_one(self.pool().await?)
+                    .await?;
+                let remaining: i64 = row.try_get("remaining")?;
+                Ok(remaining as u64)
+            }
+        }
+    };
+}
+
+sql_second_factor_store!(PgStore, sqlx::postgres::PgRow);
+sql_second_factor_store!(SqliteStore, sqlx::sqlite::SqliteRow);
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::store::tests::user;
+    use crate::store::AuthStore;
+
+    pub(in crate::store) async fn check_totp_and_recovery_codes(store: &dyn AuthStore) {
+        let alice = user("alice");
+        store.create_user(&alice).await.unwrap();
+
+        let credential = TotpCredential {
+            user_id: alice.id.clone(),
+            secret: Secret::from("SECRET"),
+            enabled_at: None,
+            last_used_step: 0,
+            created_at: 100,
+        };
+        store.save_totp(&credential).await.unwrap();
+        store.enable_totp(&alice.id, 150).await.unwrap();
+        let found = store.find_totp(&alice.id).await.unwrap().unwrap();
+        assert_eq!((found.secret.expose(), found.enabled_at), ("SECRET", Some(150)));
+
+        assert!(store.use_totp_step(&alice.id, 10).await.unwrap());
+        assert!(!store.use_totp_step(&alice.id, 10).await.unwrap());
+        assert!(!store.use_totp_step(&alice.id, 9).await.unwrap());
+        assert!(store.use_totp_step(&alice.id, 11).await.unwrap());
+
+        let hashes = vec!["hash-1".to_string(), "hash-2".to_string()];
+        store.replace_recovery_codes(&alice.id, &hashes).await.unwrap();
+        assert!(store.use_recovery_code(&alice.id, "hash-1", 200).await.unwrap());
+        assert!(!store.use_recovery_code(&alice.id, "hash-1", 200).await.unwrap());
+        assert!(!store.use_recovery_code("someone-else", "hash-2", 200).await.unwrap());
+        assert_eq!(store.count_recovery_codes(&alice.id).await.unwrap(), 1);
+
+        // Re-enrolling replaces the pending secret
+        store.save_totp(&credential).await.unwrap();
+        assert!(store.find_totp(&alice.id).await.unwrap().unwrap().enabled_at.is_none());
+
+        assert!(store.delete_totp(&alice.id).await.unwrap());
+        assert!(!store.delete_totp(&alice.id).await.unwrap());
+        assert_eq!(store.count_recovery_codes(&alice.id).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_totp_and_recovery_codes() {
+        check_totp_and_recovery_codes(&SqliteStore::in_memory().unwrap()).await;
+    }
+}
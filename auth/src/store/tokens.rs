@@ -0,0 +1,296 @@
+//! Refresh tokens, the sessions they make up and revoked token IDs
+
+use super::{PgStore, SqliteStore};
+use crate::revocation::RevocationStore;
+use crate::AuthResult;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+const REFRESH_TOKEN_COLUMNS: &str = "token_hash, user_id, family_id, expires_at, created_at, used_at, revoked_at";
+
+/// A stored refresh token; only a hash of the token itself is kept
+#[derive(Debug, Clone)]
+pub struct RefreshTokenRecord {
+    /// SHA-256 hash of the opaque token
+    pub token_hash: String,
+    /// Owner of the token
+    pub user_id: String,
+    /// Chain of rotated tokens descending from one login
+    pub family_id: String,
+    /// Unix timestamp after which the token is rejected
+    pub expires_at: i64,
+    /// Unix timestamp of issue
+    pub created_at: i64,
+    /// Unix timestamp at which the token was exchanged for a new one
+    pub used_at: Option<i64>,
+    /// Unix timestamp at which the token was revoked
+    pub revoked_at: Option<i64>,
+}
+
+/// A login session: the chain of refresh tokens descending from one login
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    /// Session ID, carried as `sid` in access tokens
+    pub id: String,
+    /// Owner of the session
+    pub user_id: String,
+    /// Unix timestamp of the login
+    pub started_at: i64,
+    /// Unix timestamp at which the current refresh token was issued
+    pub refreshed_at: i64,
+    /// Unix timestamp after which the current refresh token is rejected
+    pub expires_at: i64,
+}
+
+/// Persistence operations for refresh tokens and sessions
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    /// Store a newly issued refresh token
+    async fn insert_refresh_token(&self, token: &RefreshTokenRecord) -> AuthResult<()>;
+
+    /// Look a refresh token up by hash
+    async fn find_refresh_token(&self, token_hash: &str) -> AuthResult<Option<RefreshTokenRecord>>;
+
+    /// Mark a refresh token as exchanged. Returns false when it was already
+    /// used or revoked, so concurrent exchanges of one token cannot both win.
+    async fn mark_refresh_token_used(&self, token_hash: &str, at: i64) -> AuthResult<bool>;
+
+    /// Revoke every refresh token in a family, returning how many were revoked
+    async fn revoke_refresh_token_family(&self, family_id: &str, at: i64) -> AuthResult<u64>;
+
+    /// Revoke every refresh token of a user, returning how many were revoked
+    async fn revoke_user_refresh_tokens(&self, user_id: &str, at: i64) -> AuthResult<u64>;
+
+    /// Sessions of a user whose current refresh token is still usable at `now`
+    async fn list_sessions(&self, user_id: &str, now: i64) -> AuthResult<Vec<Session>>;
+}
+
+/// Implements `TokenStore` and `RevocationStore` for a store; both backends
+/// share the same SQL
+macro_rules! sql_token_store {
+    ($name:ident, $row:ty) => {
+        impl $name {
+            fn refresh_token_from_row(row: &$row) -> AuthResult<RefreshTokenRecord> {
+                Ok(RefreshTokenRecord {
+                    token_hash: row.try_get("token_hash")?,
+                    user_id: row.try_get("user_id")?,
+                    family_id: row.try_get("family_id")?,
+                    expires_at: row.try_get("expires_at")?,
+                    created_at: row.try_get("created_at")?,
+                    used_at: row.try_get("used_at")?,
+                    revoked_at: row.try_get("revoked_at")?,
+                })
+            }
+        }
+
+        #[async_trait]
+        impl TokenStore for $name {
+            async fn insert_refresh_token(&self, token: &RefreshTokenRecord) -> AuthResult<()> {
+                sqlx::query(
+                    "INSERT INTO refresh_tokens (token_hash, user_id, family_id, expires_at, created_at, used_at, revoked_at) \
+                     VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                )
+                .bind(&token.token_hash)
+                .bind(&token.user_id)
+                .bind(&token.family_id)
+                .bind(token.expires_at)
+                .bind(token.created_at)
+                .bind(token.used_at)
+                .bind(token.revoked_at)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_refresh_token(&self, token_hash: &str) -> AuthResult<Option<RefreshTokenRecord>> {
+                let sql = format!("SELECT {} FROM refresh_tokens WHERE token_hash = $1", REFRESH_TOKEN_COLUMNS);
+                let row = sqlx::query(&sql).bind(token_hash).fetch_optional(self.pool().await?).await?;
+                row.as_ref().map(Self::refresh_token_from_row).transpose()
+            }
+
+            async fn mark_refresh_token_used(&self, token_hash: &str, at: i64) -> AuthResult<bool> {
+                let result = sqlx::query(
+                    "UPDATE refresh_tokens SET used_at = $2 \
+                     WHERE token_hash = $1 AND used_at IS NULL AND revoked_at IS NULL",
+                )
+                .bind(token_hash)
+                .bind(at)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(result.rows_affected() > 0)
+            }
+
+            async fn revoke_refresh_token_family(&self, family_id: &str, at: i64) -> AuthResult<u64> {
+                let result = sqlx::query(
+                    "UPDATE refresh_tokens SET revoked_at = $2 WHERE family_id = $1 AND revoked_at IS NULL",
+                )
+                .bind(family_id)
+                .bind(at)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(result.rows_affected())
+            }
+
+            async fn revoke_user_refresh_tokens(&self, user_id: &str, at: i64) -> AuthResult<u64> {
+                let result = sqlx::query(
+                    "UPDATE refresh_tokens SET revoked_at = $2 WHERE user_id = $1 AND revoked_at IS NULL",
+                )
+                .bind(user_id)
+                .bind(at)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(result.rows_affected())
+            }
+
+            async fn list_sessions(&self, user_id: &str, now: i64) -> AuthResult<Vec<Session>> {
+                let rows = sqlx::query(
+                    "SELECT t.family_id, t.user_id, t.created_at, t.expires_at, \
+                         (SELECT MIN(f.created_at) FROM refresh_tokens f WHERE f.family_id = t.family_id) AS started_at \
+                     FROM refresh_tokens t \
+                     WHERE t.user_id = $1 AND t.used_at IS NULL AND t.revoked_at IS NULL AND t.expires_at > $2 \
+                     ORDER BY started_at, t.family_id",
+                )
+                .bind(user_id)
+                .bind(now)
+                .fetch_all(self.pool().await?)
+                .await?;
+
+                rows.iter()
+                    .map(|row| {
+                        Ok(Session {
+                            id: row.try_get("family_id")?,
+                            user_id: row.try_get("user_id")?,
+                            started_at: row.try_get("started_at")?,
+                            refreshed_at: row.try_get("created_at")?,
+                            expires_at: row.try_get("expires_at")?,
+                        })
+                    })
+                    .collect()
+            }
+        }
+
+        #[async_trait]
+        impl RevocationStore for $name {
+            async fn revoke(&self, id: &str, revoked_at: i64, expires_at: i64) -> AuthResult<()> {
+                sqlx::query(
+                    "INSERT INTO revoked_tokens (id, revoked_at, expires_at) VALUES ($1, $2, $3) \
+                     ON CONFLICT (id) DO UPDATE SET revoked_at = excluded.revoked_at, expires_at = excluded.expires_at",
+                )
+                .bind(id)
+                .bind(revoked_at)
+                .bind(expires_at)
+                .execute(self.pool().await?)
+                .await?;
+                Ok(())
+            }
+
+            async fn revoked_since(&self, since: i64, now: i64) -> AuthResult<Vec<(String, i64)>> {
+                let rows = sqlx::query(
+                    "SELECT id, expires_at FROM revoked_tokens WHERE revoked_at >= $1 AND expires_at > $2",
+                )
+                .bind(since)
+                .bind(now)
+                .fetch_all(self.pool().await?)
+                .await?;
+                rows.iter()
+                    .map(|row| Ok((row.try_get("id")?, row.try_get("expires_at")?)))
+                    .collect()
+            }
+
+            async fn purge_expired_revocations(&self, now: i64) -> AuthResult<u64> {
+                let result = sqlx::query("DELETE FROM revoked_tokens WHERE expires_at <= $1")
+                    .bind(now)
+                    .execute(self.pool().await?)
+                    .await?;
+                Ok(result.rows_affected())
+            }
+        }
+    };
+}
+
+sql_token_store!(PgStore, sqlx::postgres::PgRow);
+sql_token_store!(SqliteStore, sqlx::sqlite::SqliteRow);
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::store::tests::user;
+    use crate::store::AuthStore;
+
+    pub(in crate::store) async fn check_refresh_token_rotation_and_cascade(store: &dyn AuthStore) {
+        let gina = user("gina");
+        store.create_user(&gina).await.unwrap();
+
+        let token = RefreshTokenRecord {
+            token_hash: "hash-1".to_string(),
+            user_id: gina.id.clone(),
+            family_id: "family-1".to_string(),
+            expires_at: 2_000_000_000,
+            created_at: 1_700_000_000,
+            used_at: None,
+            revoked_at: None,
+        };
+        store.insert_refresh_token(&token).await.unwrap();
+
+        assert!(store.mark_refresh_token_used("hash-1", 10).await.unwrap());
+        assert!(!store.mark_refresh_token_used("hash-1", 11).await.unwrap());
+
+        store
+            .insert_refresh_token(&RefreshTokenRecord {
+                token_hash: "hash-2".to_string(),
+                ..token.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(store.revoke_refresh_token_family("family-1", 12).await.unwrap(), 2);
+        let revoked = store.find_refresh_token("hash-2").await.unwrap().unwrap();
+        assert_eq!(revoked.revoked_at, Some(12));
+
+        // Deleting the user removes its tokens
+        store.delete_user(&gina.id).await.unwrap();
+        assert!(store.find_refresh_token("hash-2").await.unwrap().is_none());
+    }
+
+    pub(in crate::store) async fn check_sessions_and_revocations(store: &dyn AuthStore) {
+        let hank = user("hank");
+        store.create_user(&hank).await.unwrap();
+
+        let token = |hash: &str, family: &str, created_at: i64| RefreshTokenRecord {
+            token_hash: hash.to_string(),
+            user_id: hank.id.clone(),
+            family_id: family.to_string(),
+            expires_at: created_at + 1000,
+            created_at,
+            used_at: None,
+            revoked_at: None,
+        };
+        store.insert_refresh_token(&token("a1", "a", 100)).await.unwrap();
+        store.mark_refresh_token_used("a1", 200).await.unwrap();
+        store.insert_refresh_token(&token("a2", "a", 200)).await.unwrap();
+        store.insert_refresh_token(&token("b1", "b", 150)).await.unwrap();
+        store.insert_refresh_token(&token("c1", "c", 160)).await.unwrap();
+        store.revoke_refresh_token_family("c", 170).await.unwrap();
+
+        let sessions = store.list_sessions(&hank.id, 300).await.unwrap();
+        let summary: Vec<_> = sessions.iter().map(|s| (s.id.as_str(), s.started_at, s.refreshed_at)).collect();
+        assert_eq!(summary, vec![("a", 100, 200), ("b", 150, 150)]);
+        assert_eq!(store.list_sessions(&hank.id, 1150).await.unwrap().len(), 1);
+
+        store.revoke("sid-a", 300, 400).await.unwrap();
+        store.revoke("sid-a", 310, 500).await.unwrap();
+        assert_eq!(store.revoked_since(300, 450).await.unwrap(), vec![("sid-a".to_string(), 500)]);
+        assert!(store.revoked_since(311, 450).await.unwrap().is_empty());
+        assert_eq!(store.purge_expired_revocations(500).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_refresh_token_rotation_and_cascade() {
+        check_refresh_token_rotation_and_cascade(&SqliteStore::in_memory().unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_sessions_and_revocations() {
+        check_sessions_and_revocations(&SqliteStore::in_memory().unwrap()).await;
+    }
+}
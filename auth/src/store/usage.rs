@@ -0,0 +1,100 @@
+//! LLM token usage counters
+
+use super::{PgStore, SqliteStore};
+use crate::usage::{TokenUsage, UsageStore};
+use crate::AuthResult;
+use async_trait::async_trait;
+use sqlx::Row;
+
+/// Implements `UsageStore` for a store; both backends share the same SQL
+macro_rules! sql_usage_store {
+    ($name:ident) => {
+        #[async_trait]
+        impl UsageStore for $name {
+            async fn add_token_usage(
+                &self,
+                tenant: &str,
+                subject: &str,
+                period: &str,
+                tokens: u64,
+                now: i64,
+            ) -> AuthResult<u64> {
+                let total: i64 = sqlx::query_scalar(
+                    "INSERT INTO token_usage (tenant, subject, period, tokens, updated_at) VALUES ($1, $2, $3, $4, $5) \
+                     ON CONFLICT (tenant, subject, period) DO UPDATE \
+                     SET tokens = token_usage.tokens + excluded.tokens, updated_at = excluded.updated_at \
+                     RETURNING tokens",
+                )
+                .bind(tenant)
+                .bind(subject)
+                .bind(period)
+                .bind(i64::try_from(tokens).unwrap_or(i64::MAX))
+                .bind(now)
+                .fetch_one(self.pool().await?)
+                .await?;
+                Ok(total.max(0) as u64)
+            }
+
+            async fn token_usage(&self, tenant: &str, subject: &str, period: &str) -> AuthResult<u64> {
+                let total: Option<i64> = sqlx::query_scalar(
+                    "SELECT tokens FROM token_usage WHERE tenant = $1 AND subject = $2 AND period = $3",
+                )
+                .bind(tenant)
+                .bind(subject)
+                .bind(period)
+                .fetch_optional(self.pool().await?)
+                .await?;
+                Ok(total.unwrap_or(0).max(0) as u64)
+            }
+
+            async fn token_usage_for_period(&self, tenant: &str, period: &str) -> AuthResult<Vec<TokenUsage>> {
+                let rows = sqlx::query(
+                    "SELECT subject, period, tokens FROM token_usage WHERE tenant = $1 AND period = $2 ORDER BY subject",
+                )
+                .bind(tenant)
+                .bind(period)
+                .fetch_all(self.pool().await?)
+                .await?;
+                rows.iter()
+                    .map(|row| {
+                        Ok(TokenUsage {
+                            subject: row.try_get("subject")?,
+                            period: row.try_get("period")?,
+                            tokens: row.try_get::<i64, _>("tokens")?.max(0) as u64,
+                        })
+                    })
+                    .collect()
+            }
+        }
+    };
+}
+
+sql_usage_store!(PgStore);
+sql_usage_store!(SqliteStore);
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::store::AuthStore;
+
+    pub(in crate::store) async fn check_token_usage(store: &dyn AuthStore) {
+        let tenant = crate::tenants::DEFAULT_TENANT;
+        assert_eq!(store.add_token_usage(tenant, "user:alice", "2025-01", 120, 100).await.unwrap(), 120);
+        assert_eq!(store.add_token_usage(tenant, "user:alice", "2025-01", 30, 200).await.unwrap(), 150);
+        store.add_token_usage(tenant, "team:research", "2025-01", 30, 200).await.unwrap();
+        store.add_token_usage(tenant, "user:alice", "2025-01-31", 30, 200).await.unwrap();
+        store.add_token_usage("acme", "user:alice", "2025-01", 5, 200).await.unwrap();
+
+        assert_eq!(store.token_usage(tenant, "user:alice", "2025-01").await.unwrap(), 150);
+        assert_eq!(store.token_usage(tenant, "user:bob", "2025-01").await.unwrap(), 0);
+        assert_eq!(store.token_usage("acme", "user:alice", "2025-01").await.unwrap(), 5);
+        let month = store.token_usage_for_period(tenant, "2025-01").await.unwrap();
+        let totals: Vec<(&str, u64)> = month.iter().map(|usage| (usage.subject.as_str(), usage.tokens)).collect();
+        assert_eq!(totals, [("team:research", 30), ("user:alice", 150)]);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_token_usage() {
+        check_token_usage(&SqliteStore::in_memory().unwrap()).await;
+    }
+}
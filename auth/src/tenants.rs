@@ -0,0 +1,48 @@
+//! Tenants
+//!
+//! Every user belongs to one tenant (organization), carried in their tokens.
+//! Agents, vector collections, Agora topics, token usage and audit entries
+//! are scoped to it. Tenant names end up in collection and topic names, so
+//! they are restricted to lowercase letters, digits and dashes.
+
+use crate::error::AuthError;
+
+/// Tenant of accounts created without one, and of tokens issued before tenants
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Longest accepted tenant name
+const MAX_TENANT_LENGTH: usize = 63;
+
+/// Default tenant, for `#[serde(default = ...)]`
+pub fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+/// Check a tenant name: 1-63 lowercase letters, digits and inner dashes
+pub fn validate_tenant(tenant: &str) -> Result<(), AuthError> {
+    let valid = !tenant.is_empty()
+        && tenant.len() <= MAX_TENANT_LENGTH
+        && tenant.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !tenant.starts_with('-')
+        && !tenant.ends_with('-');
+    if valid {
+        Ok(())
+    } else {
+        Err(AuthError::InvalidTenant(tenant.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_tenant() {
+        for tenant in [DEFAULT_TENANT, "acme", "acme-eu-1", "42"] {
+            assert!(validate_tenant(tenant).is_ok(), "{}", tenant);
+        }
+        for tenant in ["", "Acme", "acme_eu", "-acme", "acme-", "acme/eu", &"a".repeat(64)] {
+            assert!(validate_tenant(tenant).is_err(), "{}", tenant);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! LLM token usage counters
+//!
+//! Tokens consumed on the completion path are counted per tenant, per
+//! subject, a user, an API key or a team, and per period, a UTC day or month. Counters live
+//! in the auth store so budgets hold across gateway instances and restarts.
+
+use crate::AuthResult;
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use serde::Serialize;
+
+/// Period a budget covers, in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsagePeriod {
+    Day,
+    Month,
+}
+
+impl UsagePeriod {
+    pub const ALL: [UsagePeriod; 2] = [UsagePeriod::Day, UsagePeriod::Month];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UsagePeriod::Day => "day",
+            UsagePeriod::Month => "month",
+        }
+    }
+
+    /// Counter key of the period containing `now`: `2025-01-31` or `2025-01`
+    pub fn key(&self, now: DateTime<Utc>) -> String {
+        match self {
+            UsagePeriod::Day => now.format("%Y-%m-%d").to_string(),
+            UsagePeriod::Month => now.format("%Y-%m").to_string(),
+        }
+    }
+
+    /// Unix time the period after the one containing `now` starts
+    pub fn resets_at(&self, now: DateTime<Utc>) -> i64 {
+        let today = now.date_naive();
+        let next = match self {
+            UsagePeriod::Day => today.succ_opt(),
+            UsagePeriod::Month => NaiveDate::from_ymd_opt(today.year(), today.month(), 1)
+                .and_then(|first| first.checked_add_months(Months::new(1))),
+        };
+        next.and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|start| start.and_utc().timestamp())
+            .unwrap_or(i64::MAX)
+    }
+}
+
+/// What usage is counted against
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UsageSubject {
+    /// User, by username; includes the user's API keys
+    User(String),
+    /// API key, by key ID
+    ApiKey(String),
+    /// Team, by name
+    Team(String),
+}
+
+impl UsageSubject {
+    /// Stored key, e.g. `user:alice`
+    pub fn key(&self) -> String {
+        match self {
+            UsageSubject::User(username) => format!("user:{}", username),
+            UsageSubject::ApiKey(id) => format!("api_key:{}", id),
+            UsageSubject::Team(name) => format!("team:{}", name),
+        }
+    }
+
+    /// Parse a stored key
+    pub fn from_key(key: &str) -> Option<Self> {
+        let (kind, id) = key.split_once(':')?;
+        match kind {
+            "user" => Some(UsageSubject::User(id.to_string())),
+            "api_key" => Some(UsageSubject::ApiKey(id.to_string())),
+            "team" => Some(UsageSubject::Team(id.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Tokens a subject used in a period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenUsage {
+    /// Subject key, e.g. `user:alice`
+    pub subject: String,
+    /// Period key, e.g. `2025-01` for a month
+    pub period: String,
+    pub tokens: u64,
+}
+
+/// Storage for token usage counters
+#[async_trait]
+pub trait UsageStore: Send + Sync {
+    /// Add tokens to a subject's counter for a period, returning the new total
+    async fn add_token_usage(&self, tenant: &str, subject: &str, period: &str, tokens: u64, now: i64)
+        -> AuthResult<u64>;
+
+    /// Tokens a subject of a tenant used in a period
+    async fn token_usage(&self, tenant: &str, subject: &str, period: &str) -> AuthResult<u64>;
+
+    /// Counters of every subject of a tenant for a period
+    async fn token_usage_for_period(&self, tenant: &str, period: &str) -> AuthResult<Vec<TokenUsage>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_period_keys_and_resets() {
+        let now = Utc.with_ymd_and_hms(2024, 12, 31, 18, 30, 0).unwrap();
+        assert_eq!(UsagePeriod::Day.key(now), "2024-12-31");
+        assert_eq!(UsagePeriod::Month.key(now), "2024-12");
+        assert_eq!(UsagePeriod::Day.resets_at(now), Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap().timestamp());
+        assert_eq!(UsagePeriod::Month.resets_at(now), Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap().timestamp());
+
+        let subject = UsageSubject::Team("research".to_string());
+        assert_eq!(UsageSubject::from_key(&subject.key()), Some(subject));
+        assert_eq!(UsageSubject::from_key("group:x"), None);
+    }
+}
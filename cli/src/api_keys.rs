@@ -0,0 +1,50 @@
+//! API key commands
+//!
+//! Create, list and revoke the API keys agents and CI jobs authenticate
+//! with. Keys are stored in the configured database, hashed, so a key is
+//! only shown when it is created.
+
+use anyhow::Result;
+use colored::Colorize;
+use core::config;
+
+/// Create a key for a user and print it
+pub async fn create(username: &str, name: &str, scopes: &[String], expires_in_days: Option<u32>) -> Result<()> {
+    let key = config::generate_api_key(username, name, scopes, expires_in_days).await?;
+    println!("{}", key);
+    println!("{}", "Store this key now; it cannot be shown again".yellow());
+    Ok(())
+}
+
+/// List a user's keys
+pub async fn list(username: &str) -> Result<()> {
+    let keys = config::get_api_keys(username).await?;
+    let timestamp = |ts: Option<i64>| {
+        ts.and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+            .map(|ts| ts.to_rfc3339())
+    };
+
+    println!("{}", format!("API keys of {}:", username).bold());
+    for key in keys {
+        let status = if let Some(revoked) = timestamp(key.revoked_at) {
+            format!("revoked {}", revoked).red().to_string()
+        } else {
+            match timestamp(key.expires_at) {
+                Some(expires) => format!("expires {}", expires),
+                None => "no expiry".to_string(),
+            }
+        };
+        println!("  {} {} [{}] {}", key.id, key.name.bold(), key.scopes.join(", "), status);
+        if let Some(used) = timestamp(key.last_used_at) {
+            println!("    last used {}", used);
+        }
+    }
+    Ok(())
+}
+
+/// Revoke one of a user's keys
+pub async fn revoke(username: &str, key_id: &str) -> Result<()> {
+    config::revoke_api_key(username, key_id).await?;
+    println!("{}", format!("API key '{}' revoked", key_id).green());
+    Ok(())
+}
@@ -0,0 +1,81 @@
+//! Audit log commands
+//!
+//! Show who changed what, read from the audit log in the configured
+//! database. Events come newest first, with the fields each change touched.
+
+use anyhow::{Context, Result};
+use auth::audit::{AuditOutcome, AuditQuery};
+use colored::Colorize;
+use core::config;
+
+/// Filters given on the command line
+pub struct Filters {
+    pub tenant: Option<String>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub failures: bool,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<u32>,
+}
+
+/// Unix timestamp from an RFC 3339 time
+fn timestamp(time: &str) -> Result<i64> {
+    let time = chrono::DateTime::parse_from_rfc3339(time)
+        .with_context(|| format!("Invalid time '{}', expected e.g. 2025-01-31T09:00:00Z", time))?;
+    Ok(time.timestamp())
+}
+
+/// List audit events, as text or as JSON Lines
+pub async fn list(filters: Filters, json: bool) -> Result<()> {
+    let query = AuditQuery {
+        tenant: filters.tenant,
+        actor: filters.actor,
+        action: filters.action,
+        target: filters.target,
+        outcome: filters.failures.then_some(AuditOutcome::Failure),
+        since: filters.since.as_deref().map(timestamp).transpose()?,
+        until: filters.until.as_deref().map(timestamp).transpose()?,
+        limit: filters.limit,
+    };
+    let events = config::get_audit_events(&query).await?;
+
+    if json {
+        for event in &events {
+            println!("{}", serde_json::to_string(event)?);
+        }
+        return Ok(());
+    }
+
+    if events.is_empty() {
+        println!("No audit events found.");
+        return Ok(());
+    }
+    for event in &events {
+        let time = chrono::DateTime::from_timestamp(event.occurred_at, 0)
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_else(|| event.occurred_at.to_string());
+        let outcome = match event.outcome {
+            AuditOutcome::Success => "ok".green(),
+            AuditOutcome::Failure => "failed".red(),
+        };
+        println!(
+            "{} {} {} by {} on {} from {}",
+            time.dimmed(),
+            outcome,
+            event.action.bold(),
+            event.actor.as_deref().unwrap_or("-"),
+            event.target.as_deref().unwrap_or("-"),
+            event.source_ip.as_deref().unwrap_or("-"),
+        );
+        if let Some(reason) = &event.reason {
+            println!("    {}", reason.red());
+        }
+        for change in event.changes() {
+            let field = if change.field.is_empty() { "value" } else { change.field.as_str() };
+            println!("    {}: {} -> {}", field, change.before, change.after);
+        }
+    }
+    Ok(())
+}
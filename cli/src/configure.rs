@@ -4,7 +4,7 @@
 
 use anyhow::Result;
 use console::style;
-use dialoguer::{theme::ColorfulTheme, Input, Select, Confirm, Password};
+use dialoguer::{theme::ColorfulTheme, Input, MultiSelect, Select, Confirm, Password};
 use common::config::{LlmProviderSettings, AgentCommunicationSettings};
 use core::config::{self, NetworkSettings, AuthSettings, LogSettings, OrchestratorSettings};
 
@@ -16,7 +16,7 @@ pub async fn run_configuration() -> Result<()> {
     println!("╚══════════════════════════════════╝");
     println!();
     
-    // Ask for configuration options
+    // Ask for configuration options until the changes are saved or dropped
     let config_options = &[
         "Network Settings",
         "Authentication Settings",
@@ -26,27 +26,33 @@ pub async fn run_configuration() -> Result<()> {
         "Log Settings",
         "User Management",
         "Save and Exit",
+        "Exit without Saving",
     ];
     
-    let selection = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt("Select a configuration category:")
-        .items(config_options)
-        .default(0)
-        .interact()?;
-    
-    match selection {
-        0 => configure_network().await?,
-        1 => configure_authentication().await?,
-        2 => configure_orchestrator().await?,
-        3 => configure_llm_providers().await?,
-        4 => configure_agent_communication().await?,
-        5 => configure_logging().await?,
-        6 => configure_users().await?,
-        7 => save_configuration().await?,
-        _ => unreachable!(),
+    loop {
+        let selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Select a configuration category:")
+            .items(config_options)
+            .default(0)
+            .interact()?;
+        
+        let result = match selection {
+            0 => configure_network().await,
+            1 => configure_authentication().await,
+            2 => configure_orchestrator().await,
+            3 => configure_llm_providers().await,
+            4 => configure_agent_communication().await,
+            5 => configure_logging().await,
+            6 => configure_users().await,
+            7 => return save_configuration().await,
+            8 => return discard_configuration(),
+            _ => unreachable!(),
+        };
+        // A rejected change leaves the others staged
+        if let Err(e) = result {
+            println!("{}", style(format!("Error: {:#}", e)).red());
+        }
     }
-    
-    Ok(())
 }
 
 /// Configure network settings
@@ -56,10 +62,11 @@ async fn configure_network() -> Result<()> {
     
     // Get current network settings
     let current_settings = config::get_network_settings().await?;
+    let before = current_settings.clone();
     
     // Update settings
     let hostname = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Hostname")
+        .with_prompt("Listen address")
         .default(current_settings.hostname)
         .interact_text()?;
         
@@ -69,52 +76,30 @@ async fn configure_network() -> Result<()> {
         .interact()?;
     
     let cors_origins = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Allowed CORS origins (comma-separated)")
-        .default(current_settings.cors_origins.unwrap_or_default())
+        .with_prompt("Allowed CORS origins (comma-separated, empty for the environment's default)")
+        .allow_empty(true)
+        .default(current_settings.cors_origins.clone().unwrap_or_default())
         .interact_text()?;
         
-    let use_dhcp = Confirm::with_theme(&ColorfulTheme::default())
-        .with_prompt("Use DHCP for IP configuration?")
-        .default(current_settings.use_dhcp)
-        .interact()?;
-        
-    // Static IP settings if DHCP is disabled
-    let (ip_address, subnet_mask, gateway) = if !use_dhcp {
-        let ip = Input::with_theme(&ColorfulTheme::default())
-            .with_prompt("IP Address")
-            .default(current_settings.ip_address.unwrap_or_default())
-            .interact_text()?;
-            
-        let subnet = Input::with_theme(&ColorfulTheme::default())
-            .with_prompt("Subnet Mask")
-            .default(current_settings.subnet_mask.unwrap_or_default())
-            .interact_text()?;
-            
-        let gw = Input::with_theme(&ColorfulTheme::default())
-            .with_prompt("Gateway")
-            .default(current_settings.gateway.unwrap_or_default())
-            .interact_text()?;
-            
-        (Some(ip), Some(subnet), Some(gw))
-    } else {
-        (None, None, None)
-    };
+    // Addressing of the host is not part of the gateway's configuration
+    println!("{}", style("IP addressing (DHCP or static) is managed by the operating system.").dim());
     
     // Create updated settings
     let new_settings = NetworkSettings {
         hostname,
         port,
         cors_origins: Some(cors_origins),
-        use_dhcp,
-        ip_address,
-        subnet_mask,
-        gateway,
+        use_dhcp: current_settings.use_dhcp,
+        ip_address: current_settings.ip_address,
+        subnet_mask: current_settings.subnet_mask,
+        gateway: current_settings.gateway,
     };
     
     // Save new settings
     config::update_network_settings(&new_settings).await?;
+    config::record_settings_change("network", &before, &new_settings).await;
     
-    println!("\n{}", style("Network settings updated successfully!").green());
+    println!("\n{}", style("Network settings updated; choose Save and Exit to write them.").green());
     Ok(())
 }
 
@@ -125,6 +110,7 @@ async fn configure_authentication() -> Result<()> {
     
     // Get current auth settings
     let current_settings = config::get_auth_settings().await?;
+    let before = current_settings.clone();
     
     // Choose auth method
     let auth_methods = &["API Key", "JWT Token", "OAuth2"];
@@ -159,8 +145,21 @@ async fn configure_authentication() -> Result<()> {
                 .interact()?;
                 
             let key = if generate_new {
-                // Generate a new API key
-                config::generate_api_key().await?
+                // Generate a new API key acting for a user
+                let username: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Key owner (username)")
+                    .interact_text()?;
+                let scopes: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Scopes (comma-separated permissions)")
+                    .default("*".to_string())
+                    .interact_text()?;
+                let scopes: Vec<String> = scopes.split(',')
+                    .map(|scope| scope.trim().to_string())
+                    .filter(|scope| !scope.is_empty())
+                    .collect();
+                let key = config::generate_api_key(&username, "gateway", &scopes, None).await?;
+                println!("{}", style("Store this key now; it cannot be shown again.").yellow());
+                key
             } else {
                 // Use existing or enter custom
                 let custom_key = Input::with_theme(&ColorfulTheme::default())
@@ -224,8 +223,9 @@ async fn configure_authentication() -> Result<()> {
     
     // Save new settings
     config::update_auth_settings(&new_settings).await?;
+    config::record_settings_change("auth", &before, &new_settings).await;
     
-    println!("\n{}", style("Authentication settings updated successfully!").green());
+    println!("\n{}", style("Authentication settings updated; choose Save and Exit to write them.").green());
     Ok(())
 }
 
@@ -236,6 +236,7 @@ async fn configure_orchestrator() -> Result<()> {
     
     // Get current orchestrator settings
     let current_settings = config::get_orchestrator_settings().await?;
+    let before = current_settings.clone();
     
     // Update settings
     let orchestrator_url = Input::with_theme(&ColorfulTheme::default())
@@ -257,8 +258,9 @@ async fn configure_orchestrator() -> Result<()> {
     
     // Save new settings
     config::update_orchestrator_settings(&new_settings).await?;
+    config::record_settings_change("orchestrator", &before, &new_settings).await;
     
-    println!("\n{}", style("Orchestrator settings updated successfully!").green());
+    println!("\n{}", style("Orchestrator settings updated; choose Save and Exit to write them.").green());
     Ok(())
 }
 
@@ -269,6 +271,7 @@ async fn configure_llm_providers() -> Result<()> {
     
     // Get current LLM provider settings
     let current_settings = config::get_llm_provider_settings().await?;
+    let before = current_settings.clone();
     
     // Update settings
     let provider_name = Input::with_theme(&ColorfulTheme::default())
@@ -365,7 +368,7 @@ async fn configure_llm_providers() -> Result<()> {
     // Create updated settings
     let new_settings = LlmProviderSettings {
         provider_name,
-        api_key,
+        api_key: api_key.into(),
         url,
         model,
         temperature,
@@ -376,8 +379,9 @@ async fn configure_llm_providers() -> Result<()> {
     
     // Save new settings
     config::update_llm_provider_settings(&new_settings).await?;
+    config::record_settings_change("llm", &before, &new_settings).await;
     
-    println!("\n{}", style("LLM provider settings updated successfully!").green());
+    println!("\n{}", style("LLM provider settings updated; choose Save and Exit to write them.").green());
     Ok(())
 }
 
@@ -388,6 +392,7 @@ async fn configure_agent_communication() -> Result<()> {
     
     // Get current agent communication settings
     let current_settings = config::get_agent_communication_settings().await?;
+    let before = current_settings.clone();
     
     // Update settings
     let agent_url = Input::with_theme(&ColorfulTheme::default())
@@ -404,7 +409,7 @@ async fn configure_agent_communication() -> Result<()> {
     // Create updated settings
     let new_settings = AgentCommunicationSettings {
         agent_url,
-        agent_token,
+        agent_token: agent_token.into(),
         protocol: current_settings.protocol.clone(),        // Reuse current protocol
         heartbeat_interval: current_settings.heartbeat_interval, // Reuse current interval
         timeout: current_settings.timeout,                  // Reuse current timeout
@@ -412,8 +417,9 @@ async fn configure_agent_communication() -> Result<()> {
     
     // Save new settings
     config::update_agent_communication_settings(&new_settings).await?;
+    config::record_settings_change("agent_communication", &before, &new_settings).await;
     
-    println!("\n{}", style("Agent communication settings updated successfully!").green());
+    println!("\n{}", style("Agent communication settings updated; choose Save and Exit to write them.").green());
     Ok(())
 }
 
@@ -424,6 +430,7 @@ async fn configure_logging() -> Result<()> {
     
     // Get current log settings
     let current_settings = config::get_log_settings().await?;
+    let before = current_settings.clone();
     
     // Log level
     let log_levels = &["Debug", "Info", "Warning", "Error"];
@@ -434,7 +441,7 @@ async fn configure_logging() -> Result<()> {
         .default(match current_settings.level.as_str() {
             "debug" => 0,
             "info" => 1,
-            "warning" => 2,
+            "warn" => 2,
             "error" => 3,
             _ => 1,
         })
@@ -443,14 +450,15 @@ async fn configure_logging() -> Result<()> {
     let level = match log_level_idx {
         0 => "debug".to_string(),
         1 => "info".to_string(),
-        2 => "warning".to_string(),
+        2 => "warn".to_string(),
         3 => "error".to_string(),
         _ => unreachable!(),
     };
         
     // Log file path
     let file_path = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Log file path")
+        .with_prompt("Log file path (empty for the console only)")
+        .allow_empty(true)
         .default(current_settings.file_path)
         .interact_text()?;
         
@@ -475,8 +483,9 @@ async fn configure_logging() -> Result<()> {
     
     // Save new settings
     config::update_log_settings(&new_settings).await?;
+    config::record_settings_change("logging", &before, &new_settings).await;
     
-    println!("\n{}", style("Logging settings updated successfully!").green());
+    println!("\n{}", style("Logging settings updated; choose Save and Exit to write them.").green());
     Ok(())
 }
 
@@ -488,7 +497,7 @@ async fn configure_users() -> Result<()> {
     let users = config::get_users().await?;
     
     // Show user management options
-    let options = &["List Users", "Add User", "Remove User", "Change Password", "Back"];
+    let options = &["List Users", "Add User", "Remove User", "Change Password", "Manage Sessions", "Back"];
     
     let selection = Select::with_theme(&ColorfulTheme::default())
         .with_prompt("Select action")
@@ -500,18 +509,18 @@ async fn configure_users() -> Result<()> {
         0 => {
             // List users
             println!("\nUsers:");
-            println!("{:<20} {:<15} {:<10}", 
+            println!("{:<20} {:<25} {:<10}", 
                 style("Username").bold(),
-                style("Role").bold(),
+                style("Roles").bold(),
                 style("Last Login").bold()
             );
             
-            println!("{}", style("─".repeat(45)).dim());
+            println!("{}", style("─".repeat(55)).dim());
             
             for user in users {
-                println!("{:<20} {:<15} {:<10}",
+                println!("{:<20} {:<25} {:<10}",
                     user.username,
-                    user.role,
+                    user.roles.join(", "),
                     user.last_login.unwrap_or_default()
                 );
             }
@@ -527,16 +536,24 @@ async fn configure_users() -> Result<()> {
                 .with_confirmation("Confirm password", "Passwords don't match")
                 .interact()?;
                 
-            let roles = &["Admin", "User", "ReadOnly"];
-            let role_idx = Select::with_theme(&ColorfulTheme::default())
-                .with_prompt("User role")
-                .items(roles)
-                .default(1)
+            let role_names: Vec<String> = config::get_roles().await?
+                .into_iter()
+                .map(|role| role.definition.name)
+                .collect();
+            let defaults: Vec<bool> = role_names.iter()
+                .map(|name| name == "user")
+                .collect();
+            let selected = MultiSelect::with_theme(&ColorfulTheme::default())
+                .with_prompt("User roles (space to toggle)")
+                .items(&role_names)
+                .defaults(&defaults)
                 .interact()?;
                 
-            let role = roles[role_idx].to_lowercase();
+            let roles: Vec<String> = selected.into_iter()
+                .map(|idx| role_names[idx].clone())
+                .collect();
             
-            config::add_user(&username, &password, &role).await?;
+            config::add_user(&username, &password, &roles).await?;
             println!("\n{}", style("User added successfully!").green());
         },
         2 => {
@@ -597,7 +614,95 @@ async fn configure_users() -> Result<()> {
             config::change_password(username, &password).await?;
             println!("\n{}", style("Password changed successfully!").green());
         },
-        4 => return Ok(()),
+        4 => {
+            // Manage sessions
+            if users.is_empty() {
+                println!("No users available.");
+                return Ok(());
+            }
+            
+            let user_names: Vec<&str> = users.iter()
+                .map(|u| u.username.as_str())
+                .collect();
+                
+            let selected_idx = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Select user")
+                .items(&user_names)
+                .default(0)
+                .interact()?;
+                
+            manage_sessions(&users[selected_idx].username).await?;
+        },
+        5 => return Ok(()),
+        _ => unreachable!(),
+    }
+    
+    Ok(())
+}
+
+/// List and revoke a user's sessions
+async fn manage_sessions(username: &str) -> Result<()> {
+    let sessions = config::get_sessions(username).await?;
+    if sessions.is_empty() {
+        println!("\n'{}' has no active sessions.", username);
+        return Ok(());
+    }
+    
+    let format_ts = |ts: i64| {
+        chrono::DateTime::from_timestamp(ts, 0)
+            .map(|ts| ts.to_rfc3339())
+            .unwrap_or_default()
+    };
+    
+    println!("\nSessions of '{}':", username);
+    println!("{:<38} {:<27} {:<27}",
+        style("Session").bold(),
+        style("Started").bold(),
+        style("Last Refreshed").bold()
+    );
+    println!("{}", style("─".repeat(92)).dim());
+    for session in &sessions {
+        println!("{:<38} {:<27} {:<27}",
+            session.id,
+            format_ts(session.started_at),
+            format_ts(session.refreshed_at)
+        );
+    }
+    
+    let options = &["Revoke One Session", "Revoke All Sessions", "Back"];
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Select action")
+        .items(options)
+        .default(2)
+        .interact()?;
+        
+    match selection {
+        0 => {
+            let session_ids: Vec<&str> = sessions.iter()
+                .map(|s| s.id.as_str())
+                .collect();
+                
+            let selected_idx = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Select session to revoke")
+                .items(&session_ids)
+                .default(0)
+                .interact()?;
+                
+            config::revoke_session(username, session_ids[selected_idx]).await?;
+            println!("\n{}", style("Session revoked.").green());
+        },
+        1 => {
+            let confirm = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!("Log '{}' out everywhere?", username))
+                .default(false)
+                .interact()?;
+                
+            if confirm {
+                let revoked = config::revoke_all_sessions(username).await?;
+                println!("\n{}", style(format!("Revoked {} sessions.", revoked)).green());
+            }
+        },
+        2 => {},
         _ => unreachable!(),
     }
     
@@ -606,25 +711,55 @@ async fn configure_users() -> Result<()> {
 
 /// Save configuration
 async fn save_configuration() -> Result<()> {
+    let Some(diff) = config::pending_changes() else {
+        println!("\nNo configuration changes to save.");
+        return Ok(());
+    };
+    
+    // Show what will be written
+    println!();
+    for line in diff.lines() {
+        if line.starts_with("@@") {
+            println!("{}", style(line).cyan());
+        } else if line.starts_with('+') {
+            println!("{}", style(line).green());
+        } else if line.starts_with('-') {
+            println!("{}", style(line).red());
+        } else {
+            println!("{}", line);
+        }
+    }
+    
+    let confirm = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Write these changes?")
+        .default(true)
+        .interact()?;
+    if !confirm {
+        config::discard_changes();
+        println!("{}", style("Configuration changes discarded.").yellow());
+        return Ok(());
+    }
+    
     println!("\nSaving configuration...");
     
     // Save all configuration changes
     let result = config::save_all_settings().await;
     
     match result {
-        Ok(_) => {
+        Ok(backup) => {
             println!("{}", style("Configuration saved successfully!").green());
+            if let Some(backup) = backup {
+                println!("Previous configuration kept in {}", backup.display());
+            }
             
-            // Ask if restart is needed
-            let restart = Confirm::with_theme(&ColorfulTheme::default())
-                .with_prompt("Some changes may require a restart. Restart gateway now?")
+            // A running gateway picks up the file within seconds; reloading now reports the outcome
+            let reload = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("The gateway applies LLM, rate limit, CORS and logging changes itself; others need a restart. Reload the running gateway now?")
                 .default(false)
                 .interact()?;
-                
-            if restart {
-                println!("Restarting gateway services...");
-                config::restart_gateway().await?;
-                println!("{}", style("Gateway restarted successfully!").green());
+
+            if reload {
+                reload_gateway().await?;
             }
         },
         Err(e) => {
@@ -634,3 +769,52 @@ async fn save_configuration() -> Result<()> {
     
     Ok(())
 }
+
+/// Reload the running gateway as a platform admin and show what took effect
+async fn reload_gateway() -> Result<()> {
+    let username: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Admin username")
+        .interact_text()?;
+    let password = Password::with_theme(&ColorfulTheme::default())
+        .with_prompt("Password")
+        .interact()?;
+    let second_factor = || -> Result<String> {
+        Ok(Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Authentication code")
+            .interact_text()?)
+    };
+
+    match config::reload_gateway(&username, &password, second_factor).await {
+        Ok(report) => {
+            let list = |sections: &[String]| if sections.is_empty() { "none".to_string() } else { sections.join(", ") };
+            println!("{}", style("Gateway configuration reloaded.").green());
+            println!("Applied now: {}", list(&report.applied));
+            if !report.restart_required.is_empty() {
+                println!(
+                    "{}",
+                    style(format!("Restart the gateway to apply: {}", list(&report.restart_required))).yellow()
+                );
+            }
+        }
+        Err(e) => println!("{}", style(format!("Gateway not reloaded: {}", e)).red()),
+    }
+    Ok(())
+}
+
+/// Drop the staged configuration changes, after confirmation when there are any
+fn discard_configuration() -> Result<()> {
+    if config::pending_changes().is_none() {
+        return Ok(());
+    }
+    let confirm = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Discard the unsaved configuration changes?")
+        .default(false)
+        .interact()?;
+    if confirm {
+        config::discard_changes();
+        println!("{}", style("Configuration changes discarded.").yellow());
+    } else {
+        println!("Changes are kept until they are saved or the CLI exits.");
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+//! JWT signing key commands
+//!
+//! Rotate the gateway's signing secret and list the keys that still verify
+//! tokens. Keys live in the configured database, so running gateways pick up
+//! a rotation without a restart.
+
+use anyhow::Result;
+use colored::Colorize;
+use core::config;
+
+/// Rotate the signing secret
+pub async fn rotate() -> Result<()> {
+    let kid = config::rotate_signing_key().await?;
+    println!("{}", format!("New tokens are signed with key '{}'", kid).green());
+    println!("Previous keys keep verifying tokens until those tokens expire");
+    Ok(())
+}
+
+/// List signing and verification keys
+pub async fn list() -> Result<()> {
+    let keys = config::get_signing_keys().await?;
+
+    println!("{}", "Signing keys:".bold());
+    for key in keys {
+        let status = if key.signing {
+            "signing".green().to_string()
+        } else {
+            match key.retires_at.and_then(|ts| chrono::DateTime::from_timestamp(ts, 0)) {
+                Some(until) => format!("verify only, until {}", until.to_rfc3339()),
+                None => "verify only".to_string(),
+            }
+        };
+        println!("  {} ({}) {}", key.kid, key.algorithm, status);
+    }
+    Ok(())
+}
@@ -5,6 +5,7 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use colored::*;
+use common::loader::ConfigArgs;
 use console::Term;
 use std::path::PathBuf;
 use core::status as core_status;
@@ -13,6 +14,12 @@ use core::config;
 mod dashboard; // Make sure to include the dashboard module
 mod configure;
 mod status; // Our local status module
+mod snapshot;
+mod keys;
+mod roles;
+mod api_keys;
+mod audit;
+mod settings;
 
 // Make sure the source directory exists
 #[tokio::main]
@@ -45,6 +52,83 @@ async fn main() -> Result<()> {
                 },
             }
         },
+        Some(Commands::Vectordb { command }) => {
+            match command {
+                VectordbCmd::Export { url, collection, output, format, config } => {
+                    let url = snapshot::qdrant_url(url, &config)?;
+                    snapshot::export(&url, &collection, &output, format.as_deref()).await?;
+                },
+                VectordbCmd::Import { url, input, collection, batch_size, config } => {
+                    let url = snapshot::qdrant_url(url, &config)?;
+                    snapshot::import(&url, &input, collection, batch_size).await?;
+                },
+                VectordbCmd::List { url, config } => {
+                    let url = snapshot::qdrant_url(url, &config)?;
+                    snapshot::list(&url).await?;
+                },
+            }
+        },
+        Some(Commands::Keys { command }) => {
+            match command {
+                KeysCmd::Rotate => {
+                    keys::rotate().await?;
+                },
+                KeysCmd::List => {
+                    keys::list().await?;
+                },
+            }
+        },
+        Some(Commands::ApiKeys { command }) => {
+            match command {
+                ApiKeysCmd::Create { user, name, scope, expires_in_days } => {
+                    api_keys::create(&user, &name, &scope, expires_in_days).await?;
+                },
+                ApiKeysCmd::List { user } => {
+                    api_keys::list(&user).await?;
+                },
+                ApiKeysCmd::Revoke { user, key_id } => {
+                    api_keys::revoke(&user, &key_id).await?;
+                },
+            }
+        },
+        Some(Commands::Roles { command }) => {
+            match command {
+                RolesCmd::List => {
+                    roles::list().await?;
+                },
+                RolesCmd::Set { name, permission, inherits } => {
+                    roles::set(name, permission, inherits).await?;
+                },
+                RolesCmd::Delete { name } => {
+                    roles::delete(&name).await?;
+                },
+            }
+        },
+        Some(Commands::Audit { command }) => {
+            match command {
+                AuditCmd::List { tenant, actor, action, target, failures, since, until, limit, json } => {
+                    let filters = audit::Filters { tenant, actor, action, target, failures, since, until, limit };
+                    audit::list(filters, json).await?;
+                },
+            }
+        },
+        Some(Commands::Config { command }) => {
+            match command {
+                ConfigCmd::Validate { path, mut config } => {
+                    config.config = path.or(config.config);
+                    settings::validate(&config)?;
+                }
+                ConfigCmd::Show { effective, config } => {
+                    settings::show(&config, effective)?;
+                },
+                ConfigCmd::Encrypt { value } => {
+                    settings::encrypt(value)?;
+                },
+                ConfigCmd::GenerateKey => {
+                    settings::generate_key()?;
+                },
+            }
+        },
         None => {
             // No command specified, show interactive menu with metrics
             show_interactive_menu().await?;
@@ -73,6 +157,247 @@ enum Commands {
         #[clap(subcommand)]
         command: DashboardCmd,
     },
+    
+    /// Manage vector database collections
+    Vectordb {
+        #[clap(subcommand)]
+        command: VectordbCmd,
+    },
+    
+    /// Manage JWT signing keys
+    Keys {
+        #[clap(subcommand)]
+        command: KeysCmd,
+    },
+    
+    /// Manage API keys for machine clients
+    ApiKeys {
+        #[clap(subcommand)]
+        command: ApiKeysCmd,
+    },
+    
+    /// Manage roles and their permissions
+    Roles {
+        #[clap(subcommand)]
+        command: RolesCmd,
+    },
+    
+    /// Read the audit log
+    Audit {
+        #[clap(subcommand)]
+        command: AuditCmd,
+    },
+    
+    /// Check configuration files
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCmd,
+    },
+}
+
+/// Configuration subcommands
+#[derive(Subcommand)]
+enum ConfigCmd {
+    /// Check the configuration and list every problem
+    Validate {
+        /// Configuration file without extension (default: CONFIG_PATH or config/default)
+        path: Option<String>,
+        #[clap(flatten)]
+        config: ConfigArgs,
+    },
+    /// Show the merged configuration and where each value came from
+    Show {
+        /// Include every built-in default the gateway runs with
+        #[clap(long)]
+        effective: bool,
+        #[clap(flatten)]
+        config: ConfigArgs,
+    },
+    /// Encrypt a secret with the master key from NEXA_MASTER_KEY
+    Encrypt {
+        /// Value to encrypt (default: prompted for, or read from standard input)
+        value: Option<String>,
+    },
+    /// Generate a master key for encrypted secrets
+    GenerateKey,
+}
+
+/// Audit log subcommands
+#[derive(Subcommand)]
+enum AuditCmd {
+    /// List events, newest first
+    List {
+        /// Only events of this tenant
+        #[clap(long)]
+        tenant: Option<String>,
+        
+        /// User ID or name of the actor (`cli:<user>` for CLI changes)
+        #[clap(long)]
+        actor: Option<String>,
+        
+        /// Action, or a prefix such as `user` for every user action
+        #[clap(long)]
+        action: Option<String>,
+        
+        /// ID or name of the object acted on
+        #[clap(long)]
+        target: Option<String>,
+        
+        /// Only show failed actions
+        #[clap(long)]
+        failures: bool,
+        
+        /// Only show events at or after this RFC 3339 time
+        #[clap(long)]
+        since: Option<String>,
+        
+        /// Only show events before this RFC 3339 time
+        #[clap(long)]
+        until: Option<String>,
+        
+        /// Most events shown (default 100, at most 1000)
+        #[clap(short, long)]
+        limit: Option<u32>,
+        
+        /// Print events as JSON Lines
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+/// API key subcommands
+#[derive(Subcommand)]
+enum ApiKeysCmd {
+    /// Create a key acting for a user, limited to the given scopes
+    Create {
+        /// Username of the key owner
+        #[clap(short, long)]
+        user: String,
+        
+        /// Label identifying the client using the key
+        #[clap(short, long)]
+        name: String,
+        
+        /// Permission the key is limited to, e.g. agent:read or agent:* (repeatable)
+        #[clap(short, long, required = true)]
+        scope: Vec<String>,
+        
+        /// Days until the key expires (default: never)
+        #[clap(short, long)]
+        expires_in_days: Option<u32>,
+    },
+    
+    /// List a user's keys
+    List {
+        /// Username of the key owner
+        #[clap(short, long)]
+        user: String,
+    },
+    
+    /// Revoke a key
+    Revoke {
+        /// Username of the key owner
+        #[clap(short, long)]
+        user: String,
+        
+        /// Key ID, as shown by `api-keys list`
+        key_id: String,
+    },
+}
+
+/// Role subcommands
+#[derive(Subcommand)]
+enum RolesCmd {
+    /// List roles with the permissions they grant
+    List,
+    
+    /// Create or replace a role stored in the database
+    Set {
+        /// Role name
+        name: String,
+        
+        /// Permission to grant, e.g. agent:read or agent:* (repeatable)
+        #[clap(short, long)]
+        permission: Vec<String>,
+        
+        /// Role to inherit permissions from (repeatable)
+        #[clap(short, long)]
+        inherits: Vec<String>,
+    },
+    
+    /// Delete a role stored in the database
+    Delete {
+        /// Role name
+        name: String,
+    },
+}
+
+/// JWT signing key subcommands
+#[derive(Subcommand)]
+enum KeysCmd {
+    /// Sign new tokens with a fresh secret; existing tokens stay valid
+    Rotate,
+    
+    /// List signing and verification keys
+    List,
+}
+
+/// Vector database subcommands
+#[derive(Subcommand)]
+enum VectordbCmd {
+    /// Export a collection to a snapshot file
+    Export {
+        /// Qdrant URL (default: vectordb.url of the configuration)
+        #[clap(long)]
+        url: Option<String>,
+        
+        /// Collection to export
+        #[clap(short, long)]
+        collection: String,
+        
+        /// Snapshot file to write
+        #[clap(short, long)]
+        output: PathBuf,
+        
+        /// Snapshot format: jsonl or binary (default: from file extension)
+        #[clap(short, long)]
+        format: Option<String>,
+        
+        #[clap(flatten)]
+        config: ConfigArgs,
+    },
+    
+    /// Import a snapshot file, resuming an interrupted import
+    Import {
+        /// Qdrant URL (default: vectordb.url of the configuration)
+        #[clap(long)]
+        url: Option<String>,
+        
+        /// Snapshot file to read
+        #[clap(short, long)]
+        input: PathBuf,
+        
+        /// Target collection (default: the exported collection name)
+        #[clap(short, long)]
+        collection: Option<String>,
+        
+        /// Number of points upserted per request
+        #[clap(short, long, default_value = "256")]
+        batch_size: usize,
+        
+        #[clap(flatten)]
+        config: ConfigArgs,
+    },
+    
+    /// List collections
+    List {
+        /// Qdrant URL (default: vectordb.url of the configuration)
+        #[clap(long)]
+        url: Option<String>,
+        
+        #[clap(flatten)]
+        config: ConfigArgs,
+    },
 }
 
 /// Dashboard subcommands
@@ -178,13 +503,11 @@ async fn list_dashboards() -> Result<()> {
     println!("{}", "Saved Dashboards:".bold());
     let mut found = false;
     
-    for entry in std::fs::read_dir(dir)? {
-        if let Ok(entry) = entry {
-            if let Some(filename) = entry.file_name().to_str() {
-                if filename.ends_with(".json") {
-                    println!("- {}", filename.trim_end_matches(".json"));
-                    found = true;
-                }
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        if let Some(filename) = entry.file_name().to_str() {
+            if filename.ends_with(".json") {
+                println!("- {}", filename.trim_end_matches(".json"));
+                found = true;
             }
         }
     }
@@ -339,10 +662,12 @@ fn setup_logging() -> Result<()> {
     let env_filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new("cli=info,core=info,common=info,tower_http=info,axum=info"));
 
-    // Console layer with colored output for development
+    // Console layer with colored output for development, on stderr so
+    // command output such as encrypted values can be piped
     let console_layer = fmt::layer()
         .with_target(true)
-        .with_ansi(true);
+        .with_ansi(true)
+        .with_writer(std::io::stderr);
 
     // File layer with JSON formatting for easier parsing
     let file_layer = fmt::layer()
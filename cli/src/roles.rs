@@ -0,0 +1,52 @@
+//! Role commands
+//!
+//! List roles and manage the ones created at runtime. Roles live in the
+//! configured database, so running gateways pick changes up without a
+//! restart; built-in and configured roles are changed in the configuration.
+
+use anyhow::Result;
+use colored::Colorize;
+use core::config;
+
+/// List roles with their permissions
+pub async fn list() -> Result<()> {
+    let roles = config::get_roles().await?;
+
+    println!("{}", "Roles:".bold());
+    for role in roles {
+        let source = format!("{:?}", role.source).to_lowercase();
+        println!("  {} ({})", role.definition.name.bold(), source.dimmed());
+        if !role.definition.inherits.is_empty() {
+            println!("    inherits:    {}", role.definition.inherits.join(", "));
+        }
+        println!("    permissions: {}", role.effective_permissions.join(", "));
+    }
+    Ok(())
+}
+
+/// Create or replace a role
+pub async fn set(name: String, permissions: Vec<String>, inherits: Vec<String>) -> Result<()> {
+    let role = config::save_role(auth::RoleDefinition {
+        name,
+        permissions,
+        inherits,
+    })
+    .await?;
+    println!(
+        "{}",
+        format!(
+            "Role '{}' grants: {}",
+            role.definition.name,
+            role.effective_permissions.join(", ")
+        )
+        .green()
+    );
+    Ok(())
+}
+
+/// Delete a role
+pub async fn delete(name: &str) -> Result<()> {
+    config::delete_role(name).await?;
+    println!("{}", format!("Role '{}' deleted", name).green());
+    Ok(())
+}
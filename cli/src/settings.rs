@@ -0,0 +1,119 @@
+//! Configuration file commands
+//!
+//! Check a configuration before deploying it. Validation reads the same
+//! files, `APP__` environment variables and flags as the gateway and reports
+//! every problem at once, so a broken configuration is fixed in one pass
+//! instead of one failed start at a time. `config show` lists the merged
+//! configuration with the file, variable or flag each value came from.
+//!
+//! Secrets are kept out of configuration files by encrypting them with a
+//! master key the gateway reads from its environment.
+
+use anyhow::{bail, Result};
+use colored::Colorize;
+use common::loader::{ConfigArgs, ValueSource};
+use common::secret::{MasterKey, MASTER_KEY_ENV, MASTER_KEY_FILE_ENV};
+use common::validation::InvalidConfig;
+use std::io::{IsTerminal, Read};
+
+/// Validate the configuration the flags select
+pub fn validate(args: &ConfigArgs) -> Result<()> {
+    let loader = args.loader();
+    match loader.load() {
+        Ok(settings) => {
+            println!(
+                "{}",
+                format!(
+                    "Configuration '{}' is valid ({} profile, {} environment)",
+                    loader.base(),
+                    loader.profile(),
+                    settings.environment
+                )
+                .green()
+            );
+            Ok(())
+        }
+        Err(invalid) => report_problems(loader.base(), &invalid),
+    }
+}
+
+/// Print the merged configuration with the source of each value
+///
+/// Shows the values the files, environment variables and flags set, or
+/// with `effective` every value the gateway runs with, defaults included.
+/// Secrets are shown only as references.
+pub fn show(args: &ConfigArgs, effective: bool) -> Result<()> {
+    let loader = args.loader();
+    let values = match loader.effective() {
+        Ok(values) => values,
+        Err(invalid) => return report_problems(loader.base(), &invalid),
+    };
+    let values: Vec<_> = values
+        .into_iter()
+        .filter(|value| effective || value.source != ValueSource::Default)
+        .collect();
+
+    println!(
+        "{}",
+        format!("Configuration '{}' ({} profile)", loader.base(), loader.profile()).bold().blue()
+    );
+    let width = values.iter().map(|value| value.key.len()).max().unwrap_or(0);
+    for value in &values {
+        let source = format!("# {}", value.source);
+        let source = match value.source {
+            ValueSource::Default => source.dimmed(),
+            _ => source.cyan(),
+        };
+        println!("{:width$} = {}  {}", value.key, value.value, source, width = width);
+    }
+    Ok(())
+}
+
+fn report_problems(path: &str, invalid: &InvalidConfig) -> Result<()> {
+    let count = invalid.problems.len();
+    println!(
+        "{}",
+        format!("Configuration '{}' has {} problem{}:", path, count, if count == 1 { "" } else { "s" })
+            .red()
+            .bold()
+    );
+    for problem in &invalid.problems {
+        println!("  {} {}", "✗".red(), problem);
+    }
+    bail!("Invalid configuration")
+}
+
+/// Print a new random master key
+pub fn generate_key() -> Result<()> {
+    println!("{}", MasterKey::generate()?);
+    eprintln!(
+        "{}",
+        format!(
+            "Give this key to the gateway in {} or a file named by {}; never put it in the configuration.",
+            MASTER_KEY_ENV, MASTER_KEY_FILE_ENV
+        )
+        .yellow()
+    );
+    Ok(())
+}
+
+/// Encrypt a value with the master key, printing the `enc:` value to configure
+///
+/// Without `value` it is prompted for, or read from standard input, so it
+/// does not end up in the shell history.
+pub fn encrypt(value: Option<String>) -> Result<()> {
+    let key = MasterKey::from_env()?;
+    let value = match value {
+        Some(value) => value,
+        None if std::io::stdin().is_terminal() => dialoguer::Password::new()
+            .with_prompt("Value to encrypt")
+            .interact()?,
+        None => {
+            let mut value = String::new();
+            std::io::stdin().read_to_string(&mut value)?;
+            value.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    println!("{}", key.encrypt(&value)?);
+    Ok(())
+}
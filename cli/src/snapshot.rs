@@ -0,0 +1,101 @@
+//! Vector collection snapshot commands
+//!
+//! Export, import and list Qdrant collections from the command line. The
+//! Qdrant instance is the configured `vectordb.url` unless `--url` names
+//! another one.
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use common::loader::ConfigArgs;
+use std::path::Path;
+use vectordb::client::QdrantClient;
+use vectordb::snapshot::{self, ImportOptions, SnapshotFormat};
+use vectordb::VectorStore;
+
+/// URL of the Qdrant instance to use: `url` if given, else the configured one
+///
+/// Without a `vectordb` section the gateway keeps vectors in memory, inside
+/// its own process, where no command can reach them.
+pub fn qdrant_url(url: Option<String>, config: &ConfigArgs) -> Result<String> {
+    if let Some(url) = url {
+        return Ok(url);
+    }
+    let loader = config.loader();
+    match loader.load()?.vectordb {
+        Some(vectordb) => Ok(vectordb.url),
+        None => bail!(
+            "Configuration '{}' has no vectordb section, so the gateway keeps vectors in its own memory \
+             and they cannot be exported or imported; configure vectordb.url or pass --url",
+            loader.base()
+        ),
+    }
+}
+
+/// Connect to a Qdrant instance
+fn connect(url: &str) -> Result<QdrantClient> {
+    QdrantClient::new(url).with_context(|| format!("Failed to connect to Qdrant at {}", url))
+}
+
+/// Export a collection to a snapshot file
+pub async fn export(url: &str, collection: &str, output: &Path, format: Option<&str>) -> Result<()> {
+    let format = match format {
+        Some(format) => format.parse()?,
+        None => SnapshotFormat::from_path(output),
+    };
+
+    let client = connect(url)?;
+    let stats = snapshot::export_collection(&client, collection, output, format)
+        .await
+        .with_context(|| format!("Failed to export collection {}", collection))?;
+
+    println!(
+        "{}",
+        format!("Exported {} points from '{}' to {}", stats.points, collection, output.display()).green()
+    );
+    Ok(())
+}
+
+/// Import a snapshot file, resuming a previously interrupted import
+pub async fn import(url: &str, input: &Path, collection: Option<String>, batch_size: usize) -> Result<()> {
+    let client = connect(url)?;
+    let options = ImportOptions {
+        batch_size,
+        target_collection: collection,
+        ..Default::default()
+    };
+
+    let stats = snapshot::import_collection(&client, input, &options)
+        .await
+        .with_context(|| {
+            format!(
+                "Import of {} failed; run the same command again to resume",
+                input.display()
+            )
+        })?;
+
+    println!(
+        "{}",
+        format!("Imported {} points into '{}'", stats.imported, stats.collection).green()
+    );
+    if stats.skipped > 0 {
+        println!("Skipped {} points imported by a previous run", stats.skipped);
+    }
+    Ok(())
+}
+
+/// List the collections of a Qdrant instance
+pub async fn list(url: &str) -> Result<()> {
+    let client = connect(url)?;
+    let mut collections = client.list_collections().await?;
+    collections.sort();
+
+    println!("{}", "Collections:".bold());
+    if collections.is_empty() {
+        println!("No collections found.");
+    }
+    for name in collections {
+        let spec = client.collection_spec(&name).await?;
+        println!("- {} ({} dims, {:?})", name, spec.vector_size, spec.distance);
+    }
+    Ok(())
+}
@@ -0,0 +1,640 @@
+//! Editing configuration files in place
+//!
+//! [`ConfigFile`] changes the values of a YAML configuration file line by
+//! line, so comments, the order of keys and every key it is not asked to
+//! change stay as they are. The edited text can be shown as a diff, checked
+//! with [`ConfigFile::settings`], and saved over the file after the previous
+//! version is copied to a timestamped backup.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::config::Settings;
+use crate::error::CommonError;
+use crate::loader::{ConfigLoader, ValueSource};
+use crate::validation::InvalidConfig;
+
+/// Extensions of the files that can be edited
+const YAML_EXTENSIONS: &[&str] = &["yaml", "yml"];
+
+/// Indentation of keys added below a new or empty section
+const INDENT: usize = 2;
+
+/// Unchanged lines shown around each change of a diff
+const DIFF_CONTEXT: usize = 2;
+
+/// A YAML configuration file being edited
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    path: PathBuf,
+    original: String,
+    lines: Vec<String>,
+}
+
+impl ConfigFile {
+    /// Open a configuration file; a path without extension is looked up as `.yaml` and `.yml`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CommonError> {
+        let path = resolve(path.as_ref())?;
+        let original = std::fs::read_to_string(&path)?;
+        Ok(Self {
+            lines: original.lines().map(str::to_string).collect(),
+            path,
+            original,
+        })
+    }
+
+    /// Path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Set the value at a dotted key such as `server.port`
+    ///
+    /// Missing keys and sections are added at the end of their parent.
+    /// Mappings are set key by key, so keys of a section that `value` does
+    /// not have are kept; other values replace the old value, keeping the
+    /// comment on its line.
+    pub fn set<T: Serialize>(&mut self, key: &str, value: &T) -> Result<(), CommonError> {
+        let value = serde_json::to_value(value).map_err(|e| CommonError::ParseError(e.to_string()))?;
+        let path: Vec<String> = key.split('.').map(str::to_string).collect();
+        self.set_value(&path, &value);
+        Ok(())
+    }
+
+    /// Set the values at and below `key` that `edited` changes from `shown`
+    ///
+    /// `shown` is what the user started editing from: the settings in effect,
+    /// with every layer applied. Only the values they changed are written, so
+    /// values of the profile, local and environment layers are never copied
+    /// into the file. A change to a value an environment variable sets is
+    /// refused, since the variable would still override the file.
+    pub fn set_edited<T: Serialize>(&mut self, key: &str, shown: &T, edited: &T) -> Result<(), CommonError> {
+        let to_value = |value: &T| serde_json::to_value(value).map_err(|e| CommonError::ParseError(e.to_string()));
+        let mut changes = Vec::new();
+        changed_values(key, Some(&to_value(shown)?), &to_value(edited)?, &mut changes);
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let sources = ConfigLoader::new(self.path.to_string_lossy())
+            .with_base_contents(self.contents())
+            .effective()
+            .map_err(|e| CommonError::ConfigError(e.to_string()))?;
+        for (key, _) in &changes {
+            if let Some(ValueSource::Environment(variable)) =
+                sources.iter().find(|value| value.key == *key).map(|value| &value.source)
+            {
+                return Err(CommonError::ConfigError(format!("{} is set by {}; change it there", key, variable)));
+            }
+        }
+        for (key, value) in &changes {
+            self.set(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Edited text of the file
+    pub fn contents(&self) -> String {
+        let mut contents = self.lines.join("\n");
+        if !self.lines.is_empty() && (self.original.is_empty() || self.original.ends_with('\n')) {
+            contents.push('\n');
+        }
+        contents
+    }
+
+    /// Whether the edited text differs from the file
+    pub fn is_changed(&self) -> bool {
+        self.contents() != self.original
+    }
+
+    /// Unified diff from the file to the edited text; empty when unchanged
+    pub fn diff(&self) -> String {
+        diff(&self.path.display().to_string(), &self.original, &self.contents())
+    }
+
+    /// Settings the gateway would load with the edited text as its default file
+    ///
+    /// The profile and local files next to it and the environment variables
+    /// apply on top, and the result is validated as at startup.
+    pub fn settings(&self) -> Result<Settings, InvalidConfig> {
+        ConfigLoader::new(self.path.to_string_lossy())
+            .with_base_contents(self.contents())
+            .load()
+    }
+
+    /// Write the edited text over the file, returning the backup of the previous version
+    ///
+    /// Fails without writing when the file was changed by someone else since
+    /// it was opened.
+    pub fn save(&mut self) -> Result<PathBuf, CommonError> {
+        if std::fs::read_to_string(&self.path)? != self.original {
+            return Err(CommonError::ConfigError(format!(
+                "{} was changed since it was read",
+                self.path.display()
+            )));
+        }
+        let backup = backup_path(&self.path);
+        std::fs::copy(&self.path, &backup)?;
+
+        // Replace the file in one step, so the gateway never reads half of it
+        let contents = self.contents();
+        let mut partial = self.path.clone().into_os_string();
+        partial.push(".partial");
+        std::fs::write(&partial, &contents)?;
+        std::fs::rename(&partial, &self.path)?;
+        self.original = contents;
+        Ok(backup)
+    }
+
+    fn set_value(&mut self, path: &[String], value: &Value) {
+        match value {
+            Value::Object(fields) if !fields.is_empty() => {
+                for (field, value) in fields {
+                    let mut path = path.to_vec();
+                    path.push(field.clone());
+                    self.set_value(&path, value);
+                }
+            }
+            _ => self.set_leaf(path, value),
+        }
+    }
+
+    fn set_leaf(&mut self, path: &[String], value: &Value) {
+        // Lines of the mapping the key is looked up in, and the indentation of its keys
+        let mut start = 0;
+        let mut end = self.content_end(0, self.lines.len());
+        let mut indent = 0;
+
+        for (depth, key) in path.iter().enumerate() {
+            let last = depth + 1 == path.len();
+            match self.find_key(start, end, indent, key) {
+                Some(line) if last => {
+                    let block_end = self.block_end(line);
+                    self.replace(line, block_end, value);
+                    return;
+                }
+                Some(line) => {
+                    // A scalar such as `tls: ~` becomes a mapping
+                    let (_, inline, _) = self.split_line(line);
+                    if !inline.is_empty() {
+                        self.lines[line] = format!("{}{}:", " ".repeat(indent), key);
+                    }
+                    start = line + 1;
+                    end = self.block_end(line);
+                    indent = self.child_indent(start, end).unwrap_or(indent + INDENT);
+                }
+                // An absent key is already null
+                None if value.is_null() => return,
+                None => {
+                    if indent == 0 && end > 0 {
+                        self.lines.insert(end, String::new());
+                        end += 1;
+                    }
+                    let line = if last {
+                        format!("{}{}: {}", " ".repeat(indent), render_key(key), render(value))
+                    } else {
+                        format!("{}{}:", " ".repeat(indent), render_key(key))
+                    };
+                    self.lines.insert(end, line);
+                    start = end + 1;
+                    end = start;
+                    indent += INDENT;
+                }
+            }
+        }
+    }
+
+    /// Replace the value of the key on `line`, whose block ends before `block_end`
+    fn replace(&mut self, line: usize, block_end: usize, value: &Value) {
+        let (key, inline, comment) = self.split_line(line);
+        let existing = if inline.is_empty() {
+            self.block_sequence(line + 1, block_end)
+        } else {
+            Some(parse_scalar(&inline))
+        };
+        if existing.is_some_and(|existing| same(&existing, value)) {
+            return;
+        }
+
+        let comment = comment.map(|comment| format!(" {}", comment)).unwrap_or_default();
+        let mut replacement = Vec::new();
+        match value {
+            // Block sequences stay block sequences
+            Value::Array(items) if inline.is_empty() && !items.is_empty() && items.iter().all(is_scalar) => {
+                let indent = self
+                    .child_indent(line + 1, block_end)
+                    .unwrap_or(indent_of(&self.lines[line]) + INDENT);
+                replacement.push(format!("{}{}", key, comment));
+                replacement.extend(items.iter().map(|item| format!("{}- {}", " ".repeat(indent), render(item))));
+            }
+            _ => replacement.push(format!("{} {}{}", key, render(value), comment)),
+        }
+        self.lines.splice(line..block_end, replacement);
+    }
+
+    /// Key part up to the colon, inline value and comment of a key line
+    fn split_line(&self, line: usize) -> (String, String, Option<String>) {
+        let text = &self.lines[line];
+        let colon = key_of(text).map(|(_, _, colon)| colon).unwrap_or(text.len());
+        let (key, rest) = text.split_at((colon + 1).min(text.len()));
+        let (value, comment) = split_comment(rest);
+        (key.to_string(), value.trim().to_string(), comment)
+    }
+
+    /// Scalar items of a block sequence, or `None` when the block is something else
+    fn block_sequence(&self, start: usize, end: usize) -> Option<Value> {
+        let mut items = Vec::new();
+        for line in &self.lines[start..end] {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let item = trimmed.strip_prefix("- ")?;
+            if item.starts_with("- ") || key_of(item).is_some() {
+                return None;
+            }
+            items.push(parse_scalar(&split_comment(item).0));
+        }
+        Some(Value::Array(items))
+    }
+
+    /// Line of `key` among the keys indented by `indent` in `start..end`
+    fn find_key(&self, start: usize, end: usize, indent: usize, key: &str) -> Option<usize> {
+        (start..end).find(|&line| {
+            key_of(&self.lines[line]).is_some_and(|(key_indent, name, _)| key_indent == indent && name == key)
+        })
+    }
+
+    /// End of the block of the key on `line`: its nested lines, without trailing blank lines
+    fn block_end(&self, line: usize) -> usize {
+        let indent = indent_of(&self.lines[line]);
+        let mut end = line + 1;
+        for (offset, text) in self.lines[line + 1..].iter().enumerate() {
+            let trimmed = text.trim_start();
+            if trimmed.is_empty() {
+                continue;
+            }
+            let nested = indent_of(text) > indent || (indent_of(text) == indent && trimmed.starts_with("- "));
+            if !nested {
+                break;
+            }
+            end = line + 2 + offset;
+        }
+        end
+    }
+
+    /// Indentation of the first key or item in `start..end`
+    fn child_indent(&self, start: usize, end: usize) -> Option<usize> {
+        self.lines[start..end]
+            .iter()
+            .find(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|line| indent_of(line))
+    }
+
+    /// End of `start..end` without trailing blank lines
+    fn content_end(&self, start: usize, end: usize) -> usize {
+        (start..end)
+            .rev()
+            .find(|&line| !self.lines[line].trim().is_empty())
+            .map_or(start, |line| line + 1)
+    }
+}
+
+/// File an editable configuration is read from
+fn resolve(path: &Path) -> Result<PathBuf, CommonError> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if YAML_EXTENSIONS.contains(&extension) => Ok(path.to_path_buf()),
+        Some(_) => Err(CommonError::ConfigError(format!(
+            "{} cannot be edited, only YAML files can",
+            path.display()
+        ))),
+        None => YAML_EXTENSIONS
+            .iter()
+            .map(|extension| path.with_extension(extension))
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| CommonError::ConfigError(format!("No {}.yaml or {}.yml", path.display(), path.display()))),
+    }
+}
+
+/// Unused backup path such as `default.yaml.20260101T120000.bak`
+fn backup_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%S");
+    let mut backup = path.with_file_name(format!("{}.{}.bak", name, stamp));
+    let mut copy = 1;
+    while backup.exists() {
+        backup = path.with_file_name(format!("{}.{}-{}.bak", name, stamp, copy));
+        copy += 1;
+    }
+    backup
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+/// Indentation, name and byte offset of the colon of a `key: value` line
+fn key_of(line: &str) -> Option<(usize, String, usize)> {
+    let indent = indent_of(line);
+    let text = &line[indent..];
+    if text.is_empty() || text.starts_with('#') || text.starts_with("- ") || text == "-" {
+        return None;
+    }
+    if let Some(quote @ ('"' | '\'')) = text.chars().next() {
+        let close = text[1..].find(quote)? + 1;
+        let rest = &text[close + 1..];
+        return (rest.starts_with(':') && (rest.len() == 1 || rest[1..].starts_with(' ')))
+            .then(|| (indent, text[1..close].to_string(), indent + close + 1));
+    }
+    let colon = text
+        .char_indices()
+        .find(|&(at, c)| c == ':' && text[at + 1..].chars().next().is_none_or(|next| next == ' '))
+        .map(|(at, _)| at)?;
+    Some((indent, text[..colon].trim_end().to_string(), indent + colon))
+}
+
+/// Value text and comment of the rest of a line
+fn split_comment(text: &str) -> (String, Option<String>) {
+    let (mut single, mut double, mut escaped) = (false, false, false);
+    let mut previous = ' ';
+    for (at, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if double => escaped = true,
+            '"' if !single => double = !double,
+            '\'' if !double => single = !single,
+            '#' if !single && !double && previous.is_whitespace() => {
+                return (text[..at].trim_end().to_string(), Some(text[at..].to_string()));
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    (text.trim_end().to_string(), None)
+}
+
+/// Value of an inline YAML scalar or flow collection, as far as JSON reads it
+fn parse_scalar(text: &str) -> Value {
+    match text {
+        "" | "~" | "null" => Value::Null,
+        _ if text.len() > 1 && text.starts_with('\'') && text.ends_with('\'') => {
+            Value::String(text[1..text.len() - 1].replace("''", "'"))
+        }
+        _ => serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string())),
+    }
+}
+
+/// Whether two values are equal, numbers as the `f32` most settings use
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => match (a.as_f64(), b.as_f64()) {
+            (Some(a), Some(b)) => a as f32 == b as f32,
+            _ => a == b,
+        },
+        (Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b)),
+        _ => a == b,
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    !matches!(value, Value::Array(_) | Value::Object(_))
+}
+
+fn render_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        key.to_string()
+    } else {
+        Value::String(key.to_string()).to_string()
+    }
+}
+
+/// YAML text of a value; strings are double-quoted and collections written inline
+fn render(value: &Value) -> String {
+    match value {
+        Value::Null => "~".to_string(),
+        Value::Number(number) if number.is_f64() => {
+            let float = number.as_f64().unwrap_or_default();
+            // Settings hold `f32`s; write 0.7 rather than 0.699999988079071
+            let mut text = if (float as f32) as f64 == float {
+                (float as f32).to_string()
+            } else {
+                float.to_string()
+            };
+            if !text.contains(['.', 'e', 'i', 'N']) {
+                text.push_str(".0");
+            }
+            text
+        }
+        Value::Array(items) => format!("[{}]", items.iter().map(render).collect::<Vec<_>>().join(", ")),
+        Value::Object(fields) if fields.is_empty() => "{}".to_string(),
+        Value::Object(fields) => format!(
+            "{{ {} }}",
+            fields
+                .iter()
+                .map(|(key, value)| format!("{}: {}", render_key(key), render(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        other => other.to_string(),
+    }
+}
+
+/// Dotted keys and values of the leaves of `edited` that differ from `shown`
+fn changed_values(key: &str, shown: Option<&Value>, edited: &Value, changes: &mut Vec<(String, Value)>) {
+    match edited {
+        Value::Object(fields) if !fields.is_empty() => {
+            for (field, value) in fields {
+                let shown = shown.and_then(|shown| shown.get(field));
+                changed_values(&format!("{}.{}", key, field), shown, value, changes);
+            }
+        }
+        _ if shown == Some(edited) => {}
+        _ => changes.push((key.to_string(), edited.clone())),
+    }
+}
+
+/// Unified diff of two texts
+fn diff(label: &str, old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // Length of the longest common subsequence of the lines from each position on
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            ops.push(('-', old[i]));
+            i += 1;
+        } else {
+            ops.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    let changes: Vec<usize> = (0..ops.len()).filter(|&at| ops[at].0 != ' ').collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+    let mut out = format!("--- {}\n+++ {}\n", label, label);
+    let mut next = 0;
+    while next < changes.len() {
+        let start = changes[next].saturating_sub(DIFF_CONTEXT);
+        let mut last = changes[next];
+        while next + 1 < changes.len() && changes[next + 1] <= last + 2 * DIFF_CONTEXT + 1 {
+            next += 1;
+            last = changes[next];
+        }
+        next += 1;
+        let end = (last + DIFF_CONTEXT + 1).min(ops.len());
+
+        let count = |ops: &[(char, &str)], skip: char| ops.iter().filter(|(kind, _)| *kind != skip).count();
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            count(&ops[..start], '+') + 1,
+            count(&ops[start..end], '+'),
+            count(&ops[..start], '-') + 1,
+            count(&ops[start..end], '-'),
+        ));
+        for (kind, line) in &ops[start..end] {
+            out.push_str(&format!("{}{}\n", kind, line));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const YAML: &str = "\
+environment: \"development\"
+
+server:
+  host: \"0.0.0.0\"
+  port: 8080 # HTTP port
+  tls: ~ # to serve HTTPS
+
+llm:
+  temperature: 0.7
+  available_models:
+    - \"local\"
+    - \"llama2\"
+  custom_key: true
+";
+
+    fn edit(text: &str) -> ConfigFile {
+        ConfigFile {
+            path: PathBuf::from("config/test.yaml"),
+            original: text.to_string(),
+            lines: text.lines().map(str::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn test_set_keeps_comments_and_other_keys() {
+        let mut file = edit(YAML);
+        file.set("server.port", &9090).unwrap();
+        file.set("llm", &json!({ "temperature": 0.7f32, "available_models": ["local", "mistral"] }))
+            .unwrap();
+        assert_eq!(
+            file.contents(),
+            YAML.replace("8080 # HTTP", "9090 # HTTP").replace("\"llama2\"", "\"mistral\"")
+        );
+
+        // Nested keys and sections are added; scalars become mappings
+        file.set("server.tls.cert_path", &"cert.pem").unwrap();
+        file.set("cors.allowed_origins", &vec!["https://app.example.com"]).unwrap();
+        assert!(file.contents().contains("  tls:\n    cert_path: \"cert.pem\"\n\nllm:"));
+        assert!(file.contents().ends_with("\ncors:\n  allowed_origins: [\"https://app.example.com\"]\n"));
+        assert!(file.settings().is_err());
+    }
+
+    #[test]
+    fn test_set_edited_writes_only_changed_values() {
+        let dir = std::env::temp_dir().join(format!("nexa-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = "\
+auth:
+  jwt_secret: \"a-unique-production-secret-of-40-bytes\"
+  jwt_expiration: 24
+server:
+  host: \"127.0.0.1\"
+  port: 8080
+database:
+  url: \"sqlite::memory:\"
+  max_connections: 5
+agora:
+  host: \"127.0.0.1\"
+  port: 8090
+llm:
+  model: \"local\"
+";
+        std::fs::write(dir.join("default.yaml"), base).unwrap();
+        std::fs::write(dir.join("production.yaml"), "llm:\n  temperature: 0.2\n").unwrap();
+        std::env::set_var("APP__LLM__API_KEY", "sk-from-the-environment");
+
+        // Values of the profile and the environment stay where they are
+        let mut file = ConfigFile::open(dir.join("default")).unwrap();
+        let shown = file.settings().unwrap().llm;
+        assert_eq!(shown.api_key, "sk-from-the-environment");
+        let mut edited = shown.clone();
+        edited.model = "llama2".to_string();
+        file.set_edited("llm", &shown, &edited).unwrap();
+        assert_eq!(file.contents(), base.replace("\"local\"", "\"llama2\""));
+
+        // A value the environment sets cannot be changed in the file
+        let mut edited = shown.clone();
+        edited.api_key = "sk-typed-in".into();
+        let error = file.set_edited("llm", &shown, &edited).unwrap_err();
+        assert!(error.to_string().contains("APP__LLM__API_KEY"), "{}", error);
+
+        file.save().unwrap();
+        std::env::remove_var("APP__LLM__API_KEY");
+        let saved = std::fs::read_to_string(dir.join("default.yaml")).unwrap();
+        assert!(!saved.contains("sk-"), "{}", saved);
+        assert!(!saved.contains("temperature"), "{}", saved);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_diff_and_save_with_backup() {
+        let dir = std::env::temp_dir().join(format!("nexa-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("default.yaml"), YAML).unwrap();
+
+        let mut file = ConfigFile::open(dir.join("default")).unwrap();
+        assert!(!file.is_changed());
+        assert_eq!(file.diff(), "");
+        file.set("server.port", &9090).unwrap();
+        let diff = file.diff();
+        assert!(diff.contains("@@ -3,5 +3,5 @@\n"), "{}", diff);
+        assert!(diff.contains("\n-  port: 8080 # HTTP port\n+  port: 9090 # HTTP port\n"));
+
+        let backup = file.save().unwrap();
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), YAML);
+        assert!(std::fs::read_to_string(dir.join("default.yaml")).unwrap().contains("port: 9090"));
+        assert!(!file.is_changed());
+
+        // A file changed by someone else is not overwritten
+        std::fs::write(dir.join("default.yaml"), "environment: \"test\"\n").unwrap();
+        file.set("server.port", &8080).unwrap();
+        assert!(file.save().is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,88 +1,389 @@
 //! Common error types and error handling utilities.
+//!
+//! Every error the gateway returns over HTTP has the same shape: an
+//! [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807) `application/problem+json`
+//! body carrying a stable [`ErrorCode`] and the id of the request it belongs
+//! to. Crate errors expose their code with a `code()` method, render through
+//! [`ProblemDetails`] and convert into [`AppError`], the error handlers return.
 
-// Commented out until axum dependency is properly configured
-// use axum::{
-//     http::StatusCode,
-//     response::{IntoResponse, Response},
-//     Json,
-// };
+use crate::middleware::RequestContext;
+use crate::validation::InvalidConfig;
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use thiserror::Error;
 
-/// Common API error types.
-#[derive(Debug, Error, Serialize, Deserialize)]
-pub enum ApiError {
-    /// Authentication errors
-    #[error("Authentication error: {0}")]
-    Authentication(String),
-
-    /// Authorization errors
-    #[error("Authorization error: {0}")]
-    Authorization(String),
-
-    /// Not found errors
-    #[error("Resource not found: {0}")]
-    NotFound(String),
-
-    /// Bad request errors
-    #[error("Bad request: {0}")]
-    BadRequest(String),
-
-    /// Server errors
-    #[error("Internal server error: {0}")]
-    InternalServer(String),
-
-    /// Database errors
-    #[error("Database error: {0}")]
-    Database(String),
-
-    /// Vector database errors
-    #[error("Vector database error: {0}")]
-    VectorDB(String),
-}
-
-/// Error response structure for API errors.
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ErrorResponse {
-    /// Error code
-    pub code: String,
-    /// Error message
-    pub message: String,
-}
-
-// Commented out until axum dependency is properly configured
-// impl IntoResponse for ApiError {
-//     fn into_response(self) -> Response {
-//         let (status_code, error_code) = match &self {
-//             ApiError::Authentication(_) => (StatusCode::UNAUTHORIZED, "AUTHENTICATION_ERROR"),
-//             ApiError::Authorization(_) => (StatusCode::FORBIDDEN, "AUTHORIZATION_ERROR"),
-//             ApiError::NotFound(_) => (StatusCode::NOT_FOUND, "NOT_FOUND"),
-//             ApiError::BadRequest(_) => (StatusCode::BAD_REQUEST, "BAD_REQUEST"),
-//             ApiError::InternalServer(_) => (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR"),
-//             ApiError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, "DATABASE_ERROR"),
-//             ApiError::VectorDB(_) => (StatusCode::INTERNAL_SERVER_ERROR, "VECTORDB_ERROR"),
-//         };
-
-//         let body = ErrorResponse {
-//             code: error_code.to_string(),
-//             message: self.to_string(),
-//         };
-
-//         (status_code, Json(body)).into_response()
-//     }
-// }
+/// Content type of error bodies
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Stable, machine-readable error codes
+///
+/// Clients match on these rather than on messages, so a code is never
+/// renamed once released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    AuthenticationRequired,
+    InvalidCredentials,
+    InvalidToken,
+    TokenExpired,
+    TokenRevoked,
+    InvalidTwoFactorCode,
+    PermissionDenied,
+    AccountLocked,
+    NotFound,
+    MethodNotAllowed,
+    CollectionNotFound,
+    TopicNotFound,
+    BadRequest,
+    ValidationFailed,
+    Conflict,
+    BudgetExceeded,
+    RateLimited,
+    UpstreamError,
+    DatabaseError,
+    VectorDbError,
+    MessagingError,
+    ConfigurationError,
+    InternalError,
+}
+
+impl ErrorCode {
+    /// The code as it appears in error bodies
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::AuthenticationRequired => "AUTHENTICATION_REQUIRED",
+            ErrorCode::InvalidCredentials => "INVALID_CREDENTIALS",
+            ErrorCode::InvalidToken => "INVALID_TOKEN",
+            ErrorCode::TokenExpired => "TOKEN_EXPIRED",
+            ErrorCode::TokenRevoked => "TOKEN_REVOKED",
+            ErrorCode::InvalidTwoFactorCode => "INVALID_TWO_FACTOR_CODE",
+            ErrorCode::PermissionDenied => "PERMISSION_DENIED",
+            ErrorCode::AccountLocked => "ACCOUNT_LOCKED",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::MethodNotAllowed => "METHOD_NOT_ALLOWED",
+            ErrorCode::CollectionNotFound => "COLLECTION_NOT_FOUND",
+            ErrorCode::TopicNotFound => "TOPIC_NOT_FOUND",
+            ErrorCode::BadRequest => "BAD_REQUEST",
+            ErrorCode::ValidationFailed => "VALIDATION_FAILED",
+            ErrorCode::Conflict => "CONFLICT",
+            ErrorCode::BudgetExceeded => "BUDGET_EXCEEDED",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::UpstreamError => "UPSTREAM_ERROR",
+            ErrorCode::DatabaseError => "DATABASE_ERROR",
+            ErrorCode::VectorDbError => "VECTOR_DB_ERROR",
+            ErrorCode::MessagingError => "MESSAGING_ERROR",
+            ErrorCode::ConfigurationError => "CONFIGURATION_ERROR",
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+        }
+    }
+
+    /// HTTP status errors with this code are returned with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::AuthenticationRequired
+            | ErrorCode::InvalidCredentials
+            | ErrorCode::InvalidToken
+            | ErrorCode::TokenExpired
+            | ErrorCode::TokenRevoked
+            | ErrorCode::InvalidTwoFactorCode => StatusCode::UNAUTHORIZED,
+            ErrorCode::PermissionDenied | ErrorCode::AccountLocked => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound | ErrorCode::CollectionNotFound | ErrorCode::TopicNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorCode::BadRequest | ErrorCode::ValidationFailed => StatusCode::BAD_REQUEST,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::BudgetExceeded => StatusCode::PAYMENT_REQUIRED,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::UpstreamError => StatusCode::BAD_GATEWAY,
+            ErrorCode::DatabaseError
+            | ErrorCode::VectorDbError
+            | ErrorCode::MessagingError
+            | ErrorCode::ConfigurationError
+            | ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Short summary of the problem, the same for every occurrence
+    pub fn title(&self) -> &'static str {
+        match self {
+            ErrorCode::AuthenticationRequired => "Authentication required",
+            ErrorCode::InvalidCredentials => "Invalid credentials",
+            ErrorCode::InvalidToken => "Invalid token",
+            ErrorCode::TokenExpired => "Token expired",
+            ErrorCode::TokenRevoked => "Token revoked",
+            ErrorCode::InvalidTwoFactorCode => "Invalid two-factor code",
+            ErrorCode::PermissionDenied => "Permission denied",
+            ErrorCode::AccountLocked => "Account locked",
+            ErrorCode::NotFound => "Not found",
+            ErrorCode::MethodNotAllowed => "Method not allowed",
+            ErrorCode::CollectionNotFound => "Collection not found",
+            ErrorCode::TopicNotFound => "Topic not found",
+            ErrorCode::BadRequest => "Bad request",
+            ErrorCode::ValidationFailed => "Validation failed",
+            ErrorCode::Conflict => "Conflict",
+            ErrorCode::BudgetExceeded => "Token budget exceeded",
+            ErrorCode::RateLimited => "Rate limit exceeded",
+            ErrorCode::UpstreamError => "Upstream service error",
+            ErrorCode::DatabaseError => "Database error",
+            ErrorCode::VectorDbError => "Vector database error",
+            ErrorCode::MessagingError => "Messaging error",
+            ErrorCode::ConfigurationError => "Configuration error",
+            ErrorCode::InternalError => "Internal server error",
+        }
+    }
+
+    /// URI identifying the problem type, e.g. `urn:nexa:error:token-expired`
+    pub fn problem_type(&self) -> String {
+        format!("urn:nexa:error:{}", self.as_str().to_ascii_lowercase().replace('_', "-"))
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// RFC 7807 problem details, the body of every error response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProblemDetails {
+    /// URI identifying the problem type
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Summary of the problem type
+    pub title: String,
+    /// HTTP status code
+    pub status: u16,
+    /// Explanation of this occurrence
+    pub detail: String,
+    /// Path of the request that failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Stable error code
+    pub code: ErrorCode,
+    /// Id of the request, also sent in the `x-request-id` header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Same as `detail`, kept for clients of the earlier `{"error": "..."}` bodies
+    pub error: String,
+}
+
+impl ProblemDetails {
+    /// Problem with `code`, correlated with the request being handled
+    pub fn new(code: ErrorCode, detail: impl Into<String>) -> Self {
+        let detail = detail.into();
+        let context = RequestContext::current();
+        Self {
+            problem_type: code.problem_type(),
+            title: code.title().to_string(),
+            status: code.status().as_u16(),
+            error: detail.clone(),
+            detail,
+            instance: context.as_ref().map(|context| context.path.clone()),
+            code,
+            request_id: context.map(|context| context.request_id),
+        }
+    }
+
+    /// Problem for an error, without exposing the details of server errors
+    ///
+    /// Server errors are logged with the request id and answered with the
+    /// title of their code only.
+    pub fn from_error(code: ErrorCode, error: &dyn fmt::Display) -> Self {
+        if code.status().is_server_error() {
+            let problem = Self::new(code, code.title());
+            tracing::error!(code = %code, request_id = ?problem.request_id, "{}", error);
+            problem
+        } else {
+            Self::new(code, error.to_string())
+        }
+    }
+
+    /// Answer with another status than the code's own
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status.as_u16();
+        self
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response = (status, Json(self)).into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        response
+    }
+}
+
+/// Error of a request handler
+///
+/// Errors of every crate convert into it with `?`, keeping their code, and it
+/// renders as [`ProblemDetails`]. Details of server errors are logged, not
+/// returned.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{}: {detail}", .code.title())]
+pub struct AppError {
+    /// Stable code of the error
+    pub code: ErrorCode,
+    /// Explanation of this occurrence
+    pub detail: String,
+}
+
+impl AppError {
+    pub fn new(code: ErrorCode, detail: impl Into<String>) -> Self {
+        Self {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    /// Stable code of the error
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        ProblemDetails::from_error(self.code, &self.detail).into_response()
+    }
+}
+
+pub type Result<T> = std::result::Result<T, AppError>;
 
 #[derive(Debug, thiserror::Error)]
 pub enum CommonError {
     #[error("Configuration error: {0}")]
     ConfigError(String),
-    
+
     #[error("Environment error: {0}")]
     EnvError(String),
-    
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
-    
+
     #[error("Parse error: {0}")]
     ParseError(String),
+
+    #[error("TLS error: {0}")]
+    TlsError(String),
+}
+
+impl CommonError {
+    /// Stable code of the error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            CommonError::IoError(_) => ErrorCode::InternalError,
+            CommonError::ConfigError(_)
+            | CommonError::EnvError(_)
+            | CommonError::ParseError(_)
+            | CommonError::TlsError(_) => ErrorCode::ConfigurationError,
+        }
+    }
+}
+
+impl IntoResponse for CommonError {
+    fn into_response(self) -> Response {
+        ProblemDetails::from_error(self.code(), &self).into_response()
+    }
+}
+
+impl From<CommonError> for AppError {
+    fn from(err: CommonError) -> Self {
+        AppError::new(err.code(), err.to_string())
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::new(ErrorCode::BadRequest, rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        AppError::new(ErrorCode::BadRequest, rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::new(ErrorCode::BadRequest, rejection.body_text())
+    }
+}
+
+impl From<InvalidConfig> for AppError {
+    fn from(err: InvalidConfig) -> Self {
+        AppError::new(ErrorCode::ValidationFailed, err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_problem_details_carry_request_context() {
+        let context = RequestContext {
+            request_id: "req-1".to_string(),
+            path: "/api/agents/7".to_string(),
+        };
+        let problem = context
+            .scope(async { ProblemDetails::new(ErrorCode::NotFound, "Agent 7 not found") })
+            .await;
+
+        let body = serde_json::to_value(&problem).unwrap();
+        assert_eq!(body["type"], "urn:nexa:error:not-found");
+        assert_eq!(body["title"], "Not found");
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["code"], "NOT_FOUND");
+        assert_eq!(body["detail"], "Agent 7 not found");
+        assert_eq!(body["error"], "Agent 7 not found");
+        assert_eq!(body["instance"], "/api/agents/7");
+        assert_eq!(body["request_id"], "req-1");
+
+        let response = problem.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_JSON);
+    }
+
+    #[test]
+    fn test_server_errors_hide_details() {
+        let problem = ProblemDetails::from_error(ErrorCode::DatabaseError, &"password authentication failed");
+        assert_eq!(problem.status, 500);
+        assert_eq!(problem.code, ErrorCode::DatabaseError);
+        assert_eq!(problem.detail, "Database error");
+        assert!(problem.request_id.is_none());
+
+        let problem = ProblemDetails::from_error(ErrorCode::BadRequest, &"missing model");
+        assert_eq!(problem.detail, "missing model");
+    }
+
+    #[tokio::test]
+    async fn test_app_errors_render_as_problems() {
+        let response = AppError::new(ErrorCode::Conflict, "User ada already exists").into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = axum::body::to_bytes(response.into_body(), 4096).await.unwrap();
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!((problem.code, problem.detail.as_str()), (ErrorCode::Conflict, "User ada already exists"));
+
+        let response = AppError::from(CommonError::IoError(std::io::Error::other("/etc/nexa: denied"))).into_response();
+        let body = axum::body::to_bytes(response.into_body(), 4096).await.unwrap();
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!((problem.status, problem.detail.as_str()), (500, "Internal server error"));
+    }
+
+    #[test]
+    fn test_codes_serialize_as_their_string() {
+        for code in [ErrorCode::TokenExpired, ErrorCode::VectorDbError, ErrorCode::InvalidTwoFactorCode] {
+            assert_eq!(serde_json::to_value(code).unwrap(), code.as_str());
+        }
+    }
 }
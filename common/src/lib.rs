@@ -36,14 +36,17 @@ mod tests {
 }
 
 pub mod config;
+pub mod config_file;
 pub mod error;
-pub mod errors;
+pub mod loader;
 pub mod database;
 pub mod logging;
 pub mod middleware;
 pub mod models;
+pub mod secret;
+pub mod tls;
+pub mod validation;
 
 // Re-export commonly used items
 pub use config::Settings;
-pub use error::CommonError;
-pub use errors::{AppError, Result};
+pub use error::{AppError, CommonError, Result};
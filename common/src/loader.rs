@@ -0,0 +1,483 @@
+//! Layered configuration loading
+//!
+//! Every part of the gateway loads its configuration through
+//! [`ConfigLoader`], which applies these layers, each overriding the ones
+//! before it:
+//!
+//! 1. the built-in defaults of [`Settings`]
+//! 2. the base file, `CONFIG_PATH` or `config/default`
+//! 3. the profile file next to it, named by `--profile` or `APP_ENVIRONMENT`,
+//!    `production` by default; the profile is also the `environment` setting
+//! 4. `local` next to it, for overrides that are never committed
+//! 5. `APP__` environment variables, such as `APP__SERVER__PORT=8080`
+//! 6. `--set key=value` flags
+//!
+//! [`ConfigLoader::effective`] lists the merged result with the layer each
+//! value came from.
+
+use config::{Config, ConfigBuilder, Environment, File, FileFormat, Source};
+use serde_json::Value;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::config::Settings;
+use crate::secret::{is_secret_field, Secret, REDACTED};
+use crate::validation::{ConfigProblem, InvalidConfig};
+
+/// Base file when `CONFIG_PATH` is not set
+pub const DEFAULT_CONFIG_PATH: &str = "config/default";
+
+/// Profile when neither `--profile` nor `APP_ENVIRONMENT` names one
+pub const DEFAULT_PROFILE: &str = "production";
+
+/// Environment variable naming the profile
+pub const PROFILE_ENV: &str = "APP_ENVIRONMENT";
+
+/// Prefix of the environment variables that override settings
+pub const ENV_PREFIX: &str = "APP";
+
+/// Separator of sections in those variables, and between them and the prefix
+pub const ENV_SEPARATOR: &str = "__";
+
+/// Name of the file of local overrides
+const LOCAL_FILE: &str = "local";
+
+/// Extensions a configuration file may have
+const CONFIG_EXTENSIONS: &[&str] = &["yaml", "yml", "json", "toml"];
+
+/// Command-line flags that select and override the configuration
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct ConfigArgs {
+    /// Configuration file without extension (default: CONFIG_PATH or config/default)
+    #[clap(long = "config", value_name = "PATH")]
+    pub config: Option<String>,
+
+    /// Profile file applied over it (default: APP_ENVIRONMENT or production)
+    #[clap(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Override a setting, e.g. --set server.port=8080; wins over files and environment
+    #[clap(long = "set", value_name = "KEY=VALUE", value_parser = parse_override)]
+    pub overrides: Vec<(String, String)>,
+}
+
+impl ConfigArgs {
+    /// Loader for the configuration these flags select
+    pub fn loader(&self) -> ConfigLoader {
+        let mut loader = match &self.config {
+            Some(path) => ConfigLoader::new(path),
+            None => ConfigLoader::from_env(),
+        };
+        if let Some(profile) = &self.profile {
+            loader = loader.with_profile(profile);
+        }
+        for (key, value) in &self.overrides {
+            loader = loader.with_override(key, value);
+        }
+        loader
+    }
+}
+
+fn parse_override(flag: &str) -> Result<(String, String), String> {
+    match flag.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, not '{}'", flag)),
+    }
+}
+
+/// Where a configuration value came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueSource {
+    /// Built-in default
+    Default,
+    /// A configuration file
+    File(PathBuf),
+    /// An environment variable
+    Environment(String),
+    /// A `--set` flag
+    Override,
+}
+
+impl fmt::Display for ValueSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueSource::Default => f.write_str("default"),
+            ValueSource::File(path) => write!(f, "{}", path.display()),
+            ValueSource::Environment(name) => write!(f, "env {}", name),
+            ValueSource::Override => f.write_str("--set"),
+        }
+    }
+}
+
+/// A setting of the merged configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigValue {
+    /// Dotted key, such as `server.port`
+    pub key: String,
+    /// Value in effect; secrets other than references are redacted
+    pub value: Value,
+    pub source: ValueSource,
+}
+
+/// Loads configuration from its layers
+#[derive(Debug, Clone)]
+pub struct ConfigLoader {
+    base: String,
+    /// Text read in place of the base file, such as an edited copy of it
+    base_contents: Option<String>,
+    profile: Option<String>,
+    overrides: Vec<(String, String)>,
+}
+
+impl ConfigLoader {
+    /// Load from the base file `base`, a path with or without extension
+    pub fn new(base: impl Into<String>) -> Self {
+        Self {
+            base: base.into(),
+            base_contents: None,
+            profile: None,
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Load from `CONFIG_PATH`, or `config/default`
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("CONFIG_PATH").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string()))
+    }
+
+    /// Apply this profile instead of the one `APP_ENVIRONMENT` names
+    pub fn with_profile(mut self, profile: impl Into<String>) -> Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    /// Override a setting over every other layer
+    pub fn with_override(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.overrides.push((key.into(), value.into()));
+        self
+    }
+
+    /// Read this YAML text in place of the base file
+    pub fn with_base_contents(mut self, contents: impl Into<String>) -> Self {
+        self.base_contents = Some(contents.into());
+        self
+    }
+
+    /// Base file, as given
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    /// Profile applied: `--profile`, `APP_ENVIRONMENT` or `production`
+    pub fn profile(&self) -> String {
+        self.profile
+            .clone()
+            .or_else(|| std::env::var(PROFILE_ENV).ok())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+    }
+
+    /// Every path the configuration files may be read from, existing or not
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.file_layers().iter().flat_map(|name| candidates(name)).collect()
+    }
+
+    /// Load and validate the settings
+    ///
+    /// `environment` is the profile. A layer may repeat it, but one that sets
+    /// another environment is rejected, so the production checks never depend
+    /// on which of the two was looked at.
+    pub fn load(&self) -> Result<Settings, InvalidConfig> {
+        let profile = self.profile();
+        info!(%profile, "Loading configuration from {}", self.base);
+        let config = self.builder()?.build().map_err(InvalidConfig::unreadable)?;
+        if let Ok(environment) = config.get_string("environment") {
+            if environment != profile {
+                return Err(InvalidConfig {
+                    problems: vec![ConfigProblem::new(
+                        "environment",
+                        format!(
+                            "is `{}` but the `{}` profile is loaded; select the environment with --profile or {}",
+                            environment, profile, PROFILE_ENV
+                        ),
+                    )],
+                });
+            }
+        }
+        let config = Config::builder()
+            .add_source(config)
+            .set_override("environment", profile)
+            .and_then(|builder| builder.build())
+            .map_err(InvalidConfig::unreadable)?;
+        Settings::from_config(config)
+    }
+
+    /// Every setting in effect, in key order, with the layer it came from
+    pub fn effective(&self) -> Result<Vec<ConfigValue>, InvalidConfig> {
+        let settings = serde_json::to_value(self.load()?).map_err(InvalidConfig::unreadable)?;
+        let layers = self.layers()?;
+
+        let mut values = Vec::new();
+        flatten("", &settings, &mut |key, value| {
+            let source = layers
+                .iter()
+                .rev()
+                .find(|(_, keys)| keys.iter().any(|set| overlaps(set, key)))
+                .map(|(source, _)| source.clone())
+                .unwrap_or(ValueSource::Default);
+            values.push(ConfigValue {
+                key: key.to_string(),
+                value: shown(key, value),
+                source,
+            });
+        });
+        values.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(values)
+    }
+
+    /// Base, profile and local file names, without extension
+    fn file_layers(&self) -> [String; 3] {
+        let directory = Path::new(&self.base).parent().unwrap_or(Path::new(""));
+        [
+            self.base.clone(),
+            directory.join(self.profile()).to_string_lossy().into_owned(),
+            directory.join(LOCAL_FILE).to_string_lossy().into_owned(),
+        ]
+    }
+
+    fn base_source(&self) -> File<config::FileSourceString, FileFormat> {
+        File::from_str(self.base_contents.as_deref().unwrap_or_default(), FileFormat::Yaml)
+    }
+
+    fn builder(&self) -> Result<ConfigBuilder<config::builder::DefaultState>, InvalidConfig> {
+        let [base, profile, local] = self.file_layers();
+        let mut builder = match &self.base_contents {
+            Some(_) => Config::builder().add_source(self.base_source()),
+            None => Config::builder().add_source(File::with_name(&base)),
+        };
+        builder = builder
+            .add_source(File::with_name(&profile).required(false))
+            .add_source(File::with_name(&local).required(false))
+            .add_source(environment());
+        for (key, value) in &self.overrides {
+            builder = builder.set_override(key, value.as_str()).map_err(InvalidConfig::unreadable)?;
+        }
+        Ok(builder)
+    }
+
+    /// Keys each layer sets, lowest precedence first
+    fn layers(&self) -> Result<Vec<(ValueSource, Vec<String>)>, InvalidConfig> {
+        let [base, profile, local] = self.file_layers();
+        let mut layers = Vec::new();
+
+        let base_keys = match &self.base_contents {
+            Some(_) => keys(Config::builder().add_source(self.base_source()))?,
+            None => keys(Config::builder().add_source(File::with_name(&base)))?,
+        };
+        layers.push((ValueSource::File(find(&base).unwrap_or_else(|| base.into())), base_keys));
+        for name in [profile, local] {
+            if let Some(path) = find(&name) {
+                let layer = keys(Config::builder().add_source(File::with_name(&name)))?;
+                layers.push((ValueSource::File(path), layer));
+            }
+        }
+
+        let variables = environment().collect().map_err(InvalidConfig::unreadable)?;
+        for key in variables.keys() {
+            layers.push((ValueSource::Environment(variable_name(key)), vec![key.clone()]));
+        }
+        for (key, _) in &self.overrides {
+            layers.push((ValueSource::Override, vec![key.clone()]));
+        }
+        Ok(layers)
+    }
+}
+
+fn environment() -> Environment {
+    Environment::with_prefix(ENV_PREFIX).separator(ENV_SEPARATOR)
+}
+
+/// Environment variable that sets `key`
+fn variable_name(key: &str) -> String {
+    format!("{}{}{}", ENV_PREFIX, ENV_SEPARATOR, key.to_uppercase().replace('.', ENV_SEPARATOR))
+}
+
+/// `name` as is and with each configuration extension
+fn candidates(name: &str) -> Vec<PathBuf> {
+    std::iter::once(PathBuf::from(name))
+        .chain(CONFIG_EXTENSIONS.iter().map(|extension| PathBuf::from(format!("{}.{}", name, extension))))
+        .collect()
+}
+
+/// The file `name` is read from, if there is one
+fn find(name: &str) -> Option<PathBuf> {
+    candidates(name).into_iter().find(|path| path.is_file())
+}
+
+/// Dotted keys of the values a single layer sets
+fn keys(builder: ConfigBuilder<config::builder::DefaultState>) -> Result<Vec<String>, InvalidConfig> {
+    let value: Value = builder
+        .build()
+        .and_then(|config| config.try_deserialize())
+        .map_err(InvalidConfig::unreadable)?;
+    let mut keys = Vec::new();
+    flatten("", &value, &mut |key, _| keys.push(key.to_string()));
+    Ok(keys)
+}
+
+/// Call `visit` for every value that is not a non-empty mapping
+fn flatten(prefix: &str, value: &Value, visit: &mut impl FnMut(&str, &Value)) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&key, value, visit);
+            }
+        }
+        _ if prefix.is_empty() => {}
+        _ => visit(prefix, value),
+    }
+}
+
+/// Whether setting `set` sets the value at `key`, or part of it
+fn overlaps(set: &str, key: &str) -> bool {
+    let set = set.to_lowercase();
+    set == key || key.starts_with(&format!("{}.", set)) || set.starts_with(&format!("{}.", key))
+}
+
+/// `value` as it may be shown: secrets only as references, and `f32`
+/// settings as written rather than widened to `f64`
+fn shown(key: &str, value: &Value) -> Value {
+    match value {
+        Value::Number(number) if number.is_f64() => {
+            let float = number.as_f64().unwrap_or_default();
+            match (float as f32).to_string().parse::<f64>() {
+                Ok(written) if written as f32 == float as f32 => Value::from(written),
+                _ => value.clone(),
+            }
+        }
+        Value::String(text) if is_secret_field(key) && !text.is_empty() => {
+            if Secret::from(text.as_str()).is_reference() {
+                value.clone()
+            } else {
+                Value::String(REDACTED.to_string())
+            }
+        }
+        _ => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const BASE: &str = r#"
+auth:
+  jwt_secret: a-unique-test-secret-of-at-least-40-bytes
+  jwt_expiration: 24
+server:
+  host: 127.0.0.1
+  port: 8080
+database:
+  url: "sqlite::memory:"
+  max_connections: 5
+agora:
+  host: 127.0.0.1
+  port: 8090
+logging:
+  level: info
+"#;
+
+    fn source_of<'a>(values: &'a [ConfigValue], key: &str) -> &'a ConfigValue {
+        values.iter().find(|value| value.key == key).unwrap()
+    }
+
+    #[test]
+    fn test_layers_override_in_order() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("default");
+        fs::write(dir.path().join("default.yaml"), BASE).unwrap();
+        fs::write(dir.path().join("staging.yaml"), "server:\n  port: 8081\nlogging:\n  level: debug\n").unwrap();
+        fs::write(dir.path().join("local.yaml"), "logging:\n  level: warn\n").unwrap();
+
+        let loader = ConfigLoader::new(base.to_str().unwrap()).with_profile("staging");
+        let settings = loader.load().unwrap();
+        assert_eq!(settings.environment, "staging");
+        assert_eq!(settings.server.port, 8081);
+        assert_eq!(settings.server.host, "127.0.0.1");
+        assert_eq!(settings.logging.level, "warn");
+
+        let settings = loader.clone().with_override("server.port", "9090").load().unwrap();
+        assert_eq!(settings.server.port, 9090);
+
+        assert!(loader.files().contains(&dir.path().join("staging.yaml")));
+        assert!(loader.clone().with_override("server.port", "not-a-port").load().is_err());
+    }
+
+    #[test]
+    fn test_effective_configuration_sources() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("default");
+        fs::write(dir.path().join("default.yaml"), BASE).unwrap();
+        fs::write(dir.path().join("test.yaml"), "server:\n  port: 8081\n").unwrap();
+
+        let values = ConfigLoader::new(base.to_str().unwrap())
+            .with_profile("test")
+            .with_override("logging.level", "debug")
+            .effective()
+            .unwrap();
+
+        let host = source_of(&values, "server.host");
+        assert_eq!(host.value, "127.0.0.1");
+        assert_eq!(host.source, ValueSource::File(dir.path().join("default.yaml")));
+        assert_eq!(source_of(&values, "server.port").source, ValueSource::File(dir.path().join("test.yaml")));
+        assert_eq!(source_of(&values, "logging.level").source, ValueSource::Override);
+        assert_eq!(source_of(&values, "rate_limit.enabled").source, ValueSource::Default);
+        assert_eq!(source_of(&values, "cache.similarity_threshold").value, 0.95);
+
+        let secret = source_of(&values, "auth.jwt_secret");
+        assert_eq!(secret.value, REDACTED);
+        assert!(values.windows(2).all(|pair| pair[0].key < pair[1].key));
+    }
+
+    #[test]
+    fn test_environment_follows_profile() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("default");
+        fs::write(dir.path().join("default.yaml"), BASE).unwrap();
+        let loader = ConfigLoader::new(base.to_str().unwrap()).with_profile("production");
+
+        assert_eq!(loader.load().unwrap().environment, "production");
+        let problems = loader.clone().with_override("auth.jwt_secret", "supersecretkey").load().unwrap_err().problems;
+        assert!(problems.iter().any(|problem| problem.key == "auth.jwt_secret"), "{:?}", problems);
+
+        let problems = loader.clone().with_override("environment", "development").load().unwrap_err().problems;
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].key, "environment");
+        assert!(loader.with_override("environment", "production").load().is_ok());
+    }
+
+    #[test]
+    fn test_production_refuses_any_origin() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("default");
+        let cors = "cors:\n  allowed_origins: [\"*\"]\n";
+        fs::write(dir.path().join("default.yaml"), format!("{}{}", BASE, cors)).unwrap();
+        let loader = ConfigLoader::new(base.to_str().unwrap());
+
+        let problems = loader.clone().with_profile("production").load().unwrap_err().problems;
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].key, "cors.allowed_origins");
+        assert!(loader.with_profile("development").load().is_ok());
+    }
+
+    #[test]
+    fn test_parse_override() {
+        assert_eq!(parse_override("server.port=8080").unwrap(), ("server.port".to_string(), "8080".to_string()));
+        assert_eq!(parse_override("llm.api_key=a=b").unwrap().1, "a=b");
+        assert!(parse_override("server.port").is_err());
+        assert!(parse_override("=8080").is_err());
+    }
+}
@@ -1,11 +1,14 @@
 //! Logging configuration and utilities.
 
 use std::str::FromStr;
+use std::sync::OnceLock;
 use tracing::{debug, error, info, warn, Level};
 use tracing_subscriber::{
     fmt::{self, format::FmtSpan},
     EnvFilter,
     layer::{SubscriberExt, Layer},
+    reload,
+    Registry,
     util::SubscriberInitExt,
 };
 
@@ -47,27 +50,47 @@ pub fn init_logging(service_name: &str, default_level: &str) {
     );
 }
 
-pub fn setup_logging(_config: &Settings) -> Result<(), String> {
-    // Default to INFO level
-    let level = Level::INFO;
-    
+/// Filter of the subscriber installed by [`setup_logging`], replaceable at runtime
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Install the logging subscriber for the configured `logging.level`.
+///
+/// `RUST_LOG`, when set, takes precedence at startup. The level can be
+/// changed later with [`set_log_level`].
+pub fn setup_logging(config: &Settings) -> Result<(), String> {
     let env_filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new(format!("{}", level)));
-        
+        .or_else(|_| EnvFilter::try_new(&config.logging.level))
+        .map_err(|e| format!("Invalid log level '{}': {}", config.logging.level, e))?;
+    let (filter, handle) = reload::Layer::new(env_filter);
+
     let fmt_layer = fmt::layer()
         .with_target(true)
         .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
         .boxed();
         
     tracing_subscriber::registry()
-        .with(env_filter)
+        .with(filter)
         .with(fmt_layer)
         .try_init()
         .map_err(|e| format!("Failed to set up logging: {}", e))?;
+    let _ = LOG_FILTER.set(handle);
     
     Ok(())
 }
 
+/// Change the level of the subscriber installed by [`setup_logging`]
+pub fn set_log_level(level: &str) -> Result<(), String> {
+    let handle = LOG_FILTER
+        .get()
+        .ok_or_else(|| "Logging was not set up with setup_logging".to_string())?;
+    let filter = EnvFilter::try_new(level).map_err(|e| format!("Invalid log level '{}': {}", level, e))?;
+    handle
+        .reload(filter)
+        .map_err(|e| format!("Failed to change log level: {}", e))?;
+    info!(level, "Log level changed");
+    Ok(())
+}
+
 pub fn log_error(err: &dyn std::error::Error) {
     error!(error = %err, "Operation failed");
 }
@@ -1,5 +1,14 @@
 //! Common middleware for Axum applications.
 
+use axum::{
+    body::Body,
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::future::Future;
+use tracing::Instrument;
+
 // Commented out until axum and tower_http dependencies are properly configured
 // use axum::{
 //     extract::{Request, State},
@@ -64,6 +73,63 @@
 //     next.run(request).await
 // }
 
+/// Header carrying the id of a request
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request id accepted from a client
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_CONTEXT: RequestContext;
+}
+
+/// Request being handled, for correlating errors and logs with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestContext {
+    pub request_id: String,
+    pub path: String,
+}
+
+impl RequestContext {
+    /// Context of the request the current task is handling, if any
+    pub fn current() -> Option<Self> {
+        REQUEST_CONTEXT.try_with(Clone::clone).ok()
+    }
+
+    /// Run `future` as part of this request
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        REQUEST_CONTEXT.scope(self, future).await
+    }
+}
+
+/// Request id middleware
+///
+/// Keeps the `x-request-id` sent by a client or proxy, or assigns a new
+/// one, and returns it in the response. While the request is handled its
+/// [`RequestContext`] is current, so error bodies and log lines carry the id.
+/// Attach outermost with `axum::middleware::from_fn(request_id)`.
+pub async fn request_id(mut request: Request<Body>, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH && id.bytes().all(|b| b.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let context = RequestContext {
+        request_id: request_id.clone(),
+        path: request.uri().path().to_string(),
+    };
+    request.extensions_mut().insert(context.clone());
+
+    let span = tracing::info_span!("request", request_id = %request_id);
+    let mut response = context.scope(next.run(request)).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
 // Define the interface expected from the auth service
 pub trait AuthServiceTrait {
     // Add other auth methods as needed
@@ -0,0 +1,336 @@
+//! Secrets in configuration
+//!
+//! A [`Secret`] holds a value that must not be shown: a signing secret, a
+//! password, an API key. In configuration files it is written as the value
+//! itself, or as a reference resolved when the configuration is loaded:
+//!
+//! - `env:NAME` reads the environment variable `NAME`
+//! - `file:/run/secrets/name` reads a file, without its trailing newline
+//! - `enc:...` decrypts a value encrypted with the [`MasterKey`]
+//!
+//! `Debug` shows references but never values. Serializing a secret writes it
+//! as the configuration had it, so saved configuration keeps its references.
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+use crate::error::CommonError;
+
+/// Environment variable holding the base64 master key
+pub const MASTER_KEY_ENV: &str = "NEXA_MASTER_KEY";
+
+/// Environment variable naming a file that holds the master key instead
+pub const MASTER_KEY_FILE_ENV: &str = "NEXA_MASTER_KEY_FILE";
+
+/// Shown and recorded in place of a secret value
+pub const REDACTED: &str = "[redacted]";
+
+/// Fields holding secrets: those whose name contains an entry, or whose
+/// dotted path ends with an entry containing a `.`
+const SECRET_FIELDS: &[&str] = &["password", "secret", "private_key", "api_key", "recovery_code", "database.url"];
+
+const ENV_PREFIX: &str = "env:";
+const FILE_PREFIX: &str = "file:";
+const ENCRYPTED_PREFIX: &str = "enc:";
+
+/// Length of the master key in bytes
+const KEY_LENGTH: usize = 32;
+
+/// A configuration value kept out of logs
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret {
+    /// As written in the configuration: the value or a reference
+    source: String,
+    value: String,
+}
+
+impl Secret {
+    /// Resolve a secret as written in configuration
+    pub fn resolve(source: &str) -> Result<Self, CommonError> {
+        let value = if let Some(name) = source.strip_prefix(ENV_PREFIX) {
+            std::env::var(name)
+                .map_err(|_| CommonError::EnvError(format!("environment variable {} is not set", name)))?
+        } else if let Some(path) = source.strip_prefix(FILE_PREFIX) {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| CommonError::ConfigError(format!("cannot read secret file {}: {}", path, e)))?;
+            let contents = contents.strip_suffix('\n').unwrap_or(&contents);
+            contents.strip_suffix('\r').unwrap_or(contents).to_string()
+        } else if source.starts_with(ENCRYPTED_PREFIX) {
+            MasterKey::from_env()?.decrypt(source)?
+        } else {
+            source.to_string()
+        };
+        Ok(Self {
+            source: source.to_string(),
+            value,
+        })
+    }
+
+    /// A value encrypted with `key`, written out as its `enc:` reference
+    pub fn encrypt(value: &str, key: &MasterKey) -> Result<Self, CommonError> {
+        Ok(Self {
+            source: key.encrypt(value)?,
+            value: value.to_string(),
+        })
+    }
+
+    /// A value the gateway stored itself, decrypted when it is an `enc:` reference
+    ///
+    /// Unlike [`Secret::resolve`], stored values never read the environment or files.
+    pub fn from_stored(stored: &str) -> Result<Self, CommonError> {
+        if stored.starts_with(ENCRYPTED_PREFIX) {
+            Ok(Self {
+                source: stored.to_string(),
+                value: MasterKey::from_env()?.decrypt(stored)?,
+            })
+        } else {
+            Ok(stored.into())
+        }
+    }
+
+    /// The value; keep it out of logs and responses
+    pub fn expose(&self) -> &str {
+        &self.value
+    }
+
+    /// The secret as written in configuration
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the value is read from the environment, a file or decrypted
+    pub fn is_reference(&self) -> bool {
+        [ENV_PREFIX, FILE_PREFIX, ENCRYPTED_PREFIX]
+            .iter()
+            .any(|prefix| self.source.starts_with(prefix))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+}
+
+/// A plain value, written to configuration as it is
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self {
+            source: value.clone(),
+            value,
+        }
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        value.to_string().into()
+    }
+}
+
+impl PartialEq<str> for Secret {
+    fn eq(&self, other: &str) -> bool {
+        self.value == other
+    }
+}
+
+impl PartialEq<&str> for Secret {
+    fn eq(&self, other: &&str) -> bool {
+        self.value == *other
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.source.starts_with(ENV_PREFIX) || self.source.starts_with(FILE_PREFIX) {
+            write!(f, "Secret({})", self.source)
+        } else if self.value.is_empty() {
+            f.write_str("Secret(<empty>)")
+        } else {
+            write!(f, "Secret({})", REDACTED)
+        }
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Secret::resolve(&source).map_err(de::Error::custom)
+    }
+}
+
+/// AES-256-GCM key that `enc:` values are encrypted with
+///
+/// Read from `NEXA_MASTER_KEY`, or from the file `NEXA_MASTER_KEY_FILE`
+/// names, as 32 bytes in base64. It is never part of the configuration.
+pub struct MasterKey(LessSafeKey);
+
+impl MasterKey {
+    /// A new random key, base64-encoded
+    pub fn generate() -> Result<String, CommonError> {
+        let mut key = [0u8; KEY_LENGTH];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| CommonError::ConfigError("no randomness for a master key".to_string()))?;
+        Ok(STANDARD.encode(key))
+    }
+
+    /// Key from its base64 encoding
+    pub fn parse(encoded: &str) -> Result<Self, CommonError> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| CommonError::ConfigError(format!("master key is not base64: {}", e)))?;
+        if bytes.len() != KEY_LENGTH {
+            return Err(CommonError::ConfigError(format!(
+                "master key must be {} bytes, not {}",
+                KEY_LENGTH,
+                bytes.len()
+            )));
+        }
+        let key = UnboundKey::new(&AES_256_GCM, &bytes)
+            .map_err(|_| CommonError::ConfigError("invalid master key".to_string()))?;
+        Ok(Self(LessSafeKey::new(key)))
+    }
+
+    /// Key from `NEXA_MASTER_KEY` or `NEXA_MASTER_KEY_FILE`
+    pub fn from_env() -> Result<Self, CommonError> {
+        Self::configured()?.ok_or_else(|| {
+            CommonError::EnvError(format!(
+                "encrypted values need the master key in {} or {}",
+                MASTER_KEY_ENV, MASTER_KEY_FILE_ENV
+            ))
+        })
+    }
+
+    /// Key from `NEXA_MASTER_KEY` or `NEXA_MASTER_KEY_FILE`, `None` when neither is set
+    pub fn configured() -> Result<Option<Self>, CommonError> {
+        if let Ok(encoded) = std::env::var(MASTER_KEY_ENV) {
+            return Self::parse(&encoded).map(Some);
+        }
+        match std::env::var(MASTER_KEY_FILE_ENV) {
+            Ok(path) => Self::parse(&std::fs::read_to_string(&path)?).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Encrypt a value, returning it as an `enc:` reference
+    pub fn encrypt(&self, value: &str) -> Result<String, CommonError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| CommonError::ConfigError("no randomness for a nonce".to_string()))?;
+        let mut sealed = value.as_bytes().to_vec();
+        self.0
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+            .map_err(|_| CommonError::ConfigError("encryption failed".to_string()))?;
+        Ok(format!("{}{}", ENCRYPTED_PREFIX, URL_SAFE_NO_PAD.encode([&nonce[..], &sealed].concat())))
+    }
+
+    /// Decrypt an `enc:` reference
+    pub fn decrypt(&self, encrypted: &str) -> Result<String, CommonError> {
+        let invalid = || CommonError::ConfigError("encrypted value is damaged or was encrypted with another master key".to_string());
+        let mut sealed = URL_SAFE_NO_PAD
+            .decode(encrypted.strip_prefix(ENCRYPTED_PREFIX).unwrap_or(encrypted))
+            .map_err(|_| invalid())?;
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid());
+        }
+        let mut ciphertext = sealed.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&sealed).map_err(|_| invalid())?;
+        let value = self
+            .0
+            .open_in_place(nonce, Aad::empty(), &mut ciphertext)
+            .map_err(|_| invalid())?;
+        String::from_utf8(value.to_vec()).map_err(|_| invalid())
+    }
+}
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MasterKey({})", REDACTED)
+    }
+}
+
+/// Whether the field at a dotted path, e.g. `auth.jwt_secret`, holds a secret
+pub fn is_secret_field(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    let name = path.rsplit('.').next().unwrap_or_default();
+    matches!(name, "key" | "token")
+        || name.ends_with("_token")
+        || SECRET_FIELDS.iter().any(|field| match field.contains('.') {
+            true => path == *field || path.ends_with(&format!(".{}", field)),
+            false => name.contains(field),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_references() {
+        std::env::set_var("NEXA_TEST_SECRET_REFERENCE", "from-env");
+        let secret = Secret::resolve("env:NEXA_TEST_SECRET_REFERENCE").unwrap();
+        assert_eq!(secret, "from-env");
+        assert_eq!(secret.source(), "env:NEXA_TEST_SECRET_REFERENCE");
+        assert!(secret.is_reference());
+        assert!(Secret::resolve("env:NEXA_TEST_SECRET_UNSET").is_err());
+
+        let path = std::env::temp_dir().join(format!("nexa-secret-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "from-file\n").unwrap();
+        assert_eq!(Secret::resolve(&format!("file:{}", path.display())).unwrap(), "from-file");
+        std::fs::remove_file(&path).unwrap();
+
+        let plain = Secret::resolve("plain-value").unwrap();
+        assert_eq!(plain.expose(), "plain-value");
+        assert!(!plain.is_reference());
+    }
+
+    #[test]
+    fn test_encrypted_values() {
+        let key = MasterKey::parse(&MasterKey::generate().unwrap()).unwrap();
+        let encrypted = key.encrypt("s3cret").unwrap();
+        assert!(encrypted.starts_with("enc:"));
+        assert_ne!(key.encrypt("s3cret").unwrap(), encrypted);
+        assert_eq!(key.decrypt(&encrypted).unwrap(), "s3cret");
+
+        let other = MasterKey::parse(&MasterKey::generate().unwrap()).unwrap();
+        assert!(other.decrypt(&encrypted).is_err());
+
+        let secret = Secret::encrypt("s3cret", &key).unwrap();
+        assert_eq!((secret.expose(), key.decrypt(secret.source()).unwrap().as_str()), ("s3cret", "s3cret"));
+        assert_eq!(format!("{:?}", secret), "Secret([redacted])");
+        assert_eq!(Secret::from_stored("file:/etc/passwd").unwrap(), "file:/etc/passwd");
+        assert!(MasterKey::parse("c2hvcnQ=").is_err());
+    }
+
+    #[test]
+    fn test_secret_fields() {
+        for path in ["auth.jwt_secret", "llm.api_key", "orchestrator.token", "agent_communication.agent_token", "database.url", "after.database.url", "password"] {
+            assert!(is_secret_field(path), "{}", path);
+        }
+        for path in ["llm.max_tokens", "agents.url", "auth.jwt_issuer", "username"] {
+            assert!(!is_secret_field(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_debug_and_serialize() {
+        let secret = Secret::from("s3cret");
+        assert_eq!(format!("{:?}", secret), "Secret([redacted])");
+        assert_eq!(serde_json::to_value(&secret).unwrap(), "s3cret");
+
+        std::env::set_var("NEXA_TEST_SECRET_DEBUG", "s3cret");
+        let reference: Secret = serde_json::from_str("\"env:NEXA_TEST_SECRET_DEBUG\"").unwrap();
+        assert_eq!(format!("{:?}", reference), "Secret(env:NEXA_TEST_SECRET_DEBUG)");
+        assert_eq!(serde_json::to_value(&reference).unwrap(), "env:NEXA_TEST_SECRET_DEBUG");
+    }
+}
@@ -0,0 +1,384 @@
+//! TLS termination shared by the gateway and Agora
+//!
+//! [`TlsReloader`] builds a rustls server configuration from the PEM files
+//! named in [`TlsSettings`] and rebuilds it when the files change, so
+//! renewed certificates are picked up without a restart: every accepted
+//! connection checks the files, at most once a second. A broken renewal
+//! keeps the previous certificate in use.
+//!
+//! With a client authority configured, clients may authenticate with a
+//! certificate (mutual TLS). [`TlsReloader::client_certificate`] names the
+//! client of a connection by the certificate's subject common name, mapped
+//! to a gateway identity by `client_identities`. Unlisted subjects have no
+//! identity unless `map_unlisted_subjects` is set.
+
+use crate::config::TlsSettings;
+use crate::error::CommonError;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig, ServerConnection};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+/// How often certificate files are checked for changes
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// ALPN protocols offered by HTTP listeners
+pub const HTTP_ALPN_PROTOCOLS: &[&[u8]] = &[b"h2", b"http/1.1"];
+
+/// Client that authenticated with a certificate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// Subject common name of the certificate
+    pub subject: String,
+    /// Identity the subject maps to, if any
+    pub identity: Option<String>,
+}
+
+fn tls_error(message: impl Into<String>) -> CommonError {
+    CommonError::TlsError(message.into())
+}
+
+fn open(path: &str) -> Result<BufReader<File>, CommonError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| tls_error(format!("Cannot read {}: {}", path, e)))
+}
+
+fn read_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, CommonError> {
+    let certificates = rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| tls_error(format!("Invalid certificate in {}: {}", path, e)))?;
+    if certificates.is_empty() {
+        return Err(tls_error(format!("No certificate found in {}", path)));
+    }
+    Ok(certificates)
+}
+
+fn read_private_key(path: &str) -> Result<PrivateKeyDer<'static>, CommonError> {
+    rustls_pemfile::private_key(&mut open(path)?)
+        .map_err(|e| tls_error(format!("Invalid private key in {}: {}", path, e)))?
+        .ok_or_else(|| tls_error(format!("No private key found in {}", path)))
+}
+
+/// Build a server configuration from the files named in the settings
+pub fn server_config(settings: &TlsSettings, alpn_protocols: &[&[u8]]) -> Result<ServerConfig, CommonError> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| tls_error(e.to_string()))?;
+
+    let builder = match &settings.client_ca_path {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in read_certificates(ca_path)? {
+                roots
+                    .add(certificate)
+                    .map_err(|e| tls_error(format!("Invalid client authority in {}: {}", ca_path, e)))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if settings.require_client_cert {
+                verifier
+            } else {
+                verifier.allow_unauthenticated()
+            };
+            builder.with_client_cert_verifier(verifier.build().map_err(|e| tls_error(e.to_string()))?)
+        }
+        None if settings.require_client_cert => {
+            return Err(tls_error("require_client_cert needs client_ca_path"));
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder
+        .with_single_cert(read_certificates(&settings.cert_path)?, read_private_key(&settings.key_path)?)
+        .map_err(|e| tls_error(format!("Certificate does not match key {}: {}", settings.key_path, e)))?;
+    config.alpn_protocols = alpn_protocols.iter().map(|protocol| protocol.to_vec()).collect();
+    Ok(config)
+}
+
+/// Modification time and size of each file a configuration was built from
+fn file_stamps(settings: &TlsSettings) -> Vec<Option<(SystemTime, u64)>> {
+    std::iter::once(&settings.cert_path)
+        .chain(std::iter::once(&settings.key_path))
+        .chain(settings.client_ca_path.iter())
+        .map(|path| {
+            std::fs::metadata(path)
+                .ok()
+                .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())))
+        })
+        .collect()
+}
+
+struct Loaded {
+    config: Arc<ServerConfig>,
+    stamps: Vec<Option<(SystemTime, u64)>>,
+    checked: Instant,
+}
+
+/// Server configuration rebuilt when its files change
+pub struct TlsReloader {
+    settings: TlsSettings,
+    alpn_protocols: Vec<&'static [u8]>,
+    loaded: Mutex<Loaded>,
+}
+
+impl TlsReloader {
+    /// Load the certificate, key and client authority, failing when any is invalid
+    pub fn new(settings: TlsSettings, alpn_protocols: &[&'static [u8]]) -> Result<Self, CommonError> {
+        let stamps = file_stamps(&settings);
+        let config = server_config(&settings, alpn_protocols)?;
+        Ok(Self {
+            settings,
+            alpn_protocols: alpn_protocols.to_vec(),
+            loaded: Mutex::new(Loaded {
+                config: Arc::new(config),
+                stamps,
+                checked: Instant::now(),
+            }),
+        })
+    }
+
+    /// Acceptor for the next connection, with the certificate currently on disk
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.config_at(Instant::now()))
+    }
+
+    /// Current configuration, reloaded first when the files changed
+    pub(crate) fn config_at(&self, now: Instant) -> Arc<ServerConfig> {
+        let mut loaded = self.loaded.lock().expect("TLS configuration poisoned");
+        if now.saturating_duration_since(loaded.checked) < CHECK_INTERVAL {
+            return loaded.config.clone();
+        }
+        loaded.checked = now;
+
+        let stamps = file_stamps(&self.settings);
+        if stamps != loaded.stamps {
+            loaded.stamps = stamps;
+            match server_config(&self.settings, &self.alpn_protocols) {
+                Ok(config) => {
+                    info!(cert_path = %self.settings.cert_path, "Reloaded TLS certificate");
+                    loaded.config = Arc::new(config);
+                }
+                Err(e) => warn!(error = %e, "Keeping the previous TLS certificate"),
+            }
+        }
+        loaded.config.clone()
+    }
+
+    /// Client certificate a connection authenticated with, if any
+    pub fn client_certificate(&self, connection: &ServerConnection) -> Option<ClientCertificate> {
+        let leaf = connection.peer_certificates()?.first()?;
+        let subject = subject_common_name(leaf)?;
+        let identity = self
+            .settings
+            .client_identities
+            .iter()
+            .find(|mapping| mapping.subject == subject)
+            .map(|mapping| mapping.username.clone())
+            .or_else(|| self.settings.map_unlisted_subjects.then(|| subject.clone()));
+        Some(ClientCertificate { subject, identity })
+    }
+}
+
+/// Split one DER element into its tag, contents and the bytes after it
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = if first & 0x80 == 0 {
+        (usize::from(first), rest)
+    } else {
+        let octets = usize::from(first & 0x7f);
+        if octets == 0 || octets > std::mem::size_of::<usize>() || rest.len() < octets {
+            return None;
+        }
+        let length = rest[..octets].iter().fold(0usize, |length, &b| (length << 8) | usize::from(b));
+        (length, &rest[octets..])
+    };
+    if rest.len() < length {
+        return None;
+    }
+    Some((tag, &rest[..length], &rest[length..]))
+}
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const EXPLICIT_VERSION: u8 = 0xa0;
+/// id-at-commonName, 2.5.4.3
+const COMMON_NAME_OID: &[u8] = &[0x55, 0x04, 0x03];
+
+/// Subject common name of a DER certificate
+pub fn subject_common_name(certificate: &[u8]) -> Option<String> {
+    let (SEQUENCE, certificate, _) = der_element(certificate)? else {
+        return None;
+    };
+    let (SEQUENCE, mut tbs, _) = der_element(certificate)? else {
+        return None;
+    };
+    if tbs.first() == Some(&EXPLICIT_VERSION) {
+        tbs = der_element(tbs)?.2;
+    }
+    // Serial number, signature algorithm, issuer and validity precede the subject
+    for _ in 0..4 {
+        tbs = der_element(tbs)?.2;
+    }
+    let (SEQUENCE, mut names, _) = der_element(tbs)? else {
+        return None;
+    };
+
+    while !names.is_empty() {
+        let (SET, mut attributes, rest) = der_element(names)? else {
+            return None;
+        };
+        names = rest;
+        while !attributes.is_empty() {
+            let (SEQUENCE, attribute, rest) = der_element(attributes)? else {
+                return None;
+            };
+            attributes = rest;
+            let (OBJECT_IDENTIFIER, oid, value) = der_element(attribute)? else {
+                return None;
+            };
+            if oid == COMMON_NAME_OID {
+                let (_, value, _) = der_element(value)?;
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClientIdentitySettings;
+    use rustls::pki_types::ServerName;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn fixture(name: &str) -> String {
+        format!("{}/testdata/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn settings(dir: &std::path::Path) -> TlsSettings {
+        for name in ["server.pem", "server.key"] {
+            std::fs::copy(fixture(name), dir.join(name)).unwrap();
+        }
+        TlsSettings {
+            cert_path: dir.join("server.pem").to_string_lossy().into_owned(),
+            key_path: dir.join("server.key").to_string_lossy().into_owned(),
+            client_ca_path: Some(fixture("ca.pem")),
+            require_client_cert: false,
+            client_identities: vec![ClientIdentitySettings {
+                subject: "agent-1".to_string(),
+                username: "agent-bot".to_string(),
+            }],
+            map_unlisted_subjects: false,
+        }
+    }
+
+    fn client_config(certificate: Option<&str>) -> Arc<rustls::ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(read_certificates(&fixture("ca.pem")).unwrap());
+        let builder = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        Arc::new(match certificate {
+            Some(name) => builder
+                .with_client_auth_cert(
+                    read_certificates(&fixture(&format!("{}.pem", name))).unwrap(),
+                    read_private_key(&fixture(&format!("{}.key", name))).unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        })
+    }
+
+    /// Accept one connection, answering with the client's identity
+    async fn handshake(reloader: Arc<TlsReloader>, certificate: Option<&str>) -> Result<String, std::io::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = reloader.acceptor().accept(stream).await?;
+            let identity = reloader
+                .client_certificate(stream.get_ref().1)
+                .map(|client| client.identity.unwrap_or_else(|| "unmapped".to_string()))
+                .unwrap_or_else(|| "anonymous".to_string());
+            stream.write_all(identity.as_bytes()).await?;
+            stream.shutdown().await
+        });
+
+        let connector = tokio_rustls::TlsConnector::from(client_config(certificate));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = connector.connect(ServerName::try_from("localhost").unwrap(), stream).await?;
+        let mut identity = String::new();
+        let read = stream.read_to_string(&mut identity).await;
+        server.await.unwrap()?;
+        read.map(|_| identity)
+    }
+
+    #[test]
+    fn test_subject_common_name() {
+        let client = read_certificates(&fixture("client.pem")).unwrap();
+        assert_eq!(subject_common_name(&client[0]).as_deref(), Some("agent-1"));
+        let server = read_certificates(&fixture("server.pem")).unwrap();
+        assert_eq!(subject_common_name(&server[0]).as_deref(), Some("localhost"));
+        assert_eq!(subject_common_name(b"\x30\x03\x02\x01"), None);
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls_identities() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut settings = settings(dir.path());
+        let reloader = Arc::new(TlsReloader::new(settings.clone(), &[]).unwrap());
+        assert_eq!(handshake(reloader.clone(), Some("client")).await.unwrap(), "agent-bot");
+        assert_eq!(handshake(reloader.clone(), None).await.unwrap(), "anonymous");
+        assert!(handshake(reloader, Some("rogue")).await.is_err());
+
+        settings.require_client_cert = true;
+        let reloader = Arc::new(TlsReloader::new(settings.clone(), &[]).unwrap());
+        assert!(handshake(reloader.clone(), None).await.is_err());
+        assert_eq!(handshake(reloader, Some("client")).await.unwrap(), "agent-bot");
+
+        // Unlisted subjects only act as the user of their name when allowed to
+        settings.client_identities.clear();
+        let reloader = Arc::new(TlsReloader::new(settings.clone(), &[]).unwrap());
+        assert_eq!(handshake(reloader, Some("client")).await.unwrap(), "unmapped");
+        settings.map_unlisted_subjects = true;
+        let reloader = Arc::new(TlsReloader::new(settings.clone(), &[]).unwrap());
+        assert_eq!(handshake(reloader, Some("client")).await.unwrap(), "agent-1");
+
+        settings.client_ca_path = None;
+        assert!(TlsReloader::new(settings, &[]).is_err());
+    }
+
+    #[test]
+    fn test_certificate_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = settings(dir.path());
+        let reloader = TlsReloader::new(settings.clone(), &[]).unwrap();
+        let start = Instant::now();
+        let first = reloader.config_at(start);
+
+        // Unchanged files keep the configuration
+        assert!(Arc::ptr_eq(&first, &reloader.config_at(start + CHECK_INTERVAL)));
+
+        // A broken renewal keeps the previous certificate
+        std::fs::write(&settings.cert_path, "not a certificate").unwrap();
+        assert!(Arc::ptr_eq(&first, &reloader.config_at(start + CHECK_INTERVAL * 2)));
+
+        // A valid one replaces it, once the check interval has passed
+        std::fs::copy(fixture("server2.pem"), &settings.cert_path).unwrap();
+        std::fs::copy(fixture("server2.key"), &settings.key_path).unwrap();
+        assert!(Arc::ptr_eq(&first, &reloader.config_at(start + CHECK_INTERVAL * 2)));
+        let renewed = reloader.config_at(start + CHECK_INTERVAL * 3);
+        assert!(!Arc::ptr_eq(&first, &renewed));
+    }
+}
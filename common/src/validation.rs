@@ -0,0 +1,422 @@
+//! Configuration validation
+//!
+//! Loading checks the whole configuration before anything starts: keys the
+//! schema does not know, which are usually typos that would otherwise be
+//! ignored, and values that parse but cannot work, such as port 0, a URL
+//! without a scheme or a well-known JWT secret in production. Every problem
+//! is reported at once, with the key it concerns.
+
+use crate::config::{Settings, TlsSettings};
+use serde_json::Value;
+use std::fmt;
+use std::path::Path;
+use thiserror::Error;
+use url::Url;
+
+/// Secrets shipped in sample configuration, never acceptable in production
+const DEFAULT_SECRETS: &[&str] = &[
+    "supersecretkey",
+    "dev-secret-key",
+    "test-secret-key",
+    "default-secret-key",
+    "changeme",
+    "secret",
+];
+
+/// Minimum secret length accepted in production
+const MIN_PRODUCTION_SECRET_LENGTH: usize = 32;
+
+/// Log levels accepted by `logging.level`
+pub const LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error"];
+
+/// Algorithms `auth.jwt_secret` can sign with
+const SECRET_ALGORITHMS: &[&str] = &["HS256", "HS384", "HS512"];
+
+/// Protocols agents can be reached with
+const AGENT_PROTOCOLS: &[&str] = &["http", "https", "ws", "wss"];
+
+/// Unknown keys within this edit distance of a known one get a suggestion
+const SUGGESTION_DISTANCE: usize = 2;
+
+/// Problem with one configuration key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// Dotted key, e.g. `server.port`; empty for the configuration as a whole
+    pub key: String,
+    /// What is wrong and how to fix it
+    pub message: String,
+}
+
+impl ConfigProblem {
+    pub fn new(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.key, self.message)
+        }
+    }
+}
+
+/// Configuration that cannot be used, with everything wrong with it
+#[derive(Debug, Clone, Error)]
+pub struct InvalidConfig {
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl InvalidConfig {
+    /// Configuration that could not be read at all
+    pub fn unreadable(error: impl fmt::Display) -> Self {
+        Self {
+            problems: vec![ConfigProblem::new("", error.to_string())],
+        }
+    }
+}
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.problems.as_slice() {
+            [problem] => write!(f, "Invalid configuration: {}", problem),
+            problems => {
+                write!(f, "Invalid configuration, {} problems:", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Keys of `raw` that are not part of the schema
+///
+/// `known` is the deserialized settings serialized again, so it holds every
+/// key of the schema that `raw` could have set.
+pub fn unknown_keys(raw: &Value, known: &Value) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    collect_unknown_keys("", raw, known, &mut problems);
+    problems
+}
+
+fn collect_unknown_keys(path: &str, raw: &Value, known: &Value, problems: &mut Vec<ConfigProblem>) {
+    match (raw, known) {
+        (Value::Object(raw), Value::Object(known)) => {
+            for (key, value) in raw {
+                let key_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                match known.get(key) {
+                    Some(known) => collect_unknown_keys(&key_path, value, known, problems),
+                    None => {
+                        let suggestion = known
+                            .keys()
+                            .map(|candidate| (edit_distance(key, candidate), candidate))
+                            .filter(|(distance, _)| *distance <= SUGGESTION_DISTANCE)
+                            .min()
+                            .map(|(_, candidate)| format!("; did you mean `{}`?", candidate))
+                            .unwrap_or_default();
+                        problems.push(ConfigProblem::new(key_path, format!("unknown key{}", suggestion)));
+                    }
+                }
+            }
+        }
+        (Value::Array(raw), Value::Array(known)) => {
+            for (index, (raw, known)) in raw.iter().zip(known).enumerate() {
+                collect_unknown_keys(&format!("{}[{}]", path, index), raw, known, problems);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Levenshtein distance between two keys
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Why `secret` cannot sign tokens in `environment`, if it cannot
+///
+/// Outside production any secret is accepted; in production it must not be
+/// a sample value and must be at least [`MIN_PRODUCTION_SECRET_LENGTH`] bytes.
+pub fn insecure_secret(secret: &str, environment: &str) -> Option<String> {
+    if environment != "production" {
+        None
+    } else if DEFAULT_SECRETS.contains(&secret) {
+        Some("is set to a default value; configure a unique secret for production".to_string())
+    } else if secret.len() < MIN_PRODUCTION_SECRET_LENGTH {
+        Some(format!("must be at least {} bytes in production", MIN_PRODUCTION_SECRET_LENGTH))
+    } else {
+        None
+    }
+}
+
+/// Values that parse but cannot work
+pub fn validate(settings: &Settings) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    let mut problem = |key: &str, message: String| problems.push(ConfigProblem::new(key, message));
+
+    if settings.environment.trim().is_empty() {
+        problem("environment", "must not be empty".to_string());
+    }
+
+    // Listeners
+    for (section, host, port, tls) in [
+        ("server", &settings.server.host, settings.server.port, &settings.server.tls),
+        ("agora", &settings.agora.host, settings.agora.port, &settings.agora.tls),
+    ] {
+        if host.trim().is_empty() {
+            problem(&format!("{}.host", section), "must not be empty".to_string());
+        }
+        if port == 0 {
+            problem(&format!("{}.port", section), "must be between 1 and 65535".to_string());
+        }
+        if let Some(tls) = tls {
+            validate_tls(&format!("{}.tls", section), tls, &mut problem);
+        }
+    }
+    if settings.server.port != 0 && settings.server.port == settings.agora.port {
+        problem(
+            "agora.port",
+            format!("is also server.port ({}); the listeners need their own ports", settings.server.port),
+        );
+    }
+
+    // Database
+    // The URL may hold a password, so it is not repeated
+    if !["postgres", "postgresql", "sqlite"].contains(&url_scheme(settings.database.url.expose()).unwrap_or_default().as_str()) {
+        problem("database.url", "must be a postgres:// or sqlite: URL".to_string());
+    }
+    if settings.database.max_connections == 0 {
+        problem("database.max_connections", "must be at least 1".to_string());
+    }
+
+    // Authentication
+    let auth = &settings.auth;
+    if auth.jwt_expiration == 0 {
+        problem("auth.jwt_expiration", "must be at least 1 hour".to_string());
+    }
+    if auth.refresh_expiration < auth.jwt_expiration {
+        problem(
+            "auth.refresh_expiration",
+            format!("must be at least auth.jwt_expiration ({} hours)", auth.jwt_expiration),
+        );
+    }
+    if auth.jwt_keys.is_empty() {
+        if !SECRET_ALGORITHMS.contains(&auth.jwt_algorithm.as_str()) {
+            problem(
+                "auth.jwt_algorithm",
+                format!("must be one of {} when signing with auth.jwt_secret", SECRET_ALGORITHMS.join(", ")),
+            );
+        }
+        if auth.jwt_secret.is_empty() {
+            problem("auth.jwt_secret", "must not be empty".to_string());
+        } else if let Some(message) = insecure_secret(auth.jwt_secret.expose(), &settings.environment) {
+            problem("auth.jwt_secret", message);
+        }
+    }
+    for (index, key) in auth.jwt_keys.iter().enumerate() {
+        let prefix = format!("auth.jwt_keys[{}]", index);
+        if key.kid.trim().is_empty() {
+            problem(&format!("{}.kid", prefix), "must not be empty".to_string());
+        }
+        validate_file(&format!("{}.public_key_path", prefix), &key.public_key_path, &mut problem);
+        if let Some(path) = &key.private_key_path {
+            validate_file(&format!("{}.private_key_path", prefix), path, &mut problem);
+        }
+    }
+    if let Some(kid) = &auth.jwt_signing_kid {
+        if !auth.jwt_keys.iter().any(|key| &key.kid == kid) {
+            problem("auth.jwt_signing_kid", format!("'{}' is not the kid of any auth.jwt_keys entry", kid));
+        }
+    }
+    if let Some(oauth) = &auth.oauth {
+        validate_url("auth.oauth.issuer_url", &oauth.issuer_url, &["http", "https"], &mut problem);
+        validate_url("auth.oauth.redirect_uri", &oauth.redirect_uri, &["http", "https"], &mut problem);
+        if oauth.client_id.trim().is_empty() {
+            problem("auth.oauth.client_id", "must not be empty".to_string());
+        }
+    }
+
+    // LLM provider
+    let llm = &settings.llm;
+    validate_url("llm.url", &llm.url, &["http", "https"], &mut problem);
+    if !(0.0..=2.0).contains(&llm.temperature) {
+        problem("llm.temperature", "must be between 0 and 2".to_string());
+    }
+    if llm.max_tokens == 0 {
+        problem("llm.max_tokens", "must be at least 1".to_string());
+    }
+    if !llm.available_models.is_empty() && !llm.available_models.contains(&llm.default_model) {
+        problem(
+            "llm.default_model",
+            format!("'{}' is not one of llm.available_models", llm.default_model),
+        );
+    }
+
+    // Vector database and cache
+    if let Some(vectordb) = &settings.vectordb {
+        validate_url("vectordb.url", &vectordb.url, &["http", "https"], &mut problem);
+    }
+    let cache = &settings.cache;
+    if !(cache.similarity_threshold > 0.0 && cache.similarity_threshold <= 1.0) {
+        problem("cache.similarity_threshold", "must be above 0 and at most 1".to_string());
+    }
+    if cache.enabled && cache.collection.trim().is_empty() {
+        problem("cache.collection", "must not be empty when the cache is enabled".to_string());
+    }
+
+    // Logging
+    if !LOG_LEVELS.contains(&settings.logging.level.to_ascii_lowercase().as_str()) {
+        problem("logging.level", format!("must be one of {}", LOG_LEVELS.join(", ")));
+    }
+
+    // Agent communication
+    let agents = &settings.agent_communication;
+    if !AGENT_PROTOCOLS.contains(&agents.protocol.as_str()) {
+        problem("agent_communication.protocol", format!("must be one of {}", AGENT_PROTOCOLS.join(", ")));
+    }
+    validate_url("agent_communication.agent_url", &agents.agent_url, AGENT_PROTOCOLS, &mut problem);
+    if agents.heartbeat_interval == 0 {
+        problem("agent_communication.heartbeat_interval", "must be at least 1 second".to_string());
+    }
+    if agents.timeout == 0 {
+        problem("agent_communication.timeout", "must be at least 1 second".to_string());
+    }
+
+    // Orchestrator
+    validate_url("orchestrator.url", &settings.orchestrator.url, &["http", "https"], &mut problem);
+
+    // Limits
+    let rate_limit = &settings.rate_limit;
+    if rate_limit.enabled {
+        for (key, value) in [
+            ("rate_limit.requests", u64::from(rate_limit.requests)),
+            ("rate_limit.window_seconds", rate_limit.window_seconds),
+            ("rate_limit.llm_requests", u64::from(rate_limit.llm_requests)),
+        ] {
+            if value == 0 {
+                problem(key, "must be at least 1 when rate limiting is enabled".to_string());
+            }
+        }
+    }
+    if !(1..=100).contains(&settings.quotas.warning_percent) {
+        problem("quotas.warning_percent", "must be between 1 and 100".to_string());
+    }
+    if ![402, 429].contains(&settings.quotas.exceeded_status) {
+        problem("quotas.exceeded_status", "must be 429 or 402".to_string());
+    }
+
+    // CORS
+    let cors = &settings.cors;
+    let wildcard = |values: &[String]| values.iter().any(|value| value == "*");
+    if cors.allow_credentials
+        && (cors.allowed_origins.as_deref().is_some_and(wildcard)
+            || wildcard(&cors.allowed_methods)
+            || wildcard(&cors.allowed_headers)
+            || wildcard(&cors.expose_headers))
+    {
+        problem(
+            "cors.allow_credentials",
+            "cannot be combined with `*` origins, methods or headers".to_string(),
+        );
+    }
+    if settings.environment == "production" && cors.allowed_origins.as_deref().is_some_and(wildcard) {
+        problem(
+            "cors.allowed_origins",
+            "must list the allowed origins in production; `*` lets any website call the gateway".to_string(),
+        );
+    }
+
+    problems
+}
+
+fn validate_tls(prefix: &str, tls: &TlsSettings, problem: &mut impl FnMut(&str, String)) {
+    validate_file(&format!("{}.cert_path", prefix), &tls.cert_path, problem);
+    validate_file(&format!("{}.key_path", prefix), &tls.key_path, problem);
+    match &tls.client_ca_path {
+        Some(path) => validate_file(&format!("{}.client_ca_path", prefix), path, problem),
+        None if tls.require_client_cert => problem(
+            &format!("{}.require_client_cert", prefix),
+            "requires client_ca_path".to_string(),
+        ),
+        None => {}
+    }
+}
+
+fn validate_file(key: &str, path: &str, problem: &mut impl FnMut(&str, String)) {
+    if !Path::new(path).is_file() {
+        problem(key, format!("file '{}' does not exist", path));
+    }
+}
+
+fn validate_url(key: &str, value: &str, schemes: &[&str], problem: &mut impl FnMut(&str, String)) {
+    match url_scheme(value) {
+        Some(scheme) if schemes.contains(&scheme.as_str()) => {}
+        Some(scheme) => problem(key, format!("scheme '{}' must be one of {}", scheme, schemes.join(", "))),
+        None => problem(key, format!("'{}' is not a URL", value)),
+    }
+}
+
+fn url_scheme(value: &str) -> Option<String> {
+    Url::parse(value).ok().map(|url| url.scheme().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unknown_keys_are_reported_with_suggestions() {
+        let known = json!({
+            "server": { "host": "0.0.0.0", "port": 8080 },
+            "auth": { "roles": [{ "name": "ops", "permissions": [] }] },
+        });
+        let raw = json!({
+            "server": { "host": "0.0.0.0", "prot": 8080 },
+            "auth": { "roles": [{ "name": "ops", "permisions": [] }] },
+            "telemetry": {},
+        });
+
+        let problems = unknown_keys(&raw, &known);
+        let messages: Vec<String> = problems.iter().map(ToString::to_string).collect();
+        assert_eq!(messages.len(), 3);
+        assert!(messages.contains(&"server.prot: unknown key; did you mean `port`?".to_string()));
+        assert!(messages.contains(&"auth.roles[0].permisions: unknown key; did you mean `permissions`?".to_string()));
+        assert!(messages.contains(&"telemetry: unknown key".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_config_lists_every_problem() {
+        let error = InvalidConfig {
+            problems: vec![
+                ConfigProblem::new("server.port", "must be between 1 and 65535"),
+                ConfigProblem::new("llm.url", "'localhost' is not a URL"),
+            ],
+        };
+        assert_eq!(
+            error.to_string(),
+            "Invalid configuration, 2 problems:\n  - server.port: must be between 1 and 65535\n  - llm.url: 'localhost' is not a URL"
+        );
+    }
+}
@@ -0,0 +1,37 @@
+#!/bin/sh
+# Regenerate the self-signed certificates used by the TLS tests.
+#
+#   ca.pem / ca.key          test certificate authority
+#   server.pem / server.key  localhost, signed by the CA
+#   server2.pem / server2.key  second localhost certificate, for reload tests
+#   client.pem / client.key  client certificate with subject CN=agent-1
+#   rogue.pem / rogue.key    self-signed client certificate the CA does not know
+set -e
+cd "$(dirname "$0")"
+days=36500
+
+openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -days $days \
+    -subj "/CN=Nexa Test CA" -keyout ca.key -out ca.pem \
+    -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign,cRLSign"
+
+issue() {
+    name=$1; subject=$2; ext=$3
+    openssl req -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -subj "$subject" \
+        -keyout "$name.key" -out "$name.csr"
+    printf '%s\n' "$ext" > "$name.ext"
+    openssl x509 -req -in "$name.csr" -CA ca.pem -CAkey ca.key -CAcreateserial -days $days \
+        -extfile "$name.ext" -out "$name.pem"
+    rm "$name.csr" "$name.ext"
+}
+
+server_ext="basicConstraints=CA:FALSE
+subjectAltName=DNS:localhost,IP:127.0.0.1
+extendedKeyUsage=serverAuth"
+issue server "/CN=localhost" "$server_ext"
+issue server2 "/CN=localhost" "$server_ext"
+issue client "/O=Nexa Agents/CN=agent-1" "basicConstraints=CA:FALSE
+extendedKeyUsage=clientAuth"
+
+openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -days $days \
+    -subj "/CN=agent-1" -keyout rogue.key -out rogue.pem
+rm -f ca.srl
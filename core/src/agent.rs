@@ -1,4 +1,8 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use crate::routes::Agent;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentInfo {
@@ -70,3 +74,60 @@ pub async fn get_agent_status(agent_id: &str) -> anyhow::Result<AgentStatus> {
         is_online: true,
     })
 }
+
+/// Agents of every tenant
+///
+/// The built-in assistant is offered to all tenants; agents created through
+/// the API are only visible to the tenant that created them. Kept in memory.
+#[derive(Default)]
+pub struct AgentRegistry {
+    agents: RwLock<HashMap<String, BTreeMap<String, Agent>>>,
+}
+
+impl AgentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Agent shared by all tenants
+    fn builtin() -> Agent {
+        Agent {
+            id: "1".to_string(),
+            name: "Assistant".to_string(),
+            capabilities: vec!["chat".to_string(), "summarize".to_string()],
+        }
+    }
+
+    /// Agents visible to a tenant
+    pub fn list(&self, tenant: &str) -> Vec<Agent> {
+        let agents = self.agents.read().expect("agent registry poisoned");
+        std::iter::once(Self::builtin())
+            .chain(agents.get(tenant).into_iter().flat_map(|agents| agents.values().cloned()))
+            .collect()
+    }
+
+    /// Agent of a tenant by ID; other tenants' agents are not found
+    pub fn get(&self, tenant: &str, id: &str) -> Option<Agent> {
+        let builtin = Self::builtin();
+        if builtin.id == id {
+            return Some(builtin);
+        }
+        let agents = self.agents.read().expect("agent registry poisoned");
+        agents.get(tenant).and_then(|agents| agents.get(id)).cloned()
+    }
+
+    /// Register an agent for a tenant
+    pub fn create(&self, tenant: &str, name: String, capabilities: Vec<String>) -> Agent {
+        let agent = Agent {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            capabilities,
+        };
+        let mut agents = self.agents.write().expect("agent registry poisoned");
+        agents
+            .entry(tenant.to_string())
+            .or_default()
+            .insert(agent.id.clone(), agent.clone());
+        agent
+    }
+}
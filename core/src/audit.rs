@@ -0,0 +1,82 @@
+//! Audit recording for route handlers
+//!
+//! Handlers that change something, and the login routes, take an [`Audit`]
+//! and record what they did. Events are stamped with the client address:
+//! the peer of the connection, or the last `X-Forwarded-For` entry when the
+//! gateway is configured to trust the reverse proxy in front of it. Behind
+//! a permission layer, the caller is recorded as the actor unless the
+//! handler names one.
+
+use auth::jwt::Claims;
+use auth::{AuditEvent, AuditLog};
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::{request::Parts, Extensions, HeaderMap};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// Header a reverse proxy appends the client address to
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// Audit log handle for the current request
+pub struct Audit {
+    log: Arc<AuditLog>,
+    source_ip: Option<String>,
+    /// Caller verified by a permission layer
+    claims: Option<Claims>,
+}
+
+impl Audit {
+    /// Record an event for this request
+    pub async fn record(&self, mut event: AuditEvent) {
+        if let (None, Some(claims)) = (&event.actor, &self.claims) {
+            event = event.with_actor(claims);
+        }
+        self.log.record(event.with_source_ip(self.source_ip.clone())).await;
+    }
+}
+
+impl FromRequestParts<AppState> for Audit {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            log: state.audit.clone(),
+            source_ip: client_ip(&parts.headers, &parts.extensions, state.config.current().audit.trust_forwarded_for),
+            claims: parts.extensions.get::<Claims>().cloned(),
+        })
+    }
+}
+
+/// Address of the client making a request
+///
+/// The peer of the connection, or the last `X-Forwarded-For` entry when the
+/// proxy in front of the gateway is trusted.
+pub(crate) fn client_ip(headers: &HeaderMap, extensions: &Extensions, trust_forwarded_for: bool) -> Option<String> {
+    let forwarded = if trust_forwarded_for {
+        forwarded_for(headers)
+    } else {
+        None
+    };
+    forwarded.or_else(|| {
+        extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+    })
+}
+
+/// Client address added by the proxy in front of the gateway
+///
+/// Earlier entries come from the client and cannot be trusted.
+fn forwarded_for(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .rfind(|entry| !entry.is_empty())
+        .map(str::to_string)
+}
@@ -0,0 +1,237 @@
+//! Semantic response cache for LLM completions
+//!
+//! Prompts are embedded and looked up in a dedicated vector collection,
+//! one per tenant through `vectordb::TenantStore`. A stored response is
+//! reused when a previous prompt of the same tenant, for the same model and
+//! temperature, is similar enough and has not expired.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use common::config::SemanticCacheSettings;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::{debug, info};
+use vectordb::{PayloadFilter, TenantStore, VectorPoint, VectorStore, VectorDbError};
+
+use crate::status;
+
+/// Number of candidates fetched per lookup so expired entries can be skipped
+const LOOKUP_CANDIDATES: u64 = 5;
+
+/// Semantic cache backed by a vector store
+pub struct SemanticCache {
+    store: Arc<dyn VectorStore>,
+    settings: SemanticCacheSettings,
+    /// Tenants whose cache collection is known to exist
+    ready_tenants: Mutex<HashSet<String>>,
+}
+
+impl SemanticCache {
+    /// Create a cache storing entries in the given vector store
+    pub fn new(store: Arc<dyn VectorStore>, settings: SemanticCacheSettings) -> Self {
+        Self {
+            store,
+            settings,
+            ready_tenants: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Cache settings in effect
+    pub fn settings(&self) -> &SemanticCacheSettings {
+        &self.settings
+    }
+
+    /// Look up a cached response for a prompt embedding
+    ///
+    /// Returns the stored response when a non-expired entry of the tenant
+    /// with the same model and temperature scores at or above the similarity
+    /// threshold. Hits and misses are recorded in the system metrics.
+    pub async fn lookup(
+        &self,
+        tenant: &str,
+        model: &str,
+        temperature: f32,
+        embedding: &[f32],
+    ) -> Result<Option<serde_json::Value>> {
+        let store = self.ensure_collection(tenant, embedding.len() as u64).await?;
+
+        let candidates = store
+            .search(
+                &self.settings.collection,
+                embedding,
+                LOOKUP_CANDIDATES,
+                Some(&scope_filter(tenant, model, temperature)),
+            )
+            .await
+            .context("Failed to search semantic cache")?;
+
+        let now = Utc::now().timestamp();
+        let mut expired = Vec::new();
+        let mut hit = None;
+
+        for candidate in candidates {
+            if candidate.score < self.settings.similarity_threshold {
+                break;
+            }
+
+            let expires_at = candidate
+                .payload
+                .get("expires_at")
+                .and_then(|v| v.as_i64())
+                .unwrap_or_default();
+
+            if expires_at <= now {
+                if let Some(id) = candidate.id {
+                    expired.push(id);
+                }
+                continue;
+            }
+
+            hit = candidate.payload.get("response").cloned();
+            if hit.is_some() {
+                debug!(score = candidate.score, "Semantic cache hit");
+                break;
+            }
+        }
+
+        if !expired.is_empty() {
+            debug!("Removing {} expired cache entries", expired.len());
+            store
+                .delete_points(&self.settings.collection, &expired)
+                .await
+                .context("Failed to remove expired cache entries")?;
+        }
+
+        if hit.is_some() {
+            status::record_cache_hit();
+        } else {
+            status::record_cache_miss();
+        }
+
+        Ok(hit)
+    }
+
+    /// Store a response of a tenant for a prompt embedding
+    pub async fn insert(
+        &self,
+        tenant: &str,
+        model: &str,
+        temperature: f32,
+        prompt: &str,
+        embedding: Vec<f32>,
+        response: &serde_json::Value,
+    ) -> Result<()> {
+        let store = self.ensure_collection(tenant, embedding.len() as u64).await?;
+
+        let now = Utc::now().timestamp();
+        let mut payload = HashMap::new();
+        payload.insert("tenant".to_string(), json!(tenant));
+        payload.insert("model".to_string(), json!(model));
+        payload.insert("temperature".to_string(), json!(temperature_key(temperature)));
+        payload.insert("prompt".to_string(), json!(prompt));
+        payload.insert("response".to_string(), response.clone());
+        payload.insert("created_at".to_string(), json!(now));
+        payload.insert(
+            "expires_at".to_string(),
+            json!(now + self.settings.ttl_seconds as i64),
+        );
+
+        let point = VectorPoint {
+            id: uuid::Uuid::new_v4().to_string(),
+            vector: embedding,
+            payload,
+        };
+
+        store
+            .upsert(&self.settings.collection, vec![point])
+            .await
+            .context("Failed to store semantic cache entry")
+    }
+
+    /// Remove a tenant's cached responses, either for one model or all of them
+    ///
+    /// Other tenants' entries are never touched.
+    pub async fn invalidate(&self, tenant: &str, model: Option<&str>) -> Result<()> {
+        let store = self.tenant_store(tenant);
+        match model {
+            Some(model) => {
+                info!(tenant, "Invalidating semantic cache entries for model {}", model);
+                let filter = PayloadFilter::new().must_match("tenant", tenant).must_match("model", model);
+                match store.delete_by_filter(&self.settings.collection, &filter).await {
+                    Ok(()) | Err(VectorDbError::CollectionNotFound(_)) => Ok(()),
+                    Err(e) => Err(e).context("Failed to invalidate semantic cache"),
+                }
+            }
+            None => {
+                info!(tenant, "Invalidating the tenant's semantic cache");
+                match store.delete_collection(&self.settings.collection).await {
+                    Ok(()) | Err(VectorDbError::CollectionNotFound(_)) => {}
+                    Err(e) => return Err(e).context("Failed to invalidate semantic cache"),
+                }
+                self.ready_tenants
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .remove(tenant);
+                Ok(())
+            }
+        }
+    }
+
+    /// View of the vector store holding only the tenant's collections
+    fn tenant_store(&self, tenant: &str) -> TenantStore {
+        TenantStore::new(self.store.clone(), tenant)
+    }
+
+    /// Create the tenant's cache collection on first use
+    async fn ensure_collection(&self, tenant: &str, vector_size: u64) -> Result<TenantStore> {
+        let store = self.tenant_store(tenant);
+        if self.ready_tenants.lock().unwrap_or_else(PoisonError::into_inner).contains(tenant) {
+            return Ok(store);
+        }
+
+        store
+            .ensure_collection(&self.settings.collection, vector_size)
+            .await
+            .context("Failed to create semantic cache collection")?;
+        self.ready_tenants
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(tenant.to_string());
+        Ok(store)
+    }
+}
+
+/// Temperature rounded to two decimals, used as an exact-match scope key
+fn temperature_key(temperature: f32) -> String {
+    format!("{:.2}", temperature)
+}
+
+/// Filter restricting lookups to entries of the tenant for the same model and temperature
+fn scope_filter(tenant: &str, model: &str, temperature: f32) -> PayloadFilter {
+    PayloadFilter::new()
+        .must_match("tenant", tenant)
+        .must_match("model", model)
+        .must_match("temperature", temperature_key(temperature))
+}
+
+/// Flatten chat messages into the text used for prompt embeddings
+pub fn prompt_text(request: &serde_json::Value) -> String {
+    request
+        .get("messages")
+        .and_then(|messages| messages.as_array())
+        .map(|messages| {
+            messages
+                .iter()
+                .map(|message| {
+                    format!(
+                        "{}: {}",
+                        message.get("role").and_then(|r| r.as_str()).unwrap_or("user"),
+                        message.get("content").and_then(|c| c.as_str()).unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .unwrap_or_default()
+}
@@ -1,5 +1,13 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
+use auth::{AuditEvent, AuditQuery};
+use common::config_file::ConfigFile;
+use common::loader::ConfigLoader;
+use rand::Rng;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::Mutex;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkSettings {
@@ -20,13 +28,7 @@ pub struct AuthSettings {
     pub oauth_settings: Option<OAuthSettings>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OAuthSettings {
-    pub provider: String,
-    pub client_id: String,
-    pub client_secret: String,
-    pub redirect_uri: String,
-}
+pub use common::config::OAuthSettings;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogSettings {
@@ -45,16 +47,20 @@ pub struct OrchestratorSettings {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserInfo {
     pub username: String,
-    pub role: String,
+    pub roles: Vec<String>,
     pub last_login: Option<String>,
 }
 
-// Mock functions to handle settings
+/// Host, port and CORS origins from the configuration
+///
+/// Addressing of the host itself, DHCP or static, is left to the operating
+/// system and not part of the gateway's configuration.
 pub async fn get_network_settings() -> Result<NetworkSettings> {
+    let settings = current_settings()?;
     Ok(NetworkSettings {
-        hostname: "nexa-gateway".to_string(),
-        port: 3000,
-        cors_origins: Some("*".to_string()),
+        hostname: settings.server.host,
+        port: settings.server.port,
+        cors_origins: settings.cors.allowed_origins.map(|origins| origins.join(",")),
         use_dhcp: true,
         ip_address: None,
         subnet_mask: None,
@@ -62,59 +68,90 @@ pub async fn get_network_settings() -> Result<NetworkSettings> {
     })
 }
 
-pub async fn update_network_settings(_settings: &NetworkSettings) -> Result<()> {
-    Ok(())
+/// Stage the host, port and comma-separated CORS origins; no origins unsets the list
+pub async fn update_network_settings(settings: &NetworkSettings) -> Result<()> {
+    let origins: Vec<String> = settings
+        .cors_origins
+        .iter()
+        .flat_map(|origins| origins.split(','))
+        .map(|origin| origin.trim().to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    edit_config(|file| {
+        let shown = file.settings()?;
+        file.set_edited("server.host", &shown.server.host, &settings.hostname)?;
+        file.set_edited("server.port", &shown.server.port, &settings.port)?;
+        let origins = Some(origins).filter(|origins| !origins.is_empty());
+        file.set_edited("cors.allowed_origins", &shown.cors.allowed_origins, &origins)?;
+        Ok(())
+    })
 }
 
 pub async fn get_auth_settings() -> Result<AuthSettings> {
+    let auth = current_settings()?.auth;
     Ok(AuthSettings {
-        auth_type: "jwt".to_string(),
+        auth_type: if auth.oauth.is_some() { "oauth2" } else { "jwt" }.to_string(),
         api_key: None,
-        jwt_secret: Some("default-secret-key".to_string()),
-        oauth_settings: None,
+        jwt_secret: Some(auth.jwt_secret.source().to_string()),
+        oauth_settings: auth.oauth,
     })
 }
 
-pub async fn update_auth_settings(_settings: &AuthSettings) -> Result<()> {
-    Ok(())
+/// Stage the JWT secret and OpenID Connect provider, where given and changed
+///
+/// API keys are stored in the user database, not the configuration.
+pub async fn update_auth_settings(settings: &AuthSettings) -> Result<()> {
+    edit_config(|file| {
+        let shown = file.settings()?.auth;
+        if let Some(secret) = &settings.jwt_secret {
+            file.set_edited("auth.jwt_secret", &shown.jwt_secret.source().to_string(), secret)?;
+        }
+        if let Some(oauth) = &settings.oauth_settings {
+            file.set_edited("auth.oauth", &shown.oauth, &Some(oauth.clone()))?;
+        }
+        Ok(())
+    })
 }
 
 pub async fn get_log_settings() -> Result<LogSettings> {
+    let logging = current_settings()?.logging;
     Ok(LogSettings {
-        level: "info".to_string(),
-        file_path: "/var/log/nexa-gateway/app.log".to_string(),
-        max_size_mb: 10,
-        max_files: 5,
+        level: logging.level,
+        file_path: logging.file_path.unwrap_or_default(),
+        max_size_mb: logging.max_size_mb,
+        max_files: logging.max_files,
     })
 }
 
-pub async fn update_log_settings(_settings: &LogSettings) -> Result<()> {
-    Ok(())
+/// Stage the log settings; an empty file path logs to the console only
+pub async fn update_log_settings(settings: &LogSettings) -> Result<()> {
+    let logging = common::config::LoggingSettings {
+        level: settings.level.clone(),
+        file_path: Some(settings.file_path.clone()).filter(|path| !path.is_empty()),
+        max_size_mb: settings.max_size_mb,
+        max_files: settings.max_files,
+    };
+    edit_config(|file| Ok(file.set_edited("logging", &file.settings()?.logging, &logging)?))
 }
 
 pub async fn get_orchestrator_settings() -> Result<OrchestratorSettings> {
+    let orchestrator = current_settings()?.orchestrator;
     Ok(OrchestratorSettings {
-        orchestrator_url: "http://localhost:3001".to_string(),
-        orchestrator_token: "default-token".to_string(),
+        orchestrator_url: orchestrator.url,
+        orchestrator_token: orchestrator.token.source().to_string(),
     })
 }
 
-pub async fn update_orchestrator_settings(_settings: &OrchestratorSettings) -> Result<()> {
-    Ok(())
+pub async fn update_orchestrator_settings(settings: &OrchestratorSettings) -> Result<()> {
+    let orchestrator = common::config::OrchestratorSettings {
+        url: settings.orchestrator_url.clone(),
+        token: settings.orchestrator_token.clone().into(),
+    };
+    edit_config(|file| Ok(file.set_edited("orchestrator", &file.settings()?.orchestrator, &orchestrator)?))
 }
 
 pub async fn get_llm_provider_settings() -> Result<common::config::LlmProviderSettings> {
-    // Base settings
-    let mut settings = common::config::LlmProviderSettings {
-        provider_name: "LM Studio".to_string(),
-        api_key: "".to_string(),
-        model: "local".to_string(),
-        temperature: 0.7,
-        max_tokens: 2048,
-        url: "http://localhost:1234".to_string(),
-        available_models: vec!["local".to_string()],
-        default_model: "local".to_string(),
-    };
+    let mut settings = current_settings()?.llm;
     
     // Try to fetch available models from the LLM provider
     match crate::llm::fetch_available_models(&settings.url).await {
@@ -128,71 +165,356 @@ pub async fn get_llm_provider_settings() -> Result<common::config::LlmProviderSe
             }
         },
         _ => {
-            // If fetch fails, keep the default models
-            settings.available_models = vec!["local".to_string(), "llama2".to_string(), "mistral".to_string()];
+            // If fetch fails, keep the configured models
         }
     }
     
     Ok(settings)
 }
 
-pub async fn update_llm_provider_settings(_settings: &common::config::LlmProviderSettings) -> Result<()> {
-    Ok(())
+pub async fn update_llm_provider_settings(settings: &common::config::LlmProviderSettings) -> Result<()> {
+    edit_config(|file| Ok(file.set_edited("llm", &file.settings()?.llm, settings)?))
 }
 
 pub async fn get_agent_communication_settings() -> Result<common::config::AgentCommunicationSettings> {
-    Ok(common::config::AgentCommunicationSettings {
-        agent_url: "http://localhost:3002".to_string(),
-        agent_token: "default-agent-token".to_string(),
-        protocol: "http".to_string(),
-        heartbeat_interval: 30,
-        timeout: 60,
-    })
+    Ok(current_settings()?.agent_communication)
+}
+
+pub async fn update_agent_communication_settings(settings: &common::config::AgentCommunicationSettings) -> Result<()> {
+    edit_config(|file| Ok(file.set_edited("agent_communication", &file.settings()?.agent_communication, settings)?))
 }
 
-pub async fn update_agent_communication_settings(_settings: &common::config::AgentCommunicationSettings) -> Result<()> {
+/// Changes staged by the `update_*_settings` functions, written by [`save_all_settings`]
+///
+/// Each function stages only the values the user changed from those in
+/// effect, so values of other layers are not copied into the file.
+static PENDING: Mutex<Option<PendingChanges>> = Mutex::new(None);
+
+struct PendingChanges {
+    file: ConfigFile,
+    /// Audit events of the staged changes, recorded once they are saved
+    events: Vec<AuditEvent>,
+}
+
+/// Stage a change to the configuration file
+///
+/// The change is kept only if the configuration stays valid with it.
+fn edit_config(edit: impl FnOnce(&mut ConfigFile) -> Result<()>) -> Result<()> {
+    let mut pending = PENDING.lock().expect("pending configuration poisoned");
+    let mut file = match pending.as_ref() {
+        Some(pending) => pending.file.clone(),
+        None => ConfigFile::open(ConfigLoader::from_env().base())?,
+    };
+    edit(&mut file)?;
+    file.settings()?;
+    match pending.as_mut() {
+        Some(pending) => pending.file = file,
+        None => *pending = Some(PendingChanges { file, events: Vec::new() }),
+    }
     Ok(())
 }
 
+/// Settings with the staged changes
+fn current_settings() -> Result<common::config::Settings> {
+    match PENDING.lock().expect("pending configuration poisoned").as_ref() {
+        Some(pending) => Ok(pending.file.settings()?),
+        None => load_settings(),
+    }
+}
+
+/// Diff of the staged changes against the configuration file, if there are any
+pub fn pending_changes() -> Option<String> {
+    PENDING
+        .lock()
+        .expect("pending configuration poisoned")
+        .as_ref()
+        .filter(|pending| pending.file.is_changed())
+        .map(|pending| pending.file.diff())
+}
+
+/// Drop the staged changes
+pub fn discard_changes() {
+    PENDING.lock().expect("pending configuration poisoned").take();
+}
+
+/// Load the configured settings
+fn load_settings() -> Result<common::config::Settings> {
+    Ok(ConfigLoader::from_env().load()?)
+}
+
+/// Open the user account service for the configured database
+async fn auth_service() -> Result<auth::AuthService> {
+    let settings = load_settings()?;
+    let store = auth::connect_store(settings.database.url.expose(), settings.database.max_connections)?;
+    Ok(auth::AuthService::from_settings(store, &settings)?)
+}
+
+/// Open the audit log for the configured database and file
+fn audit_log() -> Result<auth::AuditLog> {
+    let settings = load_settings()?;
+    let store = auth::connect_store(settings.database.url.expose(), settings.database.max_connections)?;
+    Ok(auth::AuditLog::from_settings(store, &settings.audit)?)
+}
+
+/// Name CLI changes are recorded under: `cli:` and the operating system user
+fn cli_actor() -> String {
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    format!("cli:{}", user)
+}
+
+/// Record a change made through the CLI
+///
+/// The change has already been made, so an audit log that cannot be opened
+/// is reported as a warning.
+async fn record_audit(event: AuditEvent) {
+    match audit_log() {
+        Ok(log) => log.record(event.with_actor_name(cli_actor())).await,
+        Err(e) => tracing::warn!(error = %e, action = %event.action, "Cannot open audit log"),
+    }
+}
+
+/// Record a change of a settings section, secrets redacted
+///
+/// A staged change is recorded when it is saved.
+pub async fn record_settings_change<T: Serialize>(section: &str, before: &T, after: &T) {
+    let event = AuditEvent::new(&format!("config.{}.update", section))
+        .with_target(section)
+        .with_before(before)
+        .with_after(after);
+    if let Some(pending) = PENDING.lock().expect("pending configuration poisoned").as_mut() {
+        pending.events.push(event);
+        return;
+    }
+    record_audit(event).await;
+}
+
+/// Audit events matching a query, newest first
+pub async fn get_audit_events(query: &AuditQuery) -> Result<Vec<AuditEvent>> {
+    Ok(audit_log()?.query(query).await?)
+}
+
 pub async fn get_users() -> Result<Vec<UserInfo>> {
-    Ok(vec![
-        UserInfo {
-            username: "admin".to_string(),
-            role: "admin".to_string(),
-            last_login: Some("2023-08-15T14:30:00Z".to_string()),
-        },
-        UserInfo {
-            username: "user".to_string(),
-            role: "user".to_string(),
-            last_login: Some("2023-08-14T10:15:00Z".to_string()),
-        },
-    ])
+    let users = auth_service().await?.list_users().await?;
+    Ok(users
+        .into_iter()
+        .map(|user| UserInfo {
+            username: user.username,
+            roles: user.roles,
+            last_login: user
+                .last_login
+                .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+                .map(|ts| ts.to_rfc3339()),
+        })
+        .collect())
 }
 
-pub async fn add_user(_username: &str, _password: &str, _role: &str) -> Result<()> {
+/// Fields of an account recorded in the audit log
+fn audited_user(user: &auth::User) -> serde_json::Value {
+    serde_json::json!({ "id": user.id, "username": user.username, "roles": user.roles })
+}
+
+pub async fn add_user(username: &str, password: &str, roles: &[String]) -> Result<()> {
+    let user = auth_service().await?.create_user(username, password, roles).await?;
+    record_audit(AuditEvent::new("user.create").with_target(&user.id).with_after(&audited_user(&user))).await;
     Ok(())
 }
 
-pub async fn remove_user(_username: &str) -> Result<()> {
+pub async fn remove_user(username: &str) -> Result<()> {
+    let service = auth_service().await?;
+    let user = service.get_user_by_username(username).await?;
+    service.delete_user(&user.id).await?;
+    record_audit(AuditEvent::new("user.delete").with_target(&user.id).with_before(&audited_user(&user))).await;
     Ok(())
 }
 
-pub async fn change_password(_username: &str, _new_password: &str) -> Result<()> {
+pub async fn change_password(username: &str, new_password: &str) -> Result<()> {
+    let service = auth_service().await?;
+    let user = service.get_user_by_username(username).await?;
+    service.change_password(&user.id, new_password).await?;
+    let after = serde_json::json!({ "password": common::secret::REDACTED });
+    record_audit(AuditEvent::new("user.update").with_target(&user.id).with_after(&after)).await;
     Ok(())
 }
 
-pub async fn generate_api_key() -> Result<String> {
-    Ok("api-key-12345".to_string())
+/// Active sessions of a user
+pub async fn get_sessions(username: &str) -> Result<Vec<auth::Session>> {
+    let service = auth_service().await?;
+    let user = service.get_user_by_username(username).await?;
+    Ok(service.list_sessions(&user.id).await?)
 }
 
-pub async fn generate_jwt_secret() -> Result<String> {
-    Ok("jwt-secret-12345".to_string())
+/// End one session of a user
+pub async fn revoke_session(username: &str, session_id: &str) -> Result<()> {
+    let service = auth_service().await?;
+    let user = service.get_user_by_username(username).await?;
+    service.revoke_session(&user.id, session_id).await?;
+    let before = serde_json::json!({ "session_id": session_id });
+    record_audit(AuditEvent::new("user.session.revoke").with_target(&user.id).with_before(&before)).await;
+    Ok(())
 }
 
-pub async fn save_all_settings() -> Result<()> {
+/// End every session of a user, returning how many were active
+pub async fn revoke_all_sessions(username: &str) -> Result<usize> {
+    let service = auth_service().await?;
+    let user = service.get_user_by_username(username).await?;
+    let revoked = service.revoke_all_sessions(&user.id).await?;
+    let after = serde_json::json!({ "revoked": revoked });
+    record_audit(AuditEvent::new("user.sessions.revoke").with_target(&user.id).with_after(&after)).await;
+    Ok(revoked)
+}
+
+/// Rotate the JWT signing secret, returning the new key ID
+pub async fn rotate_signing_key() -> Result<String> {
+    let kid = auth_service().await?.rotate_signing_key().await?;
+    record_audit(AuditEvent::new("signing_key.rotate").with_target(&kid)).await;
+    Ok(kid)
+}
+
+/// Keys currently used to sign and verify tokens
+pub async fn get_signing_keys() -> Result<Vec<auth::service::SigningKeyInfo>> {
+    Ok(auth_service().await?.signing_keys().await?)
+}
+
+/// Roles with the permissions they grant
+pub async fn get_roles() -> Result<Vec<auth::Role>> {
+    Ok(auth_service().await?.list_roles().await?)
+}
+
+/// Create or replace a role stored in the database
+pub async fn save_role(role: auth::RoleDefinition) -> Result<auth::Role> {
+    let service = auth_service().await?;
+    let before = service.get_role(&role.name).await.ok().map(|role| role.definition);
+    let role = service.save_role(role).await?;
+    let event = AuditEvent::new("role.put")
+        .with_target(&role.definition.name)
+        .with_before(&before)
+        .with_after(&role.definition);
+    record_audit(event).await;
+    Ok(role)
+}
+
+/// Delete a role stored in the database
+pub async fn delete_role(name: &str) -> Result<()> {
+    let service = auth_service().await?;
+    let before = service.get_role(name).await?.definition;
+    service.delete_role(name).await?;
+    record_audit(AuditEvent::new("role.delete").with_target(name).with_before(&before)).await;
     Ok(())
 }
 
-pub async fn restart_gateway() -> Result<()> {
+/// Create an API key for a user, returning the full key
+///
+/// The key cannot be shown again; only a hash of its secret is stored.
+pub async fn generate_api_key(
+    username: &str,
+    name: &str,
+    scopes: &[String],
+    expires_in_days: Option<u32>,
+) -> Result<String> {
+    let service = auth_service().await?;
+    let user = service.get_user_by_username(username).await?;
+    let expires_at = expires_in_days.map(|days| chrono::Utc::now().timestamp() + i64::from(days) * 86400);
+    let (api_key, key) = service.create_api_key(&user.id, name, scopes, expires_at).await?;
+    record_audit(AuditEvent::new("api_key.create").with_target(&api_key.id).with_after(&api_key)).await;
+    Ok(key)
+}
+
+/// API keys of a user, revoked ones included
+pub async fn get_api_keys(username: &str) -> Result<Vec<auth::ApiKey>> {
+    let service = auth_service().await?;
+    let user = service.get_user_by_username(username).await?;
+    Ok(service.list_api_keys(&user.id).await?)
+}
+
+/// Revoke one of a user's API keys
+pub async fn revoke_api_key(username: &str, key_id: &str) -> Result<()> {
+    let service = auth_service().await?;
+    let user = service.get_user_by_username(username).await?;
+    service.revoke_api_key(&user.id, key_id).await?;
+    record_audit(AuditEvent::new("api_key.revoke").with_target(key_id)).await;
     Ok(())
 }
+
+/// Random secret long enough for production
+pub async fn generate_jwt_secret() -> Result<String> {
+    Ok(rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect())
+}
+
+/// Write the staged changes to the configuration file
+///
+/// Returns the backup of the previous file, or `None` when nothing changed.
+/// The changes stay staged when they cannot be saved.
+pub async fn save_all_settings() -> Result<Option<PathBuf>> {
+    let (backup, path, events) = {
+        let mut guard = PENDING.lock().expect("pending configuration poisoned");
+        let Some(pending) = guard.as_mut() else {
+            return Ok(None);
+        };
+        if !pending.file.is_changed() {
+            guard.take();
+            return Ok(None);
+        }
+        let backup = pending.file.save()?;
+        let pending = guard.take().expect("pending changes just saved");
+        (backup, pending.file.path().to_path_buf(), pending.events)
+    };
+    tracing::info!(path = %path.display(), backup = %backup.display(), "Configuration saved");
+    for event in events {
+        record_audit(event).await;
+    }
+    Ok(Some(backup))
+}
+
+/// Have the running gateway reload its configuration, with `POST /api/config/reload`
+///
+/// Logs in at `server.host` and `server.port`, over HTTPS when `server.tls`
+/// is set, as `username`, who must be a platform admin. `second_factor` is
+/// asked for a code when the account needs one.
+pub async fn reload_gateway(
+    username: &str,
+    password: &str,
+    second_factor: impl FnOnce() -> Result<String>,
+) -> Result<crate::reload::ReloadReport> {
+    let server = load_settings()?.server;
+    let host = match server.host.as_str() {
+        "0.0.0.0" | "::" => "localhost",
+        host => host,
+    };
+    let scheme = if server.tls.is_some() { "https" } else { "http" };
+    let base = format!("{}://{}:{}", scheme, host, server.port);
+    let client = reqwest::Client::new();
+
+    let credentials = json!({ "username": username, "password": password });
+    let mut login: Value = send(client.post(format!("{}/api/login", base)).json(&credentials)).await?;
+    if login["two_factor_required"] == true {
+        if login["enrollment_required"] == true {
+            bail!("{} must enroll a second factor before logging in", username);
+        }
+        let code = json!({ "challenge_token": login["challenge_token"], "code": second_factor()? });
+        login = send(client.post(format!("{}/api/login/totp", base)).json(&code)).await?;
+    }
+    let token = login["token"]
+        .as_str()
+        .ok_or_else(|| anyhow!("The gateway answered the login without a token"))?;
+    send(client.post(format!("{}/api/config/reload", base)).bearer_auth(token)).await
+}
+
+/// Send a request to the gateway, reporting the problem it answers with
+async fn send<T: DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T> {
+    let response = request.send().await.context("Cannot reach the gateway")?;
+    let status = response.status();
+    if !status.is_success() {
+        let detail = match response.json::<common::error::ProblemDetails>().await {
+            Ok(problem) => problem.detail,
+            Err(_) => status.to_string(),
+        };
+        bail!("The gateway refused: {}", detail);
+    }
+    Ok(response.json().await?)
+}
@@ -0,0 +1,142 @@
+//! Cross-origin resource sharing
+//!
+//! The CORS policy comes from the `cors` settings. Origins left unset follow
+//! the environment: any origin while developing, none in production, where
+//! the allowed origins must be listed. The policy sits behind a lock so a
+//! configuration reload can replace it without rebuilding the router.
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderName, HeaderValue, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use common::config::{CorsSettings, Settings};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tower::{Layer, ServiceExt};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// Wildcard allowing any origin, method or header
+const WILDCARD: &str = "*";
+
+/// Environments allowing any origin when none are configured
+const PERMISSIVE_ENVIRONMENTS: &[&str] = &["development", "test"];
+
+/// Origins allowed in an environment: the configured ones, or the environment's default
+pub fn allowed_origins(settings: &CorsSettings, environment: &str) -> Vec<String> {
+    match &settings.allowed_origins {
+        Some(origins) => origins.clone(),
+        None if PERMISSIVE_ENVIRONMENTS.contains(&environment) => vec![WILDCARD.to_string()],
+        None => Vec::new(),
+    }
+}
+
+/// Build the CORS layer for the settings
+///
+/// Fails on values that are not valid origins, methods or headers, and on
+/// credentials combined with a wildcard, which browsers reject.
+pub fn cors_layer(settings: &Settings) -> Result<CorsLayer> {
+    let cors = &settings.cors;
+    let origins = allowed_origins(cors, &settings.environment);
+    let wildcard = |values: &[String]| values.iter().any(|value| value == WILDCARD);
+    if cors.allow_credentials
+        && (wildcard(&origins)
+            || wildcard(&cors.allowed_methods)
+            || wildcard(&cors.allowed_headers)
+            || wildcard(&cors.expose_headers))
+    {
+        bail!("cors.allow_credentials cannot be combined with `*` origins, methods or headers");
+    }
+
+    let allow_origin = if wildcard(&origins) {
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin.trim_end_matches('/'))
+                    .with_context(|| format!("Invalid CORS origin '{}'", origin))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        AllowOrigin::list(origins)
+    };
+
+    let mut layer = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_credentials(cors.allow_credentials)
+        .max_age(Duration::from_secs(cors.max_age_seconds));
+    layer = if wildcard(&cors.allowed_methods) {
+        layer.allow_methods(Any)
+    } else {
+        let methods = cors
+            .allowed_methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                    .with_context(|| format!("Invalid CORS method '{}'", method))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        layer.allow_methods(methods)
+    };
+    layer = if wildcard(&cors.allowed_headers) {
+        layer.allow_headers(Any)
+    } else {
+        layer.allow_headers(header_names(&cors.allowed_headers)?)
+    };
+    layer = if wildcard(&cors.expose_headers) {
+        layer.expose_headers(Any)
+    } else {
+        layer.expose_headers(header_names(&cors.expose_headers)?)
+    };
+    Ok(layer)
+}
+
+fn header_names(names: &[String]) -> Result<Vec<HeaderName>> {
+    names
+        .iter()
+        .map(|name| HeaderName::from_bytes(name.as_bytes()).with_context(|| format!("Invalid CORS header '{}'", name)))
+        .collect()
+}
+
+/// CORS policy in effect, replaceable at runtime
+#[derive(Clone)]
+pub struct CorsState {
+    layer: Arc<RwLock<CorsLayer>>,
+}
+
+impl CorsState {
+    /// Policy for the settings
+    pub fn from_settings(settings: &Settings) -> Result<Self> {
+        Ok(Self {
+            layer: Arc::new(RwLock::new(cors_layer(settings)?)),
+        })
+    }
+
+    /// Apply the CORS settings of reloaded configuration
+    ///
+    /// The current policy stays in effect when the new one is invalid.
+    pub fn reload(&self, settings: &Settings) -> Result<()> {
+        let layer = cors_layer(settings)?;
+        *self.layer.write().expect("CORS policy poisoned") = layer;
+        tracing::info!(origins = ?allowed_origins(&settings.cors, &settings.environment), "CORS policy reloaded");
+        Ok(())
+    }
+
+    fn current(&self) -> CorsLayer {
+        self.layer.read().expect("CORS policy poisoned").clone()
+    }
+}
+
+/// CORS middleware applying the current policy
+///
+/// Attach to the whole router with
+/// `axum::middleware::from_fn_with_state(cors_state, cors)`.
+pub async fn cors(State(state): State<CorsState>, request: Request<Body>, next: Next) -> Response {
+    match state.current().layer(next).oneshot(request).await {
+        Ok(response) => response.into_response(),
+        Err(never) => match never {},
+    }
+}
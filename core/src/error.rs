@@ -1,48 +0,0 @@
-use axum::{
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    Json,
-};
-use serde_json::json;
-use std::fmt;
-use std::error::Error as StdError;
-
-#[derive(Debug)]
-#[allow(dead_code)]
-pub enum AppError {
-    AuthenticationError(String),
-    NotFound(String),
-    InternalServerError(String),
-    BadRequest(String),
-}
-
-impl fmt::Display for AppError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            AppError::AuthenticationError(msg) => write!(f, "Authentication error: {}", msg),
-            AppError::NotFound(msg) => write!(f, "Not found: {}", msg),
-            AppError::InternalServerError(msg) => write!(f, "Internal server error: {}", msg),
-            AppError::BadRequest(msg) => write!(f, "Bad request: {}", msg),
-        }
-    }
-}
-
-impl StdError for AppError {}
-
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AppError::AuthenticationError(msg) => (StatusCode::UNAUTHORIZED, msg),
-            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
-            AppError::InternalServerError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
-            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
-        };
-
-        let body = Json(json!({
-            "error": error_message,
-        }));
-
-        (status, body).into_response()
-    }
-}
-
@@ -98,7 +98,7 @@ pub async fn fetch_available_models(url: &str) -> Result<Vec<String>> {
         status => {
             error!("Failed to fetch models: HTTP {}", status);
             // Try another approach - sometimes LM Studio uses a model route
-            match client.get(&format!("{}/model", base_url)).send().await {
+            match client.get(format!("{}/model", base_url)).send().await {
                 Ok(alt_response) if alt_response.status().is_success() => {
                     info!("Found model endpoint instead of models endpoint");
                     Ok(vec!["local".to_string()])
@@ -166,4 +166,101 @@ pub fn get_default_model(models: &[String]) -> String {
     
     // Otherwise, use the first available model
     models[0].clone()
-} 
\ No newline at end of file
+}
+
+/// Normalize a provider URL to its OpenAI-compatible `/v1` base
+fn api_base(url: &str) -> String {
+    format!("{}/v1", url.trim_end_matches('/'))
+}
+
+/// Build an HTTP client for completion-path requests
+fn completion_client() -> Result<Client> {
+    Client::builder()
+        .timeout(Duration::from_secs(120))
+        .build()
+        .context("Failed to build HTTP client")
+}
+
+/// Forward a chat completion request to the LLM provider
+///
+/// The request and response use the OpenAI chat completions format and
+/// are passed through unchanged.
+pub async fn chat_completion(url: &str, api_key: &str, request: &serde_json::Value) -> Result<serde_json::Value> {
+    let completions_url = format!("{}/chat/completions", api_base(url));
+    info!("Sending chat completion request to {}", completions_url);
+
+    let mut builder = completion_client()?.post(&completions_url).json(request);
+    if !api_key.is_empty() {
+        builder = builder.bearer_auth(api_key);
+    }
+
+    let response = builder
+        .send()
+        .await
+        .context("Failed to send request to LLM provider")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        anyhow::bail!("LLM provider returned HTTP {}: {}", status, body);
+    }
+
+    response
+        .json::<serde_json::Value>()
+        .await
+        .context("Failed to parse chat completion response")
+}
+
+/// Response from OpenAI / LM Studio embeddings API
+#[derive(Debug, Clone, Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+}
+
+/// Create an embedding vector for the given input text
+pub async fn create_embedding(url: &str, api_key: &str, model: &str, input: &str) -> Result<Vec<f32>> {
+    let embeddings_url = format!("{}/embeddings", api_base(url));
+
+    let mut builder = completion_client()?
+        .post(&embeddings_url)
+        .json(&serde_json::json!({ "model": model, "input": input }));
+    if !api_key.is_empty() {
+        builder = builder.bearer_auth(api_key);
+    }
+
+    let response = builder
+        .send()
+        .await
+        .context("Failed to send embeddings request to LLM provider")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("Embeddings request failed with HTTP {}", status);
+    }
+
+    let embeddings = response
+        .json::<EmbeddingsResponse>()
+        .await
+        .context("Failed to parse embeddings response")?;
+
+    embeddings
+        .data
+        .into_iter()
+        .next()
+        .map(|data| data.embedding)
+        .context("Embeddings response contained no data")
+}
+
+/// Total token usage reported in an OpenAI-format completion response
+pub fn response_total_tokens(response: &serde_json::Value) -> Option<usize> {
+    response
+        .get("usage")
+        .and_then(|usage| usage.get("total_tokens"))
+        .and_then(|tokens| tokens.as_u64())
+        .map(|tokens| tokens as usize)
+}
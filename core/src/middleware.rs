@@ -1,3 +1,304 @@
-pub mod middleware {
-    // Define middleware here
+//! Gateway middleware
+//!
+//! Rate limiting gives each client a token bucket per route class: one for
+//! the LLM routes, which are expensive to serve, and one for everything
+//! else. Clients are identified by the credential they present, an API key
+//! or a user session, once it verifies, and otherwise by their address, so
+//! an invalid token never buys a fresh bucket. Verifying a credential can
+//! take database lookups, so an address whose bucket is empty is refused
+//! before its credential is checked. Responses carry the `RateLimit-*`
+//! headers; a request over the limit is answered with 429 and `Retry-After`.
+
+use auth::jwt::Claims;
+use auth::AuthService;
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header::RETRY_AFTER, HeaderMap, HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use common::config::{RateLimitSettings, Settings};
+use common::error::{AppError, ErrorCode};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use crate::audit::client_ip;
+
+/// Path prefixes of routes that call an LLM
+const LLM_ROUTE_PREFIXES: &[&str] = &["/api/chat/"];
+
+/// Paths never limited, so health checks keep answering under load
+const UNLIMITED_PATHS: &[&str] = &["/", "/health"];
+
+/// Requests per window
+const RATE_LIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+/// Requests left before the limit applies
+const RATE_LIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+/// Seconds until the full limit is available again
+const RATE_LIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+/// Limit and window, as `<requests>;w=<seconds>`
+const RATE_LIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
+
+/// Routes sharing a bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteClass {
+    Standard,
+    Llm,
+}
+
+impl RouteClass {
+    /// Class of the route serving `path`
+    pub fn of(path: &str) -> Self {
+        if LLM_ROUTE_PREFIXES.iter().any(|prefix| path.starts_with(prefix)) {
+            RouteClass::Llm
+        } else {
+            RouteClass::Standard
+        }
+    }
+}
+
+/// Client a bucket belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClientKey {
+    /// User session, by user ID
+    User(String),
+    /// API key, by key ID; each key is limited separately from its owner
+    ApiKey(String),
+    /// Unauthenticated client, by address
+    Address(String),
+    /// Unauthenticated client whose address is unknown, which only happens
+    /// when the gateway is served without connection info; such clients
+    /// share one bucket
+    Unknown,
+}
+
+impl ClientKey {
+    fn from_claims(claims: &Claims) -> Self {
+        if claims.scopes.is_some() {
+            ClientKey::ApiKey(claims.jti.clone())
+        } else {
+            ClientKey::User(claims.sub.clone())
+        }
+    }
+}
+
+/// Outcome of taking a request from a bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    /// Whether the request may proceed
+    pub allowed: bool,
+    /// Requests per window
+    pub limit: u32,
+    /// Requests left
+    pub remaining: u32,
+    /// Seconds until the bucket is full again
+    pub reset: u64,
+    /// Seconds until the next request is allowed, zero when allowed
+    pub retry_after: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    buckets: HashMap<(ClientKey, RouteClass), Bucket>,
+    pruned: Instant,
+}
+
+/// In-memory token buckets for every client and route class
+///
+/// Limits apply per gateway instance. Reloaded limits apply to the existing
+/// buckets, so clients keep what they have used.
+pub struct RateLimiter {
+    settings: RwLock<RateLimitSettings>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            settings: RwLock::new(settings),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Limits in effect
+    pub fn settings(&self) -> RateLimitSettings {
+        self.settings.read().expect("rate limit settings poisoned").clone()
+    }
+
+    /// Apply the limits of reloaded configuration
+    pub fn reload(&self, settings: RateLimitSettings) {
+        *self.settings.write().expect("rate limit settings poisoned") = settings;
+    }
+
+    /// Requests per window for a route class
+    pub fn limit(&self, class: RouteClass) -> u32 {
+        limit_of(&self.settings(), class)
+    }
+
+    /// Take a request from the client's bucket
+    pub fn check(&self, key: ClientKey, class: RouteClass) -> RateLimitDecision {
+        self.check_at(key, class, Instant::now())
+    }
+
+    /// Whether the client's bucket has a request left, without taking it
+    pub fn peek(&self, key: ClientKey, class: RouteClass) -> RateLimitDecision {
+        self.take_at(key, class, Instant::now(), false)
+    }
+
+    pub(crate) fn check_at(&self, key: ClientKey, class: RouteClass, now: Instant) -> RateLimitDecision {
+        self.take_at(key, class, now, true)
+    }
+
+    fn take_at(&self, key: ClientKey, class: RouteClass, now: Instant, consume: bool) -> RateLimitDecision {
+        let settings = self.settings();
+        let window = settings.window_seconds.max(1) as f64;
+        let limit = limit_of(&settings, class);
+        let capacity = limit as f64;
+        let refill_rate = capacity / window;
+        let refilled = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * refill_rate).min(capacity)
+        };
+
+        let mut buckets = self.buckets.lock().expect("rate limit buckets poisoned");
+        // Buckets idle for a window are full again and need not be kept
+        if now.saturating_duration_since(buckets.pruned).as_secs_f64() >= window {
+            buckets.buckets.retain(|(_, class), bucket| {
+                let capacity = limit_of(&settings, *class) as f64;
+                let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+                bucket.tokens + elapsed * capacity / window < capacity
+            });
+            buckets.pruned = now;
+        }
+
+        let bucket = buckets.buckets.entry((key, class)).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = refilled(bucket);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed && consume {
+            bucket.tokens -= 1.0;
+        }
+
+        let seconds_for = |tokens: f64| {
+            if refill_rate > 0.0 {
+                (tokens.max(0.0) / refill_rate).ceil() as u64
+            } else {
+                window as u64
+            }
+        };
+        RateLimitDecision {
+            allowed,
+            limit,
+            remaining: bucket.tokens.floor() as u32,
+            reset: seconds_for(capacity - bucket.tokens),
+            retry_after: if allowed { 0 } else { seconds_for(1.0 - bucket.tokens) },
+        }
+    }
+}
+
+fn limit_of(settings: &RateLimitSettings, class: RouteClass) -> u32 {
+    match class {
+        RouteClass::Standard => settings.requests,
+        RouteClass::Llm => settings.llm_requests,
+    }
+}
+
+/// State for [`rate_limit`]
+#[derive(Clone)]
+pub struct RateLimitState {
+    limiter: Arc<RateLimiter>,
+    auth_service: AuthService,
+    trust_forwarded_for: bool,
+}
+
+impl RateLimitState {
+    /// Limit requests with `limiter`, which is switched off while `rate_limit.enabled` is false
+    pub fn new(limiter: Arc<RateLimiter>, settings: &Settings, auth_service: &AuthService) -> Self {
+        Self {
+            limiter,
+            auth_service: auth_service.clone(),
+            trust_forwarded_for: settings.audit.trust_forwarded_for,
+        }
+    }
+}
+
+/// Rate limiting middleware
+///
+/// Attach to the whole router with
+/// `axum::middleware::from_fn_with_state(RateLimitState::new(limiter, &settings, &auth), rate_limit)`.
+/// Verified [`Claims`] are added to the request extensions, where the
+/// permission check picks them up instead of verifying the credential again.
+pub async fn rate_limit(State(state): State<RateLimitState>, mut request: Request<Body>, next: Next) -> Response {
+    let path = request.uri().path();
+    if UNLIMITED_PATHS.contains(&path) || !state.limiter.settings().enabled {
+        return next.run(request).await;
+    }
+    let class = RouteClass::of(path);
+    let window = state.limiter.settings().window_seconds;
+    let address = client_ip(request.headers(), request.extensions(), state.trust_forwarded_for)
+        .map(ClientKey::Address)
+        .unwrap_or(ClientKey::Unknown);
+
+    // Claims of a client certificate are already there
+    let claims = match request.extensions().get::<Claims>() {
+        Some(claims) => Some(claims.clone()),
+        None => match auth::middleware::extract_credential(request.headers()) {
+            Ok(credential) => {
+                // Failed credentials are charged to the address, which must have a request left to try one
+                let decision = state.limiter.peek(address.clone(), class);
+                if !decision.allowed {
+                    return too_many_requests(&address, class, &decision, window);
+                }
+                state.auth_service.claims(&credential).await.ok()
+            }
+            Err(_) => None,
+        },
+    };
+    let key = match claims {
+        Some(claims) => {
+            let key = ClientKey::from_claims(&claims);
+            request.extensions_mut().insert(claims);
+            key
+        }
+        None => address,
+    };
+
+    let decision = state.limiter.check(key.clone(), class);
+    if !decision.allowed {
+        return too_many_requests(&key, class, &decision, window);
+    }
+
+    let mut response = next.run(request).await;
+    insert_headers(response.headers_mut(), &decision, window);
+    response
+}
+
+fn too_many_requests(key: &ClientKey, class: RouteClass, decision: &RateLimitDecision, window_seconds: u64) -> Response {
+    tracing::warn!(client = ?key, route_class = ?class, "Rate limit exceeded");
+    let message = format!("Rate limit exceeded, retry in {} seconds", decision.retry_after);
+    let mut response = AppError::new(ErrorCode::RateLimited, message).into_response();
+    insert_headers(response.headers_mut(), decision, window_seconds);
+    response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(decision.retry_after));
+    response
+}
+
+fn insert_headers(headers: &mut HeaderMap, decision: &RateLimitDecision, window_seconds: u64) {
+    headers.insert(RATE_LIMIT_LIMIT, HeaderValue::from(decision.limit));
+    headers.insert(RATE_LIMIT_REMAINING, HeaderValue::from(decision.remaining));
+    headers.insert(RATE_LIMIT_RESET, HeaderValue::from(decision.reset));
+    if let Ok(policy) = HeaderValue::from_str(&format!("{};w={}", decision.limit, window_seconds)) {
+        headers.insert(RATE_LIMIT_POLICY, policy);
+    }
 }
@@ -0,0 +1,357 @@
+//! LLM token budgets
+//!
+//! Completions are checked against the daily and monthly budgets of the
+//! caller, of the API key they use and of their teams before the request
+//! reaches the provider; the tokens the provider reports are counted
+//! afterwards. A request is let through while every budget has tokens
+//! left, so the last one may overrun it. Crossing the warning share of a
+//! budget, or the budget itself, publishes a `SystemNotification` through
+//! the gateway's Agora server on the tenant's `system` topic, whose
+//! subscribers include the WebSockets of `/api/notifications`. Counters,
+//! budgets and notifications are all kept per tenant.
+
+use agora::message::Message;
+use agora::AgoraServer;
+use auth::jwt::Claims;
+use auth::{AuthError, UsagePeriod, UsageStore, UsageSubject};
+use axum::http::{header::RETRY_AFTER, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use common::config::{QuotaSettings, TokenBudget};
+use common::error::{ErrorCode, ProblemDetails};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tracing::{debug, warn};
+
+/// Agora topic budget notifications are published on, per tenant
+pub const NOTIFICATION_TOPIC: &str = "system";
+
+/// Budget a completion counts against
+#[derive(Debug, Clone)]
+struct Budget {
+    subject: UsageSubject,
+    limits: TokenBudget,
+}
+
+impl Budget {
+    fn limit(&self, period: UsagePeriod) -> Option<u64> {
+        match period {
+            UsagePeriod::Day => self.limits.daily_tokens,
+            UsagePeriod::Month => self.limits.monthly_tokens,
+        }
+    }
+}
+
+/// A budget the caller has used up
+#[derive(Debug, Clone)]
+pub struct QuotaExceeded {
+    subject: UsageSubject,
+    period: UsagePeriod,
+    limit: u64,
+    /// Seconds until the period ends
+    retry_after: i64,
+    status: StatusCode,
+}
+
+impl IntoResponse for QuotaExceeded {
+    fn into_response(self) -> Response {
+        let message = format!(
+            "{} has used its {} budget of {} tokens",
+            describe(&self.subject),
+            period_adjective(self.period),
+            self.limit
+        );
+        let mut response = ProblemDetails::new(ErrorCode::BudgetExceeded, message)
+            .with_status(self.status)
+            .into_response();
+        if self.status == StatusCode::PAYMENT_REQUIRED {
+            return response;
+        }
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(self.retry_after.max(1)));
+        response
+    }
+}
+
+/// Tokens used in the current period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeriodUsage {
+    /// Period key, e.g. `2025-01` for a month
+    pub period: String,
+    pub used: u64,
+    /// Budget, `None` when unlimited
+    pub limit: Option<u64>,
+    /// Unix time the next period starts
+    pub resets_at: i64,
+}
+
+/// Usage of a subject in the current day and month
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UsageReport {
+    /// `user:<username>`, `api_key:<key id>` or `team:<name>`
+    pub subject: String,
+    pub daily: PeriodUsage,
+    pub monthly: PeriodUsage,
+}
+
+/// Token budgets enforced on the completion path
+pub struct Quotas {
+    settings: QuotaSettings,
+    store: Arc<dyn UsageStore>,
+    agora: Arc<AgoraServer>,
+}
+
+impl Quotas {
+    /// Enforce the configured budgets, counting usage in the given store and
+    /// publishing notifications through `agora`
+    pub fn new(store: Arc<dyn UsageStore>, settings: QuotaSettings, agora: Arc<AgoraServer>) -> Self {
+        Self { settings, store, agora }
+    }
+
+    /// Fail with the first budget of the caller that has no tokens left
+    pub async fn check(&self, claims: &Claims, now: DateTime<Utc>) -> Result<(), QuotaExceeded> {
+        for budget in self.budgets(claims) {
+            for period in UsagePeriod::ALL {
+                let Some(limit) = budget.limit(period) else {
+                    continue;
+                };
+                // Budgets fail open: a storage error must not stop completions
+                let used = match self
+                    .store
+                    .token_usage(&claims.tenant, &budget.subject.key(), &period.key(now))
+                    .await
+                {
+                    Ok(used) => used,
+                    Err(e) => {
+                        warn!(error = %e, subject = %budget.subject.key(), "Failed to read token usage");
+                        continue;
+                    }
+                };
+                if used >= limit {
+                    warn!(subject = %budget.subject.key(), period = period.as_str(), limit, "Token budget exhausted");
+                    return Err(QuotaExceeded {
+                        subject: budget.subject,
+                        period,
+                        limit,
+                        retry_after: period.resets_at(now) - now.timestamp(),
+                        status: self.exceeded_status(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Count tokens a completion used against every budget of the caller
+    pub async fn record(&self, claims: &Claims, tokens: u64, now: DateTime<Utc>) {
+        for budget in self.budgets(claims) {
+            let subject = budget.subject.key();
+            for period in UsagePeriod::ALL {
+                let total = match self
+                    .store
+                    .add_token_usage(&claims.tenant, &subject, &period.key(now), tokens, now.timestamp())
+                    .await
+                {
+                    Ok(total) => total,
+                    Err(e) => {
+                        warn!(error = %e, subject = %subject, "Failed to record token usage");
+                        continue;
+                    }
+                };
+                if let Some(limit) = budget.limit(period) {
+                    self.notify_crossing(
+                        &claims.tenant,
+                        &budget.subject,
+                        period,
+                        total.saturating_sub(tokens),
+                        total,
+                        limit,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Usage of the caller's budgets
+    pub async fn usage(&self, claims: &Claims, now: DateTime<Utc>) -> Result<Vec<UsageReport>, AuthError> {
+        let mut reports = Vec::new();
+        for budget in self.budgets(claims) {
+            let subject = budget.subject.key();
+            let daily = self.store.token_usage(&claims.tenant, &subject, &UsagePeriod::Day.key(now)).await?;
+            let monthly = self.store.token_usage(&claims.tenant, &subject, &UsagePeriod::Month.key(now)).await?;
+            reports.push(report(&budget, daily, monthly, now));
+        }
+        Ok(reports)
+    }
+
+    /// Usage of every subject of a tenant that used tokens this month or has a budget of its own
+    pub async fn report(&self, tenant: &str, now: DateTime<Utc>) -> Result<Vec<UsageReport>, AuthError> {
+        let mut totals: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        for usage in self.store.token_usage_for_period(tenant, &UsagePeriod::Month.key(now)).await? {
+            totals.entry(usage.subject).or_default().1 = usage.tokens;
+        }
+        for usage in self.store.token_usage_for_period(tenant, &UsagePeriod::Day.key(now)).await? {
+            totals.entry(usage.subject).or_default().0 = usage.tokens;
+        }
+        let configured = self
+            .settings
+            .users
+            .iter()
+            .filter(|user| user.tenant == tenant)
+            .map(|user| UsageSubject::User(user.username.clone()))
+            .chain(
+                self.settings
+                    .teams
+                    .iter()
+                    .filter(|team| team.tenant == tenant)
+                    .map(|team| UsageSubject::Team(team.name.clone())),
+            );
+        for subject in configured {
+            totals.entry(subject.key()).or_default();
+        }
+
+        Ok(totals
+            .into_iter()
+            .filter_map(|(key, (daily, monthly))| {
+                let budget = self.budget_for(tenant, UsageSubject::from_key(&key)?);
+                Some(report(&budget, daily, monthly, now))
+            })
+            .collect())
+    }
+
+    /// Budgets a caller's completions count against
+    fn budgets(&self, claims: &Claims) -> Vec<Budget> {
+        let tenant = claims.tenant.as_str();
+        let mut budgets = vec![self.budget_for(tenant, UsageSubject::User(claims.username.clone()))];
+        if claims.scopes.is_some() {
+            budgets.push(self.budget_for(tenant, UsageSubject::ApiKey(claims.jti.clone())));
+        }
+        budgets.extend(
+            self.settings
+                .teams
+                .iter()
+                .filter(|team| team.tenant == tenant && team.members.contains(&claims.username))
+                .map(|team| self.budget_for(tenant, UsageSubject::Team(team.name.clone()))),
+        );
+        budgets
+    }
+
+    fn budget_for(&self, tenant: &str, subject: UsageSubject) -> Budget {
+        let limits = match &subject {
+            UsageSubject::User(username) => self
+                .settings
+                .users
+                .iter()
+                .find(|user| user.tenant == tenant && &user.username == username)
+                .map(|user| TokenBudget {
+                    daily_tokens: user.daily_tokens,
+                    monthly_tokens: user.monthly_tokens,
+                })
+                .unwrap_or_else(|| self.settings.user.clone()),
+            UsageSubject::ApiKey(_) => self.settings.api_key.clone(),
+            UsageSubject::Team(name) => self
+                .settings
+                .teams
+                .iter()
+                .find(|team| team.tenant == tenant && &team.name == name)
+                .map(|team| TokenBudget {
+                    daily_tokens: team.daily_tokens,
+                    monthly_tokens: team.monthly_tokens,
+                })
+                .unwrap_or_default(),
+        };
+        Budget { subject, limits }
+    }
+
+    fn exceeded_status(&self) -> StatusCode {
+        match self.settings.exceeded_status {
+            402 => StatusCode::PAYMENT_REQUIRED,
+            _ => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// Notify when usage went from `before` to `after` past the warning share or the budget
+    fn notify_crossing(
+        &self,
+        tenant: &str,
+        subject: &UsageSubject,
+        period: UsagePeriod,
+        before: u64,
+        after: u64,
+        limit: u64,
+    ) {
+        let warning_at = (u128::from(limit) * u128::from(self.settings.warning_percent) / 100) as u64;
+        let (level, message) = if before < limit && after >= limit {
+            (
+                "error",
+                format!(
+                    "{} has used its {} budget of {} tokens",
+                    describe(subject),
+                    period_adjective(period),
+                    limit
+                ),
+            )
+        } else if warning_at < limit && before < warning_at && after >= warning_at {
+            (
+                "warning",
+                format!(
+                    "{} has used {} of its {} budget of {} tokens",
+                    describe(subject),
+                    after,
+                    period_adjective(period),
+                    limit
+                ),
+            )
+        } else {
+            return;
+        };
+
+        warn!(tenant, subject = %subject.key(), period = period.as_str(), used = after, limit, "{}", message);
+        let notification = Message::SystemNotification {
+            level: level.to_string(),
+            message,
+        };
+        let frame = match serde_json::to_string(&notification) {
+            Ok(text) => WsMessage::Text(text.into()),
+            Err(e) => {
+                warn!(error = %e, "Failed to encode budget notification");
+                return;
+            }
+        };
+        if self.agora.publish_to_tenant(tenant, NOTIFICATION_TOPIC, frame).is_err() {
+            debug!(tenant, "No subscribers for budget notifications");
+        }
+    }
+}
+
+fn report(budget: &Budget, daily: u64, monthly: u64, now: DateTime<Utc>) -> UsageReport {
+    let usage = |period: UsagePeriod, used: u64| PeriodUsage {
+        period: period.key(now),
+        used,
+        limit: budget.limit(period),
+        resets_at: period.resets_at(now),
+    };
+    UsageReport {
+        subject: budget.subject.key(),
+        daily: usage(UsagePeriod::Day, daily),
+        monthly: usage(UsagePeriod::Month, monthly),
+    }
+}
+
+fn describe(subject: &UsageSubject) -> String {
+    match subject {
+        UsageSubject::User(username) => format!("User {}", username),
+        UsageSubject::ApiKey(id) => format!("API key {}", id),
+        UsageSubject::Team(name) => format!("Team {}", name),
+    }
+}
+
+fn period_adjective(period: UsagePeriod) -> &'static str {
+    match period {
+        UsagePeriod::Day => "daily",
+        UsagePeriod::Month => "monthly",
+    }
+}
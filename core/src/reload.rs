@@ -0,0 +1,201 @@
+//! Configuration reload
+//!
+//! The gateway reloads its configuration when the files change, on SIGHUP
+//! and on `POST /api/config/reload`. The new configuration is validated as
+//! at startup; an invalid one is rejected and the running configuration
+//! stays in effect. Of a valid one, the LLM provider, rate limits, CORS
+//! policy and log level apply at once. Changes to other sections are
+//! reported as needing a restart, and the running gateway keeps using their
+//! old values, so [`LiveSettings`] always describes what is in effect.
+
+use common::config::Settings;
+use common::loader::ConfigLoader;
+use common::validation::{ConfigProblem, InvalidConfig};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::cors::CorsState;
+use crate::middleware::RateLimiter;
+
+/// Sections applied to a running gateway
+pub const LIVE_SECTIONS: &[&str] = &["llm", "rate_limit", "cors", "logging"];
+
+/// How often the configuration files are checked for changes
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Settings in effect, replaced as a whole when the configuration is reloaded
+#[derive(Clone)]
+pub struct LiveSettings {
+    current: Arc<RwLock<Arc<Settings>>>,
+    /// Held while a reload runs, so reloads never interleave
+    reloading: Arc<Mutex<()>>,
+}
+
+impl LiveSettings {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(settings))),
+            reloading: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Settings in effect; a request keeps the snapshot it took
+    pub fn current(&self) -> Arc<Settings> {
+        self.current.read().expect("settings poisoned").clone()
+    }
+
+    fn replace(&self, settings: Settings) {
+        *self.current.write().expect("settings poisoned") = Arc::new(settings);
+    }
+}
+
+/// Outcome of a reload
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReloadReport {
+    /// Changed sections now in effect
+    pub applied: Vec<String>,
+    /// Changed sections that take effect at the next restart
+    pub restart_required: Vec<String>,
+}
+
+/// Applies reloaded configuration to the parts of a running gateway
+#[derive(Clone)]
+pub struct ConfigReloader {
+    loader: ConfigLoader,
+    settings: LiveSettings,
+    cors: CorsState,
+    rate_limiter: Arc<RateLimiter>,
+}
+
+impl ConfigReloader {
+    /// Reload with `loader`, so the profile and overrides the gateway started with still apply
+    pub fn new(loader: ConfigLoader, settings: LiveSettings, cors: CorsState, rate_limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            loader,
+            settings,
+            cors,
+            rate_limiter,
+        }
+    }
+
+    /// Read, validate and apply the configuration files
+    pub fn reload(&self) -> Result<ReloadReport, InvalidConfig> {
+        let settings = self.loader.load()?;
+        self.apply(settings)
+    }
+
+    /// Apply validated settings
+    pub fn apply(&self, new: Settings) -> Result<ReloadReport, InvalidConfig> {
+        let _reloading = self.settings.reloading.lock().expect("reload lock poisoned");
+        let current = self.settings.current();
+        let report = compare(&current, &new)?;
+
+        let mut effective = (*current).clone();
+        if report.applied.iter().any(|section| section == "cors") {
+            // The policy depends on `environment` too, which keeps its running value until a restart
+            effective.cors = new.cors.clone();
+            self.cors
+                .reload(&effective)
+                .map_err(|e| InvalidConfig {
+                    problems: vec![ConfigProblem::new("cors", format!("{:#}", e))],
+                })?;
+        }
+        if report.applied.iter().any(|section| section == "logging") {
+            // Without a reloadable subscriber, e.g. in tests, the level stays as it is
+            if let Err(e) = common::logging::set_log_level(&new.logging.level) {
+                warn!(error = %e, "Log level not changed");
+            }
+            effective.logging = new.logging.clone();
+        }
+        if report.applied.iter().any(|section| section == "rate_limit") {
+            self.rate_limiter.reload(new.rate_limit.clone());
+            effective.rate_limit = new.rate_limit.clone();
+        }
+        effective.llm = new.llm;
+        self.settings.replace(effective);
+
+        if !report.restart_required.is_empty() {
+            warn!(sections = ?report.restart_required, "Configuration changes need a restart to take effect");
+        }
+        info!(applied = ?report.applied, "Configuration reloaded");
+        Ok(report)
+    }
+
+    /// Reload whenever a configuration file changes
+    pub fn watch(self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut stamps = self.file_stamps();
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let current = self.file_stamps();
+                if current != stamps {
+                    stamps = current;
+                    info!("Configuration files changed");
+                    self.reload_logged();
+                }
+            }
+        })
+    }
+
+    /// Reload on SIGHUP
+    #[cfg(unix)]
+    pub fn reload_on_hangup(self) -> std::io::Result<tokio::task::JoinHandle<()>> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        Ok(tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                info!("SIGHUP received");
+                self.reload_logged();
+            }
+        }))
+    }
+
+    fn reload_logged(&self) {
+        if let Err(invalid) = self.reload() {
+            warn!("Configuration not reloaded, the running configuration stays in effect: {}", invalid);
+        }
+    }
+
+    /// Modification time and length of every file the configuration is read from
+    fn file_stamps(&self) -> Vec<Option<(SystemTime, u64)>> {
+        self.loader
+            .files()
+            .into_iter()
+            .map(|path| {
+                let metadata = std::fs::metadata(path).ok()?;
+                Some((metadata.modified().ok()?, metadata.len()))
+            })
+            .collect()
+    }
+}
+
+/// Sections changed between the running and the new settings
+///
+/// Settings that cannot be compared are rejected, since what would change
+/// is unknown.
+fn compare(current: &Settings, new: &Settings) -> Result<ReloadReport, InvalidConfig> {
+    let (Value::Object(current), Value::Object(new)) = (
+        serde_json::to_value(current).map_err(InvalidConfig::unreadable)?,
+        serde_json::to_value(new).map_err(InvalidConfig::unreadable)?,
+    ) else {
+        return Err(InvalidConfig::unreadable("settings are not an object"));
+    };
+    let mut report = ReloadReport::default();
+    for (section, value) in &new {
+        if current.get(section) == Some(value) {
+            continue;
+        }
+        if LIVE_SECTIONS.contains(&section.as_str()) {
+            report.applied.push(section.clone());
+        } else {
+            report.restart_required.push(section.clone());
+        }
+    }
+    Ok(report)
+}
@@ -1,26 +1,47 @@
+use auth::jwt::Claims;
+use auth::service::{Credentials, LoginOutcome, TokenPair, TotpEnrollment, TotpStatus, TwoFactorChallenge, UserInfo};
+use auth::permissions::ADMIN_PERMISSION;
+use auth::{ApiKey, AuditEvent, AuditQuery, AuthError, Role, RoleDefinition, Session, User};
+use auth::keys::JwkSet;
 use axum::{
-    extract::{Path, State},
-    Json,
+    extract::ws::{Message as WsMessage, WebSocketUpgrade},
+    extract::{Extension, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
 };
+use common::error::{AppError, ErrorCode};
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::Message as TungsteniteMessage;
+use tracing::{info, warn};
 
-use crate::AppState;
+use crate::extract::{Json, Path, Query};
+use crate::{audit::Audit, cache, llm, quota::UsageReport, reload::ReloadReport, status, tenant::Tenant, AppState};
 
-pub mod routes {
-    // Define routes here
-}
+/// Response header reporting whether a completion was served from the cache
+pub const CACHE_STATUS_HEADER: &str = "x-cache";
 
 // Health check endpoint
 pub async fn health_check() -> &'static str {
     "Nexa Gateway API Server is running"
 }
 
-#[derive(Debug, Serialize)]
+// Answers requests for a path no route matches
+pub async fn not_found(uri: axum::http::Uri) -> AppError {
+    AppError::new(ErrorCode::NotFound, format!("No route for {}", uri.path()))
+}
+
+// Answers requests for a route that does not accept their method
+pub async fn method_not_allowed(method: axum::http::Method, uri: axum::http::Uri) -> AppError {
+    AppError::new(ErrorCode::MethodNotAllowed, format!("{} does not accept {}", uri.path(), method))
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Agent {
-    id: String,
-    name: String,
-    capabilities: Vec<String>,
+    pub id: String,
+    pub name: String,
+    pub capabilities: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -29,43 +50,977 @@ pub struct CreateAgentRequest {
     capabilities: Vec<String>,
 }
 
-// List all agents
-pub async fn list_agents(State(_state): State<AppState>) -> Json<Vec<Agent>> {
-    info!("Listing all agents");
-    // This would integrate with vectordb in a real implementation
-    Json(vec![
-        Agent {
-            id: "1".to_string(),
-            name: "Assistant".to_string(),
-            capabilities: vec!["chat".to_string(), "summarize".to_string()],
-        }
-    ])
+// List the agents of the caller's tenant
+pub async fn list_agents(State(state): State<AppState>, tenant: Tenant) -> Json<Vec<Agent>> {
+    info!(tenant = tenant.as_str(), "Listing all agents");
+    Json(state.agents.list(tenant.as_str()))
 }
 
 // Get agent by ID
 pub async fn get_agent(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
+    tenant: Tenant,
     Path(id): Path<String>,
-) -> Json<Agent> {
+) -> Result<Json<Agent>, AppError> {
     info!("Getting agent with ID: {}", id);
-    // This would integrate with vectordb in a real implementation
-    Json(Agent {
-        id,
-        name: "Assistant".to_string(),
-        capabilities: vec!["chat".to_string(), "summarize".to_string()],
-    })
+    state
+        .agents
+        .get(tenant.as_str(), &id)
+        .map(Json)
+        .ok_or_else(|| AppError::new(ErrorCode::NotFound, format!("Agent {} not found", id)))
 }
 
-// Create a new agent
+// Create a new agent in the caller's tenant
 pub async fn create_agent(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
+    tenant: Tenant,
+    audit: Audit,
     Json(payload): Json<CreateAgentRequest>,
 ) -> Json<Agent> {
     info!("Creating new agent: {}", payload.name);
-    // This would integrate with vectordb in a real implementation
-    Json(Agent {
-        id: "new-id".to_string(),
-        name: payload.name,
-        capabilities: payload.capabilities,
-    })
+    let agent = state.agents.create(tenant.as_str(), payload.name, payload.capabilities);
+
+    audit
+        .record(AuditEvent::new("agent.create").with_target(&agent.id).with_after(&agent))
+        .await;
+    Json(agent)
+}
+
+// Proxy a chat completion to the LLM provider, consulting the semantic cache
+// and the caller's token budgets
+pub async fn chat_completion(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(mut request): Json<serde_json::Value>,
+) -> Result<Response, AppError> {
+    status::increment_request_counter();
+    let settings = state.config.current();
+    let llm_settings = &settings.llm;
+
+    // Fill in model and temperature from configuration when not given
+    let body = request
+        .as_object_mut()
+        .ok_or_else(|| AppError::new(ErrorCode::BadRequest, "Request body must be a JSON object"))?;
+    if body.get("stream").and_then(|v| v.as_bool()).unwrap_or(false) {
+        return Err(AppError::new(ErrorCode::BadRequest, "Streaming completions are not supported"));
+    }
+    let model = body
+        .entry("model")
+        .or_insert_with(|| json!(llm_settings.model))
+        .as_str()
+        .unwrap_or(&llm_settings.model)
+        .to_string();
+    let temperature = body
+        .entry("temperature")
+        .or_insert_with(|| json!(llm_settings.temperature))
+        .as_f64()
+        .unwrap_or(llm_settings.temperature as f64) as f32;
+
+    if let Some(quotas) = &state.quotas {
+        if let Err(exceeded) = quotas.check(&claims, chrono::Utc::now()).await {
+            return Ok(exceeded.into_response());
+        }
+    }
+
+    // Look the prompt up in the cache; cache failures fall through to the provider
+    let mut pending_entry = None;
+    if let Some(cache) = &state.cache {
+        let prompt = cache::prompt_text(&request);
+        let lookup = async {
+            let embedding = llm::create_embedding(
+                &llm_settings.url,
+                llm_settings.api_key.expose(),
+                &cache.settings().embedding_model,
+                &prompt,
+            )
+            .await?;
+            let cached = cache.lookup(&claims.tenant, &model, temperature, &embedding).await?;
+            Ok::<_, anyhow::Error>((embedding, cached))
+        };
+
+        match lookup.await {
+            Ok((_, Some(cached))) => {
+                info!("Serving chat completion from semantic cache");
+                return Ok(([(CACHE_STATUS_HEADER, "HIT")], Json(cached)).into_response());
+            }
+            Ok((embedding, None)) => pending_entry = Some((cache, prompt, embedding)),
+            Err(e) => warn!("Semantic cache lookup failed: {}", e),
+        }
+    }
+
+    let response = llm::chat_completion(&llm_settings.url, llm_settings.api_key.expose(), &request)
+        .await
+        .map_err(|e| AppError::new(ErrorCode::UpstreamError, e.to_string()))?;
+
+    if let Some(tokens) = llm::response_total_tokens(&response) {
+        status::increment_token_counter(tokens);
+        if let Some(quotas) = &state.quotas {
+            quotas.record(&claims, tokens as u64, chrono::Utc::now()).await;
+        }
+    }
+
+    let cache_status = if let Some((cache, prompt, embedding)) = pending_entry {
+        if let Err(e) = cache.insert(&claims.tenant, &model, temperature, &prompt, embedding, &response).await {
+            warn!("Failed to store completion in semantic cache: {}", e);
+        }
+        "MISS"
+    } else if state.cache.is_some() {
+        "MISS"
+    } else {
+        "BYPASS"
+    };
+
+    Ok(([(CACHE_STATUS_HEADER, cache_status)], Json(response)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InvalidateCacheParams {
+    /// Restrict invalidation to entries for this model
+    model: Option<String>,
+}
+
+// Invalidate the cached completions of the caller's tenant
+pub async fn invalidate_cache(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    audit: Audit,
+    Query(params): Query<InvalidateCacheParams>,
+) -> Result<StatusCode, AppError> {
+    let cache = state
+        .cache
+        .as_ref()
+        .ok_or_else(|| AppError::new(ErrorCode::NotFound, "Semantic cache is not enabled"))?;
+
+    cache
+        .invalidate(tenant.as_str(), params.model.as_deref())
+        .await
+        .map_err(|e| AppError::new(ErrorCode::VectorDbError, e.to_string()))?;
+
+    audit
+        .record(AuditEvent::new("cache.invalidate").with_target(params.model.as_deref().unwrap_or("*")))
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Reload the configuration files
+pub async fn reload_config(State(state): State<AppState>, audit: Audit) -> Result<Json<ReloadReport>, AppError> {
+    match state.reloader.reload() {
+        Ok(report) => {
+            audit.record(AuditEvent::new("config.reload").with_after(&report)).await;
+            Ok(Json(report))
+        }
+        Err(invalid) => {
+            audit.record(AuditEvent::new("config.reload").failed(&invalid)).await;
+            Err(invalid.into())
+        }
+    }
+}
+
+/// Whether the caller is the account owner signed in with a session
+///
+/// API keys only ever act through their scopes, so a key cannot change
+/// its owner's password or create unscoped keys.
+fn is_self(claims: &Claims, id: &str) -> bool {
+    claims.sub == id && claims.scopes.is_none()
+}
+
+/// Require the account owner, or a caller whose roles grant `system:admin`
+/// in the account's tenant
+async fn require_self_or_admin(state: &AppState, claims: &Claims, id: &str) -> Result<(), AppError> {
+    if is_self(claims, id) {
+        return Ok(());
+    }
+    if !state.auth.has_permission(claims, ADMIN_PERMISSION).await {
+        return Err(AppError::new(ErrorCode::PermissionDenied, "Admin role required"));
+    }
+    tenant_user(state, claims, id).await?;
+    Ok(())
+}
+
+/// Look a user up in the caller's tenant
+///
+/// Users of other tenants are reported as missing, except to platform admins.
+async fn tenant_user(state: &AppState, claims: &Claims, id: &str) -> Result<User, AppError> {
+    let user = state.auth.get_user(id).await?;
+    if user.tenant != claims.tenant && !state.auth.is_platform_admin(claims).await {
+        return Err(AuthError::UserNotFound.into());
+    }
+    Ok(user)
+}
+
+/// A user account as returned by the API
+#[derive(Debug, Serialize)]
+pub struct UserResponse {
+    id: String,
+    username: String,
+    roles: Vec<String>,
+    tenant: String,
+    last_login: Option<i64>,
+    created_at: i64,
+}
+
+impl From<User> for UserResponse {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            roles: user.roles,
+            tenant: user.tenant,
+            last_login: user.last_login,
+            created_at: user.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    token: String,
+    refresh_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+}
+
+impl From<TokenPair> for TokenResponse {
+    fn from(tokens: TokenPair) -> Self {
+        Self {
+            token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+            token_type: "Bearer",
+            expires_in: tokens.expires_in,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginResponse {
+    #[serde(flatten)]
+    tokens: TokenResponse,
+    user: UserInfo,
+}
+
+/// Body of a password login: tokens, or a challenge for the second factor
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginResult {
+    Authenticated(LoginResponse),
+    SecondFactorRequired {
+        two_factor_required: bool,
+        #[serde(flatten)]
+        challenge: TwoFactorChallenge,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpLoginRequest {
+    challenge_token: String,
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpEnrollRequest {
+    challenge_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpConfirmRequest {
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+/// Query parameters the OpenID Connect provider redirects back with
+#[derive(Debug, Deserialize)]
+pub struct OAuthCallback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+// Exchange a username and password for an access token
+//
+// Users with a second factor get a challenge token instead, to be completed
+// at /api/login/totp.
+pub async fn login(
+    State(state): State<AppState>,
+    audit: Audit,
+    Json(credentials): Json<Credentials>,
+) -> Result<Json<LoginResult>, AppError> {
+    let username = credentials.username.clone();
+    let outcome = match state.auth.authenticate(credentials).await {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            warn!("Login failed for {}: {}", username, e);
+            audit
+                .record(AuditEvent::new("auth.login").with_actor_name(&username).failed(&e))
+                .await;
+            return Err(e.into());
+        }
+    };
+
+    match outcome {
+        LoginOutcome::Authenticated(user, tokens) => {
+            info!("User {} logged in", user.username);
+            audit
+                .record(
+                    AuditEvent::new("auth.login")
+                        .with_user(&user.id, &user.username)
+                        .with_tenant(&user.tenant),
+                )
+                .await;
+            Ok(Json(LoginResult::Authenticated(LoginResponse {
+                tokens: tokens.into(),
+                user,
+            })))
+        }
+        LoginOutcome::SecondFactorRequired(challenge) => {
+            info!("User {} needs a second factor to log in", username);
+            audit
+                .record(AuditEvent::new("auth.login.challenge").with_actor_name(&username))
+                .await;
+            Ok(Json(LoginResult::SecondFactorRequired {
+                two_factor_required: true,
+                challenge,
+            }))
+        }
+    }
+}
+
+// Complete a login with a TOTP or recovery code
+pub async fn login_totp(
+    State(state): State<AppState>,
+    audit: Audit,
+    Json(payload): Json<TotpLoginRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+    let (user, tokens) = match state.auth.complete_login(&payload.challenge_token, &payload.code).await {
+        Ok(login) => login,
+        Err(e) => {
+            warn!("Second factor login failed: {}", e);
+            let mut event = AuditEvent::new("auth.login.totp").failed(&e);
+            if let Some(user_id) = state.auth.challenge_subject(&payload.challenge_token) {
+                event = event.with_target(user_id);
+            }
+            audit.record(event).await;
+            return Err(e.into());
+        }
+    };
+    info!("User {} logged in with a second factor", user.username);
+    audit
+        .record(
+            AuditEvent::new("auth.login.totp")
+                .with_user(&user.id, &user.username)
+                .with_tenant(&user.tenant)
+                .with_target(&user.id),
+        )
+        .await;
+
+    Ok(Json(LoginResponse {
+        tokens: tokens.into(),
+        user,
+    }))
+}
+
+// Enroll a second factor during a login that requires one
+pub async fn login_totp_enroll(
+    State(state): State<AppState>,
+    audit: Audit,
+    Json(payload): Json<TotpEnrollRequest>,
+) -> Result<(StatusCode, Json<TotpEnrollment>), AppError> {
+    let enrollment = state.auth.start_login_enrollment(&payload.challenge_token).await?;
+    if let Some(user_id) = state.auth.challenge_subject(&payload.challenge_token) {
+        audit.record(AuditEvent::new("user.totp.enroll").with_target(user_id)).await;
+    }
+    Ok((StatusCode::CREATED, Json(enrollment)))
+}
+
+// Send the user to the OpenID Connect provider to log in
+pub async fn oauth_login(State(state): State<AppState>) -> Result<Redirect, AppError> {
+    let url = state.auth.oidc_authorization_url().await?;
+    Ok(Redirect::to(&url))
+}
+
+// Complete an OpenID Connect login, issuing gateway tokens
+pub async fn oauth_callback(
+    State(state): State<AppState>,
+    audit: Audit,
+    Query(callback): Query<OAuthCallback>,
+) -> Result<Json<LoginResult>, AppError> {
+    if let Some(error) = callback.error {
+        let reason = callback.error_description.unwrap_or(error);
+        warn!("OpenID Connect login refused by provider: {}", reason);
+        let reason = format!("Login refused by provider: {}", reason);
+        audit.record(AuditEvent::new("auth.oidc_login").failed(&reason)).await;
+        return Err(AppError::new(ErrorCode::AuthenticationRequired, reason));
+    }
+    let (Some(code), Some(login_state)) = (callback.code, callback.state) else {
+        return Err(AppError::new(ErrorCode::BadRequest, "code and state are required"));
+    };
+
+    let outcome = match state.auth.oidc_authenticate(&code, &login_state).await {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            warn!("OpenID Connect login failed: {}", e);
+            audit.record(AuditEvent::new("auth.oidc_login").failed(&e)).await;
+            return Err(e.into());
+        }
+    };
+
+    match outcome {
+        LoginOutcome::Authenticated(user, tokens) => {
+            info!("User {} logged in through OpenID Connect", user.username);
+            audit
+                .record(
+                    AuditEvent::new("auth.oidc_login")
+                        .with_user(&user.id, &user.username)
+                        .with_tenant(&user.tenant),
+                )
+                .await;
+            Ok(Json(LoginResult::Authenticated(LoginResponse {
+                tokens: tokens.into(),
+                user,
+            })))
+        }
+        LoginOutcome::SecondFactorRequired(challenge) => {
+            info!("OpenID Connect login needs a second factor");
+            let mut event = AuditEvent::new("auth.oidc_login.challenge");
+            if let Some(user_id) = state.auth.challenge_subject(&challenge.challenge_token) {
+                event = event.with_target(user_id);
+            }
+            audit.record(event).await;
+            Ok(Json(LoginResult::SecondFactorRequired {
+                two_factor_required: true,
+                challenge,
+            }))
+        }
+    }
+}
+
+// Exchange a refresh token for a new access and refresh token
+pub async fn refresh_token(
+    State(state): State<AppState>,
+    audit: Audit,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<Json<TokenResponse>, AppError> {
+    let tokens = match state.auth.refresh_token(&payload.refresh_token).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            audit.record(AuditEvent::new("auth.token.refresh").failed(&e)).await;
+            return Err(e.into());
+        }
+    };
+
+    let mut event = AuditEvent::new("auth.token.refresh");
+    if let Ok(claims) = state.auth.claims(&tokens.access_token).await {
+        event = event.with_actor(&claims).with_target(claims.sid);
+    }
+    audit.record(event).await;
+    Ok(Json(tokens.into()))
+}
+
+// End the session a refresh token belongs to
+pub async fn logout(
+    State(state): State<AppState>,
+    audit: Audit,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<StatusCode, AppError> {
+    if let Some(user_id) = state.auth.logout(&payload.refresh_token).await? {
+        audit.record(AuditEvent::new("auth.logout").with_target(user_id)).await;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Publish the public keys tokens are signed with
+pub async fn jwks(State(state): State<AppState>) -> Json<JwkSet> {
+    Json(state.auth.jwks())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeTokenRequest {
+    token: String,
+}
+
+// Revoke an access token before it expires; holding the token is enough
+pub async fn revoke_token(
+    State(state): State<AppState>,
+    audit: Audit,
+    Json(payload): Json<RevokeTokenRequest>,
+) -> Result<StatusCode, AppError> {
+    // Read before revoking; an already revoked token is not recorded again
+    let claims = state.auth.claims(&payload.token).await.ok();
+    state.auth.revoke_token(&payload.token).await?;
+    if let Some(claims) = claims {
+        audit
+            .record(AuditEvent::new("auth.token.revoke").with_actor(&claims).with_target(&claims.jti))
+            .await;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUserRequest {
+    username: String,
+    password: String,
+    #[serde(default = "default_user_roles")]
+    roles: Vec<String>,
+    /// Tenant of the new user, the creator's when not given
+    tenant: Option<String>,
+}
+
+fn default_user_roles() -> Vec<String> {
+    vec!["user".to_string()]
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateUserRequest {
+    password: Option<String>,
+    roles: Option<Vec<String>>,
+}
+
+// List the users of the caller's tenant, or of every tenant for platform admins
+pub async fn list_users(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<UserResponse>>, AppError> {
+    let every_tenant = state.auth.is_platform_admin(&claims).await;
+    let users = state.auth.list_users().await?;
+    Ok(Json(
+        users
+            .into_iter()
+            .filter(|user| every_tenant || user.tenant == claims.tenant)
+            .map(UserResponse::from)
+            .collect(),
+    ))
+}
+
+// Create a user (admin only)
+pub async fn create_user(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Json(payload): Json<CreateUserRequest>,
+) -> Result<(StatusCode, Json<UserResponse>), AppError> {
+    if payload.tenant.as_ref().is_some_and(|tenant| *tenant != claims.tenant)
+        && !state.auth.is_platform_admin(&claims).await
+    {
+        return Err(AppError::new(ErrorCode::PermissionDenied, "Only platform admins can create users in other tenants"));
+    }
+    let user = UserResponse::from(
+        state
+            .auth
+            .create_tenant_user(
+                payload.tenant.as_deref().unwrap_or(&claims.tenant),
+                &payload.username,
+                &payload.password,
+                &payload.roles,
+            )
+            .await?,
+    );
+    audit
+        .record(
+            AuditEvent::new("user.create")
+                .with_actor(&claims)
+                .with_target(&user.id)
+                .with_after(&user),
+        )
+        .await;
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+// Get a user by ID (admin, or the user themselves)
+pub async fn get_user(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<UserResponse>, AppError> {
+    if !is_self(&claims, &id) && !state.auth.has_permission(&claims, ADMIN_PERMISSION).await {
+        return Err(AppError::new(ErrorCode::PermissionDenied, "Cannot view other users"));
+    }
+    Ok(Json(UserResponse::from(tenant_user(&state, &claims, &id).await?)))
+}
+
+// Update a user's password or roles
+//
+// Users may change their own password; role changes and other users'
+// passwords require an admin.
+pub async fn update_user(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateUserRequest>,
+) -> Result<Json<UserResponse>, AppError> {
+    let is_admin = state.auth.has_permission(&claims, ADMIN_PERMISSION).await;
+    if !is_admin && (!is_self(&claims, &id) || payload.roles.is_some()) {
+        return Err(AppError::new(ErrorCode::PermissionDenied, "Admin role required"));
+    }
+
+    // Fail before changing anything when the user does not exist
+    let before = UserResponse::from(tenant_user(&state, &claims, &id).await?);
+    if let Some(roles) = &payload.roles {
+        state.auth.change_roles(&id, roles).await?;
+    }
+    if let Some(password) = &payload.password {
+        state.auth.change_password(&id, password).await?;
+    }
+
+    let user = UserResponse::from(state.auth.get_user(&id).await?);
+    // Passwords are never recorded, only that one was set
+    let mut after = json!(user);
+    if payload.password.is_some() {
+        after["password"] = json!(common::secret::REDACTED);
+    }
+    audit
+        .record(
+            AuditEvent::new("user.update")
+                .with_actor(&claims)
+                .with_target(&id)
+                .with_before(&before)
+                .with_after(&after),
+        )
+        .await;
+    Ok(Json(user))
+}
+
+// Delete a user (admin only)
+pub async fn delete_user(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    if claims.sub == id {
+        return Err(AppError::new(ErrorCode::BadRequest, "Admins cannot delete their own account"));
+    }
+    let before = UserResponse::from(tenant_user(&state, &claims, &id).await?);
+    state.auth.delete_user(&id).await?;
+    audit
+        .record(
+            AuditEvent::new("user.delete")
+                .with_actor(&claims)
+                .with_target(&id)
+                .with_before(&before),
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// List a user's active sessions (admin only)
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<Session>>, AppError> {
+    tenant_user(&state, &claims, &id).await?;
+    Ok(Json(state.auth.list_sessions(&id).await?))
+}
+
+// End every session of a user (admin only)
+pub async fn revoke_sessions(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    tenant_user(&state, &claims, &id).await?;
+    let revoked = state.auth.revoke_all_sessions(&id).await?;
+    info!("Revoked {} sessions of user {}", revoked, id);
+    audit
+        .record(
+            AuditEvent::new("user.sessions.revoke")
+                .with_actor(&claims)
+                .with_target(&id)
+                .with_after(&json!({ "revoked": revoked })),
+        )
+        .await;
+    Ok(Json(json!({ "revoked": revoked })))
+}
+
+// End one session of a user (admin only)
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Path((id, session_id)): Path<(String, String)>,
+) -> Result<StatusCode, AppError> {
+    tenant_user(&state, &claims, &id).await?;
+    state.auth.revoke_session(&id, &session_id).await?;
+    audit
+        .record(
+            AuditEvent::new("user.session.revoke")
+                .with_actor(&claims)
+                .with_target(&id)
+                .with_before(&json!({ "session_id": session_id })),
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    name: String,
+    scopes: Vec<String>,
+    #[serde(default)]
+    expires_in_days: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateApiKeyResponse {
+    /// The full key, shown only once
+    key: String,
+    #[serde(flatten)]
+    api_key: ApiKey,
+}
+
+// List a user's API keys (admin, or the user themselves)
+pub async fn list_api_keys(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<ApiKey>>, AppError> {
+    require_self_or_admin(&state, &claims, &id).await?;
+    Ok(Json(state.auth.list_api_keys(&id).await?))
+}
+
+// Create an API key for a user (admin, or the user themselves)
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Path(id): Path<String>,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreateApiKeyResponse>), AppError> {
+    require_self_or_admin(&state, &claims, &id).await?;
+    let expires_at = payload
+        .expires_in_days
+        .map(|days| chrono::Utc::now().timestamp() + i64::from(days) * 86400);
+    let (api_key, key) = state
+        .auth
+        .create_api_key(&id, &payload.name, &payload.scopes, expires_at)
+        .await?;
+    info!("Created API key {} for user {}", api_key.id, id);
+    audit
+        .record(
+            AuditEvent::new("api_key.create")
+                .with_actor(&claims)
+                .with_target(&api_key.id)
+                .with_after(&api_key),
+        )
+        .await;
+    Ok((StatusCode::CREATED, Json(CreateApiKeyResponse { key, api_key })))
+}
+
+// Revoke one of a user's API keys (admin, or the user themselves)
+pub async fn revoke_api_key(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Path((id, key_id)): Path<(String, String)>,
+) -> Result<StatusCode, AppError> {
+    require_self_or_admin(&state, &claims, &id).await?;
+    state.auth.revoke_api_key(&id, &key_id).await?;
+    audit
+        .record(AuditEvent::new("api_key.revoke").with_actor(&claims).with_target(&key_id))
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Show whether a user has a second factor (admin, or the user themselves)
+pub async fn totp_status(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<TotpStatus>, AppError> {
+    require_self_or_admin(&state, &claims, &id).await?;
+    Ok(Json(state.auth.totp_status(&id).await?))
+}
+
+// Start enrolling a second factor (the user themselves)
+pub async fn enroll_totp(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<TotpEnrollment>), AppError> {
+    if !is_self(&claims, &id) {
+        return Err(AppError::new(ErrorCode::PermissionDenied, "Only the user can enroll a second factor"));
+    }
+    let enrollment = state.auth.enroll_totp(&id).await?;
+    audit
+        .record(AuditEvent::new("user.totp.enroll").with_actor(&claims).with_target(&id))
+        .await;
+    Ok((StatusCode::CREATED, Json(enrollment)))
+}
+
+// Confirm a second factor with a first code (the user themselves)
+pub async fn confirm_totp(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Path(id): Path<String>,
+    Json(payload): Json<TotpConfirmRequest>,
+) -> Result<StatusCode, AppError> {
+    if !is_self(&claims, &id) {
+        return Err(AppError::new(ErrorCode::PermissionDenied, "Only the user can enroll a second factor"));
+    }
+    state.auth.confirm_totp(&id, &payload.code).await?;
+    audit
+        .record(AuditEvent::new("user.totp.confirm").with_actor(&claims).with_target(&id))
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Remove a user's second factor
+//
+// Admins can reset a lost device; users can only remove their own second
+// factor when policy does not require one.
+pub async fn disable_totp(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    if !state.auth.has_permission(&claims, ADMIN_PERMISSION).await {
+        if !is_self(&claims, &id) {
+            return Err(AppError::new(ErrorCode::PermissionDenied, "Admin role required"));
+        }
+        if state.auth.requires_two_factor(&state.auth.get_user(&id).await?).await {
+            return Err(AppError::new(ErrorCode::PermissionDenied, "Two-factor authentication is required for this account"));
+        }
+    } else {
+        tenant_user(&state, &claims, &id).await?;
+    }
+    state.auth.disable_totp(&id).await?;
+    info!("Disabled two-factor authentication of user {}", id);
+    audit
+        .record(AuditEvent::new("user.totp.disable").with_actor(&claims).with_target(&id))
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleRequest {
+    #[serde(default)]
+    permissions: Vec<String>,
+    #[serde(default)]
+    inherits: Vec<String>,
+}
+
+// List roles with the permissions they grant
+pub async fn list_roles(State(state): State<AppState>) -> Result<Json<Vec<Role>>, AppError> {
+    Ok(Json(state.auth.list_roles().await?))
+}
+
+// Get a role by name
+pub async fn get_role(State(state): State<AppState>, Path(name): Path<String>) -> Result<Json<Role>, AppError> {
+    Ok(Json(state.auth.get_role(&name).await?))
+}
+
+// Create or replace a role; built-in and configured roles are read-only
+pub async fn put_role(
+    State(state): State<AppState>,
+    audit: Audit,
+    Path(name): Path<String>,
+    Json(payload): Json<RoleRequest>,
+) -> Result<Json<Role>, AppError> {
+    let before = state.auth.get_role(&name).await.ok().map(|role| role.definition);
+    let role = state
+        .auth
+        .save_role(RoleDefinition {
+            name,
+            permissions: payload.permissions,
+            inherits: payload.inherits,
+        })
+        .await?;
+    info!("Saved role {}", role.definition.name);
+    audit
+        .record(
+            AuditEvent::new("role.put")
+                .with_target(&role.definition.name)
+                .with_before(&before)
+                .with_after(&role.definition),
+        )
+        .await;
+    Ok(Json(role))
+}
+
+// Delete a role created at runtime
+pub async fn delete_role(
+    State(state): State<AppState>,
+    audit: Audit,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let before = state.auth.get_role(&name).await?.definition;
+    state.auth.delete_role(&name).await?;
+    audit
+        .record(AuditEvent::new("role.delete").with_target(&name).with_before(&before))
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Read the audit log of the caller's tenant, newest first, filtered by
+// actor, action, target, outcome and time
+pub async fn list_audit_events(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Query(mut query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEvent>>, AppError> {
+    query.tenant = Some(tenant.0);
+    Ok(Json(state.audit.query(&query).await?))
+}
+
+/// Quotas of the gateway, or 404 when budgets are not enabled
+fn quotas(state: &AppState) -> Result<&crate::quota::Quotas, AppError> {
+    state
+        .quotas
+        .as_deref()
+        .ok_or_else(|| AppError::new(ErrorCode::NotFound, "Token budgets are not enabled"))
+}
+
+// Token usage of every user, API key and team of the caller's tenant this
+// day and month
+pub async fn usage_report(State(state): State<AppState>, tenant: Tenant) -> Result<Json<Vec<UsageReport>>, AppError> {
+    Ok(Json(quotas(&state)?.report(tenant.as_str(), chrono::Utc::now()).await?))
+}
+
+// Stream the notifications on the Agora system topic of the caller's tenant over a WebSocket
+pub async fn notifications(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    // Subscribe before the upgrade, so nothing published meanwhile is missed
+    let mut receiver = state.agora.subscribe_tenant(tenant.as_str(), crate::quota::NOTIFICATION_TOPIC)?;
+    Ok(upgrade.on_upgrade(move |mut socket| async move {
+        loop {
+            tokio::select! {
+                frame = receiver.recv() => match frame {
+                    Ok(TungsteniteMessage::Text(text)) => {
+                        if socket.send(WsMessage::Text(text.as_str().into())).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Notification subscriber of tenant {} missed {} messages", tenant.as_str(), skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                incoming = socket.recv() => match incoming {
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    }))
+}
+
+// Token usage of the caller's own budgets
+pub async fn my_usage(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<UsageReport>>, AppError> {
+    Ok(Json(quotas(&state)?.usage(&claims, chrono::Utc::now()).await?))
 }
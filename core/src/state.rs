@@ -1,3 +1 @@
-pub mod state {
-    // Define state here
-}
+// Define state here
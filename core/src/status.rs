@@ -11,6 +11,10 @@ static LAST_METRICS_TIME: std::sync::atomic::AtomicU64 = std::sync::atomic::Atom
 static LAST_REQUEST_COUNT: AtomicUsize = AtomicUsize::new(0);
 static LAST_TOKEN_COUNT: AtomicUsize = AtomicUsize::new(0);
 
+// Semantic cache lookup counters
+static CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
+static CACHE_MISSES: AtomicUsize = AtomicUsize::new(0);
+
 /// System metrics struct
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemMetrics {
@@ -20,6 +24,9 @@ pub struct SystemMetrics {
     pub active_connections: u32,
     pub requests_per_second: f32,
     pub tokens_per_second: f32,
+    /// Fraction of semantic cache lookups that were hits (0.0 - 1.0)
+    #[serde(default)]
+    pub cache_hit_rate: f32,
 }
 
 /// Agent metrics struct
@@ -42,6 +49,27 @@ pub fn increment_token_counter(tokens: usize) {
     TOKEN_COUNTER.fetch_add(tokens, Ordering::SeqCst);
 }
 
+/// Record a semantic cache hit
+pub fn record_cache_hit() {
+    CACHE_HITS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Record a semantic cache miss
+pub fn record_cache_miss() {
+    CACHE_MISSES.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Fraction of semantic cache lookups that were hits since startup
+pub fn cache_hit_rate() -> f32 {
+    let hits = CACHE_HITS.load(Ordering::SeqCst);
+    let total = hits + CACHE_MISSES.load(Ordering::SeqCst);
+    if total == 0 {
+        0.0
+    } else {
+        hits as f32 / total as f32
+    }
+}
+
 /// Get system metrics
 pub fn get_system_metrics() -> Result<SystemMetrics> {
     // Create and refresh system info
@@ -57,7 +85,7 @@ pub fn get_system_metrics() -> Result<SystemMetrics> {
     let uptime_seconds = current_time - boot_time;
     
     // Calculate CPU usage
-    let cpu_usage = sys.global_cpu_usage() as f32;
+    let cpu_usage = sys.global_cpu_usage();
     
     // Calculate memory usage
     let total_memory = sys.total_memory() as f64;
@@ -70,8 +98,8 @@ pub fn get_system_metrics() -> Result<SystemMetrics> {
     
     // Estimate active connections based on network activity and CPU usage
     // This is just a heuristic - more accurate methods would integrate with actual service stats
-    let network_activity: usize = networks.iter()
-        .map(|(_, network)| (network.received() as usize) + (network.transmitted() as usize))
+    let network_activity: usize = networks.values()
+        .map(|network| (network.received() as usize) + (network.transmitted() as usize))
         .sum();
     
     // Combine CPU activity and network activity to make a rough guess
@@ -105,6 +133,7 @@ pub fn get_system_metrics() -> Result<SystemMetrics> {
         active_connections,
         requests_per_second,
         tokens_per_second,
+        cache_hit_rate: cache_hit_rate(),
     };
     
     debug!("System metrics: {:?}", metrics);
@@ -0,0 +1,33 @@
+//! Tenant of a request
+//!
+//! Handlers that read or write tenant-scoped data take a [`Tenant`]. It is
+//! the tenant of the caller verified by a permission layer; a request that
+//! reaches such a handler without one is refused rather than served as
+//! another tenant.
+
+use auth::jwt::Claims;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use common::error::{AppError, ErrorCode};
+
+/// Tenant the request acts for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant(pub String);
+
+impl Tenant {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Tenant {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Claims>()
+            .map(|claims| Tenant(claims.tenant.clone()))
+            .ok_or_else(|| AppError::new(ErrorCode::AuthenticationRequired, "Request has no verified caller"))
+    }
+}
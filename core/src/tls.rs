@@ -0,0 +1,117 @@
+//! HTTPS serving
+//!
+//! With `server.tls` configured the gateway terminates TLS itself. The
+//! certificate is reloaded when its files change. Clients presenting a
+//! certificate signed by `client_ca_path` get a [`ClientCertificate`] in
+//! their request extensions, which [`certificate_auth`] turns into the
+//! claims of the gateway user the certificate subject maps to, so agents
+//! can call the API without a token.
+
+use auth::jwt::Claims;
+use auth::AuthService;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+    Router,
+};
+use common::config::TlsSettings;
+use common::tls::{ClientCertificate, TlsReloader, HTTP_ALPN_PROTOCOLS};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+use tracing::{debug, info, warn};
+
+/// Time a client has to complete the TLS handshake before it is disconnected
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve the router on the listener, over TLS when configured
+pub async fn serve(listener: TcpListener, app: Router, tls: Option<&TlsSettings>) -> anyhow::Result<()> {
+    match tls {
+        Some(tls) => {
+            let reloader = TlsReloader::new(tls.clone(), HTTP_ALPN_PROTOCOLS)?;
+            info!(cert_path = %tls.cert_path, mutual = tls.client_ca_path.is_some(), "Serving HTTPS");
+            serve_tls(listener, app, Arc::new(reloader)).await;
+            Ok(())
+        }
+        None => {
+            // Client addresses are recorded in the audit log
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Accept TLS connections and serve the router on each
+pub async fn serve_tls(listener: TcpListener, app: Router, reloader: Arc<TlsReloader>) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!(error = %e, "Failed to accept connection");
+                continue;
+            }
+        };
+        let acceptor = reloader.acceptor();
+        let reloader = reloader.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    debug!(%addr, error = %e, "TLS handshake failed");
+                    return;
+                }
+                Err(_) => {
+                    debug!(%addr, "TLS handshake timed out");
+                    return;
+                }
+            };
+            let client = reloader.client_certificate(stream.get_ref().1);
+            if let Some(client) = &client {
+                debug!(%addr, subject = %client.subject, identity = ?client.identity, "Client certificate accepted");
+            }
+
+            let service = hyper::service::service_fn(move |mut request: Request<hyper::body::Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(addr));
+                if let Some(client) = &client {
+                    request.extensions_mut().insert(client.clone());
+                }
+                app.clone().oneshot(request)
+            });
+            if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!(%addr, error = %e, "Connection closed with an error");
+            }
+        });
+    }
+}
+
+/// Authenticate requests by client certificate
+///
+/// Requests carrying a token or API key are left to the usual checks. A
+/// certificate whose subject maps to no identity, or to one that is not a
+/// gateway user, authenticates nobody.
+pub async fn certificate_auth(State(auth): State<AuthService>, mut request: Request<Body>, next: Next) -> Response {
+    let presented_credential = auth::middleware::extract_credential(request.headers()).is_ok();
+    if let (false, Some(client)) = (presented_credential, request.extensions().get::<ClientCertificate>().cloned()) {
+        match &client.identity {
+            Some(identity) => match auth.certificate_claims(identity, &client.subject).await {
+                Ok(claims) => {
+                    request.extensions_mut().insert::<Claims>(claims);
+                }
+                Err(e) => warn!(subject = %client.subject, %identity, error = %e, "Client certificate not accepted"),
+            },
+            None => warn!(subject = %client.subject, "Client certificate subject is not mapped to an identity"),
+        }
+    }
+    next.run(request).await
+}
@@ -1,5 +1,6 @@
 //! Service layer error definitions
 
+use common::error::{AppError, ErrorCode};
 use thiserror::Error;
 
 /// Errors that can occur in service operations
@@ -45,3 +46,17 @@ impl From<sqlx::Error> for ServiceError {
         ServiceError::DatabaseError(err.to_string())
     }
 }
+
+impl From<ServiceError> for AppError {
+    fn from(err: ServiceError) -> Self {
+        match err {
+            ServiceError::NotFound(msg) => AppError::new(ErrorCode::NotFound, msg),
+            ServiceError::DatabaseError(msg) => AppError::new(ErrorCode::DatabaseError, msg),
+            ServiceError::AuthenticationFailed(msg) => AppError::new(ErrorCode::AuthenticationRequired, msg),
+            ServiceError::AuthorizationFailed(msg) => AppError::new(ErrorCode::PermissionDenied, msg),
+            ServiceError::ValidationError(msg) => AppError::new(ErrorCode::ValidationFailed, msg),
+            ServiceError::ExternalServiceError(msg) => AppError::new(ErrorCode::UpstreamError, msg),
+            ServiceError::Unknown(msg) => AppError::new(ErrorCode::InternalError, msg),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 //! Service layer for Nexa Gateway
 
-use common::errors::Result;
+use common::Result;
 use async_trait::async_trait;
 // Removed unused import: tracing::info
 
@@ -2,7 +2,7 @@
 
 use crate::Service;
 // Removed unused import: ServiceError
-use common::errors::Result;
+use common::Result;
 use async_trait::async_trait;
 use sqlx::PgPool;
 use std::sync::Arc;
@@ -1,15 +1,28 @@
 use crate::error::VectorDbError;
+use crate::store::{CollectionSpec, PayloadFilter, ScrollPage, VectorPoint, VectorStore};
+use crate::VectorDbResult;
+use async_trait::async_trait;
 use qdrant_client::{
     Qdrant,
     qdrant::{
-        CreateCollection, Distance, PointStruct, VectorParams, VectorsConfig,
-        point_id::PointIdOptions,
+        Condition, CreateCollection, DeletePoints, Distance, Filter, PointId, PointStruct,
+        PointsIdsList, PointsSelector, RetrievedPoint, ScrollPoints, VectorParams, VectorsConfig,
+        point_id::PointIdOptions, points_selector::PointsSelectorOneOf,
+        vector_output, vectors_config, vectors_output,
     },
     config::QdrantConfig,
+    QdrantError,
 };
 use serde_json;
 use std::collections::HashMap;
 
+/// Payload key keeping a point ID Qdrant cannot store
+///
+/// Qdrant accepts only unsigned integers and UUIDs as point IDs. Other IDs,
+/// such as those of the in-memory store, are stored under a UUID derived
+/// from them, with the original ID in the payload, and read back as it.
+pub const ORIGINAL_ID_KEY: &str = "_point_id";
+
 /// Client for interacting with Qdrant vector database
 pub struct QdrantClient {
     client: Qdrant,
@@ -40,13 +53,31 @@ impl QdrantClient {
     /// # Returns
     /// * `Result<(), VectorDbError>` - Success or an error
     pub async fn create_collection(&self, collection_name: &str, vector_size: u64) -> Result<(), VectorDbError> {
+        self.create_collection_with_distance(collection_name, vector_size, Distance::Cosine).await
+    }
+    
+    /// Create a new collection in Qdrant using a specific distance metric
+    /// 
+    /// # Arguments
+    /// * `collection_name` - The name of the collection to create
+    /// * `vector_size` - The dimensionality of vectors to store
+    /// * `distance` - The distance metric for similarity search
+    /// 
+    /// # Returns
+    /// * `Result<(), VectorDbError>` - Success or an error
+    pub async fn create_collection_with_distance(
+        &self,
+        collection_name: &str,
+        vector_size: u64,
+        distance: Distance,
+    ) -> Result<(), VectorDbError> {
         let create_collection = CreateCollection {
             collection_name: collection_name.to_string(),
             vectors_config: Some(VectorsConfig {
                 config: Some(qdrant_client::qdrant::vectors_config::Config::Params(
                     VectorParams {
                         size: vector_size,
-                        distance: Distance::Cosine.into(),
+                        distance: distance.into(),
                         ..Default::default()
                     },
                 )),
@@ -79,28 +110,54 @@ impl QdrantClient {
         vector: &[f32],
         payload: Option<HashMap<String, serde_json::Value>>,
     ) -> Result<(), VectorDbError> {
-        // Create a simple payload for testing
-        let mut qdrant_payload = HashMap::new();
-        if let Some(p) = payload {
-            for (k, v) in p {
-                if let serde_json::Value::String(s) = v {
-                    qdrant_payload.insert(k, qdrant_client::qdrant::Value {
-                        kind: Some(qdrant_client::qdrant::value::Kind::StringValue(s)),
-                    });
+        self.upsert_points(
+            collection_name,
+            vec![VectorPoint {
+                id: id.to_string(),
+                vector: vector.to_vec(),
+                payload: payload.unwrap_or_default(),
+            }],
+        )
+        .await
+    }
+    
+    /// Insert or replace several points in a collection
+    /// 
+    /// # Arguments
+    /// * `collection_name` - The name of the collection
+    /// * `points` - The points to upsert
+    /// 
+    /// # Returns
+    /// * `Result<(), VectorDbError>` - Success or an error
+    pub async fn upsert_points(
+        &self,
+        collection_name: &str,
+        points: Vec<VectorPoint>,
+    ) -> Result<(), VectorDbError> {
+        let points = points
+            .into_iter()
+            .map(|mut point| {
+                if !is_native_id(&point.id) {
+                    point
+                        .payload
+                        .insert(ORIGINAL_ID_KEY.to_string(), serde_json::Value::String(point.id.clone()));
                 }
-            }
-        }
-        
-        let point = PointStruct {
-            id: Some(id.to_string().into()),
-            vectors: Some(vector.to_vec().into()),
-            payload: qdrant_payload,
-        };
+                PointStruct {
+                    id: Some(point_id(&point.id)),
+                    vectors: Some(point.vector.into()),
+                    payload: point
+                        .payload
+                        .into_iter()
+                        .map(|(k, v)| (k, qdrant_client::qdrant::Value::from(v)))
+                        .collect(),
+                }
+            })
+            .collect();
         
         // Create a qdrant::UpsertPoints request
         let request = qdrant_client::qdrant::UpsertPoints {
             collection_name: collection_name.to_string(),
-            points: vec![point],
+            points,
             ..Default::default()
         };
         
@@ -127,10 +184,33 @@ impl QdrantClient {
         query_vector: &[f32],
         limit: u64,
     ) -> Result<Vec<SearchResult>, VectorDbError> {
+        self.search_filtered(collection_name, query_vector, limit, None).await
+    }
+    
+    /// Search for similar vectors among points matching a payload filter
+    /// 
+    /// # Arguments
+    /// * `collection_name` - The name of the collection
+    /// * `query_vector` - The vector to search for
+    /// * `limit` - The maximum number of results to return
+    /// * `filter` - Optional payload conditions every result must satisfy
+    /// 
+    /// # Returns
+    /// * `Result<Vec<SearchResult>, VectorDbError>` - The search results or an error
+    pub async fn search_filtered(
+        &self,
+        collection_name: &str,
+        query_vector: &[f32],
+        limit: u64,
+        filter: Option<&PayloadFilter>,
+    ) -> Result<Vec<SearchResult>, VectorDbError> {
+        let filter = filter.map(to_qdrant_filter).transpose()?;
+        
         // Create a search request
         let request = qdrant_client::qdrant::SearchPoints {
             collection_name: collection_name.to_string(),
             vector: query_vector.to_vec(),
+            filter,
             limit,
             with_payload: Some(qdrant_client::qdrant::WithPayloadSelector {
                 selector_options: Some(qdrant_client::qdrant::with_payload_selector::SelectorOptions::Enable(true)),
@@ -144,40 +224,17 @@ impl QdrantClient {
         let results = self.client
             .search_points(request)
             .await
-            .map_err(|e| VectorDbError::OperationError(e.to_string()))?;
+            .map_err(|e| collection_error(collection_name, e))?;
         
         let mut search_results = Vec::new();
         
         for point in results.result {
             // Convert to our own format
-            let mut json_payload = HashMap::new();
-            
-            for (k, v) in point.payload {
-                if let Some(kind) = v.kind {
-                    match kind {
-                        qdrant_client::qdrant::value::Kind::StringValue(s) => {
-                            json_payload.insert(k, serde_json::Value::String(s));
-                        },
-                        _ => { /* Skip other value types for now */ }
-                    }
-                }
-            }
-            
-            let id = point.id.map(|id| {
-                if let Some(point_id_options) = id.point_id_options {
-                    match point_id_options {
-                        PointIdOptions::Uuid(uuid) => uuid,
-                        PointIdOptions::Num(num) => num.to_string(),
-                    }
-                } else {
-                    "unknown".to_string()
-                }
-            });
-            
+            let mut payload = payload_to_json(point.payload);
             search_results.push(SearchResult {
-                id,
+                id: point.id.map(|id| original_id(point_id_to_string(id), &mut payload)),
                 score: point.score,
-                payload: json_payload,
+                payload,
             });
         }
             
@@ -195,10 +252,276 @@ impl QdrantClient {
         self.client
             .delete_collection(collection_name)
             .await
-            .map_err(|e| VectorDbError::OperationError(e.to_string()))?;
+            .map_err(|e| collection_error(collection_name, e))?;
             
         Ok(())
     }
+    
+    /// Check whether a collection exists
+    /// 
+    /// # Arguments
+    /// * `collection_name` - The name of the collection
+    /// 
+    /// # Returns
+    /// * `Result<bool, VectorDbError>` - Whether the collection exists or an error
+    pub async fn collection_exists(&self, collection_name: &str) -> Result<bool, VectorDbError> {
+        self.client
+            .collection_exists(collection_name)
+            .await
+            .map_err(|e| VectorDbError::OperationError(e.to_string()))
+    }
+    
+    /// Delete points selected by IDs or by a payload filter
+    async fn delete_selected(
+        &self,
+        collection_name: &str,
+        selector: PointsSelectorOneOf,
+    ) -> Result<(), VectorDbError> {
+        let request = DeletePoints {
+            collection_name: collection_name.to_string(),
+            points: Some(PointsSelector {
+                points_selector_one_of: Some(selector),
+            }),
+            ..Default::default()
+        };
+        
+        self.client
+            .delete_points(request)
+            .await
+            .map_err(|e| match collection_error(collection_name, e) {
+                VectorDbError::OperationError(message) => VectorDbError::PointDeletion(message),
+                not_found => not_found,
+            })?;
+            
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VectorStore for QdrantClient {
+    async fn ensure_collection_with_spec(&self, collection_name: &str, spec: &CollectionSpec) -> VectorDbResult<()> {
+        if !self.collection_exists(collection_name).await? {
+            self.create_collection_with_distance(collection_name, spec.vector_size, to_qdrant_distance(spec.distance))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn list_collections(&self) -> VectorDbResult<Vec<String>> {
+        let response = self
+            .client
+            .list_collections()
+            .await
+            .map_err(|e| VectorDbError::OperationError(e.to_string()))?;
+        Ok(response.collections.into_iter().map(|c| c.name).collect())
+    }
+
+    async fn collection_spec(&self, collection_name: &str) -> VectorDbResult<CollectionSpec> {
+        let info = self
+            .client
+            .collection_info(collection_name)
+            .await
+            .map_err(|e| collection_error(collection_name, e))?
+            .result
+            .ok_or_else(|| VectorDbError::CollectionNotFound(collection_name.to_string()))?;
+
+        let params = info
+            .config
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors| vectors.config);
+
+        match params {
+            Some(vectors_config::Config::Params(params)) => Ok(CollectionSpec {
+                vector_size: params.size,
+                distance: from_qdrant_distance(params.distance)?,
+            }),
+            _ => Err(VectorDbError::Config(format!(
+                "Collection {} does not use a single unnamed vector",
+                collection_name
+            ))),
+        }
+    }
+
+    async fn scroll(&self, collection_name: &str, offset: Option<String>, limit: u32) -> VectorDbResult<ScrollPage> {
+        let request = ScrollPoints {
+            collection_name: collection_name.to_string(),
+            offset: offset.as_deref().map(point_id),
+            limit: Some(limit),
+            with_payload: Some(qdrant_client::qdrant::WithPayloadSelector {
+                selector_options: Some(qdrant_client::qdrant::with_payload_selector::SelectorOptions::Enable(true)),
+            }),
+            with_vectors: Some(qdrant_client::qdrant::WithVectorsSelector {
+                selector_options: Some(qdrant_client::qdrant::with_vectors_selector::SelectorOptions::Enable(true)),
+            }),
+            ..Default::default()
+        };
+
+        let response = self
+            .client
+            .scroll(request)
+            .await
+            .map_err(|e| collection_error(collection_name, e))?;
+
+        Ok(ScrollPage {
+            points: response.result.into_iter().map(retrieved_to_point).collect(),
+            next_offset: response.next_page_offset.map(point_id_to_string),
+        })
+    }
+
+    async fn upsert(&self, collection_name: &str, points: Vec<VectorPoint>) -> VectorDbResult<()> {
+        self.upsert_points(collection_name, points).await
+    }
+
+    async fn search(
+        &self,
+        collection_name: &str,
+        query_vector: &[f32],
+        limit: u64,
+        filter: Option<&PayloadFilter>,
+    ) -> VectorDbResult<Vec<SearchResult>> {
+        self.search_filtered(collection_name, query_vector, limit, filter).await
+    }
+
+    async fn delete_points(&self, collection_name: &str, ids: &[String]) -> VectorDbResult<()> {
+        let ids = PointsIdsList {
+            ids: ids.iter().map(|id| point_id(id)).collect(),
+        };
+        self.delete_selected(collection_name, PointsSelectorOneOf::Points(ids)).await
+    }
+
+    async fn delete_by_filter(&self, collection_name: &str, filter: &PayloadFilter) -> VectorDbResult<()> {
+        let filter = to_qdrant_filter(filter)?;
+        self.delete_selected(collection_name, PointsSelectorOneOf::Filter(filter)).await
+    }
+
+    async fn delete_collection(&self, collection_name: &str) -> VectorDbResult<()> {
+        QdrantClient::delete_collection(self, collection_name).await
+    }
+}
+
+/// Map a failed request on a collection, telling a missing collection apart
+///
+/// Qdrant answers requests on a collection that does not exist with a
+/// `NotFound` status rather than an empty result.
+fn collection_error(collection_name: &str, error: QdrantError) -> VectorDbError {
+    match &error {
+        QdrantError::ResponseError { status } if status.code() == tonic::Code::NotFound => {
+            VectorDbError::CollectionNotFound(collection_name.to_string())
+        }
+        _ => VectorDbError::OperationError(error.to_string()),
+    }
+}
+
+/// Convert our string point ID into a Qdrant ID, using numeric IDs where possible
+///
+/// IDs that are neither numbers nor UUIDs map to a UUID derived from their
+/// SHA-256, so the same ID always names the same point.
+fn point_id(id: &str) -> PointId {
+    if let Ok(num) = id.parse::<u64>() {
+        return num.into();
+    }
+    match uuid::Uuid::parse_str(id) {
+        Ok(uuid) => uuid.to_string().into(),
+        Err(_) => {
+            let digest = ring::digest::digest(&ring::digest::SHA256, id.as_bytes());
+            let mut bytes = [0u8; 16];
+            bytes.copy_from_slice(&digest.as_ref()[..16]);
+            uuid::Builder::from_custom_bytes(bytes).into_uuid().to_string().into()
+        }
+    }
+}
+
+/// Whether Qdrant can store the ID as it is
+fn is_native_id(id: &str) -> bool {
+    id.parse::<u64>().is_ok() || uuid::Uuid::parse_str(id).is_ok()
+}
+
+/// The ID a point was stored under, taking it out of the payload
+fn original_id(id: String, payload: &mut HashMap<String, serde_json::Value>) -> String {
+    match payload.remove(ORIGINAL_ID_KEY) {
+        Some(serde_json::Value::String(original)) => original,
+        _ => id,
+    }
+}
+
+/// Convert a Qdrant point ID back to its string form
+pub(crate) fn point_id_to_string(id: PointId) -> String {
+    match id.point_id_options {
+        Some(PointIdOptions::Uuid(uuid)) => uuid,
+        Some(PointIdOptions::Num(num)) => num.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Convert a Qdrant payload into JSON values
+pub(crate) fn payload_to_json(
+    payload: HashMap<String, qdrant_client::qdrant::Value>,
+) -> HashMap<String, serde_json::Value> {
+    payload
+        .into_iter()
+        .map(|(k, v)| (k, serde_json::Value::from(v)))
+        .collect()
+}
+
+/// Convert a retrieved Qdrant point into our point format
+fn retrieved_to_point(point: RetrievedPoint) -> VectorPoint {
+    let vector = match point.vectors.and_then(|v| v.vectors_options) {
+        Some(vectors_output::VectorsOptions::Vector(output)) => match output.vector {
+            Some(vector_output::Vector::Dense(dense)) => dense.data,
+            _ => output.data,
+        },
+        _ => Vec::new(),
+    };
+
+    let mut payload = payload_to_json(point.payload);
+    VectorPoint {
+        id: original_id(point.id.map(point_id_to_string).unwrap_or_default(), &mut payload),
+        vector,
+        payload,
+    }
+}
+
+/// Map our distance metric to Qdrant's
+fn to_qdrant_distance(distance: crate::store::Distance) -> Distance {
+    match distance {
+        crate::store::Distance::Cosine => Distance::Cosine,
+        crate::store::Distance::Dot => Distance::Dot,
+        crate::store::Distance::Euclid => Distance::Euclid,
+        crate::store::Distance::Manhattan => Distance::Manhattan,
+    }
+}
+
+/// Map a Qdrant distance metric to ours
+fn from_qdrant_distance(distance: i32) -> Result<crate::store::Distance, VectorDbError> {
+    match Distance::try_from(distance) {
+        Ok(Distance::Cosine) => Ok(crate::store::Distance::Cosine),
+        Ok(Distance::Dot) => Ok(crate::store::Distance::Dot),
+        Ok(Distance::Euclid) => Ok(crate::store::Distance::Euclid),
+        Ok(Distance::Manhattan) => Ok(crate::store::Distance::Manhattan),
+        _ => Err(VectorDbError::Config(format!("Unknown distance metric: {}", distance))),
+    }
+}
+
+/// Translate a payload filter into Qdrant match conditions
+fn to_qdrant_filter(filter: &PayloadFilter) -> Result<Filter, VectorDbError> {
+    let conditions = filter
+        .must
+        .iter()
+        .map(|(key, value)| match value {
+            serde_json::Value::String(s) => Ok(Condition::matches(key.clone(), s.clone())),
+            serde_json::Value::Bool(b) => Ok(Condition::matches(key.clone(), *b)),
+            serde_json::Value::Number(n) if n.is_i64() => {
+                Ok(Condition::matches(key.clone(), n.as_i64().unwrap_or_default()))
+            }
+            other => Err(VectorDbError::Config(format!(
+                "Unsupported filter value for field {}: {}",
+                key, other
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    
+    Ok(Filter::must(conditions))
 }
 
 /// Search result from Qdrant
@@ -211,3 +534,45 @@ pub struct SearchResult {
     /// Associated payload
     pub payload: HashMap<String, serde_json::Value>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_collection_error() {
+        let missing = QdrantError::ResponseError {
+            status: tonic::Status::not_found("Not found: Collection `cache` doesn't exist!"),
+        };
+        assert!(matches!(
+            collection_error("cache", missing),
+            VectorDbError::CollectionNotFound(name) if name == "cache"
+        ));
+
+        let unavailable = QdrantError::ResponseError {
+            status: tonic::Status::unavailable("connection refused"),
+        };
+        assert!(matches!(collection_error("cache", unavailable), VectorDbError::OperationError(_)));
+    }
+
+    #[test]
+    fn test_portable_point_ids() {
+        assert_eq!(point_id("42"), PointId::from(42));
+        let uuid = "6a1f5f36-1c7b-4d0e-9a53-2f0f3c2a7b10";
+        assert_eq!(point_id(uuid), PointId::from(uuid.to_string()));
+        assert!(is_native_id(uuid));
+
+        // Other IDs map to a stable UUID and are read back from the payload
+        assert!(!is_native_id("p0001"));
+        let derived = point_id_to_string(point_id("p0001"));
+        assert!(uuid::Uuid::parse_str(&derived).is_ok());
+        assert_eq!(derived, point_id_to_string(point_id("p0001")));
+        assert_ne!(derived, point_id_to_string(point_id("p0002")));
+
+        let mut payload = HashMap::new();
+        payload.insert(ORIGINAL_ID_KEY.to_string(), serde_json::json!("p0001"));
+        assert_eq!(original_id(derived.clone(), &mut payload), "p0001");
+        assert!(payload.is_empty());
+        assert_eq!(original_id(derived.clone(), &mut payload), derived);
+    }
+}
@@ -1,11 +1,12 @@
 use thiserror::Error;
 use qdrant_client::QdrantError;
 use axum::http::StatusCode;
+use common::error::{AppError, ErrorCode, ProblemDetails};
 
 #[derive(Debug, Error)]
 pub enum VectorDbError {
     #[error("Qdrant connection error: {0}")]
-    Connection(#[from] QdrantError),
+    Connection(#[source] Box<QdrantError>),
     
     #[error("Connection error: {0}")]
     ConnectionError(String),
@@ -42,37 +43,52 @@ pub enum VectorDbError {
     
     #[error("Deserialization error: {0}")]
     Deserialization(String),
+    
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<QdrantError> for VectorDbError {
+    fn from(err: QdrantError) -> Self {
+        VectorDbError::Connection(Box::new(err))
+    }
 }
 
 impl VectorDbError {
-    pub fn status_code(&self) -> StatusCode {
+    /// Stable code of the error
+    pub fn code(&self) -> ErrorCode {
         match self {
-            VectorDbError::Connection(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            VectorDbError::ConnectionError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            VectorDbError::OperationError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            VectorDbError::CollectionCreation(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            VectorDbError::CollectionNotFound(_) => StatusCode::NOT_FOUND,
-            VectorDbError::PointInsertion(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            VectorDbError::PointSearch(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            VectorDbError::PointUpdate(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            VectorDbError::PointDeletion(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            VectorDbError::EmbeddingGeneration(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            VectorDbError::Config(_) => StatusCode::BAD_REQUEST,
-            VectorDbError::Serialization(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            VectorDbError::Deserialization(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            VectorDbError::CollectionNotFound(_) => ErrorCode::CollectionNotFound,
+            VectorDbError::Config(_) => ErrorCode::BadRequest,
+            VectorDbError::Connection(_)
+            | VectorDbError::ConnectionError(_)
+            | VectorDbError::OperationError(_)
+            | VectorDbError::CollectionCreation(_)
+            | VectorDbError::PointInsertion(_)
+            | VectorDbError::PointSearch(_)
+            | VectorDbError::PointUpdate(_)
+            | VectorDbError::PointDeletion(_)
+            | VectorDbError::EmbeddingGeneration(_)
+            | VectorDbError::Serialization(_)
+            | VectorDbError::Deserialization(_)
+            | VectorDbError::Io(_) => ErrorCode::VectorDbError,
         }
     }
+
+    pub fn status_code(&self) -> StatusCode {
+        self.code().status()
+    }
 }
 
 impl axum::response::IntoResponse for VectorDbError {
     fn into_response(self) -> axum::response::Response {
-        let status = self.status_code();
-        let body = axum::Json(serde_json::json!({
-            "error": self.to_string(),
-            "code": status.as_u16(),
-        }));
+        ProblemDetails::from_error(self.code(), &self).into_response()
+    }
+}
 
-        (status, body).into_response()
+impl From<VectorDbError> for AppError {
+    fn from(err: VectorDbError) -> Self {
+        AppError::new(err.code(), err.to_string())
     }
 }
 
@@ -99,5 +115,9 @@ mod tests {
             VectorDbError::Config("test".to_string()).status_code(),
             StatusCode::BAD_REQUEST
         );
+        assert_eq!(
+            VectorDbError::CollectionNotFound("test".to_string()).code(),
+            ErrorCode::CollectionNotFound
+        );
     }
 }
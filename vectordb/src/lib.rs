@@ -4,8 +4,16 @@
 
 pub mod client;
 pub mod error;
+pub mod memory;
+pub mod snapshot;
+pub mod store;
+pub mod tenant;
 
 pub use error::VectorDbError;
+pub use memory::InMemoryStore;
+pub use snapshot::{export_collection, import_collection, ImportOptions, SnapshotFormat};
+pub use store::{CollectionSpec, Distance, PayloadFilter, VectorPoint, VectorStore};
+pub use tenant::TenantStore;
 
 /// Result type for vector database operations
 pub type VectorDbResult<T> = Result<T, VectorDbError>;
@@ -0,0 +1,265 @@
+//! In-memory vector store
+//!
+//! A local `VectorStore` backend scoring every point by brute force.
+//! Useful for tests and single-node deployments without Qdrant.
+
+use crate::client::SearchResult;
+use crate::error::VectorDbError;
+use crate::store::{CollectionSpec, Distance, PayloadFilter, ScrollPage, VectorPoint, VectorStore};
+use crate::VectorDbResult;
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use tokio::sync::RwLock;
+
+/// A collection held in memory, with points ordered by ID for stable scrolling
+#[derive(Debug)]
+struct MemoryCollection {
+    spec: CollectionSpec,
+    points: BTreeMap<String, VectorPoint>,
+}
+
+/// Vector store keeping all collections in process memory
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    collections: RwLock<HashMap<String, MemoryCollection>>,
+}
+
+impl InMemoryStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Cosine similarity between two vectors, 0.0 when either is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+/// Score a candidate vector using the collection's distance metric
+///
+/// Similarity metrics (cosine, dot) rank higher scores first; distance
+/// metrics (euclid, manhattan) rank lower scores first, as in Qdrant.
+fn score(distance: Distance, query: &[f32], vector: &[f32]) -> f32 {
+    match distance {
+        Distance::Cosine => cosine_similarity(query, vector),
+        Distance::Dot => query.iter().zip(vector).map(|(x, y)| x * y).sum(),
+        Distance::Euclid => query
+            .iter()
+            .zip(vector)
+            .map(|(x, y)| (x - y) * (x - y))
+            .sum::<f32>()
+            .sqrt(),
+        Distance::Manhattan => query.iter().zip(vector).map(|(x, y)| (x - y).abs()).sum(),
+    }
+}
+
+#[async_trait]
+impl VectorStore for InMemoryStore {
+    async fn ensure_collection_with_spec(&self, collection_name: &str, spec: &CollectionSpec) -> VectorDbResult<()> {
+        let mut collections = self.collections.write().await;
+        collections
+            .entry(collection_name.to_string())
+            .or_insert_with(|| MemoryCollection {
+                spec: spec.clone(),
+                points: BTreeMap::new(),
+            });
+        Ok(())
+    }
+
+    async fn list_collections(&self) -> VectorDbResult<Vec<String>> {
+        Ok(self.collections.read().await.keys().cloned().collect())
+    }
+
+    async fn collection_spec(&self, collection_name: &str) -> VectorDbResult<CollectionSpec> {
+        self.collections
+            .read()
+            .await
+            .get(collection_name)
+            .map(|collection| collection.spec.clone())
+            .ok_or_else(|| VectorDbError::CollectionNotFound(collection_name.to_string()))
+    }
+
+    async fn scroll(&self, collection_name: &str, offset: Option<String>, limit: u32) -> VectorDbResult<ScrollPage> {
+        let collections = self.collections.read().await;
+        let collection = collections
+            .get(collection_name)
+            .ok_or_else(|| VectorDbError::CollectionNotFound(collection_name.to_string()))?;
+
+        let start = match offset {
+            Some(offset) => Bound::Included(offset),
+            None => Bound::Unbounded,
+        };
+        let mut range = collection.points.range((start, Bound::Unbounded));
+        let points: Vec<VectorPoint> = range
+            .by_ref()
+            .take(limit as usize)
+            .map(|(_, point)| point.clone())
+            .collect();
+        let next_offset = range.next().map(|(id, _)| id.clone());
+
+        Ok(ScrollPage { points, next_offset })
+    }
+
+    async fn upsert(&self, collection_name: &str, points: Vec<VectorPoint>) -> VectorDbResult<()> {
+        let mut collections = self.collections.write().await;
+        let collection = collections
+            .get_mut(collection_name)
+            .ok_or_else(|| VectorDbError::CollectionNotFound(collection_name.to_string()))?;
+
+        for point in points {
+            if point.vector.len() as u64 != collection.spec.vector_size {
+                return Err(VectorDbError::PointInsertion(format!(
+                    "Vector for point {} has {} dimensions, expected {}",
+                    point.id,
+                    point.vector.len(),
+                    collection.spec.vector_size
+                )));
+            }
+            collection.points.insert(point.id.clone(), point);
+        }
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        collection_name: &str,
+        query_vector: &[f32],
+        limit: u64,
+        filter: Option<&PayloadFilter>,
+    ) -> VectorDbResult<Vec<SearchResult>> {
+        let collections = self.collections.read().await;
+        let collection = collections
+            .get(collection_name)
+            .ok_or_else(|| VectorDbError::CollectionNotFound(collection_name.to_string()))?;
+
+        let mut results: Vec<SearchResult> = collection
+            .points
+            .values()
+            .filter(|point| filter.is_none_or(|f| f.matches(&point.payload)))
+            .map(|point| SearchResult {
+                id: Some(point.id.clone()),
+                score: score(collection.spec.distance, query_vector, &point.vector),
+                payload: point.payload.clone(),
+            })
+            .collect();
+
+        match collection.spec.distance {
+            Distance::Cosine | Distance::Dot => results.sort_by(|a, b| b.score.total_cmp(&a.score)),
+            Distance::Euclid | Distance::Manhattan => results.sort_by(|a, b| a.score.total_cmp(&b.score)),
+        }
+        results.truncate(limit as usize);
+        Ok(results)
+    }
+
+    async fn delete_points(&self, collection_name: &str, ids: &[String]) -> VectorDbResult<()> {
+        let mut collections = self.collections.write().await;
+        let collection = collections
+            .get_mut(collection_name)
+            .ok_or_else(|| VectorDbError::CollectionNotFound(collection_name.to_string()))?;
+
+        for id in ids {
+            collection.points.remove(id);
+        }
+        Ok(())
+    }
+
+    async fn delete_by_filter(&self, collection_name: &str, filter: &PayloadFilter) -> VectorDbResult<()> {
+        let mut collections = self.collections.write().await;
+        let collection = collections
+            .get_mut(collection_name)
+            .ok_or_else(|| VectorDbError::CollectionNotFound(collection_name.to_string()))?;
+
+        collection.points.retain(|_, point| !filter.matches(&point.payload));
+        Ok(())
+    }
+
+    async fn delete_collection(&self, collection_name: &str) -> VectorDbResult<()> {
+        self.collections.write().await.remove(collection_name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn point(id: &str, vector: Vec<f32>, model: &str) -> VectorPoint {
+        let mut payload = HashMap::new();
+        payload.insert("model".to_string(), json!(model));
+        VectorPoint {
+            id: id.to_string(),
+            vector,
+            payload,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_search_and_filter() {
+        let store = InMemoryStore::new();
+        store.ensure_collection("test", 2).await.unwrap();
+        store
+            .upsert(
+                "test",
+                vec![
+                    point("a", vec![1.0, 0.0], "m1"),
+                    point("b", vec![0.0, 1.0], "m1"),
+                    point("c", vec![1.0, 0.1], "m2"),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let results = store.search("test", &[1.0, 0.0], 3, None).await.unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].id.as_deref(), Some("a"));
+
+        let filter = PayloadFilter::new().must_match("model", "m2");
+        let results = store.search("test", &[1.0, 0.0], 3, Some(&filter)).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id.as_deref(), Some("c"));
+
+        store.delete_by_filter("test", &filter).await.unwrap();
+        let results = store.search("test", &[1.0, 0.0], 3, None).await.unwrap();
+        assert_eq!(results.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_scroll_pages() {
+        let store = InMemoryStore::new();
+        store.ensure_collection("test", 1).await.unwrap();
+        let points = (0..5).map(|i| point(&format!("p{}", i), vec![i as f32], "m1")).collect();
+        store.upsert("test", points).await.unwrap();
+
+        let first = store.scroll("test", None, 2).await.unwrap();
+        assert_eq!(first.points.len(), 2);
+        assert_eq!(first.next_offset.as_deref(), Some("p2"));
+
+        let mut seen = first.points.len();
+        let mut offset = first.next_offset;
+        while let Some(next) = offset {
+            let page = store.scroll("test", Some(next), 2).await.unwrap();
+            seen += page.points.len();
+            offset = page.next_offset;
+        }
+        assert_eq!(seen, 5);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_rejects_wrong_dimension() {
+        let store = InMemoryStore::new();
+        store.ensure_collection("test", 3).await.unwrap();
+        let result = store.upsert("test", vec![point("a", vec![1.0], "m1")]).await;
+        assert!(matches!(result, Err(VectorDbError::PointInsertion(_))));
+    }
+}
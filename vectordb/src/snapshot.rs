@@ -0,0 +1,725 @@
+//! Collection snapshot export and import
+//!
+//! Snapshots capture a collection's configuration and every point (ID,
+//! vector and full payload) in a portable file, so a collection can be
+//! moved between Qdrant instances or into any other `VectorStore` backend.
+//!
+//! Two formats are supported:
+//! * JSON Lines (`.jsonl`) - a header line followed by one point per line
+//! * Binary (`.nxsnap`) - a compact little-endian encoding with raw `f32` vectors
+
+use crate::error::VectorDbError;
+use crate::store::{CollectionSpec, VectorPoint, VectorStore};
+use crate::VectorDbResult;
+use chrono::{DateTime, Utc};
+use ring::digest::{Context, SHA256};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::{debug, info};
+
+/// Current snapshot format version
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+/// Magic bytes opening every binary snapshot
+const BINARY_MAGIC: &[u8; 8] = b"NXVSNAP1";
+
+/// Number of points fetched per scroll page during export
+const EXPORT_PAGE_SIZE: u32 = 256;
+
+/// Largest binary snapshot header read
+const MAX_HEADER_BYTES: usize = 1024 * 1024;
+
+/// Largest point ID read from a binary snapshot
+const MAX_ID_BYTES: usize = 4096;
+
+/// Largest point payload read from a binary snapshot
+const MAX_PAYLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+/// On-disk snapshot encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    /// Human-readable JSON Lines
+    JsonLines,
+    /// Compact binary encoding
+    Binary,
+}
+
+impl SnapshotFormat {
+    /// Pick a format from a file extension, defaulting to JSON Lines
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("nxsnap") | Some("bin") => SnapshotFormat::Binary,
+            _ => SnapshotFormat::JsonLines,
+        }
+    }
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = VectorDbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jsonl" | "json" | "jsonlines" => Ok(SnapshotFormat::JsonLines),
+            "binary" | "bin" => Ok(SnapshotFormat::Binary),
+            other => Err(VectorDbError::Config(format!("Unknown snapshot format: {}", other))),
+        }
+    }
+}
+
+/// Metadata stored at the start of every snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotHeader {
+    /// Snapshot format version
+    pub format_version: u32,
+    /// Name of the exported collection
+    pub collection: String,
+    /// Configuration of the exported collection
+    pub spec: CollectionSpec,
+    /// Time the export started
+    pub created_at: DateTime<Utc>,
+}
+
+/// Summary of a completed export
+#[derive(Debug, Clone, Default)]
+pub struct ExportStats {
+    /// Number of points written
+    pub points: usize,
+}
+
+/// Options controlling an import
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Number of points upserted per request
+    pub batch_size: usize,
+    /// Collection to import into, defaults to the exported collection name
+    pub target_collection: Option<String>,
+    /// Progress file used to resume an interrupted import, defaults to `<snapshot>.progress`
+    pub checkpoint_path: Option<PathBuf>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 256,
+            target_collection: None,
+            checkpoint_path: None,
+        }
+    }
+}
+
+/// Summary of a completed import
+#[derive(Debug, Clone, Default)]
+pub struct ImportStats {
+    /// Collection the points were imported into
+    pub collection: String,
+    /// Number of points upserted by this run
+    pub imported: usize,
+    /// Number of points skipped because a previous run already imported them
+    pub skipped: usize,
+}
+
+/// Export a collection to a snapshot file
+///
+/// # Arguments
+/// * `store` - The vector store holding the collection
+/// * `collection_name` - The collection to export
+/// * `path` - Destination file, overwritten if it exists
+/// * `format` - Snapshot encoding
+///
+/// # Returns
+/// * `VectorDbResult<ExportStats>` - Number of exported points or an error
+pub async fn export_collection(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    path: &Path,
+    format: SnapshotFormat,
+) -> VectorDbResult<ExportStats> {
+    let header = SnapshotHeader {
+        format_version: SNAPSHOT_FORMAT_VERSION,
+        collection: collection_name.to_string(),
+        spec: store.collection_spec(collection_name).await?,
+        created_at: Utc::now(),
+    };
+
+    info!("Exporting collection {} to {}", collection_name, path.display());
+
+    let mut writer = SnapshotWriter::create(path, format, &header)?;
+    let mut stats = ExportStats::default();
+    let mut offset = None;
+
+    loop {
+        let page = store.scroll(collection_name, offset, EXPORT_PAGE_SIZE).await?;
+        for point in &page.points {
+            writer.write_point(point)?;
+        }
+        stats.points += page.points.len();
+        debug!("Exported {} points from {}", stats.points, collection_name);
+
+        match page.next_offset {
+            Some(next) => offset = Some(next),
+            None => break,
+        }
+    }
+
+    writer.finish()?;
+    info!("Exported {} points from {}", stats.points, collection_name);
+    Ok(stats)
+}
+
+/// Import a snapshot file into a vector store
+///
+/// Progress is recorded in a checkpoint file after every batch. When an
+/// import fails part way, running it again with the same snapshot and
+/// checkpoint skips the points that were already imported. The checkpoint
+/// names the snapshot by its SHA-256 and the target collection, and an
+/// import refuses a checkpoint written for another file or collection
+/// rather than skipping points it never imported. The checkpoint is removed
+/// once the import completes.
+///
+/// # Arguments
+/// * `store` - The vector store to import into
+/// * `path` - Snapshot file to read
+/// * `options` - Batch size, target collection and checkpoint location
+///
+/// # Returns
+/// * `VectorDbResult<ImportStats>` - Import summary or an error
+pub async fn import_collection(
+    store: &dyn VectorStore,
+    path: &Path,
+    options: &ImportOptions,
+) -> VectorDbResult<ImportStats> {
+    if options.batch_size == 0 {
+        return Err(VectorDbError::Config("Import batch size must be greater than zero".to_string()));
+    }
+
+    let (header, mut reader) = SnapshotReader::open(path)?;
+    if header.format_version > SNAPSHOT_FORMAT_VERSION {
+        return Err(VectorDbError::Deserialization(format!(
+            "Unsupported snapshot format version {}",
+            header.format_version
+        )));
+    }
+
+    let collection = options
+        .target_collection
+        .clone()
+        .unwrap_or_else(|| header.collection.clone());
+    let checkpoint_path = options
+        .checkpoint_path
+        .clone()
+        .unwrap_or_else(|| default_checkpoint_path(path));
+
+    store.ensure_collection_with_spec(&collection, &header.spec).await?;
+    let existing = store.collection_spec(&collection).await?;
+    if existing != header.spec {
+        return Err(VectorDbError::Config(format!(
+            "Collection {} stores {}-dimensional vectors compared by {:?} distance, snapshot has {}-dimensional {:?}",
+            collection, existing.vector_size, existing.distance, header.spec.vector_size, header.spec.distance
+        )));
+    }
+
+    let snapshot_sha256 = file_sha256(path)?;
+    let already_imported = match read_checkpoint(&checkpoint_path)? {
+        Some(checkpoint) if checkpoint.snapshot_sha256 != snapshot_sha256 => {
+            return Err(VectorDbError::Config(format!(
+                "Checkpoint {} was written for a different version of {}; remove it to import from the start",
+                checkpoint_path.display(),
+                path.display()
+            )));
+        }
+        Some(checkpoint) if checkpoint.target_collection != collection => {
+            return Err(VectorDbError::Config(format!(
+                "Checkpoint {} records an import into {}, not {}; remove it to import from the start",
+                checkpoint_path.display(),
+                checkpoint.target_collection,
+                collection
+            )));
+        }
+        Some(checkpoint) => checkpoint.imported,
+        None => 0,
+    };
+    if already_imported > 0 {
+        info!(
+            "Resuming import into {} after {} points",
+            collection, already_imported
+        );
+    }
+
+    let mut stats = ImportStats {
+        collection: collection.clone(),
+        ..Default::default()
+    };
+    let mut batch = Vec::with_capacity(options.batch_size);
+
+    while let Some(point) = reader.next_point()? {
+        if point.vector.len() as u64 != header.spec.vector_size {
+            return Err(VectorDbError::Deserialization(format!(
+                "Point {} has {} dimensions, the snapshot declares {}",
+                point.id,
+                point.vector.len(),
+                header.spec.vector_size
+            )));
+        }
+        if stats.skipped < already_imported {
+            stats.skipped += 1;
+            continue;
+        }
+
+        batch.push(point);
+        if batch.len() == options.batch_size {
+            stats.imported += batch.len();
+            store.upsert(&collection, std::mem::take(&mut batch)).await?;
+            write_checkpoint(
+                &checkpoint_path,
+                &Checkpoint {
+                    snapshot_sha256: snapshot_sha256.clone(),
+                    target_collection: collection.clone(),
+                    imported: stats.skipped + stats.imported,
+                },
+            )?;
+            debug!("Imported {} points into {}", stats.imported, collection);
+        }
+    }
+
+    if !batch.is_empty() {
+        stats.imported += batch.len();
+        store.upsert(&collection, batch).await?;
+    }
+
+    if checkpoint_path.exists() {
+        fs::remove_file(&checkpoint_path)?;
+    }
+
+    info!(
+        "Imported {} points into {} ({} already present)",
+        stats.imported, collection, stats.skipped
+    );
+    Ok(stats)
+}
+
+/// Default checkpoint location next to the snapshot file
+pub fn default_checkpoint_path(snapshot: &Path) -> PathBuf {
+    let mut name = snapshot.as_os_str().to_os_string();
+    name.push(".progress");
+    PathBuf::from(name)
+}
+
+/// Progress of an interrupted import
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Checkpoint {
+    /// SHA-256 of the snapshot file, hex-encoded
+    snapshot_sha256: String,
+    /// Collection the points are imported into
+    target_collection: String,
+    /// Number of points already imported, in snapshot order
+    imported: usize,
+}
+
+/// Recorded progress, `None` when there is no checkpoint
+fn read_checkpoint(path: &Path) -> VectorDbResult<Option<Checkpoint>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    serde_json::from_slice(&fs::read(path)?)
+        .map(Some)
+        .map_err(|_| VectorDbError::Deserialization(format!("Invalid checkpoint file {}", path.display())))
+}
+
+/// Record import progress, replacing the file atomically
+fn write_checkpoint(path: &Path, checkpoint: &Checkpoint) -> VectorDbResult<()> {
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    fs::write(
+        &tmp,
+        serde_json::to_vec(checkpoint).map_err(|e| VectorDbError::Serialization(e.to_string()))?,
+    )?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// SHA-256 of a file, hex-encoded
+fn file_sha256(path: &Path) -> VectorDbResult<String> {
+    let mut input = BufReader::new(File::open(path)?);
+    let mut context = Context::new(&SHA256);
+    loop {
+        let chunk = input.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        context.update(chunk);
+        let len = chunk.len();
+        input.consume(len);
+    }
+    Ok(context.finish().as_ref().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Streaming writer for either snapshot format
+struct SnapshotWriter {
+    format: SnapshotFormat,
+    out: BufWriter<File>,
+}
+
+impl SnapshotWriter {
+    fn create(path: &Path, format: SnapshotFormat, header: &SnapshotHeader) -> VectorDbResult<Self> {
+        let mut writer = Self {
+            format,
+            out: BufWriter::new(File::create(path)?),
+        };
+
+        let header_json = serde_json::to_vec(header).map_err(|e| VectorDbError::Serialization(e.to_string()))?;
+        match format {
+            SnapshotFormat::JsonLines => {
+                writer.out.write_all(&header_json)?;
+                writer.out.write_all(b"\n")?;
+            }
+            SnapshotFormat::Binary => {
+                writer.out.write_all(BINARY_MAGIC)?;
+                write_bytes(&mut writer.out, &header_json)?;
+            }
+        }
+        Ok(writer)
+    }
+
+    fn write_point(&mut self, point: &VectorPoint) -> VectorDbResult<()> {
+        match self.format {
+            SnapshotFormat::JsonLines => {
+                serde_json::to_writer(&mut self.out, point).map_err(|e| VectorDbError::Serialization(e.to_string()))?;
+                self.out.write_all(b"\n")?;
+            }
+            SnapshotFormat::Binary => {
+                write_bytes(&mut self.out, point.id.as_bytes())?;
+                self.out.write_all(&(point.vector.len() as u32).to_le_bytes())?;
+                for value in &point.vector {
+                    self.out.write_all(&value.to_le_bytes())?;
+                }
+                let payload =
+                    serde_json::to_vec(&point.payload).map_err(|e| VectorDbError::Serialization(e.to_string()))?;
+                write_bytes(&mut self.out, &payload)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> VectorDbResult<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Streaming reader detecting the snapshot format from its first bytes
+///
+/// Lengths in a binary snapshot are checked against the header and fixed
+/// limits before anything is allocated, so a damaged or hostile file fails
+/// instead of exhausting memory.
+enum SnapshotReader {
+    JsonLines(std::io::Lines<BufReader<File>>),
+    Binary { input: BufReader<File>, vector_size: u64 },
+}
+
+impl SnapshotReader {
+    fn open(path: &Path) -> VectorDbResult<(SnapshotHeader, Self)> {
+        let mut input = BufReader::new(File::open(path)?);
+
+        if input.fill_buf()?.starts_with(BINARY_MAGIC) {
+            input.consume(BINARY_MAGIC.len());
+            let header = read_bytes(&mut input, MAX_HEADER_BYTES, "header")?
+                .ok_or_else(|| VectorDbError::Deserialization("Snapshot is missing its header".to_string()))?;
+            let header: SnapshotHeader =
+                serde_json::from_slice(&header).map_err(|e| VectorDbError::Deserialization(e.to_string()))?;
+            let vector_size = header.spec.vector_size;
+            return Ok((header, SnapshotReader::Binary { input, vector_size }));
+        }
+
+        let mut lines = input.lines();
+        let header = lines
+            .next()
+            .transpose()?
+            .ok_or_else(|| VectorDbError::Deserialization("Snapshot is missing its header".to_string()))?;
+        let header = serde_json::from_str(&header).map_err(|e| VectorDbError::Deserialization(e.to_string()))?;
+        Ok((header, SnapshotReader::JsonLines(lines)))
+    }
+
+    fn next_point(&mut self) -> VectorDbResult<Option<VectorPoint>> {
+        match self {
+            SnapshotReader::JsonLines(lines) => {
+                for line in lines.by_ref() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let point = serde_json::from_str(&line).map_err(|e| VectorDbError::Deserialization(e.to_string()))?;
+                    return Ok(Some(point));
+                }
+                Ok(None)
+            }
+            SnapshotReader::Binary { input, vector_size } => {
+                let id = match read_bytes(input, MAX_ID_BYTES, "point ID")? {
+                    Some(id) => String::from_utf8(id).map_err(|e| VectorDbError::Deserialization(e.to_string()))?,
+                    None => return Ok(None),
+                };
+
+                let dimensions = read_u32(input)? as u64;
+                if dimensions != *vector_size {
+                    return Err(VectorDbError::Deserialization(format!(
+                        "Point {} has {} dimensions, the snapshot declares {}",
+                        id, dimensions, vector_size
+                    )));
+                }
+                let mut raw = vec![0u8; dimensions as usize * 4];
+                input.read_exact(&mut raw)?;
+                let vector = raw
+                    .chunks_exact(4)
+                    .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                    .collect();
+
+                let payload = read_bytes(input, MAX_PAYLOAD_BYTES, "payload")?
+                    .ok_or_else(|| VectorDbError::Deserialization(format!("Truncated payload for point {}", id)))?;
+                let payload = serde_json::from_slice(&payload).map_err(|e| VectorDbError::Deserialization(e.to_string()))?;
+
+                Ok(Some(VectorPoint { id, vector, payload }))
+            }
+        }
+    }
+}
+
+/// Write a u32 length prefix followed by the bytes
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> VectorDbResult<()> {
+    out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    out.write_all(bytes)?;
+    Ok(())
+}
+
+/// Read a u32 little-endian integer
+fn read_u32(input: &mut impl Read) -> VectorDbResult<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Read a length-prefixed byte string of at most `max` bytes, `None` at a clean end of file
+fn read_bytes(input: &mut BufReader<File>, max: usize, what: &str) -> VectorDbResult<Option<Vec<u8>>> {
+    if input.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+
+    let len = read_u32(input)? as usize;
+    if len > max {
+        return Err(VectorDbError::Deserialization(format!(
+            "Snapshot {} of {} bytes exceeds the limit of {}",
+            what, len, max
+        )));
+    }
+    let mut bytes = vec![0u8; len];
+    input.read_exact(&mut bytes)?;
+    Ok(Some(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryStore;
+    use crate::store::Distance;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    async fn populated_store(count: usize) -> InMemoryStore {
+        let store = InMemoryStore::new();
+        store
+            .ensure_collection_with_spec(
+                "source",
+                &CollectionSpec {
+                    vector_size: 3,
+                    distance: Distance::Dot,
+                },
+            )
+            .await
+            .unwrap();
+
+        let points = (0..count)
+            .map(|i| {
+                let mut payload = HashMap::new();
+                payload.insert("index".to_string(), json!(i));
+                payload.insert("tags".to_string(), json!({ "name": format!("point {}", i), "even": i % 2 == 0 }));
+                VectorPoint {
+                    id: format!("p{:04}", i),
+                    vector: vec![i as f32, 0.5, -1.25],
+                    payload,
+                }
+            })
+            .collect();
+        store.upsert("source", points).await.unwrap();
+        store
+    }
+
+    async fn assert_roundtrip(format: SnapshotFormat, file_name: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(file_name);
+        let source = populated_store(600).await;
+
+        let exported = export_collection(&source, "source", &path, format).await.unwrap();
+        assert_eq!(exported.points, 600);
+
+        let target = InMemoryStore::new();
+        let stats = import_collection(&target, &path, &ImportOptions::default()).await.unwrap();
+        assert_eq!(stats.imported, 600);
+        assert_eq!(stats.skipped, 0);
+        assert!(!default_checkpoint_path(&path).exists());
+
+        assert_eq!(
+            target.collection_spec("source").await.unwrap(),
+            source.collection_spec("source").await.unwrap()
+        );
+        let original = source.scroll("source", None, 1000).await.unwrap().points;
+        let restored = target.scroll("source", None, 1000).await.unwrap().points;
+        assert_eq!(original.len(), restored.len());
+        for (a, b) in original.iter().zip(&restored) {
+            assert_eq!(a.id, b.id);
+            assert_eq!(a.vector, b.vector);
+            assert_eq!(a.payload, b.payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_roundtrip_jsonl() {
+        assert_roundtrip(SnapshotFormat::JsonLines, "snapshot.jsonl").await;
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_roundtrip_binary() {
+        assert_roundtrip(SnapshotFormat::Binary, "snapshot.nxsnap").await;
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_import_resumes_from_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.nxsnap");
+        let source = populated_store(10).await;
+        export_collection(&source, "source", &path, SnapshotFormat::Binary)
+            .await
+            .unwrap();
+
+        // Simulate a previous run that stopped after the first 4 points
+        let checkpoint = Checkpoint {
+            snapshot_sha256: file_sha256(&path).unwrap(),
+            target_collection: "restored".to_string(),
+            imported: 4,
+        };
+        write_checkpoint(&default_checkpoint_path(&path), &checkpoint).unwrap();
+
+        let target = InMemoryStore::new();
+        let options = ImportOptions {
+            batch_size: 3,
+            target_collection: Some("restored".to_string()),
+            ..Default::default()
+        };
+        let stats = import_collection(&target, &path, &options).await.unwrap();
+        assert_eq!(stats.skipped, 4);
+        assert_eq!(stats.imported, 6);
+        assert!(!default_checkpoint_path(&path).exists());
+
+        let restored = target.scroll("restored", None, 100).await.unwrap().points;
+        assert_eq!(restored.first().map(|p| p.id.as_str()), Some("p0004"));
+        assert_eq!(restored.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_import_refuses_mismatched_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.jsonl");
+        let source = populated_store(10).await;
+        export_collection(&source, "source", &path, SnapshotFormat::JsonLines)
+            .await
+            .unwrap();
+
+        let checkpoint_path = default_checkpoint_path(&path);
+        let checkpoint = Checkpoint {
+            snapshot_sha256: file_sha256(&path).unwrap(),
+            target_collection: "restored".to_string(),
+            imported: 4,
+        };
+        write_checkpoint(&checkpoint_path, &checkpoint).unwrap();
+
+        // Another target collection
+        let target = InMemoryStore::new();
+        let error = import_collection(&target, &path, &ImportOptions::default()).await.unwrap_err();
+        assert!(matches!(error, VectorDbError::Config(_)), "{}", error);
+        assert!(target.scroll("source", None, 100).await.unwrap().points.is_empty());
+
+        // The snapshot changed since the checkpoint was written
+        export_collection(&populated_store(12).await, "source", &path, SnapshotFormat::JsonLines)
+            .await
+            .unwrap();
+        let options = ImportOptions {
+            target_collection: Some("restored".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            import_collection(&target, &path, &options).await,
+            Err(VectorDbError::Config(_))
+        ));
+        assert!(checkpoint_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_import_checks_distance() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.jsonl");
+        export_collection(&populated_store(3).await, "source", &path, SnapshotFormat::JsonLines)
+            .await
+            .unwrap();
+
+        let target = InMemoryStore::new();
+        target.ensure_collection("source", 3).await.unwrap();
+        assert!(matches!(
+            import_collection(&target, &path, &ImportOptions::default()).await,
+            Err(VectorDbError::Config(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_binary_snapshot_rejects_bad_lengths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.nxsnap");
+        export_collection(&populated_store(1).await, "source", &path, SnapshotFormat::Binary)
+            .await
+            .unwrap();
+        let valid = fs::read(&path).unwrap();
+        let header_len = u32::from_le_bytes(valid[8..12].try_into().unwrap()) as usize;
+        let point = 12 + header_len;
+        let id_len = u32::from_le_bytes(valid[point..point + 4].try_into().unwrap()) as usize;
+        let dimensions = point + 4 + id_len;
+
+        // A vector longer than the header declares
+        let mut damaged = valid.clone();
+        damaged[dimensions..dimensions + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &damaged).unwrap();
+        let target = InMemoryStore::new();
+        assert!(matches!(
+            import_collection(&target, &path, &ImportOptions::default()).await,
+            Err(VectorDbError::Deserialization(_))
+        ));
+
+        // A payload length beyond the limit
+        let payload = dimensions + 4 + 3 * 4;
+        let mut damaged = valid;
+        damaged[payload..payload + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &damaged).unwrap();
+        fs::remove_file(default_checkpoint_path(&path)).ok();
+        assert!(matches!(
+            import_collection(&target, &path, &ImportOptions::default()).await,
+            Err(VectorDbError::Deserialization(_))
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_format_selection() {
+        assert_eq!(SnapshotFormat::from_path(Path::new("a.nxsnap")), SnapshotFormat::Binary);
+        assert_eq!(SnapshotFormat::from_path(Path::new("a.jsonl")), SnapshotFormat::JsonLines);
+        assert_eq!("binary".parse::<SnapshotFormat>().unwrap(), SnapshotFormat::Binary);
+        assert!("xml".parse::<SnapshotFormat>().is_err());
+    }
+}
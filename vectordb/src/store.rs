@@ -0,0 +1,146 @@
+//! Backend-agnostic vector store abstraction
+//!
+//! This module defines the `VectorStore` trait implemented by the Qdrant
+//! client and the in-memory store, so callers can work against either.
+
+use crate::client::SearchResult;
+use crate::VectorDbResult;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A point to be stored in a collection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VectorPoint {
+    /// Point ID
+    pub id: String,
+    /// Vector data
+    pub vector: Vec<f32>,
+    /// Associated payload
+    #[serde(default)]
+    pub payload: HashMap<String, serde_json::Value>,
+}
+
+/// Distance metric used to compare vectors in a collection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Distance {
+    Cosine,
+    Dot,
+    Euclid,
+    Manhattan,
+}
+
+/// Configuration of a collection, enough to recreate it elsewhere
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CollectionSpec {
+    /// Dimensionality of stored vectors
+    pub vector_size: u64,
+    /// Distance metric
+    pub distance: Distance,
+}
+
+/// One page of points returned by a scroll
+#[derive(Debug, Clone, Default)]
+pub struct ScrollPage {
+    /// Points in this page
+    pub points: Vec<VectorPoint>,
+    /// Offset to pass to fetch the next page, `None` when exhausted
+    pub next_offset: Option<String>,
+}
+
+/// Exact-match conditions on payload fields; a point matches when all hold
+#[derive(Debug, Clone, Default)]
+pub struct PayloadFilter {
+    /// Field name and expected value pairs
+    pub must: Vec<(String, serde_json::Value)>,
+}
+
+impl PayloadFilter {
+    /// Create an empty filter that matches every point
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a condition requiring `key` to equal `value`
+    pub fn must_match(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.must.push((key.to_string(), value.into()));
+        self
+    }
+
+    /// Check whether a payload satisfies every condition
+    pub fn matches(&self, payload: &HashMap<String, serde_json::Value>) -> bool {
+        self.must
+            .iter()
+            .all(|(key, value)| payload.get(key) == Some(value))
+    }
+}
+
+/// Operations shared by all vector store backends
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Create a collection if it does not exist yet
+    async fn ensure_collection(&self, collection_name: &str, vector_size: u64) -> VectorDbResult<()> {
+        self.ensure_collection_with_spec(
+            collection_name,
+            &CollectionSpec {
+                vector_size,
+                distance: Distance::Cosine,
+            },
+        )
+        .await
+    }
+
+    /// Create a collection with the given configuration if it does not exist yet
+    async fn ensure_collection_with_spec(&self, collection_name: &str, spec: &CollectionSpec) -> VectorDbResult<()>;
+
+    /// Names of all collections
+    async fn list_collections(&self) -> VectorDbResult<Vec<String>>;
+
+    /// Configuration of an existing collection
+    async fn collection_spec(&self, collection_name: &str) -> VectorDbResult<CollectionSpec>;
+
+    /// Page through all points of a collection, including vectors and payloads
+    async fn scroll(&self, collection_name: &str, offset: Option<String>, limit: u32) -> VectorDbResult<ScrollPage>;
+
+    /// Insert or replace points in a collection
+    async fn upsert(&self, collection_name: &str, points: Vec<VectorPoint>) -> VectorDbResult<()>;
+
+    /// Search for the nearest points, optionally restricted by a payload filter
+    async fn search(
+        &self,
+        collection_name: &str,
+        query_vector: &[f32],
+        limit: u64,
+        filter: Option<&PayloadFilter>,
+    ) -> VectorDbResult<Vec<SearchResult>>;
+
+    /// Delete points by ID
+    async fn delete_points(&self, collection_name: &str, ids: &[String]) -> VectorDbResult<()>;
+
+    /// Delete every point matching a payload filter
+    async fn delete_by_filter(&self, collection_name: &str, filter: &PayloadFilter) -> VectorDbResult<()>;
+
+    /// Delete a collection and all of its points
+    async fn delete_collection(&self, collection_name: &str) -> VectorDbResult<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_payload_filter_matches() {
+        let mut payload = HashMap::new();
+        payload.insert("model".to_string(), json!("local"));
+        payload.insert("temperature".to_string(), json!("0.70"));
+
+        assert!(PayloadFilter::new().matches(&payload));
+        assert!(PayloadFilter::new().must_match("model", "local").matches(&payload));
+        assert!(!PayloadFilter::new()
+            .must_match("model", "local")
+            .must_match("temperature", "0.20")
+            .matches(&payload));
+        assert!(!PayloadFilter::new().must_match("missing", "x").matches(&payload));
+    }
+}
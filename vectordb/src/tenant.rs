@@ -0,0 +1,172 @@
+//! Tenant-scoped vector store
+//!
+//! `TenantStore` wraps any `VectorStore` and keeps each tenant's collections
+//! apart by prefixing their names with the tenant, `acme__documents` for the
+//! `documents` collection of `acme`. A tenant only sees, searches and
+//! deletes its own collections; listing strips the prefix again.
+
+use crate::client::SearchResult;
+use crate::error::VectorDbError;
+use crate::store::{CollectionSpec, PayloadFilter, ScrollPage, VectorPoint, VectorStore};
+use crate::VectorDbResult;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Separates the tenant from the collection name
+const TENANT_SEPARATOR: &str = "__";
+
+/// Name a tenant's collection is stored under
+pub fn tenant_collection_name(tenant: &str, collection_name: &str) -> String {
+    format!("{}{}{}", tenant, TENANT_SEPARATOR, collection_name)
+}
+
+/// View of a vector store limited to one tenant's collections
+#[derive(Clone)]
+pub struct TenantStore {
+    inner: Arc<dyn VectorStore>,
+    tenant: String,
+}
+
+impl TenantStore {
+    /// Scope `inner` to the collections of `tenant`
+    pub fn new(inner: Arc<dyn VectorStore>, tenant: &str) -> Self {
+        Self {
+            inner,
+            tenant: tenant.to_string(),
+        }
+    }
+
+    /// Tenant this store is scoped to
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    fn scoped(&self, collection_name: &str) -> String {
+        tenant_collection_name(&self.tenant, collection_name)
+    }
+
+    /// Report missing collections under the name the tenant used
+    fn unscoped_error(&self, collection_name: &str, error: VectorDbError) -> VectorDbError {
+        match error {
+            VectorDbError::CollectionNotFound(_) => VectorDbError::CollectionNotFound(collection_name.to_string()),
+            other => other,
+        }
+    }
+}
+
+#[async_trait]
+impl VectorStore for TenantStore {
+    async fn ensure_collection_with_spec(&self, collection_name: &str, spec: &CollectionSpec) -> VectorDbResult<()> {
+        self.inner
+            .ensure_collection_with_spec(&self.scoped(collection_name), spec)
+            .await
+    }
+
+    async fn list_collections(&self) -> VectorDbResult<Vec<String>> {
+        let prefix = self.scoped("");
+        Ok(self
+            .inner
+            .list_collections()
+            .await?
+            .into_iter()
+            .filter_map(|name| name.strip_prefix(&prefix).map(str::to_string))
+            .collect())
+    }
+
+    async fn collection_spec(&self, collection_name: &str) -> VectorDbResult<CollectionSpec> {
+        self.inner
+            .collection_spec(&self.scoped(collection_name))
+            .await
+            .map_err(|e| self.unscoped_error(collection_name, e))
+    }
+
+    async fn scroll(&self, collection_name: &str, offset: Option<String>, limit: u32) -> VectorDbResult<ScrollPage> {
+        self.inner
+            .scroll(&self.scoped(collection_name), offset, limit)
+            .await
+            .map_err(|e| self.unscoped_error(collection_name, e))
+    }
+
+    async fn upsert(&self, collection_name: &str, points: Vec<VectorPoint>) -> VectorDbResult<()> {
+        self.inner
+            .upsert(&self.scoped(collection_name), points)
+            .await
+            .map_err(|e| self.unscoped_error(collection_name, e))
+    }
+
+    async fn search(
+        &self,
+        collection_name: &str,
+        query_vector: &[f32],
+        limit: u64,
+        filter: Option<&PayloadFilter>,
+    ) -> VectorDbResult<Vec<SearchResult>> {
+        self.inner
+            .search(&self.scoped(collection_name), query_vector, limit, filter)
+            .await
+            .map_err(|e| self.unscoped_error(collection_name, e))
+    }
+
+    async fn delete_points(&self, collection_name: &str, ids: &[String]) -> VectorDbResult<()> {
+        self.inner
+            .delete_points(&self.scoped(collection_name), ids)
+            .await
+            .map_err(|e| self.unscoped_error(collection_name, e))
+    }
+
+    async fn delete_by_filter(&self, collection_name: &str, filter: &PayloadFilter) -> VectorDbResult<()> {
+        self.inner
+            .delete_by_filter(&self.scoped(collection_name), filter)
+            .await
+            .map_err(|e| self.unscoped_error(collection_name, e))
+    }
+
+    async fn delete_collection(&self, collection_name: &str) -> VectorDbResult<()> {
+        self.inner
+            .delete_collection(&self.scoped(collection_name))
+            .await
+            .map_err(|e| self.unscoped_error(collection_name, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryStore;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_tenants_cannot_see_each_others_collections() {
+        let shared: Arc<dyn VectorStore> = Arc::new(InMemoryStore::new());
+        let acme = TenantStore::new(shared.clone(), "acme");
+        let globex = TenantStore::new(shared.clone(), "globex");
+
+        acme.ensure_collection("documents", 2).await.unwrap();
+        acme.upsert(
+            "documents",
+            vec![VectorPoint {
+                id: "plan".to_string(),
+                vector: vec![1.0, 0.0],
+                payload: HashMap::new(),
+            }],
+        )
+        .await
+        .unwrap();
+
+        let hits = acme.search("documents", &[1.0, 0.0], 5, None).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(acme.list_collections().await.unwrap(), ["documents"]);
+
+        // Globex neither lists nor searches acme's collection
+        assert!(globex.list_collections().await.unwrap().is_empty());
+        match globex.search("documents", &[1.0, 0.0], 5, None).await {
+            Err(VectorDbError::CollectionNotFound(name)) => assert_eq!(name, "documents"),
+            other => panic!("expected a missing collection, got {:?}", other.map(|hits| hits.len())),
+        }
+
+        // A collection of the same name is its own
+        globex.ensure_collection("documents", 2).await.unwrap();
+        assert!(globex.search("documents", &[1.0, 0.0], 5, None).await.unwrap().is_empty());
+        assert_eq!(shared.list_collections().await.unwrap().len(), 2);
+    }
+}